@@ -0,0 +1,348 @@
+//! Benchmarks for the hot paths: parsing, hashing, P2P message framing and cloning scripts.
+//!
+//! Run with `cargo bench -p bitcoinsv`. Criterion keeps the results of the previous run in
+//! `target/criterion` and reports the change against them, so before and after numbers for a change
+//! can be obtained by running the benchmarks on both versions of the code.
+use bitcoinsv::bitcoin::{
+    merkle_root, scan_tx, varint_decode, varint_encode, verify_signature, verify_signatures_batch,
+    AsyncEncodable, Block, BlockHeader, BlockTemplateBuilder, BlockchainId, ByteSequence, Hash,
+    Operation, Script, SigCheckItem, SighashCache, Tx, TxInput, TxOutput, SIGHASH_ALL,
+    SIGHASH_FORKID,
+};
+use bitcoinsv::p2p::{ChannelConfig, P2PMessage};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::executor::block_on;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+/// A real mainnet block with 222 transactions.
+const BLOCK_FILE: &str =
+    "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin";
+
+fn read_block_bin() -> Vec<u8> {
+    std::fs::read(BLOCK_FILE).expect("could not read block file")
+}
+
+/// Parse the block header and all of the transactions of a block.
+async fn parse_block(bin: &[u8]) -> (BlockHeader, Vec<Tx>) {
+    let mut reader = Cursor::new(bin);
+    let header = BlockHeader::async_from_binary(&mut reader).await.unwrap();
+    let num_tx = varint_decode(&mut reader).await.unwrap();
+    let mut txs = Vec::with_capacity(num_tx as usize);
+    for _ in 0..num_tx {
+        txs.push(Tx::async_from_binary(&mut reader).await.unwrap());
+    }
+    (header, txs)
+}
+
+fn bench_block(c: &mut Criterion) {
+    let bin = read_block_bin();
+    let mut group = c.benchmark_group("block");
+    group.throughput(Throughput::Bytes(bin.len() as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| block_on(parse_block(black_box(&bin))))
+    });
+    let (_, txs) = block_on(parse_block(&bin));
+    group.bench_function("txids", |b| {
+        b.iter(|| {
+            for tx in black_box(&txs).iter() {
+                black_box(tx.hash());
+            }
+        })
+    });
+    // index the transactions, by parsing them and hashing their encoding, and by scanning them
+    let body = &bin[BlockHeader::SIZE + 1..];
+    group.bench_function("index_parsed", |b| {
+        b.iter(|| {
+            let mut reader = Cursor::new(black_box(body));
+            for _ in 0..txs.len() {
+                let tx = block_on(Tx::async_from_binary(&mut reader)).unwrap();
+                black_box((tx.hash(), tx.async_size()));
+            }
+        })
+    });
+    group.bench_function("index_scanned", |b| {
+        b.iter(|| {
+            let mut buf = black_box(body);
+            for _ in 0..txs.len() {
+                black_box(scan_tx(&mut buf).unwrap());
+            }
+        })
+    });
+    // scan for the outputs paying to an address which is paid 53 times in the block, by matching the
+    // encoded scripts and by decoding every script into operations
+    let hash = hex::decode("a933cfc5d05e36fa4270bf6fb2c7d74a4e1e9aef").unwrap();
+    group.bench_function("scan_address", |b| {
+        b.iter(|| {
+            for tx in black_box(&txs).iter() {
+                black_box(tx.find_outputs(|s| s.p2pkh_hash() == Some(&hash[..])));
+            }
+        })
+    });
+    let target = Operation::OP_PUSH(ByteSequence::new(hash.clone().into()));
+    group.bench_function("scan_address_decoded", |b| {
+        b.iter(|| {
+            for tx in black_box(&txs).iter() {
+                black_box(tx.find_outputs(|s| match s.decode() {
+                    Ok((ops, _)) => ops.len() == 5 && ops[2] == target,
+                    Err(_) => false,
+                }));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_varint(c: &mut Criterion) {
+    // a spread of values covering all of the encoded sizes
+    let values: Vec<u64> = (0..1000u64)
+        .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (i % 64))
+        .collect();
+    let mut encoded = Vec::new();
+    for v in values.iter() {
+        block_on(varint_encode(&mut encoded, *v)).unwrap();
+    }
+    let mut group = c.benchmark_group("varint");
+    group.throughput(Throughput::Elements(values.len() as u64));
+    group.bench_function("encode", |b| {
+        b.iter(|| {
+            let mut v = Vec::with_capacity(encoded.len());
+            for value in values.iter() {
+                block_on(varint_encode(&mut v, black_box(*value))).unwrap();
+            }
+            v
+        })
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            let mut reader = Cursor::new(black_box(&encoded));
+            for _ in 0..values.len() {
+                black_box(block_on(varint_decode(&mut reader)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_p2p_framing(c: &mut Criterion) {
+    // a transaction message with a 4MB payload
+    let tx = Tx {
+        version: 1,
+        inputs: vec![TxInput::new(
+            Hash::ZERO,
+            0,
+            Script::from(vec![0u8; 100]),
+            None,
+        )],
+        outputs: vec![TxOutput::new(0, Script::from(vec![0x6a; 4_000_000]))],
+        lock_time: 0,
+    };
+    let msg = P2PMessage::Tx(tx);
+    let config = ChannelConfig::default();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let mut encoded = Vec::new();
+    rt.block_on(msg.write(&mut encoded, &config)).unwrap();
+    let mut group = c.benchmark_group("p2p_framing");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function("write_4mb", |b| {
+        b.iter_batched_ref(
+            || Vec::with_capacity(encoded.len()),
+            |v| rt.block_on(msg.write(v, &config)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("read_4mb", |b| {
+        b.iter(|| {
+            rt.block_on(P2PMessage::read(
+                &mut Cursor::new(black_box(&encoded)),
+                &config,
+            ))
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_sighash(c: &mut Criterion) {
+    // a consolidation transaction with 5,000 P2PKH inputs
+    let subscript =
+        Script::from(hex::decode("76a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac").unwrap());
+    let tx = Tx {
+        version: 1,
+        inputs: (0..5_000u32)
+            .map(|i| {
+                TxInput::new(
+                    Hash::sha256d(&i.to_le_bytes()),
+                    0,
+                    Script::from(vec![0u8; 107]),
+                    None,
+                )
+            })
+            .collect(),
+        outputs: vec![TxOutput::new(1_000_000, subscript.clone())],
+        lock_time: 0,
+    };
+    let sighash_type = SIGHASH_ALL | SIGHASH_FORKID;
+    let mut group = c.benchmark_group("sighash");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(tx.inputs.len() as u64));
+    group.bench_function("5000_inputs_cached", |b| {
+        b.iter(|| {
+            let cache = SighashCache::new(black_box(&tx));
+            for i in 0..tx.inputs.len() {
+                black_box(cache.sighash_for_input(i, &subscript, 1000, sighash_type)).unwrap();
+            }
+        })
+    });
+    group.bench_function("5000_inputs_uncached", |b| {
+        b.iter(|| {
+            for i in 0..tx.inputs.len() {
+                black_box(black_box(&tx).sighash(i, &subscript, 1000, sighash_type)).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_signatures(c: &mut Criterion) {
+    // the P2PKH inputs of the block that spend outputs in the same block, the values spent are known
+    let (_, txs) = block_on(parse_block(&read_block_bin()));
+    let by_hash: HashMap<Hash, &Tx> = txs.iter().map(|t| (t.hash(), t)).collect();
+    let mut items = Vec::new();
+    for tx in txs.iter() {
+        let cache = SighashCache::new(tx);
+        for (index, input) in tx.inputs.iter().enumerate() {
+            let Some(prev) = by_hash.get(&input.outpoint.tx_hash) else {
+                continue;
+            };
+            let spent = &prev.outputs[input.outpoint.index as usize];
+            if let [Operation::OP_PUSH(sig), Operation::OP_PUSH(key)] =
+                &input.script.decode().unwrap().0[..]
+            {
+                let sig = sig.get_bytes();
+                let (der, sighash_type) = sig.split_at(sig.len() - 1);
+                items.push(SigCheckItem {
+                    signature: der.to_vec(),
+                    pubkey: key.get_bytes().to_vec(),
+                    sighash: cache
+                        .sighash_for_input(index, &spent.script, spent.value, sighash_type[0])
+                        .unwrap(),
+                });
+            }
+        }
+    }
+    let mut group = c.benchmark_group("signatures");
+    group.throughput(Throughput::Elements(items.len() as u64));
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            for item in black_box(&items).iter() {
+                black_box(verify_signature(item));
+            }
+        })
+    });
+    group.bench_function("batch", |b| {
+        b.iter(|| verify_signatures_batch(black_box(&items)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_script(c: &mut Criterion) {
+    // a clone shares the bytes of the script, so it takes the same time whatever the size
+    let mut group = c.benchmark_group("script");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1_000_000));
+    for size in [100, 100_000] {
+        let script = Script::from(vec![0x6a; size]);
+        group.bench_function(format!("clone_{}_bytes", size), |b| {
+            b.iter(|| {
+                for _ in 0..1_000_000 {
+                    black_box(black_box(&script).clone());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_large_block(c: &mut Criterion) {
+    // a synthetic block of 10,000 P2PKH payments, a little over 2MB, assembled by the template builder
+    let script =
+        Script::from(hex::decode("76a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac").unwrap());
+    let candidates: Vec<(Tx, u64)> = (0..10_000u32)
+        .map(|i| {
+            let tx = Tx {
+                version: 1,
+                inputs: vec![TxInput::new(
+                    Hash::sha256d(&i.to_le_bytes()),
+                    0,
+                    Script::from(vec![0u8; 107]),
+                    None,
+                )],
+                outputs: vec![
+                    TxOutput::new(100_000, script.clone()),
+                    TxOutput::new(u64::from(i), script.clone()),
+                ],
+                lock_time: 0,
+            };
+            (tx, 250)
+        })
+        .collect();
+    let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+    let builder = BlockTemplateBuilder::new(&genesis, 1, genesis.bits, script.clone());
+    let block = builder.build(candidates.clone()).unwrap().block;
+    let bin = block.to_binary_buf().unwrap();
+    assert!(bin.len() >= 1_000_000);
+    let hashes: Vec<Hash> = block.transactions.iter().map(|t| t.hash()).collect();
+
+    let mut group = c.benchmark_group("large_block");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bin.len() as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| Block::from_binary_buf(black_box(&bin)).unwrap())
+    });
+    group.bench_function("merkle_root", |b| {
+        b.iter(|| merkle_root(black_box(&hashes)))
+    });
+    // hashing the transactions as well as combining the hashes
+    group.bench_function("merkle_root_from_txs", |b| {
+        b.iter(|| black_box(&block).merkle_root())
+    });
+    group.bench_function("template", |b| {
+        b.iter_batched(
+            || candidates.clone(),
+            |txs| builder.build(txs).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_merkle_root(c: &mut Criterion) {
+    // the hashes of a block with 100,000 transactions
+    let hashes: Vec<Hash> = (0..100_000u32)
+        .map(|i| Hash::sha256d(&i.to_le_bytes()))
+        .collect();
+    let mut group = c.benchmark_group("merkle_root");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(hashes.len() as u64));
+    group.bench_function("100000_hashes", |b| {
+        b.iter(|| merkle_root(black_box(&hashes)))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_block,
+    bench_large_block,
+    bench_merkle_root,
+    bench_varint,
+    bench_p2p_framing,
+    bench_sighash,
+    bench_signatures,
+    bench_script
+);
+criterion_main!(benches);
@@ -1,15 +1,18 @@
-use crate::bitcoin::base58ck::encode_with_checksum;
+use crate::bitcoin::base58ck::{decode_with_checksum, encode_with_checksum};
 use crate::bitcoin::crypto::{PrivateKey, PublicKey};
 use crate::bitcoin::hash160::Hash160;
 use crate::bitcoin::params::KeyAddressKind;
-use crate::bitcoin::BlockchainId;
+use crate::bitcoin::{BlockchainId, ByteSequence, Operation, Script, ScriptBuilder};
+use crate::{Error, Result};
+use bytes::Bytes;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 /// A Bitcoin Address is a destination for a Bitcoin payment, using the P2PKH script template.
 ///
 /// The address is the 160-bit hash of the public key, encoded in base58check format, with
 /// a single byte prefix depending on the blockchain.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Address {
     pub hash160: Hash160,
     pub kind: KeyAddressKind,
@@ -39,6 +42,41 @@ impl Address {
             kind,
         }
     }
+
+    /// Get the address that pays to a public key hash, such as one read from an index, for a
+    /// particular [BlockchainId].
+    pub fn from_hash160(hash160: Hash160, blockchain: BlockchainId) -> Address {
+        Address {
+            hash160,
+            kind: KeyAddressKind::from(blockchain),
+        }
+    }
+
+    /// The hash of the public key that the address pays to.
+    pub fn hash160(&self) -> Hash160 {
+        self.hash160
+    }
+
+    /// Get the address of a newly generated key, for when only a destination is needed, such as in
+    /// tests.
+    #[cfg(feature = "key-generation")]
+    pub fn random(blockchain: BlockchainId) -> Address {
+        KeyPair::generate(blockchain).address
+    }
+
+    /// Get the P2PKH locking script which pays to this address.
+    pub fn locking_script(&self) -> Script {
+        use Operation::*;
+        let hash = ByteSequence::new(Bytes::copy_from_slice(&self.hash160.hash));
+        ScriptBuilder::new()
+            .add(OP_DUP)
+            .add(OP_HASH160)
+            .add(OP_PUSH(hash))
+            .add(OP_EQUALVERIFY)
+            .add(OP_CHECKSIG)
+            .build()
+            .expect("P2PKH script is always valid")
+    }
 }
 
 impl Display for Address {
@@ -49,6 +87,65 @@ impl Display for Address {
     }
 }
 
+impl FromStr for Address {
+    type Err = Error;
+
+    /// Decode an address from its base58check encoding.
+    fn from_str(s: &str) -> Result<Address> {
+        let data = decode_with_checksum(&s.to_string())?;
+        if data.len() != 21 {
+            return Err(Error::WrongLength {
+                expected: 21,
+                actual: data.len(),
+            });
+        }
+        let kind = match data[0] {
+            0x00 => KeyAddressKind::Main,
+            0x6f => KeyAddressKind::NotMain,
+            _ => return Err(Error::InvalidBlockchainSpecifier),
+        };
+        Ok(Address {
+            hash160: Hash160::from(&data[1..]),
+            kind,
+        })
+    }
+}
+
+/// A private key with its public key and address, for when a fresh destination and the means to
+/// spend from it are needed together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPair {
+    pub private_key: PrivateKey,
+    pub public_key: PublicKey,
+    pub address: Address,
+}
+
+impl KeyPair {
+    /// Generate a key pair from the operating system's random number generator.
+    #[cfg(feature = "key-generation")]
+    pub fn generate(blockchain: BlockchainId) -> KeyPair {
+        KeyPair::from_private_key(PrivateKey::generate(), blockchain)
+    }
+
+    /// Make a key pair deterministically from a seed, see [PrivateKey::from_seed()].
+    ///
+    /// Only for reproducible tests, never use such a key on a production blockchain.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_seed(seed: u64, blockchain: BlockchainId) -> KeyPair {
+        KeyPair::from_private_key(PrivateKey::from_seed(seed), blockchain)
+    }
+
+    /// Complete the key pair of a private key.
+    pub fn from_private_key(private_key: PrivateKey, blockchain: BlockchainId) -> KeyPair {
+        let public_key = private_key.public_key();
+        KeyPair {
+            private_key,
+            public_key,
+            address: Address::from_pubkey(&public_key, KeyAddressKind::from(blockchain)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +182,11 @@ mod tests {
             addr.to_string(),
             "1BA47GLhQZrTtPt21CJ73cY9YSSsCXX7gF".to_string()
         );
+        // output 0 of the same tx pays to the address
+        assert_eq!(
+            hex::encode(&addr.locking_script().raw),
+            "76a9146f67988ec4b7bf498c9164d76b52dffdc805ff8c88ac"
+        );
     }
 
     /// Create a public key from a hex literal extracted from a confirmed STN tx, then
@@ -102,4 +204,53 @@ mod tests {
             "mvkjTSVMgGnSmjxkrDmYL6qKv9f5Hzefog".to_string()
         );
     }
+
+    #[test]
+    fn from_hash160() {
+        // the hash paid to by output 0 of tx d2bb697e3555cb0e4a82f0d4990d1c826eee9f648a5efc598f648bdb524093ff
+        let hash =
+            Hash160::from(&hex::decode("6f67988ec4b7bf498c9164d76b52dffdc805ff8c").unwrap()[..]);
+        let main = Address::from_hash160(hash, BlockchainId::Main);
+        assert_eq!(main.to_string(), "1BA47GLhQZrTtPt21CJ73cY9YSSsCXX7gF");
+        assert_eq!(main.hash160(), hash);
+        assert_eq!(
+            main,
+            Address::from_str("1BA47GLhQZrTtPt21CJ73cY9YSSsCXX7gF").unwrap()
+        );
+        let test = Address::from_hash160(hash, BlockchainId::Test);
+        assert_eq!(test.to_string(), "mqg1QKRgDbHifWMdimGUsXkUQS3aAPTPKn");
+        assert_eq!(test, Address::from_hash160(hash, BlockchainId::Regtest));
+        assert_ne!(test, main);
+    }
+
+    #[test]
+    fn key_pairs() {
+        for chain in [
+            BlockchainId::Main,
+            BlockchainId::Test,
+            BlockchainId::Regtest,
+        ] {
+            let pair = KeyPair::from_seed(7, chain);
+            assert_eq!(pair.public_key, pair.private_key.public_key());
+            assert_eq!(
+                pair.address,
+                Address::from_pv_chain(&pair.private_key, chain)
+            );
+            let parsed = Address::from_str(&pair.address.to_string()).unwrap();
+            assert_eq!(parsed, pair.address);
+            #[cfg(feature = "key-generation")]
+            {
+                let random = Address::random(chain);
+                assert_eq!(Address::from_str(&random.to_string()).unwrap(), random);
+            }
+        }
+        let pair = KeyPair::from_seed(1, BlockchainId::Main);
+        assert_eq!(pair, KeyPair::from_seed(1, BlockchainId::Main));
+        assert_ne!(pair, KeyPair::from_seed(2, BlockchainId::Main));
+        assert_eq!(
+            KeyPair::from_seed(1, BlockchainId::Test).private_key,
+            pair.private_key
+        );
+        assert!(Address::from_str("1C4UbrvcfKKTugSYRD5MKtvqTkrKMwgEHc").is_err());
+    }
 }
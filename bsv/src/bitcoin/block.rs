@@ -1,10 +1,245 @@
-use crate::bitcoin::{varint_decode, AsyncEncodable, BlockHeader, Tx};
-use crate::Result;
+use crate::bitcoin::encoding::decode_hex;
+use crate::bitcoin::{
+    merkle_root, merkle_root_checked, varint_decode, varint_encode, varint_size, AsyncEncodable,
+    BlockHash, BlockHeader, BlockchainId, MerkleRoot, Tx, TxHash,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use hex::{FromHex, ToHex};
+use std::cmp::min;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Cursor, ErrorKind};
+use std::path::Path;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use tokio::sync::mpsc;
-use tokio_stream::Stream;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::{fs::File, io::BufReader};
+use tokio_stream::{Stream, StreamExt};
+
+/// Why the structure of a block is invalid, see [Block::validate()].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValidationError {
+    /// The first transaction is not a coinbase, or there are no transactions.
+    NoCoinbase,
+    /// A transaction other than the first is a coinbase.
+    UnexpectedCoinbase(TxHash),
+    /// The transaction appears more than once.
+    DuplicateTransaction(TxHash),
+    /// The merkle root in the header does not match the transactions.
+    MerkleRootMismatch,
+    /// The merkle root matches but the transactions repeat hashes so that the merkle tree is
+    /// malleated, see [merkle_root_checked()].
+    MerkleMalleated,
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BlockValidationError::*;
+        match self {
+            NoCoinbase => write!(f, "first transaction is not a coinbase"),
+            UnexpectedCoinbase(hash) => write!(f, "unexpected coinbase {}", hash),
+            DuplicateTransaction(hash) => write!(f, "duplicate transaction {}", hash),
+            MerkleRootMismatch => write!(f, "merkle root mismatch"),
+            MerkleMalleated => write!(f, "merkle tree is malleated by duplicate transactions"),
+        }
+    }
+}
+
+/// A complete block, the header and every transaction in the block.
+///
+/// The whole block is held in memory, see [FullBlockStream] for processing large blocks.
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct Block {
+    /// The block header
+    pub header: BlockHeader,
+    /// The transactions in the block
+    pub transactions: Vec<Tx>,
+}
+
+impl Block {
+    // the transaction count is read from the source, dont trust it for allocation
+    const MAX_PREALLOCATE: usize = 1024;
+
+    /// The hash of the block.
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+
+    /// Calculate the merkle root of the transactions in the block.
+    pub fn merkle_root(&self) -> MerkleRoot {
+        let hashes: Vec<TxHash> = self.transactions.iter().map(|t| t.hash()).collect();
+        merkle_root(&hashes)
+    }
+
+    /// Check the structure of the block.
+    ///
+    /// The block must start with a coinbase transaction, must not contain any other coinbase
+    /// transaction or a duplicate transaction, and the merkle root in the header must match the
+    /// transactions. A block whose merkle tree is malleated, see [merkle_root_checked()], is refused
+    /// even if the root matches. Proof of work and the transactions themselves are not checked.
+    pub fn validate(&self) -> Result<()> {
+        match self.transactions.first() {
+            Some(tx) if tx.is_coinbase() => {}
+            _ => return Err(BlockValidationError::NoCoinbase.into()),
+        }
+        let hashes: Vec<TxHash> = self.transactions.iter().map(|t| t.hash()).collect();
+        let (root, malleated) = merkle_root_checked(&hashes);
+        if root != self.header.merkle_root {
+            return Err(BlockValidationError::MerkleRootMismatch.into());
+        }
+        if malleated {
+            return Err(BlockValidationError::MerkleMalleated.into());
+        }
+        let mut seen = HashSet::with_capacity(hashes.len());
+        for (tx, hash) in self.transactions.iter().zip(hashes.iter()).skip(1) {
+            if tx.is_coinbase() {
+                return Err(BlockValidationError::UnexpectedCoinbase(*hash).into());
+            }
+            if !seen.insert(*hash) {
+                return Err(BlockValidationError::DuplicateTransaction(*hash).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The serialized size of the block broken down into the header and the transactions.
+    pub fn size_breakdown(&self) -> BlockSizeBreakdown {
+        let tx_sizes = self.transactions.iter().map(|t| t.async_size());
+        BlockSizeBreakdown {
+            header: self.header.async_size(),
+            tx_count: varint_size(self.transactions.len() as u64),
+            tx_bytes: tx_sizes.clone().sum(),
+            num_tx: self.transactions.len(),
+            largest_tx: tx_sizes.max().unwrap_or(0),
+        }
+    }
+
+    /// Write a block in the binary format from a stream of transactions, without holding the whole
+    /// block in memory.
+    ///
+    /// The header and the transaction count are written first, then each transaction as it arrives
+    /// from the stream. This is the write side of [FullBlockStream]. An error is returned if the
+    /// stream does not produce exactly `tx_count` transactions, by which time the bytes that have been
+    /// written are not a valid block. Returns the number of bytes written.
+    pub async fn write_streaming<W, S>(
+        header: &BlockHeader,
+        tx_count: u64,
+        txs: S,
+        writer: &mut W,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + Send,
+        S: Stream<Item = Tx> + Send,
+    {
+        header.async_to_binary(writer).await?;
+        varint_encode(writer, tx_count).await?;
+        let mut written = (header.async_size() + varint_size(tx_count)) as u64;
+        let mut txs = std::pin::pin!(txs);
+        let mut count = 0;
+        while let Some(tx) = txs.next().await {
+            if count == tx_count {
+                return Err(Error::BadArgument(format!(
+                    "stream has more than {} transactions",
+                    tx_count
+                )));
+            }
+            tx.async_to_binary(writer).await?;
+            written += tx.async_size() as u64;
+            count += 1;
+        }
+        if count != tx_count {
+            return Err(Error::BadArgument(format!(
+                "stream has {} transactions, expected {}",
+                count, tx_count
+            )));
+        }
+        Ok(written)
+    }
+}
+
+/// The serialized size of a block, broken down by part.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockSizeBreakdown {
+    /// The size of the block header.
+    pub header: usize,
+    /// The size of the varint holding the number of transactions.
+    pub tx_count: usize,
+    /// The total size of the transactions.
+    pub tx_bytes: usize,
+    /// The number of transactions.
+    pub num_tx: usize,
+    /// The size of the largest transaction.
+    pub largest_tx: usize,
+}
+
+impl BlockSizeBreakdown {
+    /// The total serialized size of the block.
+    pub fn total(&self) -> usize {
+        self.header + self.tx_count + self.tx_bytes
+    }
+}
+
+impl FromHex for Block {
+    type Error = Error;
+
+    fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self> {
+        decode_hex(hex)
+    }
+}
+
+impl ToHex for Block {
+    fn encode_hex<T: FromIterator<char>>(&self) -> T {
+        let bytes = self.to_binary_buf().unwrap();
+        bytes.encode_hex()
+    }
+
+    fn encode_hex_upper<T: FromIterator<char>>(&self) -> T {
+        let bytes = self.to_binary_buf().unwrap();
+        bytes.encode_hex_upper()
+    }
+}
+
+#[async_trait]
+impl AsyncEncodable for Block {
+    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let header = BlockHeader::async_from_binary(reader).await?;
+        let txn_count = varint_decode(reader).await?;
+        let mut transactions =
+            Vec::with_capacity(min(txn_count, Block::MAX_PREALLOCATE as u64) as usize);
+        for _ in 0..txn_count {
+            transactions.push(Tx::async_from_binary(reader).await?);
+        }
+        Ok(Block {
+            header,
+            transactions,
+        })
+    }
+
+    async fn async_to_binary<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
+        self.header.async_to_binary(writer).await?;
+        varint_encode(writer, self.transactions.len() as u64).await?;
+        for txn in self.transactions.iter() {
+            txn.async_to_binary(writer).await?;
+        }
+        Ok(())
+    }
+
+    fn async_size(&self) -> usize {
+        self.header.async_size()
+            + varint_size(self.transactions.len() as u64)
+            + self
+                .transactions
+                .iter()
+                .map(|t| t.async_size())
+                .sum::<usize>()
+    }
+}
 
 /// Deserialize the bytes in a block and produce a stream of the transactions.
 ///
@@ -131,16 +366,156 @@ impl FullBlockTxReader {
     }
 }
 
+/// Reads the blocks in a block file, such as the `blk*.dat` files written by the node software.
+///
+/// A block file is a sequence of records, each of which is the magic bytes of the blockchain, the length
+/// of the block as a 4 byte little-endian integer, and then the block itself. The node software
+/// pre-allocates the files, so there may be regions of zero bytes between or after the records, these are
+/// skipped.
+///
+/// Something like:
+///         let mut reader = BlockFileReader::open("blk00000.dat", BlockchainId::Main).await?;
+///         while let Some(block) = reader.next_block().await? {
+///             // process block
+///         }
+pub struct BlockFileReader<R> {
+    reader: R,
+    magic: [u8; 4],
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BlockFileReader<BufReader<File>> {
+    /// Open a block file.
+    pub async fn open<P: AsRef<Path>>(
+        path: P,
+        chain: BlockchainId,
+    ) -> Result<BlockFileReader<BufReader<File>>> {
+        let file = File::open(path).await?;
+        Ok(BlockFileReader::new(BufReader::new(file), chain))
+    }
+}
+
+impl<R: AsyncRead + Unpin + Send> BlockFileReader<R> {
+    /// Create a new reader for a block file of the given blockchain.
+    ///
+    /// The padding is skipped a byte at a time, so the reader should be buffered.
+    pub fn new(reader: R, chain: BlockchainId) -> BlockFileReader<R> {
+        BlockFileReader {
+            reader,
+            magic: chain.magic(),
+        }
+    }
+
+    /// Read and parse the next block, returns None at the end of the file.
+    pub async fn next_block(&mut self) -> Result<Option<Block>> {
+        let length = match self.next_record().await? {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let mut record = (&mut self.reader).take(length);
+        let block = Block::async_from_binary(&mut record).await?;
+        if record.limit() != 0 {
+            return Err(Error::BadData(format!(
+                "block record has {} bytes after the block",
+                record.limit()
+            )));
+        }
+        Ok(Some(block))
+    }
+
+    /// Get the next block as a [FullBlockStream], returns None at the end of the file.
+    ///
+    /// The header is available immediately and the transactions are parsed as the stream is consumed.
+    /// The bytes of the record are read into memory first.
+    pub async fn next_block_stream(&mut self) -> Result<Option<FullBlockStream>> {
+        let length = match self.next_record().await? {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let mut buffer = Vec::with_capacity(min(length, Block::MAX_PREALLOCATE as u64) as usize);
+        (&mut self.reader)
+            .take(length)
+            .read_to_end(&mut buffer)
+            .await?;
+        if buffer.len() as u64 != length {
+            return Err(Error::BadData("block record is truncated".to_string()));
+        }
+        Ok(Some(
+            FullBlockStream::new(Box::new(Cursor::new(buffer))).await?,
+        ))
+    }
+
+    /// Read the header of the next block and skip over the rest of it, returns None at the end of the file.
+    ///
+    /// This is much faster than parsing the block and can be used to scan a file.
+    pub async fn skip_block(&mut self) -> Result<Option<BlockHeader>> {
+        let length = match self.next_record().await? {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let mut record = (&mut self.reader).take(length);
+        let header = BlockHeader::async_from_binary(&mut record).await?;
+        let remaining = record.limit();
+        if tokio::io::copy(&mut record, &mut tokio::io::sink()).await? != remaining {
+            return Err(Error::BadData("block record is truncated".to_string()));
+        }
+        Ok(Some(header))
+    }
+
+    /// Get the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the start of the next record, skipping any padding, and return the length of the block.
+    async fn next_record(&mut self) -> Result<Option<u64>> {
+        let first = loop {
+            match self.reader.read_u8().await {
+                Ok(0) => continue,
+                Ok(b) => break b,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut magic = [first, 0, 0, 0];
+        self.reader.read_exact(&mut magic[1..]).await?;
+        if magic != self.magic {
+            return Err(Error::BadData(format!(
+                "bad magic in block file: {:02x},{:02x},{:02x},{:02x}",
+                magic[0], magic[1], magic[2], magic[3]
+            )));
+        }
+        Ok(Some(self.reader.read_u32_le().await? as u64))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::bitcoin::hash::Hash;
+    use crate::bitcoin::{Outpoint, ScriptTemplate};
     use hex::FromHex;
     use std::io::Cursor;
     use tokio::fs::File;
     use tokio::io::AsyncReadExt;
     use tokio_stream::StreamExt;
 
+    /// Parse the genesis block from hex
+    #[test]
+    fn block_hex() {
+        let hex = concat!(
+            "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+            "01",
+            "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000"
+        );
+        let block = Block::from_hex(hex).unwrap();
+        assert_eq!(block.header, BlockHeader::get_genesis(BlockchainId::Main));
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(block.merkle_root(), block.header.merkle_root);
+        assert_eq!(block.encode_hex::<String>(), hex);
+        assert!(Block::from_hex(format!("{}00", hex)).is_err());
+    }
+
     // Stream a block and check that we can read the transactions from it.
     // Use a small buffer size and pause for a short time before the first transaction read.
     // this will cause the background task to also pause, and we can check that it resumes and is
@@ -179,4 +554,249 @@ mod tests {
             .expect("Could not read file");
         buffer
     }
+
+    /// Scan a real block for the outputs paying to an address and for the input spending an output.
+    #[tokio::test]
+    async fn scan_outputs() {
+        let block = Block::from_binary_buf(&get_small_block_bin().await).unwrap();
+        let hash = hex::decode("a933cfc5d05e36fa4270bf6fb2c7d74a4e1e9aef").unwrap();
+        let mut found = Vec::new();
+        for (n, tx) in block.transactions.iter().enumerate() {
+            for (i, _) in tx.find_outputs(|s| s.p2pkh_hash() == Some(&hash[..])) {
+                found.push((n, i));
+            }
+        }
+        assert_eq!(found.len(), 53);
+        assert_eq!(found[..3], [(145, 100), (164, 1), (165, 1)]);
+        assert_eq!(found[52], (220, 1));
+        let p2pkh: usize = block
+            .transactions
+            .iter()
+            .map(|tx| tx.outputs_matching_template(ScriptTemplate::P2pkh).len())
+            .sum();
+        assert_eq!(p2pkh, 328);
+        // the outputs of transaction 145 are spent by later transactions in the block
+        let spent = Outpoint {
+            tx_hash: block.transactions[145].hash(),
+            index: 53,
+        };
+        let spenders: Vec<(usize, u32)> = block
+            .transactions
+            .iter()
+            .enumerate()
+            .flat_map(|(n, tx)| {
+                tx.inputs_spending(&spent)
+                    .into_iter()
+                    .map(move |(i, _)| (n, i))
+            })
+            .collect();
+        assert_eq!(spenders, vec![(167, 0)]);
+    }
+
+    #[tokio::test]
+    async fn size_breakdown() {
+        let block_bin = get_small_block_bin().await;
+        let block = Block::from_binary_buf(&block_bin).unwrap();
+        let sizes = block.size_breakdown();
+        assert_eq!(sizes.total(), block_bin.len());
+        assert_eq!(sizes.num_tx, 222);
+        assert_eq!((sizes.header, sizes.tx_count), (80, 1));
+        let mut largest = 0;
+        for tx in block.transactions.iter() {
+            let tx_sizes = tx.size_breakdown();
+            let tx_bin = tx.to_binary_buf().unwrap();
+            assert_eq!(tx_sizes.total(), tx_bin.len());
+            assert!(tx_sizes.input_scripts < tx_sizes.inputs);
+            assert!(tx_sizes.output_scripts < tx_sizes.outputs);
+            largest = largest.max(tx_bin.len());
+        }
+        assert_eq!(sizes.largest_tx, largest);
+    }
+
+    #[tokio::test]
+    async fn validate() {
+        let block_bin = get_small_block_bin().await;
+        let mut block = Block::from_binary_buf(&block_bin).unwrap();
+        assert_eq!(block.merkle_root(), block.header.merkle_root);
+        block.validate().unwrap();
+        let tx = block.transactions.remove(5);
+        assert!(block.validate().is_err());
+        block.transactions.push(tx);
+        assert!(block.validate().is_err());
+        block.header.merkle_root = block.merkle_root();
+        block.validate().unwrap();
+        block.transactions.push(block.transactions[1].clone());
+        block.header.merkle_root = block.merkle_root();
+        assert!(block.validate().is_err());
+        block.transactions.remove(0);
+        assert!(block.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn malleated_merkle_tree() {
+        let block_bin = get_small_block_bin().await;
+        let honest = Block::from_binary_buf(&block_bin).unwrap();
+        honest.validate().unwrap();
+        assert_eq!(honest.transactions.len(), 222);
+        // the second level of the tree has 111 hashes, so the last is paired with itself, and
+        // repeating the two transactions under it gives the same root
+        let mut malicious = honest.clone();
+        malicious
+            .transactions
+            .extend_from_slice(&honest.transactions[220..]);
+        assert_eq!(malicious.merkle_root(), honest.header.merkle_root);
+        assert!(matches!(
+            malicious.validate(),
+            Err(Error::BlockValidation(
+                BlockValidationError::MerkleMalleated
+            ))
+        ));
+        let hashes: Vec<TxHash> = honest.transactions.iter().map(|t| t.hash()).collect();
+        assert_eq!(
+            merkle_root_checked(&hashes),
+            (honest.header.merkle_root, false)
+        );
+        // a duplicate that changes the root is reported as a mismatch
+        let mut duplicate = honest.clone();
+        duplicate.transactions.push(honest.transactions[5].clone());
+        assert!(matches!(
+            duplicate.validate(),
+            Err(Error::BlockValidation(
+                BlockValidationError::MerkleRootMismatch
+            ))
+        ));
+    }
+
+    const TESTNET_BLOCKS: &str = "../testdata/blk-testnet-0-1.dat";
+    const TESTNET_HASHES: [&str; 2] = [
+        "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+        "00000000b873e79784647a6c82962c70d228557d24a747ea4d1b8bbe878e1206",
+    ];
+
+    #[tokio::test]
+    async fn block_file_blocks() {
+        let mut reader = BlockFileReader::open(TESTNET_BLOCKS, BlockchainId::Test)
+            .await
+            .unwrap();
+        let mut hashes = Vec::new();
+        while let Some(block) = reader.next_block().await.unwrap() {
+            assert_eq!(block.transactions.len(), 1);
+            hashes.push(block.hash());
+        }
+        let expected: Vec<BlockHash> = TESTNET_HASHES
+            .iter()
+            .map(|h| BlockHash::from_hex(h).unwrap())
+            .collect();
+        assert_eq!(hashes, expected);
+        assert_eq!(hashes[1], {
+            let mut reader = BlockFileReader::open(TESTNET_BLOCKS, BlockchainId::Test)
+                .await
+                .unwrap();
+            reader.skip_block().await.unwrap().unwrap();
+            reader.skip_block().await.unwrap().unwrap().hash()
+        });
+    }
+
+    #[tokio::test]
+    async fn block_file_skip_and_stream() {
+        let mut reader = BlockFileReader::open(TESTNET_BLOCKS, BlockchainId::Test)
+            .await
+            .unwrap();
+        let header = reader.skip_block().await.unwrap().unwrap();
+        assert_eq!(header.hash().to_string(), TESTNET_HASHES[0]);
+        let mut stream = reader.next_block_stream().await.unwrap().unwrap();
+        assert_eq!(stream.block_header.hash().to_string(), TESTNET_HASHES[1]);
+        assert_eq!(stream.num_tx, 1);
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(reader.skip_block().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn block_file_wrong_chain() {
+        let mut reader = BlockFileReader::open(TESTNET_BLOCKS, BlockchainId::Main)
+            .await
+            .unwrap();
+        assert!(reader.next_block().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn block_file_truncated() {
+        let bin = tokio::fs::read(TESTNET_BLOCKS).await.unwrap();
+        // cut the first block short
+        let mut reader = BlockFileReader::new(Cursor::new(&bin[..200]), BlockchainId::Test);
+        assert!(reader.next_block().await.is_err());
+        let mut reader = BlockFileReader::new(Cursor::new(&bin[..200]), BlockchainId::Test);
+        assert!(reader.skip_block().await.is_err());
+        let mut reader = BlockFileReader::new(Cursor::new(&bin[..200]), BlockchainId::Test);
+        assert!(reader.next_block_stream().await.is_err());
+        // a record that claims to be 4GB long is not allocated up front
+        let mut huge = bin[..200].to_vec();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = BlockFileReader::new(Cursor::new(huge), BlockchainId::Test);
+        assert!(reader.next_block_stream().await.is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn round_trip(block in crate::testing::arb_block(8)) {
+            proptest::prop_assert!(block.validate().is_ok());
+            let bin = block.to_binary_buf().unwrap();
+            proptest::prop_assert_eq!(bin.len(), block.async_size());
+            proptest::prop_assert_eq!(Block::from_binary_buf(&bin).unwrap(), block);
+        }
+    }
+
+    /// Stream a block through a pipe in both directions, the transactions are generated as they are
+    /// written and checked as they are read.
+    #[tokio::test]
+    async fn write_streaming_duplex() {
+        use crate::bitcoin::{Outpoint, Script, TxInput, TxOutput};
+
+        fn tx(i: u32) -> Tx {
+            Tx {
+                version: 1,
+                inputs: vec![TxInput {
+                    outpoint: Outpoint {
+                        tx_hash: Hash::sha256d(&i.to_le_bytes()),
+                        index: 0,
+                    },
+                    script: Script::from(vec![0x51]),
+                    sequence: u32::MAX,
+                }],
+                outputs: vec![TxOutput::new(i as u64, Script::from(vec![0x51]))],
+                lock_time: 0,
+            }
+        }
+
+        const N: u32 = 10_000;
+        let header = BlockHeader::default();
+        let (mut ours, theirs) = tokio::io::duplex(4096);
+        let h = header.clone();
+        let writer = tokio::spawn(async move {
+            let txs = tokio_stream::iter(0..N).map(tx);
+            Block::write_streaming(&h, N as u64, txs, &mut ours).await
+        });
+        let mut stream = FullBlockStream::new(Box::new(theirs)).await.unwrap();
+        assert_eq!(stream.block_header, header);
+        assert_eq!(stream.num_tx, N as u64);
+        let mut received = Vec::new();
+        while let Some(t) = stream.next().await {
+            received.push(t.unwrap().hash());
+        }
+        let expected: Vec<TxHash> = (0..N).map(|i| tx(i).hash()).collect();
+        assert_eq!(received, expected);
+        let written = writer.await.unwrap().unwrap();
+        assert_eq!(written, 80 + 3 + N as u64 * tx(0).async_size() as u64);
+
+        // the count must match the stream
+        let mut v = Vec::new();
+        let txs = tokio_stream::iter(0..3).map(tx);
+        assert!(Block::write_streaming(&header, 2, txs, &mut v)
+            .await
+            .is_err());
+        let txs = tokio_stream::iter(0..3).map(tx);
+        assert!(Block::write_streaming(&header, 4, txs, &mut v)
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,430 @@
+use crate::bitcoin::rules::MAX_BLOCK_SIZE;
+use crate::bitcoin::{
+    merkle_root, varint_size, AsyncEncodable, Block, BlockHash, BlockHeader, CoinbaseBuilder, Hash,
+    Script, Tx, TxHash, TxInput, TxOutput,
+};
+use crate::{Error, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The number of blocks between halvings of the block subsidy.
+const HALVING_INTERVAL: u32 = 210_000;
+/// The subsidy of the first block, in satoshis.
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+
+/// The block subsidy for a block at the given height, in satoshis.
+pub fn block_subsidy(height: u32) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        0
+    } else {
+        INITIAL_SUBSIDY >> halvings
+    }
+}
+
+/// A candidate block assembled by a [BlockTemplateBuilder].
+///
+/// The nonce in the header is zero, the caller searches for a nonce that satisfies the proof of work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTemplate {
+    /// The block, starting with the coinbase transaction.
+    pub block: Block,
+    /// The total fees of the transactions in the block, in satoshis.
+    pub fees: u64,
+    /// The number of signature operations in the block, including the coinbase.
+    pub sigops: u64,
+    /// The position of the extranonce space in the unlocking script of the coinbase.
+    pub extranonce: Range<usize>,
+}
+
+// the state of a candidate during assembly
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    Visiting,
+    Included,
+    Excluded,
+}
+
+/// Assembles a candidate block from a set of transactions.
+///
+/// Transactions are added in the order they are given, except that a transaction is always placed after
+/// the transactions it spends. A transaction is left out if it does not fit within the maximum block
+/// size or signature operation budget, and so are the transactions that spend it. The coinbase pays the
+/// subsidy for the height plus the fees of the included transactions to the coinbase script.
+///
+/// Something like:
+///         let template = BlockTemplateBuilder::new(&tip, height, bits, script)
+///             .max_block_size(32_000_000)
+///             .build(candidates)?;
+pub struct BlockTemplateBuilder {
+    prev_hash: BlockHash,
+    height: u32,
+    bits: u32,
+    coinbase_script: Script,
+    coinbase: CoinbaseBuilder,
+    version: u32,
+    timestamp: u32,
+    max_block_size: u64,
+    max_sigops: u64,
+}
+
+impl BlockTemplateBuilder {
+    /// Create a builder for a block at `height` that follows `prev`, paying the coinbase to
+    /// `coinbase_script`.
+    ///
+    /// The timestamp defaults to the current time, but is never earlier than one second after `prev`.
+    /// There is no system clock on wasm32, so there it defaults to one second after `prev` and the
+    /// caller should set it with [BlockTemplateBuilder::timestamp()].
+    pub fn new(
+        prev: &BlockHeader,
+        height: u32,
+        bits: u32,
+        coinbase_script: Script,
+    ) -> BlockTemplateBuilder {
+        let now = current_time();
+        BlockTemplateBuilder {
+            prev_hash: prev.hash(),
+            height,
+            bits,
+            coinbase_script,
+            coinbase: CoinbaseBuilder::new(height),
+            version: 0x2000_0000,
+            timestamp: now.max(prev.timestamp.saturating_add(1)),
+            max_block_size: MAX_BLOCK_SIZE(false),
+            max_sigops: u64::MAX,
+        }
+    }
+
+    /// Set the version of the block header.
+    pub fn version(&mut self, version: u32) -> &mut BlockTemplateBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Set the timestamp of the block header.
+    pub fn timestamp(&mut self, timestamp: u32) -> &mut BlockTemplateBuilder {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Set extra data to include at the end of the coinbase unlocking script.
+    pub fn coinbase_data(&mut self, data: Bytes) -> &mut BlockTemplateBuilder {
+        self.coinbase.data(data);
+        self
+    }
+
+    /// Set the tag that identifies the miner in the coinbase unlocking script.
+    pub fn miner_tag(&mut self, tag: &str) -> &mut BlockTemplateBuilder {
+        self.coinbase.miner_tag(tag);
+        self
+    }
+
+    /// Set the number of bytes of extranonce space in the coinbase unlocking script, none by default.
+    pub fn extranonce_size(&mut self, size: usize) -> &mut BlockTemplateBuilder {
+        self.coinbase.extranonce_size(size);
+        self
+    }
+
+    /// Set the maximum size of the block, in bytes. The default is the consensus limit.
+    pub fn max_block_size(&mut self, size: u64) -> &mut BlockTemplateBuilder {
+        self.max_block_size = size;
+        self
+    }
+
+    /// Set the maximum number of signature operations in the block. There is no limit by default.
+    pub fn max_sigops(&mut self, sigops: u64) -> &mut BlockTemplateBuilder {
+        self.max_sigops = sigops;
+        self
+    }
+
+    /// Assemble the block from candidate transactions, each paired with its fee in satoshis.
+    ///
+    /// Candidates that are coinbase transactions or duplicates are ignored.
+    pub fn build<I>(&self, candidates: I) -> Result<BlockTemplate>
+    where
+        I: IntoIterator<Item = (Tx, u64)>,
+    {
+        let candidates: Vec<(Tx, u64)> = candidates.into_iter().collect();
+        let hashes: Vec<TxHash> = candidates.iter().map(|(tx, _)| tx.hash()).collect();
+        let mut state = vec![State::Pending; candidates.len()];
+        let mut index = HashMap::with_capacity(candidates.len());
+        for (i, hash) in hashes.iter().enumerate() {
+            if candidates[i].0.is_coinbase() || index.contains_key(hash) {
+                state[i] = State::Excluded;
+            } else {
+                index.insert(*hash, i);
+            }
+        }
+        let parents: Vec<Vec<usize>> = candidates
+            .iter()
+            .map(|(tx, _)| {
+                let mut p: Vec<usize> = tx
+                    .inputs
+                    .iter()
+                    .filter_map(|i| index.get(&i.outpoint.tx_hash).copied())
+                    .collect();
+                p.dedup();
+                p
+            })
+            .collect();
+
+        // the coinbase value does not change its size, so it can be sized before the fees are known
+        let (mut coinbase, extranonce) = self.coinbase(0)?;
+        let base_size = BlockHeader::SIZE as u64 + coinbase.async_size() as u64;
+        let mut tx_bytes = 0u64;
+        let mut sigops = coinbase.sigop_count();
+        let mut fees = 0u64;
+        if sigops > self.max_sigops || base_size + 1 > self.max_block_size {
+            return Err(Error::BadArgument(
+                "coinbase exceeds the block limits".to_string(),
+            ));
+        }
+        let mut included: Vec<usize> = Vec::new();
+
+        // depth first through the parents of each candidate, so that parents are placed first
+        for start in 0..candidates.len() {
+            if state[start] != State::Pending {
+                continue;
+            }
+            state[start] = State::Visiting;
+            let mut stack = vec![(start, 0usize)];
+            while let Some((i, next)) = stack.last_mut() {
+                let i = *i;
+                if let Some(p) = parents[i].get(*next) {
+                    *next += 1;
+                    if state[*p] == State::Pending {
+                        state[*p] = State::Visiting;
+                        stack.push((*p, 0));
+                    }
+                    continue;
+                }
+                stack.pop();
+                let (tx, fee) = &candidates[i];
+                let size = tx.async_size() as u64;
+                let tx_sigops = tx.sigop_count();
+                let block_size =
+                    base_size + varint_size(included.len() as u64 + 2) as u64 + tx_bytes + size;
+                if parents[i].iter().all(|p| state[*p] == State::Included)
+                    && block_size <= self.max_block_size
+                    && sigops + tx_sigops <= self.max_sigops
+                {
+                    state[i] = State::Included;
+                    included.push(i);
+                    tx_bytes += size;
+                    sigops += tx_sigops;
+                    fees += fee;
+                } else {
+                    state[i] = State::Excluded;
+                }
+            }
+        }
+
+        coinbase.outputs[0].value = block_subsidy(self.height) + fees;
+        let mut transactions = Vec::with_capacity(included.len() + 1);
+        let mut tx_hashes = Vec::with_capacity(included.len() + 1);
+        tx_hashes.push(coinbase.hash());
+        transactions.push(coinbase);
+        for i in included {
+            tx_hashes.push(hashes[i]);
+            transactions.push(candidates[i].0.clone());
+        }
+        let header = BlockHeader {
+            version: self.version,
+            prev_hash: self.prev_hash,
+            merkle_root: merkle_root(&tx_hashes),
+            timestamp: self.timestamp,
+            bits: self.bits,
+            nonce: 0,
+        };
+        Ok(BlockTemplate {
+            block: Block {
+                header,
+                transactions,
+            },
+            fees,
+            sigops,
+            extranonce,
+        })
+    }
+
+    // the coinbase transaction, the unlocking script starts with the height as required by BIP34
+    fn coinbase(&self, value: u64) -> Result<(Tx, Range<usize>)> {
+        let (script, extranonce) = self.coinbase.build()?;
+        let tx = Tx {
+            version: 1,
+            inputs: vec![TxInput::new(Hash::ZERO, u32::MAX, script, None)],
+            outputs: vec![TxOutput::new(value, self.coinbase_script.clone())],
+            lock_time: 0,
+        };
+        Ok((tx, extranonce))
+    }
+}
+
+// the current time in seconds since the epoch, zero where there is no system clock
+#[cfg(not(target_arch = "wasm32"))]
+fn current_time() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn current_time() -> u32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{BlockchainId, CoinbaseInfo};
+    use hex::FromHex;
+
+    fn p2pkh() -> Script {
+        Script::from_hex("76a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac").unwrap()
+    }
+
+    /// A transaction that spends output 0 of `parent`.
+    fn spend(parent: TxHash, n: u8) -> Tx {
+        Tx {
+            version: 1,
+            inputs: vec![TxInput::new(parent, 0, Script::from(vec![1, n]), None)],
+            outputs: vec![TxOutput::new(1000, p2pkh())],
+            lock_time: 0,
+        }
+    }
+
+    /// Twelve transactions, three chains of four, given children first.
+    fn candidates() -> Vec<(Tx, u64)> {
+        let mut chains = Vec::new();
+        for c in 0..3u8 {
+            let mut chain = vec![spend(Hash::sha256d(&[c]), c * 10)];
+            for n in 1..4 {
+                chain.push(spend(chain.last().unwrap().hash(), c * 10 + n));
+            }
+            chains.push(chain);
+        }
+        let mut candidates = Vec::new();
+        for n in (0..4).rev() {
+            for chain in chains.iter() {
+                candidates.push((chain[n].clone(), 100 + n as u64));
+            }
+        }
+        candidates
+    }
+
+    fn prev() -> BlockHeader {
+        BlockHeader {
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        }
+    }
+
+    fn builder() -> BlockTemplateBuilder {
+        BlockTemplateBuilder::new(&prev(), 900_000, 0x1d00ffff, p2pkh())
+    }
+
+    #[test]
+    fn subsidy() {
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209_999), 5_000_000_000);
+        assert_eq!(block_subsidy(210_000), 2_500_000_000);
+        assert_eq!(block_subsidy(840_000), 312_500_000);
+        assert_eq!(block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn dependent_transactions() {
+        let template = builder()
+            .coinbase_data(Bytes::from_static(b"/test/"))
+            .build(candidates())
+            .unwrap();
+        let block = &template.block;
+        block.validate().unwrap();
+        assert_eq!(block.transactions.len(), 13);
+        // every transaction follows the transaction it spends
+        for (i, tx) in block.transactions.iter().enumerate().skip(1) {
+            let parent = tx.inputs[0].outpoint.tx_hash;
+            if let Some(p) = block.transactions.iter().position(|t| t.hash() == parent) {
+                assert!(p < i);
+            }
+        }
+        assert_eq!(template.fees, 3 * (100 + 101 + 102 + 103));
+        let coinbase = &block.transactions[0];
+        assert_eq!(
+            coinbase.outputs[0].value,
+            block_subsidy(900_000) + template.fees
+        );
+        assert!(coinbase.inputs[0]
+            .script
+            .raw
+            .starts_with(&[3, 0xa0, 0xbb, 0x0d]));
+        assert_eq!(template.sigops, 13);
+        assert_eq!(block.header.prev_hash, prev().hash());
+        assert!(block.header.timestamp > 1_700_000_000);
+        assert_eq!(block.size_breakdown().total(), block.async_size());
+    }
+
+    #[test]
+    fn coinbase_tag() {
+        let template = builder()
+            .miner_tag("/test/")
+            .extranonce_size(8)
+            .build(candidates())
+            .unwrap();
+        let coinbase = &template.block.transactions[0];
+        let info = CoinbaseInfo::parse(coinbase).unwrap();
+        assert_eq!(info.height, 900_000);
+        assert_eq!(info.miner_tag.as_deref(), Some("/test/"));
+        assert_eq!(template.extranonce, 5..13);
+        assert!(builder()
+            .miner_tag(&"x".repeat(100))
+            .build(candidates())
+            .is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let tx_size = candidates()[0].0.async_size() as u64;
+        let full = builder().build(candidates()).unwrap();
+        let full_size = full.block.async_size() as u64;
+        // room for all but one transaction, the last transaction of one chain is left out
+        let template = builder()
+            .max_block_size(full_size - 1)
+            .build(candidates())
+            .unwrap();
+        template.block.validate().unwrap();
+        assert_eq!(template.block.transactions.len(), 12);
+        assert_eq!(template.fees, full.fees - 103);
+        // room for five transactions
+        let template = builder()
+            .max_block_size(full_size - 7 * tx_size)
+            .build(candidates())
+            .unwrap();
+        assert_eq!(template.block.transactions.len(), 6);
+        // a chain is cut off where its transactions stop fitting, the rest of the chain is left out
+        let template = builder().max_sigops(3).build(candidates()).unwrap();
+        template.block.validate().unwrap();
+        assert_eq!(template.block.transactions.len(), 3);
+        assert_eq!(template.sigops, 3);
+        assert!(builder().max_sigops(0).build(candidates()).is_err());
+    }
+
+    /// Fabricate a chain of regtest blocks that satisfy the proof of work.
+    #[test]
+    fn regtest_chain() {
+        let mut tip = BlockHeader::get_genesis(BlockchainId::Regtest);
+        for height in 1..=5 {
+            let mut template = BlockTemplateBuilder::new(&tip, height, tip.bits, p2pkh())
+                .timestamp(tip.timestamp + 600)
+                .build(candidates())
+                .unwrap();
+            template.block.header.solve(1000).unwrap();
+            template.block.validate().unwrap();
+            template.block.header.validate_pow().unwrap();
+            assert_eq!(template.block.header.prev_hash, tip.hash());
+            tip = template.block.header;
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::bitcoin::{Encodable, Operation, Script, Tx};
+use crate::{Error, Result};
+use bytes::{Buf, Bytes};
+use std::ops::Range;
+
+/// The maximum size of the unlocking script of a coinbase transaction.
+const MAX_COINBASE_SCRIPT_SIZE: usize = 100;
+// the shortest run of printable characters that is taken to be a miner tag
+const MIN_TAG_LEN: usize = 4;
+
+/// The contents of the unlocking script of a coinbase transaction.
+///
+/// Since BIP34, from block 227,931 on mainnet, the script starts with the height of the block. The
+/// rest is chosen by the miner, and usually holds extranonce bytes that are varied while searching
+/// for a proof of work and a tag that identifies the miner, such as `/taal.com/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoinbaseInfo {
+    /// The height of the block, as encoded by BIP34.
+    pub height: u32,
+    /// The longest run of printable ASCII in the rest of the script, if there is one.
+    pub miner_tag: Option<String>,
+    /// The script after the height.
+    pub script_remainder: Bytes,
+}
+
+impl CoinbaseInfo {
+    /// Parse the unlocking script of a coinbase transaction.
+    ///
+    /// The tag is found by heuristics. If the rest of the script is a sequence of data pushes then
+    /// the tag is looked for in the data pushed, otherwise in the raw bytes, as many miners append
+    /// their tag without a push. The script of a block from before BIP34 does not start with the
+    /// height, and the number that is there is returned instead.
+    pub fn parse(tx: &Tx) -> Result<CoinbaseInfo> {
+        if !tx.is_coinbase() {
+            return Err(Error::BadArgument("not a coinbase transaction".to_string()));
+        }
+        let mut buf = tx.inputs[0].script.raw.clone();
+        let height = Operation::from_binary(&mut buf)
+            .ok()
+            .and_then(|op| op.small_num_pushed())
+            .and_then(|h| u32::try_from(h).ok())
+            .ok_or_else(|| Error::BadData("coinbase does not start with a height".to_string()))?;
+        Ok(CoinbaseInfo {
+            height,
+            miner_tag: find_tag(&buf),
+            script_remainder: buf,
+        })
+    }
+}
+
+// the longest printable run in the data pushed by the script, or in its bytes if it is not a
+// sequence of pushes
+fn find_tag(script: &Bytes) -> Option<String> {
+    let mut buf = script.clone();
+    let mut pushed = Vec::new();
+    while buf.has_remaining() {
+        match Operation::from_binary(&mut buf)
+            .ok()
+            .and_then(|op| op.is_data_push().then(|| op.data_pushed()).flatten())
+        {
+            Some(data) => pushed.push(data),
+            None => {
+                pushed = vec![script.clone()];
+                break;
+            }
+        }
+    }
+    pushed
+        .iter()
+        .flat_map(|data| data.split(|b| !(0x20..0x7f).contains(b)))
+        .map(|run| String::from_utf8_lossy(run).trim().to_string())
+        .filter(|run| run.len() >= MIN_TAG_LEN)
+        .max_by_key(|run| run.len())
+}
+
+/// Builds the unlocking script of a coinbase transaction.
+///
+/// The script is the height, then a push of zeros that is the extranonce space, then a push of the
+/// miner tag, followed by any other data. [CoinbaseInfo::parse()] recovers the height and the tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoinbaseBuilder {
+    height: u32,
+    extranonce_size: usize,
+    miner_tag: Option<String>,
+    data: Bytes,
+}
+
+impl CoinbaseBuilder {
+    /// Create a builder for the coinbase of a block at `height`.
+    pub fn new(height: u32) -> CoinbaseBuilder {
+        CoinbaseBuilder {
+            height,
+            extranonce_size: 0,
+            miner_tag: None,
+            data: Bytes::new(),
+        }
+    }
+
+    /// Set the number of bytes of extranonce space, none by default.
+    pub fn extranonce_size(&mut self, size: usize) -> &mut CoinbaseBuilder {
+        self.extranonce_size = size;
+        self
+    }
+
+    /// Set the tag that identifies the miner.
+    pub fn miner_tag(&mut self, tag: &str) -> &mut CoinbaseBuilder {
+        self.miner_tag = Some(tag.to_string());
+        self
+    }
+
+    /// Set other data to append to the script.
+    pub fn data(&mut self, data: Bytes) -> &mut CoinbaseBuilder {
+        self.data = data;
+        self
+    }
+
+    /// Build the script, also returning the position of the extranonce space in it.
+    pub fn build(&self) -> Result<(Script, Range<usize>)> {
+        let mut script = encode_height(self.height);
+        let mut extranonce = script.len()..script.len();
+        if self.extranonce_size > 0 {
+            push(&mut script, &vec![0; self.extranonce_size])?;
+            extranonce = script.len() - self.extranonce_size..script.len();
+        }
+        if let Some(tag) = &self.miner_tag {
+            push(&mut script, tag.as_bytes())?;
+        }
+        script.extend_from_slice(&self.data);
+        if script.len() < 2 {
+            // consensus requires at least two bytes
+            script.push(0);
+        }
+        if script.len() > MAX_COINBASE_SCRIPT_SIZE {
+            return Err(Error::BadArgument(format!(
+                "coinbase script size {} exceeds maximum {}",
+                script.len(),
+                MAX_COINBASE_SCRIPT_SIZE
+            )));
+        }
+        Ok((Script::from(script), extranonce))
+    }
+}
+
+// a direct push of the data, which fits as the whole script is limited to 100 bytes
+fn push(script: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    if data.len() > 75 {
+        return Err(Error::BadArgument(format!(
+            "coinbase push of {} bytes is too large",
+            data.len()
+        )));
+    }
+    script.push(data.len() as u8);
+    script.extend_from_slice(data);
+    Ok(())
+}
+
+// the height as pushed by a script, small heights use OP_0 and OP_1 to OP_16
+fn encode_height(height: u32) -> Vec<u8> {
+    match height {
+        0 => vec![0],
+        1..=16 => vec![0x50 + height as u8],
+        _ => {
+            let mut num: Vec<u8> = height.to_le_bytes().to_vec();
+            while num.last() == Some(&0) {
+                num.pop();
+            }
+            if num.last().is_some_and(|b| b & 0x80 != 0) {
+                num.push(0);
+            }
+            let mut push = vec![num.len() as u8];
+            push.extend(num);
+            push
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{AsyncEncodable, Block, Hash, TxInput};
+    use hex::FromHex;
+
+    fn coinbase(script: &str) -> Tx {
+        Tx {
+            version: 1,
+            inputs: vec![TxInput::new(
+                Hash::ZERO,
+                u32::MAX,
+                Script::from_hex(script).unwrap(),
+                None,
+            )],
+            outputs: Vec::new(),
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn height() {
+        assert_eq!(encode_height(0), vec![0]);
+        assert_eq!(encode_height(16), vec![0x60]);
+        assert_eq!(encode_height(17), vec![1, 17]);
+        assert_eq!(encode_height(128), vec![2, 128, 0]);
+        assert_eq!(encode_height(900_000), vec![3, 0xa0, 0xbb, 0x0d]);
+    }
+
+    #[tokio::test]
+    async fn parse_mainnet() {
+        // a TAAL block, the tag is appended without a push and followed by the extranonce
+        let mut file = tokio::fs::File::open(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .await
+        .unwrap();
+        let block = Block::async_from_binary(&mut file).await.unwrap();
+        let info = CoinbaseInfo::parse(&block.transactions[0]).unwrap();
+        assert_eq!(info.height, 825_188);
+        assert_eq!(info.miner_tag.as_deref(), Some("/taal.com/"));
+        assert_eq!(info.script_remainder.len(), 22);
+
+        // the genesis block, from before BIP34, the message is pushed
+        let genesis = coinbase("04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73");
+        let info = CoinbaseInfo::parse(&genesis).unwrap();
+        assert_eq!(info.height, 0x1d00ffff);
+        assert_eq!(
+            info.miner_tag.as_deref(),
+            Some("The Times 03/Jan/2009 Chancellor on brink of second bailout for banks")
+        );
+
+        // block 1, no tag
+        let info = CoinbaseInfo::parse(&coinbase("04ffff001d0104")).unwrap();
+        assert_eq!(info.miner_tag, None);
+
+        assert!(CoinbaseInfo::parse(&block.transactions[1]).is_err());
+        assert!(CoinbaseInfo::parse(&coinbase("ac")).is_err());
+    }
+
+    #[test]
+    fn build_and_parse() {
+        let (script, extranonce) = CoinbaseBuilder::new(900_000)
+            .extranonce_size(8)
+            .miner_tag("/rust-bitcoinsv/")
+            .build()
+            .unwrap();
+        assert_eq!(extranonce, 5..13);
+        assert!(script.raw[extranonce].iter().all(|b| *b == 0));
+        let tx = coinbase(&hex::encode(&script.raw));
+        let info = CoinbaseInfo::parse(&tx).unwrap();
+        assert_eq!(info.height, 900_000);
+        assert_eq!(info.miner_tag.as_deref(), Some("/rust-bitcoinsv/"));
+
+        // the shortest script is padded to two bytes
+        let (script, extranonce) = CoinbaseBuilder::new(1).build().unwrap();
+        assert_eq!(script.raw.as_ref(), &[0x51, 0]);
+        assert!(extranonce.is_empty());
+        assert!(CoinbaseBuilder::new(1)
+            .miner_tag(&"x".repeat(76))
+            .build()
+            .is_err());
+        assert!(CoinbaseBuilder::new(1)
+            .extranonce_size(60)
+            .miner_tag(&"x".repeat(40))
+            .build()
+            .is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use crate::bitcoin::base58ck;
 use crate::bitcoin::hash160::Hash160;
 use crate::bitcoin::params::KeyAddressKind;
+use crate::bitcoin::Hash;
 use crate::{Error, Result};
-use secp256k1::Secp256k1;
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -18,11 +20,29 @@ pub struct PrivateKey {
 impl PrivateKey {
     /// Constructs new compressed ECDSA private key using the secp256k1 algorithm and
     /// a secure random number generator.
+    #[cfg(feature = "key-generation")]
     pub fn generate() -> PrivateKey {
         let secret_key = secp256k1::SecretKey::new(&mut rand::thread_rng());
         PrivateKey::new(secret_key)
     }
 
+    /// Constructs a private key deterministically from a seed, the same seed always giving the same
+    /// key.
+    ///
+    /// This is for reproducible tests only, and is available with the `test-utils` feature. The key
+    /// space of a u64 seed can be searched, so a key made this way must never hold real funds.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_seed(seed: u64) -> PrivateKey {
+        let mut digest = Hash::sha256d(&seed.to_le_bytes());
+        loop {
+            // all but a negligible fraction of digests are valid keys
+            if let Ok(key) = secp256k1::SecretKey::from_slice(&digest.hash) {
+                return PrivateKey::new(key);
+            }
+            digest = Hash::sha256d(&digest.hash);
+        }
+    }
+
     /// Constructs private key from the provided generic Secp256k1 private key.
     pub fn new(key: secp256k1::SecretKey) -> PrivateKey {
         PrivateKey { inner: key }
@@ -33,11 +53,31 @@ impl PrivateKey {
         self.inner[..].to_vec()
     }
 
+    /// The public key of this private key.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(self)
+    }
+
     /// Deserializes a private key from a slice.
     pub fn from_slice(data: &[u8]) -> Result<PrivateKey> {
         Ok(PrivateKey::new(secp256k1::SecretKey::from_slice(data)?))
     }
 
+    /// Sign a digest, producing a 65-byte compact signature from which the public key can be
+    /// recovered.
+    ///
+    /// The first byte is the header used by Bitcoin message signing, 27 + the recovery id + 4, the
+    /// 4 indicating a compressed public key. The remaining 64 bytes are r and s.
+    pub fn sign_compact_recoverable(&self, digest: &Hash) -> [u8; 65] {
+        let secp = Secp256k1::signing_only();
+        let sig = secp.sign_ecdsa_recoverable(&Message::from_digest(digest.hash), &self.inner);
+        let (recovery_id, data) = sig.serialize_compact();
+        let mut compact = [0; 65];
+        compact[0] = COMPACT_HEADER_BASE + COMPACT_HEADER_COMPRESSED + recovery_id.to_i32() as u8;
+        compact[1..].copy_from_slice(&data);
+        compact
+    }
+
     /// Gets the WIF encoding of this private key.
     pub fn to_wif(self, kind: KeyAddressKind) -> String {
         let mut ret = Vec::with_capacity(34);
@@ -112,6 +152,35 @@ impl PublicKey {
     pub fn to_bytes(self) -> Vec<u8> {
         self.inner.serialize().to_vec()
     }
+
+    /// Recover the public key that produced a compact signature of the digest, see
+    /// [PrivateKey::sign_compact_recoverable()].
+    ///
+    /// Both the compressed and uncompressed header bytes are accepted, use [compact_is_compressed()]
+    /// to find which form of the key was signalled.
+    pub fn recover_from_compact(digest: &Hash, sig: &[u8; 65]) -> Result<PublicKey> {
+        let header = sig[0];
+        if !(COMPACT_HEADER_BASE..COMPACT_HEADER_BASE + 8).contains(&header) {
+            return Err(Error::BadArgument(format!(
+                "invalid compact signature header: {}",
+                header
+            )));
+        }
+        let recovery_id = RecoveryId::from_i32(((header - COMPACT_HEADER_BASE) & 3) as i32)?;
+        let sig = RecoverableSignature::from_compact(&sig[1..], recovery_id)?;
+        let secp = Secp256k1::verification_only();
+        let key = secp.recover_ecdsa(&Message::from_digest(digest.hash), &sig)?;
+        Ok(PublicKey::new(key))
+    }
+}
+
+// the header byte of a compact signature is 27 + the recovery id, plus 4 for a compressed key
+const COMPACT_HEADER_BASE: u8 = 27;
+const COMPACT_HEADER_COMPRESSED: u8 = 4;
+
+/// Whether the header of a compact signature indicates a compressed public key.
+pub fn compact_is_compressed(sig: &[u8; 65]) -> bool {
+    sig[0] >= COMPACT_HEADER_BASE + COMPACT_HEADER_COMPRESSED
 }
 
 impl From<secp256k1::PublicKey> for PublicKey {
@@ -157,6 +226,7 @@ mod tests {
 
     /// Test generating a random private key and printing the WIF
     #[test]
+    #[cfg(feature = "key-generation")]
     fn test_wif() {
         let privkey = PrivateKey::generate();
         let wif = privkey.to_wif(KeyAddressKind::Main);
@@ -177,10 +247,56 @@ mod tests {
         assert_eq!(addr.to_string(), stn_addr);
     }
 
+    /// Sign with a known key and recover it, with both the compressed and uncompressed headers.
+    #[test]
+    fn recover_from_compact() {
+        let wif = String::from("cU5N3pE6QnRd3rZFgv1KMvUkDwMY4Vnya3bLE5JtZG3Hb549pzDN");
+        let (privkey, _) = PrivateKey::from_wif(&wif).unwrap();
+        let pubkey = PublicKey::from(&privkey);
+        let digest = Hash::sha256d(b"recover me");
+        let sig = privkey.sign_compact_recoverable(&digest);
+        assert!((31..=34).contains(&sig[0]));
+        assert!(compact_is_compressed(&sig));
+        // signing is deterministic
+        assert_eq!(sig, privkey.sign_compact_recoverable(&digest));
+        assert_eq!(
+            PublicKey::recover_from_compact(&digest, &sig).unwrap(),
+            pubkey
+        );
+
+        let mut uncompressed = sig;
+        uncompressed[0] -= 4;
+        assert!(!compact_is_compressed(&uncompressed));
+        assert_eq!(
+            PublicKey::recover_from_compact(&digest, &uncompressed).unwrap(),
+            pubkey
+        );
+
+        // another digest recovers another key
+        let other = Hash::sha256d(b"something else");
+        assert_ne!(
+            PublicKey::recover_from_compact(&other, &sig).ok(),
+            Some(pubkey)
+        );
+        let mut bad = sig;
+        bad[0] = 35;
+        assert!(PublicKey::recover_from_compact(&digest, &bad).is_err());
+        bad[0] = 26;
+        assert!(PublicKey::recover_from_compact(&digest, &bad).is_err());
+    }
+
+    #[test]
+    fn from_seed() {
+        let key = PrivateKey::from_seed(7);
+        assert_eq!(key, PrivateKey::from_seed(7));
+        assert_ne!(key, PrivateKey::from_seed(8));
+        assert_eq!(key.public_key(), PublicKey::from(&key));
+    }
+
     /// Test bincode serialization and deserialization
     #[test]
     fn test_bincode() {
-        let privkey = PrivateKey::generate();
+        let privkey = PrivateKey::from_seed(3);
         let e = bincode::serialize(&privkey).unwrap();
         let d = bincode::deserialize(&e[..]).unwrap();
         assert_eq!(privkey, d);
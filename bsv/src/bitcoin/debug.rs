@@ -0,0 +1,268 @@
+use crate::bitcoin::{Block, ByteSequence, Script, ScriptTemplate, Tx, TxInput, TxOutput};
+use std::fmt;
+
+// the number of bytes shown from each end of a long byte string
+const PREVIEW_BYTES: usize = 16;
+// the number of items shown from a long list of inputs, outputs or transactions
+const PREVIEW_ITEMS: usize = 8;
+
+/// Write the bytes as hex, or only the first and last [PREVIEW_BYTES] of them if there are more.
+fn fmt_hex_preview(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    if bytes.len() <= 2 * PREVIEW_BYTES {
+        f.write_str(&hex::encode(bytes))
+    } else {
+        write!(
+            f,
+            "{}…{}",
+            hex::encode(&bytes[..PREVIEW_BYTES]),
+            hex::encode(&bytes[bytes.len() - PREVIEW_BYTES..])
+        )
+    }
+}
+
+/// Write a list of the items, showing at most [PREVIEW_ITEMS] of them unless `full` is set.
+fn fmt_items<'a, T, D: fmt::Debug>(
+    f: &mut fmt::Formatter,
+    items: &'a [T],
+    full: bool,
+    entry: impl Fn(&'a T) -> D,
+) -> fmt::Result {
+    let shown = if full {
+        items.len()
+    } else {
+        items.len().min(PREVIEW_ITEMS)
+    };
+    let mut list = f.debug_list();
+    list.entries(items[..shown].iter().map(entry));
+    if shown < items.len() {
+        list.entry(&format_args!("… {} more", items.len() - shown));
+    }
+    list.finish()
+}
+
+/// Formats with a closure, for fields whose Debug output is written by hand.
+struct DebugFn<F>(F);
+
+impl<F: Fn(&mut fmt::Formatter) -> fmt::Result> fmt::Debug for DebugFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (self.0)(f)
+    }
+}
+
+/// The unabridged Debug output of a value, see the `full_debug()` methods of [Script],
+/// [ByteSequence], [Tx] and [Block].
+///
+/// The Debug output of these types is bounded in length so that logging a large transaction or
+/// block does not flood the log. This shows every byte and every item instead.
+pub struct FullDebug<'a, T>(&'a T);
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Script({} bytes, ", self.len())?;
+        if let Some(template) = ScriptTemplate::classify(self) {
+            write!(f, "{}, ", template)?;
+        }
+        fmt_hex_preview(f, &self.raw)?;
+        f.write_str(")")
+    }
+}
+
+impl Script {
+    /// Debug output that shows the whole script.
+    pub fn full_debug(&self) -> FullDebug<'_, Script> {
+        FullDebug(self)
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, Script> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Script({})", hex::encode(&self.0.raw))
+    }
+}
+
+impl fmt::Debug for ByteSequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ByteSequence({} bytes, ", self.len())?;
+        fmt_hex_preview(f, &self.get_bytes())?;
+        f.write_str(")")
+    }
+}
+
+impl ByteSequence {
+    /// Debug output that shows the whole byte sequence.
+    pub fn full_debug(&self) -> FullDebug<'_, ByteSequence> {
+        FullDebug(self)
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, ByteSequence> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ByteSequence({})", hex::encode(self.0.get_bytes()))
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, TxInput> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxInput")
+            .field("outpoint", &self.0.outpoint)
+            .field("script", &self.0.script.full_debug())
+            .field("sequence", &self.0.sequence)
+            .finish()
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, TxOutput> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxOutput")
+            .field("value", &self.0.value)
+            .field("script", &self.0.script.full_debug())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Tx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tx")
+            .field("version", &self.version)
+            .field(
+                "inputs",
+                &DebugFn(|f: &mut fmt::Formatter| fmt_items(f, &self.inputs, false, |i| i)),
+            )
+            .field(
+                "outputs",
+                &DebugFn(|f: &mut fmt::Formatter| fmt_items(f, &self.outputs, false, |o| o)),
+            )
+            .field("lock_time", &self.lock_time)
+            .finish()
+    }
+}
+
+impl Tx {
+    /// Debug output that shows every input and output and their whole scripts.
+    pub fn full_debug(&self) -> FullDebug<'_, Tx> {
+        FullDebug(self)
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, Tx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tx = self.0;
+        f.debug_struct("Tx")
+            .field("version", &tx.version)
+            .field(
+                "inputs",
+                &DebugFn(|f: &mut fmt::Formatter| fmt_items(f, &tx.inputs, true, FullDebug)),
+            )
+            .field(
+                "outputs",
+                &DebugFn(|f: &mut fmt::Formatter| fmt_items(f, &tx.outputs, true, FullDebug)),
+            )
+            .field("lock_time", &tx.lock_time)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Block")
+            .field("header", &self.header)
+            .field(
+                "transactions",
+                &DebugFn(|f: &mut fmt::Formatter| fmt_items(f, &self.transactions, false, |tx| tx)),
+            )
+            .finish()
+    }
+}
+
+impl Block {
+    /// Debug output that shows every transaction in full.
+    pub fn full_debug(&self) -> FullDebug<'_, Block> {
+        FullDebug(self)
+    }
+}
+
+impl fmt::Debug for FullDebug<'_, Block> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Block")
+            .field("header", &self.0.header)
+            .field(
+                "transactions",
+                &DebugFn(|f: &mut fmt::Formatter| {
+                    fmt_items(f, &self.0.transactions, true, FullDebug)
+                }),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::Hash;
+    #[cfg(feature = "p2p")]
+    use crate::p2p::P2PMessage;
+    use bytes::Bytes;
+    use hex::FromHex;
+
+    // a data carrier of 10,000 bytes
+    fn large_script() -> Script {
+        let mut raw = vec![0x00, 0x6a, 0x4d, 0x10, 0x27];
+        raw.extend((0..10_000).map(|i| i as u8));
+        Script::from(raw)
+    }
+
+    #[test]
+    fn bounded_scripts() {
+        let script = large_script();
+        let debug = format!("{:?}", script);
+        assert_eq!(
+            debug,
+            "Script(10005 bytes, OP_RETURN, 006a4d1027000102030405060708090a…000102030405060708090a0b0c0d0e0f)"
+        );
+        assert!(debug.len() < 120);
+        assert_eq!(
+            format!("{:?}", script.full_debug()).len(),
+            "Script()".len() + 2 * 10_005
+        );
+        let p2pkh = Script::from_hex("76a9146f67988ec4b7bf498c9164d76b52dffdc805ff8c88ac").unwrap();
+        assert_eq!(
+            format!("{:?}", p2pkh),
+            "Script(25 bytes, P2PKH, 76a9146f67988ec4b7bf498c9164d76b52dffdc805ff8c88ac)"
+        );
+        let seq = ByteSequence::new(Bytes::from(vec![0xab; 100]));
+        assert_eq!(
+            format!("{:?}", seq),
+            format!(
+                "ByteSequence(100 bytes, {}…{})",
+                "ab".repeat(16),
+                "ab".repeat(16)
+            )
+        );
+        assert_eq!(format!("{:?}", seq.full_debug()).len(), 214);
+    }
+
+    #[test]
+    fn bounded_transactions() {
+        let tx = Tx {
+            version: 1,
+            inputs: (0..1_000)
+                .map(|i| TxInput::new(Hash::sha256d(&[1]), i, large_script(), None))
+                .collect(),
+            outputs: vec![TxOutput::new(1, large_script())],
+            lock_time: 0,
+        };
+        let budget = 8 * 1024;
+        #[allow(unused_mut)]
+        let mut outputs = vec![format!("{:?}", tx), format!("{:#?}", tx)];
+        #[cfg(feature = "p2p")]
+        outputs.extend([
+            format!("{:?}", P2PMessage::Tx(tx.clone())),
+            format!("{}", P2PMessage::Tx(tx.clone())),
+        ]);
+        for debug in outputs {
+            assert!(debug.len() < budget, "{} characters", debug.len());
+            assert!(debug.contains("… 992 more"));
+        }
+        assert!(format!("{:?}", tx.full_debug()).len() > 1_000 * 2 * 10_005);
+    }
+}
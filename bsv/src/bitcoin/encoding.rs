@@ -1,7 +1,8 @@
-use crate::Result;
+use crate::{Error, Result};
 use async_trait::async_trait;
 use bytes::{Buf, BufMut};
 use futures::executor::block_on;
+use hex::FromHex;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 /// Read & write Bitcoin data structures to and from binary in Bitcoin encoding format.
@@ -83,3 +84,195 @@ pub trait AsyncEncodable {
         Ok(v)
     }
 }
+
+/// Decode a data structure from hex, failing if the hex is not exactly one encoded structure.
+pub(crate) fn decode_hex<T: AsyncEncodable, H: AsRef<[u8]>>(hex: H) -> Result<T> {
+    let bytes = Vec::<u8>::from_hex(hex)?;
+    let value = T::from_binary_buf(&bytes)?;
+    let size = value.async_size();
+    if size != bytes.len() {
+        return Err(Error::BadData(format!(
+            "{} trailing bytes after the encoded data",
+            bytes.len() - size
+        )));
+    }
+    Ok(value)
+}
+
+/// An [AsyncRead] that yields a single byte per read and is pending on every other poll.
+///
+/// This is used in tests to flush out bugs in the handling of partial reads.
+#[cfg(test)]
+pub(crate) struct TrickleReader<'a> {
+    data: &'a [u8],
+    pending: bool,
+}
+
+#[cfg(test)]
+impl<'a> TrickleReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        TrickleReader {
+            data,
+            pending: true,
+        }
+    }
+}
+
+#[cfg(test)]
+impl AsyncRead for TrickleReader<'_> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+        if self.pending {
+            self.pending = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.pending = true;
+        if let Some((first, rest)) = self.data.split_first() {
+            if buf.remaining() > 0 {
+                buf.put_slice(&[*first]);
+                self.data = rest;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An [AsyncWrite] that accepts a single byte per write, used in tests to flush out partial write bugs.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct TrickleWriter {
+    pub(crate) data: Vec<u8>,
+}
+
+#[cfg(test)]
+impl AsyncWrite for TrickleWriter {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match buf.first() {
+            Some(b) => {
+                self.data.push(*b);
+                std::task::Poll::Ready(Ok(1))
+            }
+            None => std::task::Poll::Ready(Ok(0)),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{
+        varint_decode, varint_encode, BlockHeader, BlockchainId, Hash, Outpoint, Tx, TxHash,
+    };
+    use hex::FromHex;
+
+    // round trip a value through the trickle reader and writer, checking the encoding is unchanged
+    async fn trickle_round_trip<T: AsyncEncodable + PartialEq + std::fmt::Debug>(bin: &[u8]) -> T {
+        let value = T::async_from_binary(&mut TrickleReader::new(bin))
+            .await
+            .unwrap();
+        let mut writer = TrickleWriter::default();
+        value.async_to_binary(&mut writer).await.unwrap();
+        assert_eq!(writer.data, bin);
+        assert_eq!(value.async_size(), bin.len());
+        value
+    }
+
+    #[tokio::test]
+    async fn trickle_hash() {
+        let h = Hash::sha256d(b"trickle");
+        let h2: Hash = trickle_round_trip(&h.hash).await;
+        assert_eq!(h, h2);
+        assert!(
+            Hash::async_from_binary(&mut TrickleReader::new(&h.hash[..31]))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn trickle_varint() {
+        for n in [0, 252, 253, 0xffff, 0x10000, 0xffffffff, u64::MAX] {
+            let mut writer = TrickleWriter::default();
+            varint_encode(&mut writer, n).await.unwrap();
+            let mut reader = TrickleReader::new(&writer.data);
+            assert_eq!(varint_decode(&mut reader).await.unwrap(), n);
+            let short = &writer.data[..writer.data.len() - 1];
+            assert!(varint_decode(&mut TrickleReader::new(short)).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn trickle_block_header() {
+        let genesis = BlockHeader::get_genesis(BlockchainId::Main);
+        let bin = genesis.to_binary_buf().unwrap();
+        let hdr: BlockHeader = trickle_round_trip(&bin).await;
+        assert_eq!(hdr, genesis);
+        assert!(
+            BlockHeader::async_from_binary(&mut TrickleReader::new(&bin[..79]))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn trickle_tx() {
+        // tx 3abc31f8ff40ffb66d9037e156842fe782e6fa1ae728759263471c68660095f1
+        let bin = Vec::from_hex("01000000018a052edc7ae2136bfc0a860cdc91185ab0d7329107802f0a9c1cd0026c815f75010000006b483045022100e587ef1b4497a6694cad646cab468b6ece2fa98c7f49f9488611ca34eecebd1002205c4ea9066484bd1bffb7fdd7d84b5ae0ee6b7cdc20a8a513e41e420e0633b98841210262142850483b6728b8ecd299e4d0c8cf30ea0636f66205166814e52d73b64b4bffffffff0200000000000000000a006a075354554b2e434fb8ce3f01000000001976a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac00000000").unwrap();
+        let tx: Tx = trickle_round_trip(&bin).await;
+        assert_eq!(
+            tx.hash(),
+            TxHash::from_hex("3abc31f8ff40ffb66d9037e156842fe782e6fa1ae728759263471c68660095f1")
+                .unwrap()
+        );
+        for i in [1, 5, 50, 150, bin.len() - 1] {
+            assert!(Tx::async_from_binary(&mut TrickleReader::new(&bin[..i]))
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn tx_count_limits() {
+        // version followed by a huge number of inputs, there is no data to back them up
+        let bin = Vec::from_hex("01000000ffffffffffffffffff").unwrap();
+        assert!(Tx::async_from_binary(&mut TrickleReader::new(&bin))
+            .await
+            .is_err());
+        // a large but possible number of inputs must not be allocated up front
+        let bin = Vec::from_hex("01000000feffffff00").unwrap();
+        assert!(Tx::async_from_binary(&mut TrickleReader::new(&bin))
+            .await
+            .is_err());
+        // a script of 4GB is too large
+        let bin =
+            Vec::from_hex("0100000001".to_owned() + &"00".repeat(Outpoint::SIZE) + "feffffffff")
+                .unwrap();
+        match Tx::async_from_binary(&mut TrickleReader::new(&bin)).await {
+            Err(crate::Error::DataTooLarge) => {}
+            r => panic!("expected DataTooLarge, got {:?}", r),
+        }
+    }
+}
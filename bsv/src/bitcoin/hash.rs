@@ -5,6 +5,7 @@ use ring::digest::{digest, SHA256};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// A struct representing a hash, specifically a SHA256d hash.
@@ -32,6 +33,40 @@ impl Hash {
         Hash { hash: hash256 }
     }
 
+    /// Returns the hash as a hex string in display order.
+    ///
+    /// The display order is the reverse of the internal byte order and is the form used by block explorers and
+    /// node RPC interfaces, e.g. the genesis block hash starts with "000000000019d6".
+    pub fn to_display_hex(&self) -> String {
+        self.encode_hex()
+    }
+
+    /// Creates a hash from its bytes in internal (wire) order.
+    ///
+    /// Returns [Error::WrongLength](crate::Error::WrongLength) unless there are exactly 32 bytes.
+    pub fn from_slice(bytes: &[u8]) -> crate::Result<Hash> {
+        match <[u8; Hash::SIZE]>::try_from(bytes) {
+            Ok(hash) => Ok(Hash { hash }),
+            Err(_) => Err(crate::Error::WrongLength {
+                expected: Hash::SIZE,
+                actual: bytes.len(),
+            }),
+        }
+    }
+
+    /// Parses a hash from hex in display order, panicking if it is invalid. For literals in tests.
+    #[cfg(test)]
+    pub(crate) fn from_hex_unwrap(hex: &str) -> Hash {
+        Hash::from_hex(hex).unwrap()
+    }
+
+    /// Returns the bytes of the hash in internal (wire) order.
+    ///
+    /// This is the order in which the hash is serialized in transactions, block headers and P2P messages.
+    pub fn to_wire_bytes(&self) -> [u8; 32] {
+        self.hash
+    }
+
     // helper for ToHex trait implementation
     fn generic_encode_hex<T, F>(&self, mut encode_fn: F) -> T
     where
@@ -73,25 +108,14 @@ impl FromHex for Hash {
 
     /// Converts a string of 64 hex characters into a hash. The bytes of the hex encoded form are reversed in
     /// accordance with Bitcoin standards.
+    ///
+    /// Returns [Error::FromHexError](crate::Error::FromHexError) if the string is not valid hex and
+    /// [Error::WrongLength](crate::Error::WrongLength) if it does not decode to 32 bytes.
     fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        let hex = hex.as_ref();
-        if hex.len() != Hash::HEX_SIZE {
-            let msg = format!(
-                "Length of hex encoded hash must be 64. Len is {:}.",
-                hex.len()
-            );
-            return Err(crate::Error::BadArgument(msg));
-        }
-        match hex::decode(hex) {
-            Ok(mut hash_bytes) => {
-                // Reverse bytes in place to match Bitcoin standard representation.
-                hash_bytes.reverse();
-                let mut hash_array = [0u8; Hash::SIZE];
-                hash_array.copy_from_slice(&hash_bytes);
-                Ok(Hash { hash: hash_array })
-            }
-            Err(e) => Err(crate::Error::FromHexError(e)),
-        }
+        let mut hash_bytes = hex::decode(hex)?;
+        // Reverse bytes in place to match Bitcoin standard representation.
+        hash_bytes.reverse();
+        Hash::from_slice(&hash_bytes)
     }
 }
 
@@ -107,12 +131,12 @@ impl ToHex for Hash {
     }
 }
 
-impl From<&[u8]> for Hash {
-    /// This converts a u8 encoded hash into a Hash struct.
-    fn from(hash_as_bytes: &[u8]) -> Hash {
-        Hash {
-            hash: <[u8; 32]>::try_from(hash_as_bytes).expect("Hash must be 32 bytes"),
-        }
+impl TryFrom<&[u8]> for Hash {
+    type Error = crate::Error;
+
+    /// Converts a hash in internal byte order, see [Hash::from_slice()].
+    fn try_from(hash_as_bytes: &[u8]) -> Result<Self, Self::Error> {
+        Hash::from_slice(hash_as_bytes)
     }
 }
 
@@ -123,23 +147,30 @@ impl From<Hash> for [u8; 32] {
     }
 }
 
-impl From<&str> for Hash {
-    /// This converts a hex encoded hash into a Hash struct.
-    fn from(hash_as_hex: &str) -> Hash {
-        Hash::from_hex(hash_as_hex).unwrap()
+impl TryFrom<&str> for Hash {
+    type Error = crate::Error;
+
+    /// Converts a hex encoded hash in display order, see [Hash::from_hex()].
+    fn try_from(hash_as_hex: &str) -> Result<Self, Self::Error> {
+        Hash::from_hex(hash_as_hex)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = crate::Error;
+
+    /// Parses a hash from its conventional display form, i.e. hex with the bytes reversed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hash::from_hex(s)
     }
 }
 
+/// Hashes are ordered by their value as a little-endian 256-bit number, so the last byte of the
+/// internal order is the most significant. This is the same as comparing the display hex, and a block
+/// hash is less than a target when it meets it.
 impl Ord for Hash {
     fn cmp(&self, other: &Hash) -> Ordering {
-        for i in (0..32).rev() {
-            if self.hash[i] < other.hash[i] {
-                return Ordering::Less;
-            } else if self.hash[i] > other.hash[i] {
-                return Ordering::Greater;
-            }
-        }
-        Ordering::Equal
+        self.hash.iter().rev().cmp(other.hash.iter().rev())
     }
 }
 
@@ -180,8 +211,50 @@ impl<'de> Deserialize<'de> for Hash {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bitcoin::{BlockHash, BlockHeader, BlockchainId, TxHash};
     use hex;
 
+    /// Random hashes that differ in only a few bytes, so that equal hashes and long common
+    /// prefixes are frequent.
+    fn random_hashes(n: usize) -> Vec<Hash> {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        (0..n)
+            .map(|_| {
+                let mut h = Hash::ZERO;
+                for i in [0, 15, 31] {
+                    h.hash[i] = rng.gen_range(0..3);
+                }
+                h
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ordering_properties() {
+        use std::hash::{BuildHasher, RandomState};
+        let hashes = random_hashes(40);
+        let state = RandomState::new();
+        for a in hashes.iter() {
+            for b in hashes.iter() {
+                // total, and consistent with Eq and with the std hash
+                assert_eq!(a.cmp(b) == Ordering::Equal, a == b);
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+                if a == b {
+                    assert_eq!(state.hash_one(a), state.hash_one(b));
+                }
+                // the same as comparing the display hex
+                assert_eq!(a.cmp(b), a.to_string().cmp(&b.to_string()));
+                for c in hashes.iter().take(10) {
+                    if a <= b && b <= c {
+                        assert!(a <= c);
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn sha256d_test() {
         let x = hex::decode("0123456789abcdef").unwrap();
@@ -211,6 +284,45 @@ mod tests {
         assert!(Hash::from_hex(s3).is_err());
     }
 
+    #[test]
+    fn try_from() {
+        let s = "abcdef0000112233445566778899abcdef000011223344556677889912345678";
+        let h = Hash::try_from(s).unwrap();
+        assert_eq!(h.to_string(), s);
+        assert_eq!(Hash::try_from(&h.hash[..]).unwrap(), h);
+        assert_eq!(Hash::from_slice(&h.to_wire_bytes()).unwrap(), h);
+
+        for bytes in [&[0u8; 31][..], &[0u8; 33][..], &[]] {
+            match Hash::try_from(bytes) {
+                Err(crate::Error::WrongLength { expected, actual }) => {
+                    assert_eq!(expected, 32);
+                    assert_eq!(actual, bytes.len());
+                }
+                r => panic!("expected wrong length, got {:?}", r),
+            }
+        }
+        // valid hex of the wrong length
+        assert!(matches!(
+            Hash::try_from(&s[..62]),
+            Err(crate::Error::WrongLength { actual: 31, .. })
+        ));
+        assert!(matches!(
+            Hash::try_from(format!("{}00", s).as_str()),
+            Err(crate::Error::WrongLength { actual: 33, .. })
+        ));
+        // odd length and bad characters are invalid hex
+        assert!(matches!(
+            Hash::try_from(&s[..63]),
+            Err(crate::Error::FromHexError(hex::FromHexError::OddLength))
+        ));
+        assert!(matches!(
+            Hash::try_from(s.replace('a', "g").as_str()),
+            Err(crate::Error::FromHexError(
+                hex::FromHexError::InvalidHexCharacter { .. }
+            ))
+        ));
+    }
+
     #[test]
     fn hash_compare() {
         let s1 = "5555555555555555555555555555555555555555555555555555555555555555";
@@ -262,6 +374,32 @@ mod tests {
         assert_eq!(b, c);
     }
 
+    #[test]
+    fn display_and_from_str() {
+        let genesis = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        let s = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        assert_eq!(genesis.to_string(), s);
+        assert_eq!(genesis.to_display_hex(), s);
+        assert_eq!(format!("{:?}", genesis), s);
+        assert_eq!(BlockHash::from_str(s).unwrap(), genesis);
+        assert_eq!(s.parse::<BlockHash>().unwrap(), genesis);
+        // the wire order is the reverse of the display order
+        let mut wire = genesis.to_wire_bytes();
+        assert_eq!(wire[31], 0);
+        wire.reverse();
+        assert_eq!(hex::encode(wire), s);
+        assert!("000000000019d6".parse::<BlockHash>().is_err());
+    }
+
+    #[test]
+    fn tx_hash_display() {
+        // the coinbase of the genesis block
+        let s = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let h = TxHash::from_str(s).unwrap();
+        assert_eq!(h.to_string(), s);
+        assert_eq!(h.to_wire_bytes()[0], 0x3b);
+    }
+
     #[test]
     fn json_serialize_hash() {
         let hash =
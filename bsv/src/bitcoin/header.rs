@@ -1,3 +1,4 @@
+use crate::bitcoin::encoding::decode_hex;
 use crate::bitcoin::hash::Hash;
 use crate::bitcoin::params::BlockchainId;
 use crate::bitcoin::AsyncEncodable;
@@ -10,6 +11,54 @@ pub type BlockHash = Hash;
 /// The MerkleRoot is the root of the merkle tree of this block's transaction hashes.
 pub type MerkleRoot = Hash;
 
+/// Calculate the root of the merkle tree of the given transaction hashes.
+///
+/// Where a level of the tree has an odd number of hashes, the last hash is paired with itself. The root
+/// of an empty list is [Hash::ZERO].
+pub fn merkle_root(hashes: &[Hash]) -> MerkleRoot {
+    if hashes.is_empty() {
+        return Hash::ZERO;
+    }
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = merkle_parents(&level);
+    }
+    level[0]
+}
+
+/// Calculate the root of the merkle tree of the given transaction hashes, and whether the tree is
+/// malleated.
+///
+/// Pairing the last hash of a level with itself means that a list of hashes which repeats its final
+/// hashes can have the same root as the list without them (CVE-2012-2459). The tree is malleated if
+/// any pair of hashes that are combined are identical, which an honest list of distinct transactions
+/// never produces.
+pub fn merkle_root_checked(hashes: &[Hash]) -> (MerkleRoot, bool) {
+    if hashes.is_empty() {
+        return (Hash::ZERO, false);
+    }
+    let mut malleated = false;
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        malleated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        level = merkle_parents(&level);
+    }
+    (level[0], malleated)
+}
+
+/// Calculate the next level up of a merkle tree.
+pub(crate) fn merkle_parents(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            let mut data = [0u8; 64];
+            data[..32].copy_from_slice(&pair[0].hash);
+            data[32..].copy_from_slice(&pair[pair.len() - 1].hash);
+            Hash::sha256d(&data)
+        })
+        .collect()
+}
+
 /// BlockHeaders are linked to together to form a blockchain.
 #[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
 pub struct BlockHeader {
@@ -38,6 +87,84 @@ impl BlockHeader {
         Hash::sha256d(&v)
     }
 
+    /// The proof of work target encoded in `bits`, or None if `bits` is not a valid positive target.
+    pub fn target(&self) -> Option<Hash> {
+        let exponent = (self.bits >> 24) as usize;
+        let mantissa = self.bits & 0x007f_ffff;
+        if mantissa == 0 || self.bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        let mut target = Hash::ZERO;
+        for (i, byte) in mantissa.to_le_bytes()[..3].iter().enumerate() {
+            // the mantissa is the most significant three bytes of a number that is exponent bytes long
+            if *byte == 0 || i + exponent < 3 {
+                continue;
+            }
+            let pos = i + exponent - 3;
+            if pos >= Hash::SIZE {
+                return None;
+            }
+            target.hash[pos] = *byte;
+        }
+        Some(target)
+    }
+
+    /// Check that the hash of the header satisfies the proof of work target encoded in `bits`.
+    ///
+    /// Whether `bits` is the correct target for the block is not checked, that requires the preceding
+    /// headers.
+    pub fn validate_pow(&self) -> crate::Result<()> {
+        match self.target() {
+            None => Err(crate::Error::BadData(format!(
+                "invalid target bits {:#010x}",
+                self.bits
+            ))),
+            Some(target) if self.hash() > target => Err(crate::Error::BadData(format!(
+                "block hash {} does not meet target {}",
+                self.hash(),
+                target
+            ))),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Return the header with the nonce replaced.
+    pub fn with_nonce(mut self, nonce: u32) -> BlockHeader {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Return the header with the timestamp replaced.
+    pub fn with_timestamp(mut self, timestamp: u32) -> BlockHeader {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Search for a nonce that satisfies the proof of work target, trying at most `max_iterations`
+    /// values. Returns the nonce, which is also set in the header, or None if none was found.
+    ///
+    /// The search starts at the current nonce. If every nonce is tried, the timestamp is increased
+    /// by one second and the search continues, so the timestamp may also have changed.
+    ///
+    /// This is for fabricating blocks on regtest and in tests, where the target is easy enough
+    /// that a few attempts are enough. It is far too slow for mining at real difficulty.
+    pub fn solve(&mut self, max_iterations: u64) -> Option<u32> {
+        let target = self.target()?;
+        let mut buf = self.to_binary_buf().unwrap();
+        for _ in 0..max_iterations {
+            buf[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+            buf[68..72].copy_from_slice(&self.timestamp.to_le_bytes());
+            if Hash::sha256d(&buf) <= target {
+                return Some(self.nonce);
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+            if self.nonce == 0 {
+                self.timestamp = self.timestamp.wrapping_add(1);
+            }
+        }
+        None
+    }
+
     /// Get the Genesis BlockHeader for the given chain.
     pub fn get_genesis(block_chain: BlockchainId) -> BlockHeader {
         match block_chain {
@@ -86,8 +213,7 @@ impl AsyncEncodable for BlockHeader {
 impl FromHex for BlockHeader {
     type Error = crate::Error;
     fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        let bytes = Vec::<u8>::from_hex(hex)?;
-        BlockHeader::from_binary_buf(bytes.as_slice())
+        decode_hex(hex)
     }
 }
 
@@ -108,6 +234,22 @@ mod tests {
     use super::*;
     use hex::FromHex;
 
+    /// Parse the genesis block header from hex
+    #[test]
+    fn block_header_hex() {
+        let hex = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+        let header = BlockHeader::from_hex(hex).unwrap();
+        assert_eq!(
+            header.hash(),
+            Hash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
+                .unwrap()
+        );
+        assert_eq!(header, BlockHeader::get_genesis(BlockchainId::Main));
+        assert_eq!(header.encode_hex::<String>(), hex);
+        assert!(BlockHeader::from_hex(format!("{}00", hex)).is_err());
+        assert!(BlockHeader::from_hex(&hex[..158]).is_err());
+    }
+
     /// Read a block header from a byte array and check it
     #[test]
     fn block_header_read() {
@@ -130,6 +272,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn proof_of_work() {
+        let (block_header_bin, _) = get_block_header824962();
+        let mut header = BlockHeader::from_binary_buf(block_header_bin.as_slice()).unwrap();
+        assert_eq!(
+            header.target().unwrap(),
+            Hash::from_hex("000000000000000008583c000000000000000000000000000000000000000000")
+                .unwrap()
+        );
+        header.validate_pow().unwrap();
+        BlockHeader::get_genesis(BlockchainId::Main)
+            .validate_pow()
+            .unwrap();
+        BlockHeader::get_genesis(BlockchainId::Regtest)
+            .validate_pow()
+            .unwrap();
+        header.nonce += 1;
+        assert!(header.validate_pow().is_err());
+        for bits in [0x1800_0000, 0x1880_0001, 0x2300_ffff] {
+            header.bits = bits;
+            assert_eq!(header.target(), None, "{:#x}", bits);
+        }
+        header.bits = 0x0300_ffff;
+        assert_eq!(header.target().unwrap().hash[..3], [0xff, 0xff, 0]);
+        header.bits = 0x0200_ff00;
+        assert_eq!(header.target().unwrap().hash[..3], [0xff, 0, 0]);
+    }
+
+    #[test]
+    fn solve() {
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let mut header = genesis.clone().with_nonce(0);
+        let nonce = header.solve(1000).unwrap();
+        header.validate_pow().unwrap();
+        assert_eq!(header, genesis.clone().with_nonce(nonce));
+
+        // rolls the timestamp when the nonces run out, with a target of about 1 in 16 hashes
+        let mut header = genesis.clone().with_nonce(u32::MAX);
+        header.bits = 0x200fffff;
+        assert!(header.validate_pow().is_err());
+        assert_eq!(header.solve(1000), Some(11));
+        assert_eq!(header.timestamp, genesis.timestamp + 1);
+        header.validate_pow().unwrap();
+
+        // a mainnet target is not found in a few attempts, and an invalid target never is
+        let mut header = BlockHeader::get_genesis(BlockchainId::Main).with_nonce(0);
+        assert_eq!(header.solve(100), None);
+        assert_eq!(header.nonce, 100);
+        header.bits = 0;
+        assert_eq!(header.solve(100), None);
+    }
+
     fn get_block_header824962() -> (Vec<u8>, BlockHash) {
         (
             Vec::from_hex("00405324d8facaf19ce3efc5f6b3fbdc1cb1f5369a56c3de3e50280300000000000000002742bdb5930e5bf24be6e7521ceeecf6d3199871e2a6438f54cb5fd95d3f5139a38d90653c5808186eac9b4c").unwrap(),
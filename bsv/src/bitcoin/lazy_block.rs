@@ -0,0 +1,223 @@
+use crate::bitcoin::{
+    scan_tx, varint_read, AsyncEncodable, Block, BlockHeader, Tx, TxHash, TxSummary,
+};
+use crate::{Error, Result};
+use bytes::Bytes;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A block held in its serialized form, with the transactions parsed on demand.
+///
+/// Creating a LazyBlock only parses the header and scans each transaction with [scan_tx()], which
+/// finds where it ends and its txid in one pass, no [Tx] is built. A transaction is parsed when it
+/// is requested with [LazyBlock::tx_at]. This suits callers that need a few transactions from a
+/// large block, or only the txids.
+#[derive(Debug)]
+pub struct LazyBlock {
+    header: BlockHeader,
+    raw: Bytes,
+    // the offset of each transaction in raw, followed by the end of the last transaction
+    offsets: Vec<usize>,
+    summaries: Vec<TxSummary>,
+    #[cfg(test)]
+    parsed: AtomicUsize,
+}
+
+impl LazyBlock {
+    /// Index the transactions in a serialized block.
+    ///
+    /// The bytes must be exactly one block, trailing data is rejected.
+    pub fn new(raw: Bytes) -> Result<LazyBlock> {
+        if raw.len() < BlockHeader::SIZE {
+            return Err(Error::DataTooSmall);
+        }
+        let header = BlockHeader::from_binary_buf(&raw[..BlockHeader::SIZE])?;
+        let mut rest = &raw[BlockHeader::SIZE..];
+        let tx_count = varint_read(&mut rest)?;
+        let mut pos = raw.len() - rest.len();
+        // every transaction is at least 10 bytes, dont trust the count any further than that
+        let capacity = tx_count.min(((raw.len() - pos) / 10) as u64) as usize;
+        let mut offsets = Vec::with_capacity(capacity + 1);
+        let mut summaries = Vec::with_capacity(capacity);
+        for _ in 0..tx_count {
+            offsets.push(pos);
+            let summary = scan_tx(&mut &raw[pos..])?;
+            pos += summary.size;
+            summaries.push(summary);
+        }
+        offsets.push(pos);
+        if pos != raw.len() {
+            return Err(Error::BadData(format!(
+                "{} trailing bytes after the block",
+                raw.len() - pos
+            )));
+        }
+        Ok(LazyBlock {
+            header,
+            raw,
+            offsets,
+            summaries,
+            #[cfg(test)]
+            parsed: AtomicUsize::new(0),
+        })
+    }
+
+    /// The block header.
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// The serialized block.
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+
+    /// The number of transactions in the block.
+    pub fn tx_count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// The serialized transaction at `index`, without copying.
+    pub fn tx_bytes(&self, index: usize) -> Result<Bytes> {
+        if index >= self.tx_count() {
+            return Err(Error::BadArgument(format!(
+                "transaction index {} out of range, the block has {} transactions",
+                index,
+                self.tx_count()
+            )));
+        }
+        Ok(self.raw.slice(self.offsets[index]..self.offsets[index + 1]))
+    }
+
+    /// Parse the transaction at `index`.
+    pub fn tx_at(&self, index: usize) -> Result<Tx> {
+        let bytes = self.tx_bytes(index)?;
+        #[cfg(test)]
+        self.parsed.fetch_add(1, Ordering::Relaxed);
+        Tx::from_binary_buf(&bytes)
+    }
+
+    /// The txid of the transaction at `index`, found when the block was indexed.
+    pub fn txid_at(&self, index: usize) -> Result<TxHash> {
+        Ok(self.summary_at(index)?.txid)
+    }
+
+    /// The summary of the transaction at `index`, found when the block was indexed.
+    pub fn summary_at(&self, index: usize) -> Result<&TxSummary> {
+        self.summaries.get(index).ok_or_else(|| {
+            Error::BadArgument(format!(
+                "transaction index {} out of range, the block has {} transactions",
+                index,
+                self.tx_count()
+            ))
+        })
+    }
+
+    /// Iterate over the transactions, parsing each one as it is reached.
+    pub fn transactions(&self) -> impl Iterator<Item = Result<Tx>> + '_ {
+        (0..self.tx_count()).map(|i| self.tx_at(i))
+    }
+
+    /// Parse every transaction, producing a [Block].
+    pub fn to_block(&self) -> Result<Block> {
+        Ok(Block {
+            header: self.header.clone(),
+            transactions: self.transactions().collect::<Result<Vec<Tx>>>()?,
+        })
+    }
+}
+
+impl TryFrom<Bytes> for LazyBlock {
+    type Error = Error;
+
+    fn try_from(raw: Bytes) -> Result<Self> {
+        LazyBlock::new(raw)
+    }
+}
+
+impl From<&Block> for LazyBlock {
+    fn from(block: &Block) -> Self {
+        let raw = Bytes::from(block.to_binary_buf().unwrap());
+        LazyBlock::new(raw).expect("a serialized block is valid")
+    }
+}
+
+impl TryFrom<&LazyBlock> for Block {
+    type Error = Error;
+
+    fn try_from(block: &LazyBlock) -> Result<Self> {
+        block.to_block()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::Hash;
+    use hex::FromHex;
+
+    fn small_block() -> Bytes {
+        Bytes::from(
+            std::fs::read(
+                "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn index_without_parsing() {
+        let raw = small_block();
+        let lazy = LazyBlock::new(raw.clone()).unwrap();
+        assert_eq!(
+            lazy.header().hash(),
+            Hash::from_hex("0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7")
+                .unwrap()
+        );
+        assert_eq!(lazy.tx_count(), 222);
+        assert_eq!(lazy.parsed.load(Ordering::Relaxed), 0);
+
+        let block = Block::from_binary_buf(&raw).unwrap();
+        let coinbase = lazy.tx_at(0).unwrap();
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase, block.transactions[0]);
+        let last = lazy.tx_at(221).unwrap();
+        assert_eq!(last, block.transactions[221]);
+        assert_eq!(lazy.txid_at(0).unwrap(), block.transactions[0].hash());
+        assert_eq!(lazy.txid_at(221).unwrap(), last.hash());
+        assert_eq!(lazy.summary_at(221).unwrap().size, last.async_size());
+        // only the two requested transactions were parsed
+        assert_eq!(lazy.parsed.load(Ordering::Relaxed), 2);
+        assert!(lazy.tx_at(222).is_err());
+        assert!(lazy.txid_at(222).is_err());
+    }
+
+    #[test]
+    fn convert() {
+        let raw = small_block();
+        let block = Block::from_binary_buf(&raw).unwrap();
+        let lazy = LazyBlock::from(&block);
+        assert_eq!(lazy.raw(), &raw);
+        assert_eq!(Block::try_from(&lazy).unwrap(), block);
+        let txids: Vec<TxHash> = lazy.transactions().map(|t| t.unwrap().hash()).collect();
+        assert_eq!(lazy.parsed.load(Ordering::Relaxed), 2 * 222);
+        assert_eq!(
+            crate::bitcoin::merkle_root(&txids),
+            block.header.merkle_root
+        );
+    }
+
+    #[test]
+    fn reject_bad_data() {
+        let raw = small_block();
+        assert!(LazyBlock::try_from(raw.slice(..raw.len() - 1)).is_err());
+        assert!(LazyBlock::try_from(raw.slice(..50)).is_err());
+        let mut extra = raw.to_vec();
+        extra.push(0);
+        assert!(LazyBlock::new(Bytes::from(extra)).is_err());
+        // a huge transaction count must not cause a huge allocation
+        let mut huge = raw[..BlockHeader::SIZE].to_vec();
+        huge.extend_from_slice(&[0xff; 9]);
+        assert!(LazyBlock::new(Bytes::from(huge)).is_err());
+    }
+}
@@ -0,0 +1,999 @@
+use crate::bitcoin::hash160::Hash160;
+use crate::bitcoin::{
+    verify_signatures_batch, AsyncEncodable, Block, NonStandardReason, Operation, Outpoint,
+    SigCheckItem, SighashCache, StandardnessPolicy, Tx, TxBuilder, TxHash, TxOutput,
+    REJECT_DUPLICATE, REJECT_DUST, REJECT_INSUFFICIENT_FEE, REJECT_INVALID, REJECT_NONSTANDARD,
+};
+#[cfg(feature = "p2p")]
+use crate::p2p::{Reject, TxProvider};
+use crate::util::FeeRate;
+use crate::{Error, Result};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+/// A source of the unspent outputs that transactions spend, such as a UTXO set.
+pub trait UtxoProvider {
+    /// Get the unspent output at the outpoint, or None if it does not exist or has been spent.
+    fn get_utxo(&self, outpoint: &Outpoint) -> Option<TxOutput>;
+}
+
+impl UtxoProvider for HashMap<Outpoint, TxOutput> {
+    fn get_utxo(&self, outpoint: &Outpoint) -> Option<TxOutput> {
+        self.get(outpoint).cloned()
+    }
+}
+
+/// The rules applied by [SimpleMempool::accept()].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptPolicy {
+    /// The standardness rules that transactions must satisfy.
+    pub standardness: StandardnessPolicy,
+    /// The minimum fee rate that transactions must pay.
+    pub min_fee_rate: FeeRate,
+    /// Accept transactions that spend outputs whose scripts can not be verified, which are those
+    /// that are not P2PKH. The inputs are listed in [Accepted::unverified_inputs]. Off by default, as a
+    /// transaction with an invalid script would hold its outpoints against the valid spends, the
+    /// first one seen is kept.
+    pub accept_unverified_inputs: bool,
+}
+
+impl Default for AcceptPolicy {
+    /// The default standardness policy and the default fee rate of [TxBuilder], so that transactions
+    /// built with the default settings are accepted.
+    fn default() -> Self {
+        AcceptPolicy {
+            standardness: StandardnessPolicy::default(),
+            min_fee_rate: TxBuilder::DEFAULT_FEE_RATE,
+            accept_unverified_inputs: false,
+        }
+    }
+}
+
+/// A transaction that was added to the mempool by [SimpleMempool::accept()].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accepted {
+    pub hash: TxHash,
+    /// The fee paid, in satoshis.
+    pub fee: u64,
+    pub size: usize,
+    /// The inputs whose scripts were not verified because the output they spend is not P2PKH, see
+    /// [AcceptPolicy::accept_unverified_inputs].
+    pub unverified_inputs: Vec<usize>,
+    /// The transactions that were evicted to make room.
+    pub evicted: Vec<TxHash>,
+}
+
+/// The reason that [SimpleMempool::accept()] did not accept a transaction.
+///
+/// See [RejectReason::reject_code()] for the code that should be sent to the peer that relayed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The structure of the transaction is invalid, see [Tx::validate()].
+    Invalid(String),
+    /// The transaction is a coinbase, which can only be included in a block.
+    Coinbase,
+    /// The transaction is not standard.
+    NonStandard(NonStandardReason),
+    /// The transaction is already in the mempool.
+    AlreadyKnown,
+    /// The transaction spends an outpoint that is spent by the given transaction in the mempool. The
+    /// first transaction seen is kept, there is no replacement.
+    Conflict(TxHash),
+    /// The outputs spent by the transaction were not found in the UTXO set or the mempool. The
+    /// transaction is an orphan if its parents have not been received yet.
+    MissingInputs(Vec<Outpoint>),
+    /// The outputs are worth more than the inputs.
+    InputsBelowOutputs { inputs: u64, outputs: u64 },
+    /// The fee is less than the minimum fee for the size of the transaction.
+    InsufficientFee { fee: u64, required: u64 },
+    /// The unlocking script of the input does not satisfy the output it spends.
+    ScriptFailed { index: usize },
+    /// The fee rate is too low for the transaction to be kept in a full mempool.
+    MempoolFull,
+}
+
+impl RejectReason {
+    /// The code of the reject message to send to the peer that relayed the transaction.
+    ///
+    /// Returns None when no reject message should be sent, which is when the transaction is already
+    /// known or is missing its inputs.
+    pub fn reject_code(&self) -> Option<u8> {
+        use RejectReason::*;
+        match self {
+            Invalid(_) | Coinbase | InputsBelowOutputs { .. } | ScriptFailed { .. } => {
+                Some(REJECT_INVALID)
+            }
+            NonStandard(NonStandardReason::DustOutput { .. }) => Some(REJECT_DUST),
+            NonStandard(_) => Some(REJECT_NONSTANDARD),
+            Conflict(_) => Some(REJECT_DUPLICATE),
+            InsufficientFee { .. } | MempoolFull => Some(REJECT_INSUFFICIENT_FEE),
+            AlreadyKnown | MissingInputs(_) => None,
+        }
+    }
+
+    /// The short reason given in a reject message, these are the reasons used by the node software.
+    pub fn reject_reason(&self) -> &'static str {
+        use RejectReason::*;
+        match self {
+            Invalid(_) => "bad-txns",
+            Coinbase => "coinbase",
+            NonStandard(NonStandardReason::TxTooLarge { .. }) => "tx-size",
+            NonStandard(NonStandardReason::InputNotPushOnly { .. }) => "scriptsig-not-pushonly",
+            NonStandard(NonStandardReason::DustOutput { .. }) => "dust",
+            NonStandard(NonStandardReason::OpReturnTooLarge { .. }) => "datacarrier-size",
+            NonStandard(NonStandardReason::UnverifiableInput { .. }) => {
+                "bad-txns-nonstandard-inputs"
+            }
+            AlreadyKnown => "txn-already-known",
+            Conflict(_) => "txn-mempool-conflict",
+            MissingInputs(_) => "missing-inputs",
+            InputsBelowOutputs { .. } => "bad-txns-in-belowout",
+            InsufficientFee { .. } => "insufficient priority",
+            ScriptFailed { .. } => "mandatory-script-verify-flag-failed",
+            MempoolFull => "mempool full",
+        }
+    }
+
+    /// The reject message to send to the peer that relayed the transaction, if any.
+    #[cfg(feature = "p2p")]
+    pub fn to_reject(&self, hash: &TxHash) -> Option<Reject> {
+        Some(Reject {
+            message: "tx".to_string(),
+            code: self.reject_code()?,
+            reason: self.reject_reason().to_string(),
+            data: hash.hash.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RejectReason::*;
+        match self {
+            Invalid(reason) => write!(f, "invalid transaction: {}", reason),
+            Coinbase => f.write_str("coinbase transaction"),
+            NonStandard(reason) => write!(f, "non-standard transaction: {}", reason),
+            AlreadyKnown => f.write_str("transaction already in mempool"),
+            Conflict(hash) => write!(f, "conflicts with mempool transaction {}", hash),
+            MissingInputs(missing) => write!(f, "{} inputs not found", missing.len()),
+            InputsBelowOutputs { inputs, outputs } => {
+                write!(f, "inputs {} are less than outputs {}", inputs, outputs)
+            }
+            InsufficientFee { fee, required } => {
+                write!(f, "fee {} is less than required fee {}", fee, required)
+            }
+            ScriptFailed { index } => write!(f, "script of input {} failed", index),
+            MempoolFull => f.write_str("mempool full"),
+        }
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
+#[derive(Debug)]
+struct Entry {
+    tx: Tx,
+    fee: u64,
+    size: usize,
+}
+
+/// Orders transactions by fee rate, lowest first, and then by hash.
+#[derive(Debug, PartialEq, Eq)]
+struct RateKey {
+    fee: u64,
+    size: usize,
+    hash: TxHash,
+}
+
+impl Ord for RateKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // compare fee / size without rounding
+        let a = self.fee as u128 * other.size as u128;
+        let b = other.fee as u128 * self.size as u128;
+        a.cmp(&b).then_with(|| self.hash.cmp(&other.hash))
+    }
+}
+
+impl PartialOrd for RateKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<TxHash, Entry>,
+    by_rate: BTreeSet<RateKey>,
+    /// The transaction in the mempool that spends each outpoint.
+    spent_by: HashMap<Outpoint, TxHash>,
+    bytes: usize,
+}
+
+impl Inner {
+    fn key(&self, hash: &TxHash) -> RateKey {
+        let e = &self.entries[hash];
+        RateKey {
+            fee: e.fee,
+            size: e.size,
+            hash: *hash,
+        }
+    }
+
+    /// Remove a transaction and everything in the mempool that spends its outputs, adding their hashes
+    /// to `removed`.
+    fn remove_with_descendants(&mut self, hash: &TxHash, removed: &mut Vec<TxHash>) {
+        let mut pending = vec![*hash];
+        while let Some(hash) = pending.pop() {
+            if !self.entries.contains_key(&hash) {
+                continue;
+            }
+            let key = self.key(&hash);
+            self.by_rate.remove(&key);
+            let entry = self.entries.remove(&hash).unwrap();
+            self.bytes -= entry.size;
+            for input in entry.tx.inputs.iter() {
+                self.spent_by.remove(&input.outpoint);
+            }
+            for index in 0..entry.tx.outputs.len() as u32 {
+                let outpoint = Outpoint {
+                    tx_hash: hash,
+                    index,
+                };
+                if let Some(child) = self.spent_by.get(&outpoint) {
+                    pending.push(*child);
+                }
+            }
+            removed.push(hash);
+        }
+    }
+}
+
+/// An in-memory pool of unconfirmed transactions, bounded in size.
+///
+/// Transactions are indexed by their hash and by the outpoints that they spend, so that a transaction
+/// which double spends one already in the pool can be detected. When the total size of the transactions
+/// exceeds the byte budget, the transactions with the lowest fee rate are evicted together with any
+/// transactions in the pool that depend on them. Transactions are removed when they are confirmed in a
+/// block.
+///
+/// The pool is not validated: inputs are not checked against the UTXO set and the fee is taken on trust.
+/// With the `p2p` feature it implements [TxProvider](crate::p2p::TxProvider) so that it can be used
+/// to answer requests from peers.
+#[derive(Debug)]
+pub struct SimpleMempool {
+    max_bytes: usize,
+    policy: AcceptPolicy,
+    inner: Mutex<Inner>,
+}
+
+impl SimpleMempool {
+    /// Create a mempool that holds at most `max_bytes` of serialized transactions.
+    pub fn new(max_bytes: usize) -> SimpleMempool {
+        SimpleMempool::with_policy(max_bytes, AcceptPolicy::default())
+    }
+
+    /// Create a mempool that holds at most `max_bytes`, using the policy in [SimpleMempool::accept()].
+    pub fn with_policy(max_bytes: usize, policy: AcceptPolicy) -> SimpleMempool {
+        SimpleMempool {
+            max_bytes,
+            policy,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Check a transaction received from a peer and add it to the pool if it is acceptable.
+    ///
+    /// The structure and standardness of the transaction are checked, and then the outputs that it
+    /// spends are found in the pool or in `utxos`, so that a chain of unconfirmed transactions is
+    /// accepted when they arrive in order. A transaction that spends an outpoint which is already spent
+    /// in the pool is rejected, the first one seen is kept. The fee must meet the minimum fee rate of
+    /// the policy.
+    ///
+    /// The signatures of inputs that spend P2PKH outputs are verified. Other scripts can not be
+    /// executed, so a transaction that spends them is rejected as not standard unless the policy
+    /// accepts unverified inputs, when they are listed in [Accepted::unverified_inputs].
+    pub fn accept(
+        &self,
+        tx: Tx,
+        utxos: &dyn UtxoProvider,
+    ) -> std::result::Result<Accepted, RejectReason> {
+        let hash = tx.hash();
+        if self.contains(&hash) {
+            return Err(RejectReason::AlreadyKnown);
+        }
+        tx.validate()
+            .map_err(|e| RejectReason::Invalid(e.to_string()))?;
+        if tx.is_coinbase() {
+            return Err(RejectReason::Coinbase);
+        }
+        tx.check_standard(&self.policy.standardness)
+            .map_err(RejectReason::NonStandard)?;
+
+        let spent = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(other) = tx
+                .inputs
+                .iter()
+                .find_map(|i| inner.spent_by.get(&i.outpoint))
+            {
+                return Err(RejectReason::Conflict(*other));
+            }
+            let mut spent = Vec::with_capacity(tx.inputs.len());
+            let mut missing = Vec::new();
+            for input in tx.inputs.iter() {
+                let output = match inner.entries.get(&input.outpoint.tx_hash) {
+                    Some(parent) => parent
+                        .tx
+                        .outputs
+                        .get(input.outpoint.index as usize)
+                        .cloned(),
+                    None => utxos.get_utxo(&input.outpoint),
+                };
+                match output {
+                    Some(output) => spent.push(output),
+                    None => missing.push(input.outpoint.clone()),
+                }
+            }
+            if !missing.is_empty() {
+                return Err(RejectReason::MissingInputs(missing));
+            }
+            spent
+        };
+
+        let inputs = spent.iter().map(|o| o.value).sum::<u64>();
+        let outputs = tx.outputs.iter().map(|o| o.value).sum::<u64>();
+        if inputs < outputs {
+            return Err(RejectReason::InputsBelowOutputs { inputs, outputs });
+        }
+        let fee = inputs - outputs;
+        let size = tx.async_size();
+        let required = self.policy.min_fee_rate.fee_for_size(size).satoshis as u64;
+        if fee < required {
+            return Err(RejectReason::InsufficientFee { fee, required });
+        }
+
+        let unverified_inputs = verify_p2pkh_inputs(&tx, &spent)?;
+        if let Some(&index) = unverified_inputs.first() {
+            if !self.policy.accept_unverified_inputs {
+                return Err(RejectReason::NonStandard(
+                    NonStandardReason::UnverifiableInput { index },
+                ));
+            }
+        }
+        let evicted = self.insert(tx, fee).map_err(|e| match e {
+            Error::MempoolConflict(other) => RejectReason::Conflict(other),
+            e => RejectReason::Invalid(e.to_string()),
+        })?;
+        if evicted.contains(&hash) {
+            return Err(RejectReason::MempoolFull);
+        }
+        Ok(Accepted {
+            hash,
+            fee,
+            size,
+            unverified_inputs,
+            evicted,
+        })
+    }
+
+    /// Add a transaction that pays `fee` satoshis, returning the hashes of the transactions evicted to
+    /// keep within the byte budget. The new transaction may itself be evicted if its fee rate is too low.
+    ///
+    /// Adding a transaction that is already present does nothing. Returns [Error::MempoolConflict] if the
+    /// transaction spends an outpoint that is spent by a transaction already in the pool.
+    pub fn insert(&self, tx: Tx, fee: u64) -> Result<Vec<TxHash>> {
+        let hash = tx.hash();
+        let size = tx.async_size();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.contains_key(&hash) {
+            return Ok(Vec::new());
+        }
+        if let Some(other) = tx
+            .inputs
+            .iter()
+            .find_map(|i| inner.spent_by.get(&i.outpoint))
+        {
+            return Err(Error::MempoolConflict(*other));
+        }
+        for input in tx.inputs.iter() {
+            inner.spent_by.insert(input.outpoint.clone(), hash);
+        }
+        inner.by_rate.insert(RateKey { fee, size, hash });
+        inner.entries.insert(hash, Entry { tx, fee, size });
+        inner.bytes += size;
+
+        let mut evicted = Vec::new();
+        while inner.bytes > self.max_bytes {
+            let lowest = inner.by_rate.first().unwrap().hash;
+            inner.remove_with_descendants(&lowest, &mut evicted);
+        }
+        Ok(evicted)
+    }
+
+    /// The hashes of up to `limit` transactions, highest fee rate first.
+    pub fn hashes_by_fee_rate(&self, limit: usize) -> Vec<TxHash> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .by_rate
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|k| k.hash)
+            .collect()
+    }
+
+    /// Get a transaction by its hash.
+    pub fn get(&self, hash: &TxHash) -> Option<Tx> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(hash).map(|e| e.tx.clone())
+    }
+
+    /// Whether the transaction is in the pool.
+    pub fn contains(&self, hash: &TxHash) -> bool {
+        self.inner.lock().unwrap().entries.contains_key(hash)
+    }
+
+    /// The fee paid by the transaction, if it is in the pool.
+    pub fn fee(&self, hash: &TxHash) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.get(hash).map(|e| e.fee)
+    }
+
+    /// The transaction in the pool that spends the outpoint, if any.
+    pub fn spender(&self, outpoint: &Outpoint) -> Option<TxHash> {
+        self.inner.lock().unwrap().spent_by.get(outpoint).copied()
+    }
+
+    /// The transactions in the pool that spend any of the outpoints spent by `tx`, other than `tx` itself.
+    pub fn conflicts(&self, tx: &Tx) -> Vec<TxHash> {
+        let hash = tx.hash();
+        let inner = self.inner.lock().unwrap();
+        let mut conflicts: Vec<TxHash> = tx
+            .inputs
+            .iter()
+            .filter_map(|i| inner.spent_by.get(&i.outpoint))
+            .filter(|h| **h != hash)
+            .copied()
+            .collect();
+        conflicts.sort();
+        conflicts.dedup();
+        conflicts
+    }
+
+    /// Remove a transaction and the transactions in the pool that depend on it, returning their hashes.
+    pub fn remove(&self, hash: &TxHash) -> Vec<TxHash> {
+        let mut removed = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove_with_descendants(hash, &mut removed);
+        removed
+    }
+
+    /// Remove the transactions that have been confirmed, returning the hashes of those that were in the pool.
+    ///
+    /// Transactions that spend the outputs of confirmed transactions remain in the pool.
+    pub fn remove_confirmed<'a>(&self, txids: impl IntoIterator<Item = &'a TxHash>) -> Vec<TxHash> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut removed = Vec::new();
+        for hash in txids {
+            let Some(entry) = inner.entries.remove(hash) else {
+                continue;
+            };
+            inner.by_rate.remove(&RateKey {
+                fee: entry.fee,
+                size: entry.size,
+                hash: *hash,
+            });
+            inner.bytes -= entry.size;
+            for input in entry.tx.inputs.iter() {
+                inner.spent_by.remove(&input.outpoint);
+            }
+            removed.push(*hash);
+        }
+        removed
+    }
+
+    /// Remove the transactions confirmed by the block, and those that conflict with the transactions in
+    /// the block together with their descendants. Returns the hashes of all of the removed transactions.
+    pub fn remove_block(&self, block: &Block) -> Vec<TxHash> {
+        let txids: Vec<TxHash> = block.transactions.iter().map(|t| t.hash()).collect();
+        let mut removed = self.remove_confirmed(txids.iter());
+        let mut inner = self.inner.lock().unwrap();
+        for tx in block.transactions.iter() {
+            for input in tx.inputs.iter() {
+                if let Some(conflict) = inner.spent_by.get(&input.outpoint).copied() {
+                    inner.remove_with_descendants(&conflict, &mut removed);
+                }
+            }
+        }
+        removed
+    }
+
+    /// The number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Whether the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The total serialized size of the transactions in the pool.
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+}
+
+/// Verify the signatures of the inputs that spend P2PKH outputs, returning the indexes of the other inputs.
+fn verify_p2pkh_inputs(
+    tx: &Tx,
+    spent: &[TxOutput],
+) -> std::result::Result<Vec<usize>, RejectReason> {
+    let cache = SighashCache::new(tx);
+    let mut unverified = Vec::new();
+    let mut items = Vec::new();
+    for (index, (input, output)) in tx.inputs.iter().zip(spent.iter()).enumerate() {
+        let Some(pubkey_hash) = output.script.p2pkh_hash() else {
+            unverified.push(index);
+            continue;
+        };
+        let failed = RejectReason::ScriptFailed { index };
+        let (ops, _) = input.script.decode().map_err(|_| failed.clone())?;
+        let [Operation::OP_PUSH(sig), Operation::OP_PUSH(pubkey)] = &ops[..] else {
+            return Err(failed);
+        };
+        let sig = sig.get_bytes();
+        let pubkey = pubkey.get_bytes();
+        if sig.is_empty() || Hash160::generate(&pubkey).hash[..] != *pubkey_hash {
+            return Err(failed);
+        }
+        let (der, sighash_type) = sig.split_at(sig.len() - 1);
+        let sighash = cache
+            .sighash_for_input(index, &output.script, output.value, sighash_type[0])
+            .map_err(|_| failed.clone())?;
+        items.push((
+            index,
+            SigCheckItem {
+                signature: der.to_vec(),
+                pubkey: pubkey.to_vec(),
+                sighash,
+            },
+        ));
+    }
+    let checks: Vec<SigCheckItem> = items.iter().map(|(_, item)| item.clone()).collect();
+    let results =
+        verify_signatures_batch(&checks).map_err(|e| RejectReason::Invalid(e.to_string()))?;
+    if let Some((index, _)) = items
+        .iter()
+        .zip(results)
+        .find(|(_, ok)| !ok)
+        .map(|(i, _)| i)
+    {
+        return Err(RejectReason::ScriptFailed { index: *index });
+    }
+    Ok(unverified)
+}
+
+#[cfg(feature = "p2p")]
+impl TxProvider for SimpleMempool {
+    /// The hashes of the transactions with the highest fee rates.
+    fn mempool_hashes(&self, limit: usize) -> Vec<TxHash> {
+        self.hashes_by_fee_rate(limit)
+    }
+
+    fn get_tx(&self, hash: &TxHash) -> Option<Tx> {
+        self.get(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{
+        Address, BlockHeader, BlockchainId, Hash, KeyAddressKind, KeyPair, PrivateKey, PublicKey,
+        Script, TxInput, SIGHASH_ALL, SIGHASH_FORKID,
+    };
+    use secp256k1::{Message, Secp256k1};
+
+    /// A transaction that spends the given outputs of `parent` and has `outputs` outputs.
+    fn spend(parent: TxHash, indexes: &[u32], outputs: usize) -> Tx {
+        Tx {
+            version: 1,
+            inputs: indexes
+                .iter()
+                .map(|i| TxInput::new(parent, *i, Script::from(vec![1; 50]), None))
+                .collect(),
+            outputs: (0..outputs)
+                .map(|_| TxOutput::new(1000, Script::from(vec![0x51])))
+                .collect(),
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        let pool = SimpleMempool::new(1_000_000);
+        let funding = Hash::sha256d(b"funding");
+        let a = spend(funding, &[0, 1], 1);
+        pool.insert(a.clone(), 500).unwrap();
+        assert_eq!(pool.get(&a.hash()), Some(a.clone()));
+        assert_eq!(pool.fee(&a.hash()), Some(500));
+        // inserting again does nothing
+        assert!(pool.insert(a.clone(), 500).unwrap().is_empty());
+        assert_eq!(pool.len(), 1);
+        assert!(pool.conflicts(&a).is_empty());
+
+        // spends output 1 again
+        let b = spend(funding, &[1, 2], 1);
+        assert_eq!(pool.conflicts(&b), vec![a.hash()]);
+        match pool.insert(b.clone(), 5_000) {
+            Err(Error::MempoolConflict(h)) => assert_eq!(h, a.hash()),
+            r => panic!("expected a conflict, got {:?}", r),
+        }
+        assert!(!pool.contains(&b.hash()));
+        let outpoint = Outpoint {
+            tx_hash: funding,
+            index: 2,
+        };
+        assert_eq!(pool.spender(&outpoint), None);
+
+        // once a is removed, b can be added
+        assert_eq!(pool.remove(&a.hash()), vec![a.hash()]);
+        pool.insert(b.clone(), 5_000).unwrap();
+        assert_eq!(pool.spender(&outpoint), Some(b.hash()));
+        assert_eq!(pool.bytes(), b.async_size());
+    }
+
+    #[test]
+    fn eviction() {
+        let size = spend(Hash::ZERO, &[0], 1).async_size();
+        let pool = SimpleMempool::new(size * 3);
+        let txs: Vec<Tx> = (0..4)
+            .map(|n| spend(Hash::sha256d(&[n]), &[0], 1))
+            .collect();
+        for (n, tx) in txs.iter().take(3).enumerate() {
+            assert!(pool
+                .insert(tx.clone(), [300, 100, 200][n])
+                .unwrap()
+                .is_empty());
+        }
+        assert_eq!(
+            pool.hashes_by_fee_rate(10),
+            vec![txs[0].hash(), txs[2].hash(), txs[1].hash()]
+        );
+        // the lowest fee rate goes first
+        assert_eq!(
+            pool.insert(txs[3].clone(), 150).unwrap(),
+            vec![txs[1].hash()]
+        );
+        assert_eq!(pool.bytes(), size * 3);
+        // a new transaction with a lower fee rate than everything else is not kept
+        let cheap = spend(Hash::sha256d(b"cheap"), &[0], 1);
+        assert_eq!(pool.insert(cheap.clone(), 1).unwrap(), vec![cheap.hash()]);
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.get(&cheap.hash()), None);
+    }
+
+    #[test]
+    fn evict_chain() {
+        let pool = SimpleMempool::new(10_000);
+        // a chain of four where the parent pays the lowest fee rate
+        let mut chain = vec![spend(Hash::sha256d(b"chain"), &[0], 2)];
+        for _ in 0..3 {
+            chain.push(spend(chain.last().unwrap().hash(), &[0], 2));
+        }
+        // and a second child of the parent
+        let sibling = spend(chain[0].hash(), &[1], 1);
+        let other = spend(Hash::sha256d(b"other"), &[0], 1);
+        pool.insert(chain[0].clone(), 10).unwrap();
+        for tx in chain.iter().skip(1) {
+            pool.insert(tx.clone(), 1_000).unwrap();
+        }
+        pool.insert(sibling.clone(), 1_000).unwrap();
+        pool.insert(other.clone(), 500).unwrap();
+        assert_eq!(pool.len(), 6);
+
+        let pool = SimpleMempool {
+            max_bytes: pool.bytes() - 1,
+            ..pool
+        };
+        let big = spend(Hash::sha256d(b"big"), &[0], 1);
+        let mut evicted = pool.insert(big.clone(), 2_000).unwrap();
+        evicted.sort();
+        let mut expected: Vec<TxHash> = chain.iter().map(|t| t.hash()).collect();
+        expected.push(sibling.hash());
+        expected.sort();
+        assert_eq!(evicted, expected);
+        assert_eq!(pool.len(), 2);
+        assert!(pool.contains(&other.hash()));
+        assert!(pool.contains(&big.hash()));
+        assert_eq!(pool.bytes(), other.async_size() + big.async_size());
+        assert_eq!(pool.spender(&chain[1].inputs[0].outpoint), None);
+    }
+
+    #[test]
+    fn remove_block() {
+        let pool = SimpleMempool::new(1_000_000);
+        let parent = spend(Hash::sha256d(b"parent"), &[0], 1);
+        let child = spend(parent.hash(), &[0], 1);
+        let doomed = spend(Hash::sha256d(b"doomed"), &[0], 1);
+        let doomed_child = spend(doomed.hash(), &[0], 1);
+        let unrelated = spend(Hash::sha256d(b"unrelated"), &[0], 1);
+        for tx in [&parent, &child, &doomed, &doomed_child, &unrelated] {
+            pool.insert(tx.clone(), 1_000).unwrap();
+        }
+        // the block confirms the parent and double spends the input of doomed
+        let mut double_spend = spend(Hash::sha256d(b"doomed"), &[0], 1);
+        double_spend.lock_time = 1;
+        let block = Block {
+            header: BlockHeader::default(),
+            transactions: vec![parent.clone(), double_spend],
+        };
+        let mut removed = pool.remove_block(&block);
+        removed.sort();
+        let mut expected = vec![parent.hash(), doomed.hash(), doomed_child.hash()];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert_eq!(pool.len(), 2);
+        assert!(pool.contains(&child.hash()));
+        assert!(pool.contains(&unrelated.hash()));
+        assert_eq!(
+            pool.remove_confirmed([child.hash()].iter()),
+            vec![child.hash()]
+        );
+        assert_eq!(pool.bytes(), unrelated.async_size());
+    }
+    struct Wallet {
+        key: PrivateKey,
+        pubkey: PublicKey,
+    }
+
+    impl Wallet {
+        fn new(seed: u64) -> Wallet {
+            let keys = KeyPair::from_seed(seed, BlockchainId::Main);
+            Wallet {
+                key: keys.private_key,
+                pubkey: keys.public_key,
+            }
+        }
+
+        fn output(&self, value: u64) -> TxOutput {
+            let address = Address::from_pubkey(&self.pubkey, KeyAddressKind::Main);
+            TxOutput::new(value, address.locking_script())
+        }
+
+        /// A transaction that spends the outputs and pays `values` back to the wallet, signed.
+        fn pay(&self, spends: &[(Outpoint, TxOutput)], values: &[u64]) -> Tx {
+            let mut tx = Tx {
+                version: 1,
+                inputs: spends
+                    .iter()
+                    .map(|(o, _)| TxInput::new(o.tx_hash, o.index, Script::from(vec![]), None))
+                    .collect(),
+                outputs: values.iter().map(|v| self.output(*v)).collect(),
+                lock_time: 0,
+            };
+            let sighash_type = SIGHASH_ALL | SIGHASH_FORKID;
+            let secp = Secp256k1::new();
+            let mut scripts = Vec::new();
+            for (index, (_, spent)) in spends.iter().enumerate() {
+                let h = tx
+                    .sighash(index, &spent.script, spent.value, sighash_type)
+                    .unwrap();
+                let sig = secp.sign_ecdsa(&Message::from_digest(h.hash), &self.key.inner);
+                let mut sig = sig.serialize_der().to_vec();
+                sig.push(sighash_type);
+                let pubkey = self.pubkey.to_bytes();
+                let mut script = vec![sig.len() as u8];
+                script.extend(sig);
+                script.push(pubkey.len() as u8);
+                script.extend(pubkey);
+                scripts.push(Script::from(script));
+            }
+            for (input, script) in tx.inputs.iter_mut().zip(scripts) {
+                input.script = script;
+            }
+            tx
+        }
+    }
+
+    fn outpoint(tx: &Tx, index: u32) -> (Outpoint, TxOutput) {
+        let o = Outpoint {
+            tx_hash: tx.hash(),
+            index,
+        };
+        (o, tx.outputs[index as usize].clone())
+    }
+
+    /// A wallet and a confirmed output of 100,000 satoshis that it can spend.
+    fn funded() -> (Wallet, HashMap<Outpoint, TxOutput>, (Outpoint, TxOutput)) {
+        let wallet = Wallet::new(1);
+        let funding = (
+            Outpoint {
+                tx_hash: Hash::sha256d(b"funding"),
+                index: 0,
+            },
+            wallet.output(100_000),
+        );
+        let utxos = HashMap::from([funding.clone()]);
+        (wallet, utxos, funding)
+    }
+
+    #[test]
+    fn accept_chain() {
+        let (wallet, utxos, funding) = funded();
+        let pool = SimpleMempool::new(1_000_000);
+        let parent = wallet.pay(&[funding], &[60_000, 39_000]);
+        let child = wallet.pay(&[outpoint(&parent, 0)], &[59_500]);
+
+        let accepted = pool.accept(parent.clone(), &utxos).unwrap();
+        assert_eq!(accepted.hash, parent.hash());
+        assert_eq!(accepted.fee, 1_000);
+        assert_eq!(accepted.size, parent.async_size());
+        assert!(accepted.unverified_inputs.is_empty());
+        assert!(accepted.evicted.is_empty());
+        // the child spends an output of the parent, which is only in the mempool
+        let accepted = pool.accept(child.clone(), &utxos).unwrap();
+        assert_eq!(accepted.fee, 500);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.accept(child, &utxos), Err(RejectReason::AlreadyKnown));
+    }
+
+    #[test]
+    fn accept_orphan() {
+        let (wallet, utxos, funding) = funded();
+        let pool = SimpleMempool::new(1_000_000);
+        let parent = wallet.pay(&[funding], &[99_000]);
+        let child = wallet.pay(&[outpoint(&parent, 0)], &[98_000]);
+
+        let r = pool.accept(child.clone(), &utxos).unwrap_err();
+        assert_eq!(r, RejectReason::MissingInputs(vec![outpoint(&parent, 0).0]));
+        // orphans are not rejected, the parent may be on its way
+        assert_eq!(r.reject_code(), None);
+        #[cfg(feature = "p2p")]
+        assert!(r.to_reject(&child.hash()).is_none());
+        assert!(pool.is_empty());
+
+        pool.accept(parent, &utxos).unwrap();
+        pool.accept(child, &utxos).unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn accept_double_spend() {
+        let (wallet, utxos, funding) = funded();
+        let pool = SimpleMempool::new(1_000_000);
+        let first = wallet.pay(std::slice::from_ref(&funding), &[99_000]);
+        // pays a higher fee, but there is no replacement
+        let second = wallet.pay(&[funding], &[90_000]);
+        pool.accept(first.clone(), &utxos).unwrap();
+
+        let r = pool.accept(second.clone(), &utxos).unwrap_err();
+        assert_eq!(r, RejectReason::Conflict(first.hash()));
+        assert_eq!(r.reject_code(), Some(REJECT_DUPLICATE));
+        #[cfg(feature = "p2p")]
+        {
+            let reject = r.to_reject(&second.hash()).unwrap();
+            assert_eq!(reject.message, "tx");
+            assert_eq!(reject.code, REJECT_DUPLICATE);
+            assert_eq!(reject.reason, "txn-mempool-conflict");
+            assert_eq!(reject.data, second.hash().hash.to_vec());
+        }
+        assert!(pool.contains(&first.hash()));
+        assert!(!pool.contains(&second.hash()));
+    }
+
+    #[test]
+    fn accept_low_fee() {
+        let (wallet, utxos, funding) = funded();
+        let policy = AcceptPolicy {
+            min_fee_rate: FeeRate::from_sats_per_kb(1000),
+            ..Default::default()
+        };
+        let pool = SimpleMempool::with_policy(1_000_000, policy);
+        let tx = wallet.pay(std::slice::from_ref(&funding), &[99_990]);
+        let size = tx.async_size() as u64;
+        match pool.accept(tx, &utxos) {
+            Err(r @ RejectReason::InsufficientFee { fee, required }) => {
+                assert_eq!(fee, 10);
+                assert_eq!(required, size);
+                assert_eq!(r.reject_code(), Some(REJECT_INSUFFICIENT_FEE));
+            }
+            r => panic!("expected insufficient fee, got {:?}", r),
+        }
+        let tx = wallet.pay(std::slice::from_ref(&funding), &[100_001]);
+        assert_eq!(
+            pool.accept(tx, &utxos),
+            Err(RejectReason::InputsBelowOutputs {
+                inputs: 100_000,
+                outputs: 100_001
+            })
+        );
+        // signatures vary in length by a byte or two
+        let tx = wallet.pay(&[funding], &[100_000 - size - 2]);
+        assert_eq!(pool.accept(tx, &utxos).unwrap().fee, size + 2);
+    }
+
+    #[test]
+    fn accept_scripts() {
+        let (wallet, mut utxos, funding) = funded();
+        let pool = SimpleMempool::new(1_000_000);
+
+        // the signature does not cover the changed output
+        let mut tx = wallet.pay(std::slice::from_ref(&funding), &[99_000]);
+        tx.outputs[0].value = 98_000;
+        let r = pool.accept(tx, &utxos).unwrap_err();
+        assert_eq!(r, RejectReason::ScriptFailed { index: 0 });
+        assert_eq!(r.reject_code(), Some(REJECT_INVALID));
+        // signed by another key
+        let tx = Wallet::new(2).pay(std::slice::from_ref(&funding), &[99_000]);
+        assert_eq!(
+            pool.accept(tx, &utxos),
+            Err(RejectReason::ScriptFailed { index: 0 })
+        );
+        // not standard
+        let tx = wallet.pay(std::slice::from_ref(&funding), &[0]);
+        let r = pool.accept(tx, &utxos).unwrap_err();
+        assert!(matches!(r, RejectReason::NonStandard(_)), "{:?}", r);
+        assert_eq!(r.reject_code(), Some(REJECT_DUST));
+
+        // an output that is not P2PKH can not be verified
+        let bare = (
+            Outpoint {
+                tx_hash: Hash::sha256d(b"bare"),
+                index: 3,
+            },
+            TxOutput::new(50_000, Script::from(vec![0x51])),
+        );
+        utxos.insert(bare.0.clone(), bare.1.clone());
+        let mut tx = wallet.pay(&[funding.clone(), bare.clone()], &[149_000]);
+        tx.inputs[1].script = Script::from(vec![0x51]);
+        let r = pool.accept(tx.clone(), &utxos).unwrap_err();
+        assert_eq!(
+            r,
+            RejectReason::NonStandard(NonStandardReason::UnverifiableInput { index: 1 })
+        );
+        assert_eq!(r.reject_code(), Some(REJECT_NONSTANDARD));
+        // nor a P2PK output, so a spend with a bad signature is refused rather than holding the
+        // outpoint against a valid spend
+        let p2pk = (
+            Outpoint {
+                tx_hash: Hash::sha256d(b"p2pk"),
+                index: 0,
+            },
+            TxOutput::new(50_000, {
+                let mut script = vec![33];
+                script.extend(wallet.pubkey.to_bytes());
+                script.push(0xac);
+                Script::from(script)
+            }),
+        );
+        utxos.insert(p2pk.0.clone(), p2pk.1.clone());
+        let mut bad_sig = wallet.pay(&[p2pk], &[49_000]);
+        bad_sig.inputs[0].script = Script::from(vec![2, 0x30, 0x41]);
+        assert_eq!(
+            pool.accept(bad_sig, &utxos),
+            Err(RejectReason::NonStandard(
+                NonStandardReason::UnverifiableInput { index: 0 }
+            ))
+        );
+        assert!(pool.is_empty());
+        // unless the policy accepts them
+        let policy = AcceptPolicy {
+            accept_unverified_inputs: true,
+            ..Default::default()
+        };
+        let pool = SimpleMempool::with_policy(1_000_000, policy);
+        let accepted = pool.accept(tx.clone(), &utxos).unwrap();
+        assert_eq!(accepted.unverified_inputs, vec![1]);
+
+        // spends the same outpoint twice
+        let mut tx = tx;
+        tx.inputs[1] = tx.inputs[0].clone();
+        let r = pool.accept(tx, &utxos).unwrap_err();
+        assert!(matches!(r, RejectReason::Invalid(_)), "{:?}", r);
+        assert_eq!(r.reject_reason(), "bad-txns");
+    }
+}
@@ -3,25 +3,68 @@
 mod address;
 mod base58ck;
 mod block;
+mod block_template;
+mod coinbase;
 mod crypto;
+mod debug;
 mod encoding;
 mod hash;
 mod hash160;
 mod header;
+mod lazy_block;
+mod mempool;
 mod params;
+mod policy;
+mod reject_code;
 mod rules;
 mod script;
+mod sig_check;
+mod sighash;
+mod signed_message;
+mod spv;
 mod tx;
 mod var_int;
+mod work;
 
-pub use self::address::Address;
-pub use self::block::FullBlockStream;
-pub use self::crypto::{PrivateKey, PublicKey};
+pub use self::address::{Address, KeyPair};
+pub use self::block::{
+    Block, BlockFileReader, BlockSizeBreakdown, BlockValidationError, FullBlockStream,
+};
+pub use self::block_template::{block_subsidy, BlockTemplate, BlockTemplateBuilder};
+pub use self::coinbase::{CoinbaseBuilder, CoinbaseInfo};
+pub use self::crypto::{compact_is_compressed, PrivateKey, PublicKey};
+pub use self::debug::FullDebug;
 pub use self::encoding::{AsyncEncodable, Encodable};
+#[cfg(all(test, feature = "p2p"))]
+pub(crate) use self::encoding::{TrickleReader, TrickleWriter};
 pub use self::hash::Hash;
-pub use self::header::{BlockHash, BlockHeader, MerkleRoot};
+pub use self::header::{merkle_root, merkle_root_checked, BlockHash, BlockHeader, MerkleRoot};
+pub use self::lazy_block::LazyBlock;
+pub use self::mempool::{AcceptPolicy, Accepted, RejectReason, SimpleMempool, UtxoProvider};
 pub use self::params::{BlockchainId, KeyAddressKind};
-pub use self::script::*;
-pub use self::tx::{Outpoint, Tx, TxBuilder, TxHash, TxInput, TxOutput};
-pub use self::var_int::{varint_decode, varint_encode, varint_size};
+pub use self::policy::{NonStandardReason, StandardnessPolicy};
+pub use self::reject_code::{
+    REJECT_CHECKPOINT, REJECT_DUPLICATE, REJECT_DUST, REJECT_INSUFFICIENT_FEE, REJECT_INVALID,
+    REJECT_MALFORMED, REJECT_NONSTANDARD, REJECT_OBSOLETE,
+};
+pub use self::script::{ByteSequence, Operation, Script, ScriptBuilder, ScriptTemplate};
+pub use self::sig_check::{verify_signature, verify_signatures_batch, SigCheckItem};
+pub use self::sighash::{
+    SighashCache, SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_FORKID, SIGHASH_NONE, SIGHASH_SINGLE,
+};
+pub use self::signed_message::{
+    sign_message, signed_message_hash, verify_message, SIGNED_MESSAGE_MAGIC,
+};
+pub use self::spv::{
+    build_merkle_proof, verify_merkle_proof, verify_tx_inclusion, verify_txid_inclusion,
+    MerkleProof,
+};
+pub use self::tx::{
+    scan_tx, CoinSelection, Outpoint, Tx, TxBuilder, TxHash, TxInput, TxOutput, TxSizeBreakdown,
+    TxSummary,
+};
+#[cfg(feature = "p2p")]
+pub(crate) use self::var_int::varstr_decode;
+pub use self::var_int::{varint_decode, varint_encode, varint_read, varint_size, varint_write};
+pub use self::work::ChainWork;
 pub use hex::{FromHex, ToHex};
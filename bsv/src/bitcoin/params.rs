@@ -33,6 +33,18 @@ impl From<&str> for BlockchainId {
     }
 }
 
+impl BlockchainId {
+    /// The magic bytes that start P2P messages and the records in block files.
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            BlockchainId::Main => [0xe3, 0xe1, 0xf3, 0xe8],
+            BlockchainId::Test => [0xf4, 0xe5, 0xf3, 0xf4],
+            BlockchainId::Regtest => [0xda, 0xb5, 0xbf, 0xfa],
+            BlockchainId::Stn => [0xfb, 0xce, 0xc4, 0xf9],
+        }
+    }
+}
+
 /// KeyAddressKind enables us to differentiate whether a Key or Address is for the
 /// production blockchain (mainnet) or whether it is for a test blockchain.
 ///
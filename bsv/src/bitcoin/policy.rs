@@ -0,0 +1,72 @@
+use crate::bitcoin::rules::MAX_TX_SIZE;
+use std::fmt;
+
+/// The policy values used to decide whether a transaction is standard.
+///
+/// Nodes will only relay and mine standard transactions. Checking a transaction against these values
+/// before it is broadcast avoids having it silently dropped by the network. The default values are the
+/// default policy values of the Bitcoin SV node software.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StandardnessPolicy {
+    /// The maximum size of a standard transaction.
+    pub max_tx_size: u64,
+    /// Outputs with a value less than this number of satoshis are considered to be dust.
+    ///
+    /// Data carrier (OP_RETURN) outputs are never considered to be dust.
+    pub dust_limit: u64,
+    /// The maximum size of the script of a data carrier (OP_RETURN) output.
+    pub max_op_return_size: u64,
+}
+
+impl Default for StandardnessPolicy {
+    fn default() -> Self {
+        StandardnessPolicy {
+            max_tx_size: MAX_TX_SIZE(true),
+            dust_limit: 1,
+            max_op_return_size: MAX_TX_SIZE(true),
+        }
+    }
+}
+
+/// The reason that a transaction is not standard.
+///
+/// Where the reason relates to a specific input or output, its index is included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonStandardReason {
+    /// The transaction is larger than the maximum standard size.
+    TxTooLarge { size: u64, max: u64 },
+    /// The unlocking script of the input contains operations other than data pushes.
+    InputNotPushOnly { index: usize },
+    /// The value of the output is less than the dust limit.
+    DustOutput { index: usize, value: u64 },
+    /// The script of the data carrier (OP_RETURN) output is too large.
+    OpReturnTooLarge { index: usize, size: u64, max: u64 },
+    /// The input spends an output whose script can not be verified, see
+    /// [AcceptPolicy::accept_unverified_inputs](crate::bitcoin::AcceptPolicy::accept_unverified_inputs).
+    UnverifiableInput { index: usize },
+}
+
+impl fmt::Display for NonStandardReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use NonStandardReason::*;
+        match self {
+            TxTooLarge { size, max } => write!(f, "tx size {} exceeds maximum {}", size, max),
+            InputNotPushOnly { index } => write!(f, "input {} script is not push-only", index),
+            DustOutput { index, value } => write!(f, "output {} value {} is dust", index, value),
+            OpReturnTooLarge { index, size, max } => write!(
+                f,
+                "output {} data carrier size {} exceeds maximum {}",
+                index, size, max
+            ),
+            UnverifiableInput { index } => {
+                write!(
+                    f,
+                    "input {} spends a script that can not be verified",
+                    index
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for NonStandardReason {}
@@ -0,0 +1,16 @@
+//! The codes that classify why a transaction or block was rejected.
+//!
+//! These are the codes of the `reject` message of the P2P protocol, see [BIP61]. They are also used
+//! by [RejectReason](crate::bitcoin::RejectReason), so they do not depend on the `p2p` feature.
+//!
+//! [BIP61]: https://github.com/bitcoin/bips/blob/master/bip-0061.mediawiki
+
+// Message rejection error codes
+pub const REJECT_MALFORMED: u8 = 0x01;
+pub const REJECT_INVALID: u8 = 0x10;
+pub const REJECT_OBSOLETE: u8 = 0x11;
+pub const REJECT_DUPLICATE: u8 = 0x12;
+pub const REJECT_NONSTANDARD: u8 = 0x40;
+pub const REJECT_DUST: u8 = 0x41;
+pub const REJECT_INSUFFICIENT_FEE: u8 = 0x42;
+pub const REJECT_CHECKPOINT: u8 = 0x43;
@@ -130,3 +130,6 @@ pub fn MAX_NUMERIC_LEN(policy: bool) -> u64 {
         CRULE_MAX_NUMERIC_LEN.load(Ordering::Relaxed)
     }
 }
+
+/// Consensus Rule - the maximum number of satoshis in existence, no output or total of outputs may exceed it.
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
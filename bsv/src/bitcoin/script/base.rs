@@ -1,26 +1,62 @@
+use crate::bitcoin::rules::MAX_TX_SIZE;
 use crate::bitcoin::script::byte_seq::ByteSequence;
 use crate::bitcoin::script::Operation;
 use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable, Encodable};
-use crate::Error::DataTooSmall;
+use crate::Error::{DataTooLarge, DataTooSmall};
 use crate::Result;
 use async_trait::async_trait;
 use bytes::{Buf, Bytes};
 use hex::FromHex;
 use serde::{Deserialize, Serialize};
+use std::cmp::min;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// Bitcoin Scripts are used to lock and unlock outputs.
 ///
 /// This struct is a Script in its encoded form and is read-only. Use [decode()]
 /// to examine a script or [ScriptBuilder] to build a script.
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+///
+/// The bytes are reference counted, so cloning a script does not copy them. Scripts are equal, and
+/// hash the same, when their bytes are equal. Scripts that decode to operations that are aliases of
+/// each other, such as OP_0 and OP_FALSE, are equal because they are encoded the same, but a push that
+/// is encoded in two different ways is two different scripts.
+#[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Script {
     pub raw: Bytes,
 }
 
 impl Script {
+    // maximum number of bytes to allocate for a script before they have been read
+    const MAX_PREALLOCATE: usize = 64 * 1024;
+
+    /// The encoded script.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The number of bytes in the encoded script.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Returns true if the script has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
     /// Decode the script, producing a vector of operations and possibly a byte sequence of trailing data.
     pub fn decode(&self) -> Result<(Vec<Operation>, Option<ByteSequence>)> {
+        self.decode_limited(usize::MAX)
+    }
+
+    /// Decode the script, failing with [ElementTooLarge](crate::Error::ElementTooLarge) if it contains
+    /// a data push larger than `max_push_size` bytes.
+    ///
+    /// Trailing data after an OP_RETURN is not a data push and is not limited.
+    pub fn decode_limited(
+        &self,
+        max_push_size: usize,
+    ) -> Result<(Vec<Operation>, Option<ByteSequence>)> {
         use Operation::*;
 
         let mut result = Vec::new();
@@ -28,7 +64,7 @@ impl Script {
         let mut trailing = None;
         let mut if_depth = 0;
         while buf.has_remaining() {
-            let o = Operation::from_binary(&mut buf)?;
+            let o = Operation::from_binary_limited(&mut buf, max_push_size)?;
             match o {
                 OP_IF | OP_NOTIF => {
                     if_depth += 1;
@@ -49,6 +85,51 @@ impl Script {
         }
         Ok((result, trailing))
     }
+
+    /// Returns true if the script contains only data push operations.
+    ///
+    /// Policy requires the unlocking scripts of transaction inputs to be push-only. A script that can not
+    /// be decoded is not push-only.
+    pub fn is_push_only(&self) -> bool {
+        let mut buf = self.raw.clone();
+        while buf.has_remaining() {
+            match Operation::from_binary(&mut buf) {
+                Ok(o) if o.is_data_push() => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Count the signature operations in the script, using the legacy method.
+    ///
+    /// OP_CHECKSIG and OP_CHECKSIGVERIFY count as one and OP_CHECKMULTISIG and OP_CHECKMULTISIGVERIFY
+    /// count as 20. Counting stops at the first operation that can not be decoded.
+    pub fn sigop_count(&self) -> u64 {
+        use Operation::*;
+        let mut count = 0;
+        let mut buf = self.raw.clone();
+        while buf.has_remaining() {
+            match Operation::from_binary(&mut buf) {
+                Ok(OP_CHECKSIG | OP_CHECKSIGVERIFY) => count += 1,
+                Ok(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY) => count += 20,
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        count
+    }
+
+    /// Returns true if this is a data carrier script, i.e. it starts with OP_RETURN or OP_FALSE OP_RETURN.
+    ///
+    /// Outputs with these scripts are provably unspendable.
+    pub fn is_op_return(&self) -> bool {
+        match self.raw.first() {
+            Some(0x6a) => true,
+            Some(0x00) => self.raw.get(1) == Some(&0x6a),
+            _ => false,
+        }
+    }
 }
 
 impl From<Vec<u8>> for Script {
@@ -59,6 +140,12 @@ impl From<Vec<u8>> for Script {
     }
 }
 
+impl From<Bytes> for Script {
+    fn from(value: Bytes) -> Self {
+        Self { raw: value }
+    }
+}
+
 impl FromHex for Script {
     type Error = crate::Error;
 
@@ -81,9 +168,12 @@ impl AsyncEncodable for Script {
         Self: Sized,
     {
         let size = varint_decode(reader).await?;
-        // todo: check size is not too big
-        let mut buffer = vec![0u8; size as usize];
-        let i = reader.read_exact(&mut buffer).await?;
+        if size > MAX_TX_SIZE(false) {
+            return Err(DataTooLarge);
+        }
+        // the size is not trusted, only allocate space as the data arrives
+        let mut buffer = Vec::with_capacity(min(size as usize, Script::MAX_PREALLOCATE));
+        let i = reader.take(size).read_to_end(&mut buffer).await?;
         if i != (size as usize) {
             Err(DataTooSmall)
         } else {
@@ -97,7 +187,7 @@ impl AsyncEncodable for Script {
     ///
     /// A script is always encoded with its size.
     async fn async_to_binary<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
-        varint_encode(writer, self.raw.len() as u64).await?;
+        varint_encode(writer, self.len() as u64).await?;
         writer.write_all(&self.raw).await?;
         Ok(())
     }
@@ -106,7 +196,7 @@ impl AsyncEncodable for Script {
     ///
     /// The size is the number of bytes plus the number of bytes needed to encode its size.
     fn async_size(&self) -> usize {
-        let l = self.raw.len();
+        let l = self.len();
         varint_size(l as u64) + l
     }
 }
@@ -143,5 +233,74 @@ mod tests {
         assert_eq!(2, ops.len());
         assert!(trailing.is_some());
         assert_eq!(trailing.unwrap().len(), 50);
+        assert!(s.is_op_return());
+        assert!(!s.is_push_only());
+    }
+
+    #[test]
+    fn test_push_only() {
+        // unlocking script from input 0 of tx 60dcda63c57420077d67e3ae6684a1654cf9f9cc1b8edd569a847f2b5109b739
+        let s = Script::from_hex("47304402207df65c96172de240e6232daeeeccccf8655cb4aba38d968f784e34c6cc047cd30220078216eefaddb915ce55170348c3363d013693c543517ad59188901a0e7f8e50412103be56e90fb443f554140e8d260d7214c3b330cfb7da83b3dd5624f85578497841").unwrap();
+        assert!(s.is_push_only());
+        assert!(!s.is_op_return());
+        // OP_0 OP_16 OP_1NEGATE OP_PUSHDATA1
+        assert!(Script::from_hex("00604f4c0201ff").unwrap().is_push_only());
+        // a P2PKH locking script is not push-only
+        let p2pkh = Script::from_hex("76a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac").unwrap();
+        assert!(!p2pkh.is_push_only());
+        // truncated push
+        assert!(!Script::from_hex("4c05ff").unwrap().is_push_only());
+        assert_eq!(p2pkh.sigop_count(), 1);
+        // OP_1 OP_1 OP_CHECKMULTISIG OP_CHECKSIGVERIFY, then a truncated push
+        assert_eq!(Script::from_hex("5151aead4c05").unwrap().sigop_count(), 21);
+        assert!(Script::from(Vec::new()).is_push_only());
+    }
+
+    #[test]
+    fn byte_equality() {
+        use crate::bitcoin::{Operation::*, ScriptBuilder};
+        use bytes::Bytes;
+        use std::collections::HashSet;
+
+        // OP_0 and OP_FALSE are encoded the same, so the scripts are equal
+        let op_0 = ScriptBuilder::new()
+            .add(OP_0)
+            .add(OP_RETURN)
+            .build()
+            .unwrap();
+        let op_false = ScriptBuilder::new()
+            .add(OP_FALSE)
+            .add(OP_RETURN)
+            .build()
+            .unwrap();
+        assert_eq!(op_0, op_false);
+        assert_eq!(op_0.as_bytes(), &[0x00, 0x6a]);
+        // a push of the same data that is encoded differently is a different script
+        let direct = Script::from_hex("0101").unwrap();
+        let pushdata = Script::from_hex("4c0101").unwrap();
+        assert_ne!(direct, pushdata);
+        let set: HashSet<Script> = [op_0.clone(), op_false, direct, pushdata]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&Script::from(vec![0x00, 0x6a])));
+
+        // a clone shares the bytes of the original
+        let clone = op_0.clone();
+        assert_eq!(clone.as_bytes().as_ptr(), op_0.as_bytes().as_ptr());
+        assert_eq!(clone.len(), 2);
+        assert!(!clone.is_empty());
+        assert!(Script::from(Bytes::new()).is_empty());
+    }
+
+    #[test]
+    fn test_decode_limited() {
+        // OP_PUSHDATA1 of 5 bytes, OP_RETURN, then 8 bytes of trailing data
+        let s = Script::from_hex("4c0501020304056a0102030405060708").unwrap();
+        assert_eq!(s.decode_limited(5).unwrap().0.len(), 2);
+        assert!(matches!(
+            s.decode_limited(4),
+            Err(crate::Error::ElementTooLarge { size: 5, max: 4 })
+        ));
     }
 }
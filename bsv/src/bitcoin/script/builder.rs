@@ -41,12 +41,11 @@ impl ScriptBuilder {
             o.to_binary(&mut buffer)?;
             last_opreturn = *o == Operation::OP_RETURN;
         }
-        if self.trailing.is_some() {
-            let o = self.trailing.clone().unwrap();
+        if let Some(trailing) = &self.trailing {
             if !last_opreturn {
                 Operation::OP_RETURN.to_binary(&mut buffer)?;
             }
-            buffer.append(&mut o.to_vec());
+            buffer.extend_from_slice(trailing);
         }
         Ok(Script {
             raw: Bytes::from(buffer),
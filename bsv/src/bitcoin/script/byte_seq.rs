@@ -1,12 +1,11 @@
 use crate::{Error, Result};
 use bytes::Bytes;
-use num::{BigInt, ToPrimitive};
 
 /// A data value that is used in Bitcoin Script.
 ///
 /// The values in Bitcoin Script are sequences of bytes. These sequences may be interpreted as
 /// boolean or numeric values by some operations.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct ByteSequence {
     raw: Bytes,
 }
@@ -32,20 +31,68 @@ impl ByteSequence {
         self.len() <= 8
     }
 
-    /// Return the value as a small number (i64).
-    pub fn to_small_number(&self) -> Result<i64> {
-        if self.raw.len() > 8 {
-            Err(Error::DataTooLarge)
-        } else if self.raw.is_empty() {
-            Ok(0)
-        } else {
-            // Using bigint's so we can handle numerics with strange sizes such as 3 bytes
-            let i = BigInt::from_signed_bytes_le(&self.raw[..]);
-            match i.to_i64() {
-                None => Err(Error::DataTooLarge),
-                Some(val) => Ok(val),
-            }
+    /// Interpret the byte sequence as a Script number.
+    ///
+    /// Script numbers are little-endian with the sign in the most significant bit of the last byte,
+    /// so 0x81 is -1 and 0x80 is negative zero, which is zero. The empty sequence is zero. An encoding
+    /// is minimal if its last byte is needed, i.e. it is not 0x00 or 0x80 unless the sign bit of the
+    /// byte before it is set.
+    ///
+    /// Returns [Error::ElementTooLarge] if the sequence is longer than `max_len` bytes,
+    /// [Error::NonMinimalNumber] if `require_minimal` is set and the encoding is not minimal, and
+    /// [Error::DataTooLarge] if the value does not fit in an i64.
+    pub fn to_script_number(&self, max_len: usize, require_minimal: bool) -> Result<i64> {
+        let bytes = &self.raw[..];
+        if bytes.len() > max_len {
+            return Err(Error::ElementTooLarge {
+                size: bytes.len(),
+                max: max_len,
+            });
+        }
+        let Some((last, rest)) = bytes.split_last() else {
+            return Ok(0);
+        };
+        if require_minimal && last & 0x7f == 0 && rest.last().is_none_or(|b| b & 0x80 == 0) {
+            return Err(Error::NonMinimalNumber);
+        }
+        let negative = last & 0x80 != 0;
+        let mut magnitude: Vec<u8> = bytes.to_vec();
+        *magnitude.last_mut().unwrap() &= 0x7f;
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        if magnitude.len() > 8 {
+            return Err(Error::DataTooLarge);
+        }
+        let mut le = [0u8; 8];
+        le[..magnitude.len()].copy_from_slice(&magnitude);
+        let value = i64::try_from(u64::from_le_bytes(le)).map_err(|_| Error::DataTooLarge)?;
+        Ok(if negative { -value } else { value })
+    }
+
+    /// Encode a number as a minimally encoded Script number, see [ByteSequence::to_script_number].
+    ///
+    /// Returns [Error::DataTooLarge] for i64::MIN, whose magnitude needs 9 bytes and can not be read
+    /// back as an i64. Every other value encodes in at most 8 bytes.
+    pub fn from_script_number(value: i64) -> Result<ByteSequence> {
+        if value == i64::MIN {
+            return Err(Error::DataTooLarge);
         }
+        let mut bytes: Vec<u8> = value
+            .unsigned_abs()
+            .to_le_bytes()
+            .into_iter()
+            .rev()
+            .skip_while(|b| *b == 0)
+            .collect();
+        bytes.reverse();
+        match bytes.last() {
+            // the sign needs a byte of its own
+            Some(b) if b & 0x80 != 0 => bytes.push(if value < 0 { 0x80 } else { 0 }),
+            Some(_) if value < 0 => *bytes.last_mut().unwrap() |= 0x80,
+            _ => {}
+        }
+        Ok(ByteSequence::new(Bytes::from(bytes)))
     }
 }
 
@@ -53,64 +100,112 @@ impl ByteSequence {
 mod tests {
     use super::*;
 
-    /// Some tests that it evaluates small numbers correctly
-    #[test]
-    fn check_small_numbers() {
-        // null byte sequence
-        let i = ByteSequence::new(Bytes::from(vec![]));
-        assert_eq!(i.len(), 0);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), 0);
-
-        // zero
-        let i = ByteSequence::new(Bytes::from(vec![0u8]));
-        assert_eq!(i.len(), 1);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), 0);
-
-        // random single byte value
-        let i = ByteSequence::new(Bytes::from(vec![23u8]));
-        assert_eq!(i.len(), 1);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), 23);
-
-        // 2 byte value
-        let i = ByteSequence::new(Bytes::from(vec![1u8, 14u8]));
-        assert_eq!(i.len(), 2);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), 256 * 14 + 1);
-
-        // -1
-        let i = ByteSequence::new(Bytes::from(vec![255u8]));
-        assert_eq!(i.len(), 1);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), -1);
-
-        // too large
-        let i = ByteSequence::new(Bytes::from(vec![1u8, 2, 3, 4, 5, 6, 7, 8, 9]));
-        assert_eq!(i.len(), 9);
-        assert!(!i.is_small_num());
-        assert!(i.to_small_number().is_err());
+    fn num(bytes: &[u8], max_len: usize, require_minimal: bool) -> Result<i64> {
+        ByteSequence::new(Bytes::from(bytes.to_vec())).to_script_number(max_len, require_minimal)
+    }
 
-        // too large but has leading zero so it could actually resolve but is against rules
-        let i = ByteSequence::new(Bytes::from(vec![0u8, 2, 3, 4, 5, 6, 7, 8, 9]));
-        assert_eq!(i.len(), 9);
-        assert!(!i.is_small_num());
-        assert!(i.to_small_number().is_err());
+    /// Some tests that it evaluates numbers correctly
+    #[test]
+    fn check_script_numbers() {
+        let cases: Vec<(&[u8], i64)> = vec![
+            (&[], 0),
+            (&[1], 1),
+            (&[23], 23),
+            (&[0x7f], 127),
+            (&[0x80, 0x00], 128),
+            (&[1, 14], 256 * 14 + 1),
+            (&[0x81], -1),
+            (&[0xff], -127),
+            (&[0x80, 0x80], -128),
+            (&[1, 2, 3], ((3 * 256) + 2) * 256 + 1),
+            (&[0xff, 0xff, 0xff, 0x7f], i32::MAX as i64),
+            (&[0xff, 0xff, 0xff, 0xff], -(i32::MAX as i64)),
+            (&[0x00, 0x00, 0x00, 0x80, 0x00], 1 << 31),
+            (&[0x00, 0x00, 0x00, 0x80, 0x80], -(1 << 31)),
+            (&[0xff; 8], -i64::MAX),
+        ];
+        for (bytes, value) in cases {
+            assert_eq!(num(bytes, 8, true).unwrap(), value, "{:?}", bytes);
+            assert_eq!(
+                ByteSequence::from_script_number(value).unwrap().get_bytes(),
+                Bytes::from(bytes.to_vec()),
+                "{}",
+                value
+            );
+        }
+        let mut max = [0xff; 8];
+        max[7] = 0x7f;
+        assert_eq!(num(&max, 8, true).unwrap(), i64::MAX);
+    }
 
-        // 3 byte value
-        let i = ByteSequence::new(Bytes::from(vec![1u8, 2, 3]));
-        assert_eq!(i.len(), 3);
-        assert!(i.is_small_num());
-        assert_eq!(i.to_small_number().unwrap(), ((3 * 256) + 2) * 256 + 1);
+    #[test]
+    fn non_minimal_numbers() {
+        // zero and negative zero padded with 0x00 or 0x80
+        for bytes in [
+            &[0x00][..],
+            &[0x80],
+            &[0x00, 0x00],
+            &[0x00, 0x80],
+            &[0, 0, 0, 0x80],
+        ] {
+            assert!(matches!(num(bytes, 8, true), Err(Error::NonMinimalNumber)));
+            assert_eq!(num(bytes, 8, false).unwrap(), 0);
+        }
+        // a padding byte is needed when the sign bit of the previous byte is set
+        assert_eq!(num(&[0xff, 0x00], 8, true).unwrap(), 255);
+        assert_eq!(num(&[0xff, 0x80], 8, true).unwrap(), -255);
+        // but not otherwise
+        for (bytes, value) in [
+            (&[0x01, 0x00][..], 1),
+            (&[0x01, 0x80], -1),
+            (&[0x7f, 0x00, 0x00], 127),
+        ] {
+            assert!(matches!(num(bytes, 8, true), Err(Error::NonMinimalNumber)));
+            assert_eq!(num(bytes, 8, false).unwrap(), value);
+        }
+    }
 
-        // 8 byte value, no leading zero
-        let i = ByteSequence::new(Bytes::from(vec![1u8, 2, 3, 4, 5, 6, 7, 8]));
-        assert_eq!(i.len(), 8);
-        assert!(i.is_small_num());
+    #[test]
+    fn number_length_limits() {
+        // the 4 byte limit of the original rules and the 5 byte limit of some operations
+        let five = [0x00, 0x00, 0x00, 0x80, 0x00];
+        assert!(matches!(
+            num(&five, 4, true),
+            Err(Error::ElementTooLarge { size: 5, max: 4 })
+        ));
+        assert_eq!(num(&five, 5, true).unwrap(), 1 << 31);
         assert_eq!(
-            i.to_small_number().unwrap(),
-            (((((((8 * 256 + 7) * 256 + 6) * 256 + 5) * 256 + 4) * 256 + 3) * 256) + 2) * 256 + 1
+            num(&[0xff, 0xff, 0xff, 0x7f], 4, true).unwrap(),
+            i32::MAX as i64
         );
+        // a padded value longer than 8 bytes fits if it is not required to be minimal
+        let mut padded = vec![1u8];
+        padded.resize(12, 0);
+        assert_eq!(num(&padded, 12, false).unwrap(), 1);
+        assert!(matches!(
+            num(&padded, 12, true),
+            Err(Error::NonMinimalNumber)
+        ));
+        // values that do not fit in an i64
+        assert!(matches!(
+            num(&[0, 0, 0, 0, 0, 0, 0, 0x80, 0x00], 9, true),
+            Err(Error::DataTooLarge)
+        ));
+        assert!(matches!(
+            num(&[1, 2, 3, 4, 5, 6, 7, 8, 9], 9, false),
+            Err(Error::DataTooLarge)
+        ));
+        // i64::MIN would need 9 bytes, which can not be read back
+        assert!(matches!(
+            num(&[0, 0, 0, 0, 0, 0, 0, 0x80, 0x80], 9, true),
+            Err(Error::DataTooLarge)
+        ));
+        assert!(matches!(
+            ByteSequence::from_script_number(i64::MIN),
+            Err(Error::DataTooLarge)
+        ));
+        let min = ByteSequence::from_script_number(i64::MIN + 1).unwrap();
+        assert_eq!(min.len(), 8);
+        assert_eq!(min.to_script_number(8, true).unwrap(), i64::MIN + 1);
     }
 }
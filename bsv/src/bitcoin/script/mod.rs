@@ -2,8 +2,10 @@ mod base;
 mod builder;
 mod byte_seq;
 mod op;
+mod template;
 
-pub use base::*;
-pub use builder::*;
-pub use byte_seq::*;
-pub use op::*;
+pub use base::Script;
+pub use builder::ScriptBuilder;
+pub use byte_seq::ByteSequence;
+pub use op::Operation;
+pub use template::ScriptTemplate;
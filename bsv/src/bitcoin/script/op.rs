@@ -253,11 +253,16 @@ pub enum Operation {
 
 impl Operation {
     // helper function to get pushdata of a particular size from the buffer
-    fn get_pushdata(size: usize, buffer: &mut dyn Buf) -> Result<Bytes>
+    fn get_pushdata(size: usize, max_push_size: usize, buffer: &mut dyn Buf) -> Result<Bytes>
     where
         Self: Sized,
     {
-        if size > buffer.remaining() {
+        if size > max_push_size {
+            Err(Error::ElementTooLarge {
+                size,
+                max: max_push_size,
+            })
+        } else if size > buffer.remaining() {
             trace!(
                 "get_pushdata() - expected {} bytes but only have {} remaining",
                 size,
@@ -303,24 +308,24 @@ impl Operation {
     pub fn data_pushed(&self) -> Option<Bytes> {
         use Operation::*;
         match self {
-            OP_0 | OP_FALSE => Some(Bytes::from(&[0u8][..])),
-            OP_1 | OP_TRUE => Some(Bytes::from(&[1u8][..])),
-            OP_2 => Some(Bytes::from(&[2u8][..])),
-            OP_3 => Some(Bytes::from(&[3u8][..])),
-            OP_4 => Some(Bytes::from(&[4u8][..])),
-            OP_5 => Some(Bytes::from(&[5u8][..])),
-            OP_6 => Some(Bytes::from(&[6u8][..])),
-            OP_7 => Some(Bytes::from(&[7u8][..])),
-            OP_8 => Some(Bytes::from(&[8u8][..])),
-            OP_9 => Some(Bytes::from(&[9u8][..])),
-            OP_10 => Some(Bytes::from(&[10u8][..])),
-            OP_11 => Some(Bytes::from(&[11u8][..])),
-            OP_12 => Some(Bytes::from(&[12u8][..])),
-            OP_13 => Some(Bytes::from(&[13u8][..])),
-            OP_14 => Some(Bytes::from(&[14u8][..])),
-            OP_15 => Some(Bytes::from(&[15u8][..])),
-            OP_16 => Some(Bytes::from(&[16u8][..])),
-            OP_1NEGATE => Some(Bytes::from(&[255u8][..])),
+            OP_0 | OP_FALSE => Some(Bytes::from_static(&[0])),
+            OP_1 | OP_TRUE => Some(Bytes::from_static(&[1])),
+            OP_2 => Some(Bytes::from_static(&[2])),
+            OP_3 => Some(Bytes::from_static(&[3])),
+            OP_4 => Some(Bytes::from_static(&[4])),
+            OP_5 => Some(Bytes::from_static(&[5])),
+            OP_6 => Some(Bytes::from_static(&[6])),
+            OP_7 => Some(Bytes::from_static(&[7])),
+            OP_8 => Some(Bytes::from_static(&[8])),
+            OP_9 => Some(Bytes::from_static(&[9])),
+            OP_10 => Some(Bytes::from_static(&[10])),
+            OP_11 => Some(Bytes::from_static(&[11])),
+            OP_12 => Some(Bytes::from_static(&[12])),
+            OP_13 => Some(Bytes::from_static(&[13])),
+            OP_14 => Some(Bytes::from_static(&[14])),
+            OP_15 => Some(Bytes::from_static(&[15])),
+            OP_16 => Some(Bytes::from_static(&[16])),
+            OP_1NEGATE => Some(Bytes::from_static(&[255])),
             OP_PUSH(data) | OP_PUSHDATA1(data) | OP_PUSHDATA2(data) | OP_PUSHDATA4(data) => {
                 Some(data.get_bytes())
             }
@@ -330,8 +335,9 @@ impl Operation {
 
     /// Returns the number pushed to stack for pushdata operations as an i64.
     ///
-    /// If the operation does not push a value to the stack or the value  is too large to be represented
-    /// by an i64 then NONE is returned.
+    /// Pushed data is interpreted as a Script number of up to 8 bytes, which need not be minimally
+    /// encoded, see [ByteSequence::to_script_number]. If the operation does not push a value to the
+    /// stack or the value is too large to be represented by an i64 then NONE is returned.
     ///
     /// In comparison to the size of numbers supported by the Bitcoin rules, an i64 is small.
     /// See [rules::MAX_NUMERIC_LEN].
@@ -357,21 +363,18 @@ impl Operation {
             OP_16 => Some(16),
             OP_1NEGATE => Some(-1),
             OP_PUSH(data) | OP_PUSHDATA1(data) | OP_PUSHDATA2(data) | OP_PUSHDATA4(data) => {
-                match data.to_small_number() {
-                    Err(_) => None,
-                    Ok(val) => Some(val),
-                }
+                data.to_script_number(8, false).ok()
             }
             _ => None,
         }
     }
-}
 
-impl Encodable for Operation {
-    fn from_binary(buffer: &mut dyn Buf) -> Result<Self>
-    where
-        Self: Sized,
-    {
+    /// Decode an operation, rejecting data pushes larger than `max_push_size` bytes.
+    ///
+    /// The size is checked before the data is read, so a script with a hostile push is rejected without
+    /// allocating the data. See `crate::bitcoin::rules::MAX_BYTE_SEQ_LEN` for the policy and consensus
+    /// limits.
+    pub fn from_binary_limited(buffer: &mut dyn Buf, max_push_size: usize) -> Result<Self> {
         use Operation::*;
         match buffer.has_remaining() {
             false => Err(Error::DataTooSmall),
@@ -381,7 +384,9 @@ impl Encodable for Operation {
                     if buffer.has_remaining() {
                         let size = buffer.get_u8() as usize;
                         Ok(OP_PUSHDATA1(ByteSequence::new(Self::get_pushdata(
-                            size, buffer,
+                            size,
+                            max_push_size,
+                            buffer,
                         )?)))
                     } else {
                         Err(Error::DataTooSmall)
@@ -391,7 +396,9 @@ impl Encodable for Operation {
                     if buffer.remaining() >= 2 {
                         let size = buffer.get_u16_le() as usize;
                         Ok(OP_PUSHDATA2(ByteSequence::new(Self::get_pushdata(
-                            size, buffer,
+                            size,
+                            max_push_size,
+                            buffer,
                         )?)))
                     } else {
                         Err(Error::DataTooSmall)
@@ -401,7 +408,9 @@ impl Encodable for Operation {
                     if buffer.remaining() >= 4 {
                         let size = buffer.get_u32_le() as usize;
                         Ok(OP_PUSHDATA4(ByteSequence::new(Self::get_pushdata(
-                            size, buffer,
+                            size,
+                            max_push_size,
+                            buffer,
                         )?)))
                     } else {
                         Err(Error::DataTooSmall)
@@ -518,6 +527,7 @@ impl Encodable for Operation {
                     if other > 0 && other < 76 {
                         Ok(OP_PUSH(ByteSequence::new(Self::get_pushdata(
                             other as usize,
+                            max_push_size,
                             buffer,
                         )?)))
                     } else {
@@ -527,6 +537,15 @@ impl Encodable for Operation {
             },
         }
     }
+}
+
+impl Encodable for Operation {
+    fn from_binary(buffer: &mut dyn Buf) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::from_binary_limited(buffer, usize::MAX)
+    }
 
     fn to_binary(&self, buffer: &mut dyn BufMut) -> Result<()> {
         use Operation::*;
@@ -988,6 +1007,7 @@ impl Encodable for Operation {
 mod tests {
     use crate::bitcoin::script::Operation;
     use crate::bitcoin::Encodable;
+    use crate::Error;
     use bytes::BytesMut;
 
     /// Do a few simple read tests.
@@ -1032,6 +1052,34 @@ mod tests {
         }
     }
 
+    /// Pushes of exactly the limit are decoded, one byte over is rejected, for each push encoding.
+    #[test]
+    fn push_size_limit() {
+        for (prefix, size) in [
+            (vec![10u8], 10usize),
+            (vec![76u8, 100], 100),
+            (vec![77u8, 0x2c, 0x01], 300),
+            (vec![78u8, 0x70, 0x11, 0x01, 0x00], 70_000),
+        ] {
+            let mut b = prefix.clone();
+            b.resize(prefix.len() + size, 0xab);
+            let o = Operation::from_binary_limited(&mut b.as_slice(), size).unwrap();
+            assert_eq!(o.data_pushed().unwrap().len(), size);
+            match Operation::from_binary_limited(&mut b.as_slice(), size - 1) {
+                Err(Error::ElementTooLarge { size: s, max }) => {
+                    assert_eq!((s, max), (size, size - 1))
+                }
+                r => panic!("expected ElementTooLarge, got {:?}", r),
+            }
+        }
+        // the size is checked before the data, a huge push with no data is still too large
+        let mut b: &[u8] = &[78u8, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(
+            Operation::from_binary_limited(&mut b, 1_000_000),
+            Err(Error::ElementTooLarge { .. })
+        ));
+    }
+
     /// OP_0 and OP_FALSE are the same thing, same for OP_1 and OP_TRUE
     #[test]
     fn test_equality() {
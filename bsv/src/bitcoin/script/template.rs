@@ -0,0 +1,101 @@
+use crate::bitcoin::Script;
+use std::fmt;
+
+/// The standard forms of locking script.
+///
+/// A script is matched against a template by looking at its encoded bytes, without decoding it into
+/// operations, so that large numbers of scripts can be classified cheaply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptTemplate {
+    /// Pay to public key hash, `OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG`.
+    P2pkh,
+    /// Pay to public key, `<33 or 65 bytes> OP_CHECKSIG`.
+    P2pk,
+    /// A data carrier, see [Script::is_op_return()].
+    OpReturn,
+}
+
+impl ScriptTemplate {
+    /// Every template, in the order they are tried by [ScriptTemplate::classify()].
+    pub const ALL: [ScriptTemplate; 3] = [
+        ScriptTemplate::P2pkh,
+        ScriptTemplate::P2pk,
+        ScriptTemplate::OpReturn,
+    ];
+
+    /// Returns true if the script has the form of this template.
+    pub fn matches(&self, script: &Script) -> bool {
+        let raw = &script.raw[..];
+        match self {
+            ScriptTemplate::P2pkh => {
+                raw.len() == 25 && raw[..3] == [0x76, 0xa9, 0x14] && raw[23..] == [0x88, 0xac]
+            }
+            ScriptTemplate::P2pk => match raw.first() {
+                Some(&n @ (33 | 65)) => raw.len() == n as usize + 2 && raw[n as usize + 1] == 0xac,
+                _ => false,
+            },
+            ScriptTemplate::OpReturn => script.is_op_return(),
+        }
+    }
+
+    /// The template that the script matches, if any.
+    pub fn classify(script: &Script) -> Option<ScriptTemplate> {
+        Self::ALL.into_iter().find(|t| t.matches(script))
+    }
+}
+
+impl fmt::Display for ScriptTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ScriptTemplate::P2pkh => "P2PKH",
+            ScriptTemplate::P2pk => "P2PK",
+            ScriptTemplate::OpReturn => "OP_RETURN",
+        })
+    }
+}
+
+impl Script {
+    /// The public key hash paid to, if this is a P2PKH script.
+    pub fn p2pkh_hash(&self) -> Option<&[u8]> {
+        if ScriptTemplate::P2pkh.matches(self) {
+            Some(&self.raw[3..23])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::FromHex;
+
+    #[test]
+    fn classify() {
+        let p2pkh = Script::from_hex("76a9146f67988ec4b7bf498c9164d76b52dffdc805ff8c88ac").unwrap();
+        assert_eq!(
+            ScriptTemplate::classify(&p2pkh),
+            Some(ScriptTemplate::P2pkh)
+        );
+        assert_eq!(
+            hex::encode(p2pkh.p2pkh_hash().unwrap()),
+            "6f67988ec4b7bf498c9164d76b52dffdc805ff8c"
+        );
+        let p2pk = Script::from_hex(
+            "21031adba39196c65be0e61c6ddf57b397aa246729f5b639bd5bc9b5c55cf14af107ac",
+        )
+        .unwrap();
+        assert_eq!(ScriptTemplate::classify(&p2pk), Some(ScriptTemplate::P2pk));
+        assert_eq!(p2pk.p2pkh_hash(), None);
+        let data = Script::from_hex("006a0568656c6c6f").unwrap();
+        assert_eq!(
+            ScriptTemplate::classify(&data),
+            Some(ScriptTemplate::OpReturn)
+        );
+        // a P2PKH script with an extra byte is not P2PKH
+        let long =
+            Script::from_hex("76a9146f67988ec4b7bf498c9164d76b52dffdc805ff8c88ac00").unwrap();
+        assert_eq!(ScriptTemplate::classify(&long), None);
+        assert_eq!(ScriptTemplate::classify(&Script::from(vec![])), None);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::bitcoin::Hash;
+use crate::Result;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use secp256k1::ecdsa::Signature;
+use secp256k1::{Message, PublicKey, Secp256k1, VerifyOnly};
+use std::sync::OnceLock;
+
+/// A signature check, the work of a single OP_CHECKSIG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigCheckItem {
+    /// The DER encoded signature, without the sighash type byte.
+    pub signature: Vec<u8>,
+    /// The encoded public key.
+    pub pubkey: Vec<u8>,
+    /// The signature hash of the input, see [SighashCache](crate::bitcoin::SighashCache).
+    pub sighash: Hash,
+}
+
+/// Verify a single signature.
+///
+/// A signature or public key that can not be decoded does not verify. Signatures with a high S value
+/// are accepted, as they are by consensus.
+pub fn verify_signature(item: &SigCheckItem) -> bool {
+    check(&Secp256k1::verification_only(), item)
+}
+
+/// Verify many signatures, returning the result of each in the same order.
+///
+/// The items share a single verification context. With the `parallel` feature, they are verified on
+/// the rayon thread pool. The results are the same as those of [verify_signature()].
+pub fn verify_signatures_batch(items: &[SigCheckItem]) -> Result<Vec<bool>> {
+    static CONTEXT: OnceLock<Secp256k1<VerifyOnly>> = OnceLock::new();
+    let secp = CONTEXT.get_or_init(Secp256k1::verification_only);
+    #[cfg(feature = "parallel")]
+    let results = items.par_iter().map(|i| check(secp, i)).collect();
+    #[cfg(not(feature = "parallel"))]
+    let results = items.iter().map(|i| check(secp, i)).collect();
+    Ok(results)
+}
+
+fn check(secp: &Secp256k1<VerifyOnly>, item: &SigCheckItem) -> bool {
+    let (Ok(mut sig), Ok(key)) = (
+        Signature::from_der(&item.signature),
+        PublicKey::from_slice(&item.pubkey),
+    ) else {
+        return false;
+    };
+    // libsecp256k1 only verifies signatures with a low S value
+    sig.normalize_s();
+    let msg = Message::from_digest(item.sighash.hash);
+    secp.verify_ecdsa(&msg, &sig, &key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{AsyncEncodable, Block, Operation, SighashCache, Tx, TxHash};
+    use std::collections::HashMap;
+
+    /// The signature checks of the P2PKH inputs that spend outputs in the same block.
+    fn mainnet_items() -> Vec<SigCheckItem> {
+        let bin = std::fs::read(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .unwrap();
+        let block = Block::from_binary_buf(&bin).unwrap();
+        let txs: HashMap<TxHash, &Tx> = block.transactions.iter().map(|t| (t.hash(), t)).collect();
+        let mut items = Vec::new();
+        for tx in block.transactions.iter() {
+            let cache = SighashCache::new(tx);
+            for (index, input) in tx.inputs.iter().enumerate() {
+                let Some(prev) = txs.get(&input.outpoint.tx_hash) else {
+                    continue;
+                };
+                let spent = &prev.outputs[input.outpoint.index as usize];
+                if let [Operation::OP_PUSH(sig), Operation::OP_PUSH(key)] =
+                    &input.script.decode().unwrap().0[..]
+                {
+                    let sig = sig.get_bytes();
+                    let (der, sighash_type) = sig.split_at(sig.len() - 1);
+                    items.push(SigCheckItem {
+                        signature: der.to_vec(),
+                        pubkey: key.get_bytes().to_vec(),
+                        sighash: cache
+                            .sighash_for_input(index, &spent.script, spent.value, sighash_type[0])
+                            .unwrap(),
+                    });
+                }
+            }
+        }
+        items
+    }
+
+    #[test]
+    fn batch_matches_sequential() {
+        let valid = mainnet_items();
+        assert!(valid.len() > 20);
+        let mut items = Vec::new();
+        for (i, item) in valid.iter().enumerate() {
+            let mut item = item.clone();
+            match i % 5 {
+                1 => item.sighash = Hash::sha256d(&item.sighash.hash),
+                2 => {
+                    let other = valid.iter().find(|v| v.pubkey != item.pubkey).unwrap();
+                    item.pubkey = other.pubkey.clone();
+                }
+                3 => item.signature.truncate(item.signature.len() - 1),
+                4 => item.pubkey[0] = 0x05,
+                _ => {}
+            }
+            items.push(item);
+        }
+        let batch = verify_signatures_batch(&items).unwrap();
+        let sequential: Vec<bool> = items.iter().map(verify_signature).collect();
+        assert_eq!(batch, sequential);
+        for (i, ok) in batch.iter().enumerate() {
+            assert_eq!(*ok, i % 5 == 0, "{}", i);
+        }
+        assert!(verify_signatures_batch(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn high_s() {
+        let mut item = mainnet_items().remove(0);
+        let mut sig = Signature::from_der(&item.signature).unwrap();
+        sig.normalize_s();
+        // negate S, n - s
+        let mut compact = sig.serialize_compact();
+        let n = hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+            .unwrap();
+        let mut borrow = 0i16;
+        for i in (32..64).rev() {
+            let d = n[i - 32] as i16 - compact[i] as i16 - borrow;
+            borrow = (d < 0) as i16;
+            compact[i] = d.rem_euclid(256) as u8;
+        }
+        let high = Signature::from_compact(&compact).unwrap();
+        assert_ne!(high, sig);
+        item.signature = high.serialize_der().to_vec();
+        assert!(verify_signature(&item));
+        assert_eq!(verify_signatures_batch(&[item]).unwrap(), vec![true]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::bitcoin::{varint_write, Hash, Script, Tx, TxOutput};
+use crate::{Error, Result};
+use bytes::BufMut;
+use std::cell::OnceCell;
+
+/// Sign all of the inputs and all of the outputs.
+pub const SIGHASH_ALL: u8 = 0x01;
+/// Sign all of the inputs and none of the outputs.
+pub const SIGHASH_NONE: u8 = 0x02;
+/// Sign all of the inputs and the output with the same index as the input being signed.
+pub const SIGHASH_SINGLE: u8 = 0x03;
+/// Use the replay protected signature hash algorithm, this is required by Bitcoin SV.
+pub const SIGHASH_FORKID: u8 = 0x40;
+/// Sign only the input being signed, may be combined with the other flags.
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// Calculates the signature hashes of the inputs of a transaction.
+///
+/// The signature hash of every input includes hashes of all of the outpoints, sequence numbers and
+/// outputs of the transaction. Calculating these for each input makes signing or verifying all of the
+/// inputs of a transaction quadratic in its size. SighashCache calculates each of them once, when they
+/// are first needed, and reuses them for every input.
+///
+/// Only the FORKID algorithm (BIP143 with the SIGHASH_FORKID flag) is supported.
+pub struct SighashCache<'a> {
+    tx: &'a Tx,
+    hash_prevouts: OnceCell<Hash>,
+    hash_sequence: OnceCell<Hash>,
+    hash_outputs: OnceCell<Hash>,
+}
+
+impl<'a> SighashCache<'a> {
+    /// Create a new cache for the transaction.
+    pub fn new(tx: &'a Tx) -> SighashCache<'a> {
+        SighashCache {
+            tx,
+            hash_prevouts: OnceCell::new(),
+            hash_sequence: OnceCell::new(),
+            hash_outputs: OnceCell::new(),
+        }
+    }
+
+    /// Calculate the signature hash of an input.
+    ///
+    /// The `subscript` is the locking script being spent, from the last executed OP_CODESEPARATOR, and
+    /// `value` is the value of the output being spent.
+    pub fn sighash_for_input(
+        &self,
+        index: usize,
+        subscript: &Script,
+        value: u64,
+        sighash_type: u8,
+    ) -> Result<Hash> {
+        let input = self.tx.inputs.get(index).ok_or_else(|| {
+            Error::BadArgument(format!(
+                "input {} out of range, transaction has {} inputs",
+                index,
+                self.tx.inputs.len()
+            ))
+        })?;
+        if sighash_type & SIGHASH_FORKID == 0 {
+            return Err(Error::BadArgument(format!(
+                "sighash type {:#04x} does not have SIGHASH_FORKID",
+                sighash_type
+            )));
+        }
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let base_type = sighash_type & 0x1f;
+
+        let hash_prevouts = if anyone_can_pay {
+            Hash::ZERO
+        } else {
+            *self.hash_prevouts.get_or_init(|| {
+                let mut buf = Vec::with_capacity(self.tx.inputs.len() * 36);
+                for i in self.tx.inputs.iter() {
+                    buf.put_slice(&i.outpoint.tx_hash.hash);
+                    buf.put_u32_le(i.outpoint.index);
+                }
+                Hash::sha256d(&buf)
+            })
+        };
+        let hash_sequence =
+            if anyone_can_pay || base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE {
+                Hash::ZERO
+            } else {
+                *self.hash_sequence.get_or_init(|| {
+                    let mut buf = Vec::with_capacity(self.tx.inputs.len() * 4);
+                    for i in self.tx.inputs.iter() {
+                        buf.put_u32_le(i.sequence);
+                    }
+                    Hash::sha256d(&buf)
+                })
+            };
+        let hash_outputs = if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+            *self.hash_outputs.get_or_init(|| {
+                let mut buf = Vec::new();
+                for o in self.tx.outputs.iter() {
+                    put_output(&mut buf, o);
+                }
+                Hash::sha256d(&buf)
+            })
+        } else if base_type == SIGHASH_SINGLE && index < self.tx.outputs.len() {
+            let mut buf = Vec::new();
+            put_output(&mut buf, &self.tx.outputs[index]);
+            Hash::sha256d(&buf)
+        } else {
+            Hash::ZERO
+        };
+
+        let mut preimage = Vec::with_capacity(156 + subscript.len() + 9);
+        preimage.put_u32_le(self.tx.version);
+        preimage.put_slice(&hash_prevouts.hash);
+        preimage.put_slice(&hash_sequence.hash);
+        preimage.put_slice(&input.outpoint.tx_hash.hash);
+        preimage.put_u32_le(input.outpoint.index);
+        varint_write(&mut preimage, subscript.len() as u64);
+        preimage.put_slice(subscript.as_bytes());
+        preimage.put_u64_le(value);
+        preimage.put_u32_le(input.sequence);
+        preimage.put_slice(&hash_outputs.hash);
+        preimage.put_u32_le(self.tx.lock_time);
+        preimage.put_u32_le(sighash_type as u32);
+        Ok(Hash::sha256d(&preimage))
+    }
+}
+
+impl Tx {
+    /// Calculate the signature hash of an input, see [SighashCache::sighash_for_input()].
+    ///
+    /// When processing several inputs of the same transaction, use a [SighashCache] instead.
+    pub fn sighash(
+        &self,
+        index: usize,
+        subscript: &Script,
+        value: u64,
+        sighash_type: u8,
+    ) -> Result<Hash> {
+        SighashCache::new(self).sighash_for_input(index, subscript, value, sighash_type)
+    }
+}
+
+fn put_output(buf: &mut Vec<u8>, output: &TxOutput) {
+    buf.put_u64_le(output.value);
+    varint_write(buf, output.script.len() as u64);
+    buf.put_slice(output.script.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{AsyncEncodable, Block, Operation, TxHash, TxInput};
+    use secp256k1::ecdsa::Signature;
+    use secp256k1::{Message, PublicKey, Secp256k1};
+    use std::collections::HashMap;
+
+    fn block() -> Block {
+        let bin = std::fs::read(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .unwrap();
+        Block::from_binary_buf(&bin).unwrap()
+    }
+
+    /// Verify the signatures of the P2PKH inputs that spend outputs in the same block, for which the
+    /// value being spent is known.
+    #[test]
+    fn mainnet_signatures() {
+        let block = block();
+        let secp = Secp256k1::verification_only();
+        let outputs: HashMap<TxHash, &Tx> =
+            block.transactions.iter().map(|t| (t.hash(), t)).collect();
+        let mut verified = 0;
+        for tx in block.transactions.iter() {
+            let cache = SighashCache::new(tx);
+            for (index, input) in tx.inputs.iter().enumerate() {
+                let Some(prev) = outputs.get(&input.outpoint.tx_hash) else {
+                    continue;
+                };
+                let spent = &prev.outputs[input.outpoint.index as usize];
+                let (ops, _) = input.script.decode().unwrap();
+                let (Some(Operation::OP_PUSH(sig)), Some(Operation::OP_PUSH(key))) =
+                    (ops.first(), ops.get(1))
+                else {
+                    continue;
+                };
+                let sig = sig.get_bytes();
+                let (der, sighash_type) = sig.split_at(sig.len() - 1);
+                let hash = cache
+                    .sighash_for_input(index, &spent.script, spent.value, sighash_type[0])
+                    .unwrap();
+                let msg = Message::from_digest_slice(&hash.hash).unwrap();
+                let sig = Signature::from_der(der).unwrap();
+                let key = PublicKey::from_slice(&key.get_bytes()).unwrap();
+                secp.verify_ecdsa(&msg, &sig, &key).unwrap();
+                // a different value produces a different hash
+                let other = tx
+                    .sighash(index, &spent.script, spent.value + 1, sighash_type[0])
+                    .unwrap();
+                assert!(secp
+                    .verify_ecdsa(
+                        &Message::from_digest_slice(&other.hash).unwrap(),
+                        &sig,
+                        &key
+                    )
+                    .is_err());
+                verified += 1;
+            }
+        }
+        assert!(verified > 20, "{}", verified);
+    }
+
+    /// The cached hashes produce the same result as a new cache for each input, for every type.
+    #[test]
+    fn cached_matches_uncached() {
+        let block = block();
+        let template = &block.transactions[10];
+        let mut tx = template.clone();
+        tx.inputs = (0..50u8)
+            .map(|i| {
+                TxInput::new(
+                    Hash::sha256d(&[i]),
+                    i as u32,
+                    Script::from(vec![]),
+                    Some(i as u32),
+                )
+            })
+            .collect();
+        tx.outputs = vec![template.outputs[0].clone(); 3];
+        let subscript = &template.outputs[1].script;
+        let cache = SighashCache::new(&tx);
+        for base in [SIGHASH_ALL, SIGHASH_NONE, SIGHASH_SINGLE] {
+            for acp in [0, SIGHASH_ANYONECANPAY] {
+                let t = base | acp | SIGHASH_FORKID;
+                let mut hashes = Vec::new();
+                for index in 0..tx.inputs.len() {
+                    let h = cache.sighash_for_input(index, subscript, 1000, t).unwrap();
+                    assert_eq!(h, tx.sighash(index, subscript, 1000, t).unwrap());
+                    hashes.push(h);
+                }
+                hashes.sort();
+                hashes.dedup();
+                assert_eq!(hashes.len(), tx.inputs.len());
+            }
+        }
+        assert!(tx
+            .sighash(50, subscript, 1000, SIGHASH_ALL | SIGHASH_FORKID)
+            .is_err());
+        assert!(tx.sighash(0, subscript, 1000, SIGHASH_ALL).is_err());
+        assert_eq!(tx.async_size(), tx.to_binary_buf().unwrap().len());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::bitcoin::crypto::compact_is_compressed;
+use crate::bitcoin::hash160::Hash160;
+use crate::bitcoin::{varint_write, Address, Hash, PrivateKey, PublicKey};
+
+/// The prefix of a signed message, which stops a message signature being used as a transaction
+/// signature.
+pub const SIGNED_MESSAGE_MAGIC: &str = "Bitcoin Signed Message:\n";
+
+/// The digest that is signed for a message: the double SHA256 of the prefix and the message, each
+/// preceded by its length.
+pub fn signed_message_hash(message: &[u8]) -> Hash {
+    let mut buf = Vec::with_capacity(SIGNED_MESSAGE_MAGIC.len() + message.len() + 10);
+    varint_write(&mut buf, SIGNED_MESSAGE_MAGIC.len() as u64);
+    buf.extend_from_slice(SIGNED_MESSAGE_MAGIC.as_bytes());
+    varint_write(&mut buf, message.len() as u64);
+    buf.extend_from_slice(message);
+    Hash::sha256d(&buf)
+}
+
+/// Sign a message, producing the 65-byte compact signature that wallets encode as base64.
+///
+/// The signature signals a compressed public key, which is the form used for the addresses of
+/// keys in this library.
+pub fn sign_message(key: &PrivateKey, message: &[u8]) -> [u8; 65] {
+    key.sign_compact_recoverable(&signed_message_hash(message))
+}
+
+/// Verify that a message was signed by the key of an address.
+///
+/// The public key is recovered from the signature and its hash, in the form given by the signature
+/// header, is compared against the address. A signature that can not be decoded does not verify.
+pub fn verify_message(address: &Address, sig: &[u8; 65], message: &[u8]) -> bool {
+    let Ok(key) = PublicKey::recover_from_compact(&signed_message_hash(message), sig) else {
+        return false;
+    };
+    let hash = if compact_is_compressed(sig) {
+        key.pubkey_hash()
+    } else {
+        Hash160::generate(&key.inner.serialize_uncompressed())
+    };
+    hash == address.hash160
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{BlockchainId, KeyPair};
+    use hex_literal::hex;
+    use std::str::FromStr;
+
+    #[test]
+    fn sign_and_verify() {
+        let wif = String::from("cU5N3pE6QnRd3rZFgv1KMvUkDwMY4Vnya3bLE5JtZG3Hb549pzDN");
+        let (key, kind) = PrivateKey::from_wif(&wif).unwrap();
+        let address = Address::from_pv(&key, kind.clone());
+        let message = b"rust-bitcoinsv";
+        let sig = sign_message(&key, message);
+        assert!(verify_message(&address, &sig, message));
+        assert!(!verify_message(&address, &sig, b"another message"));
+        let other = KeyPair::from_seed(1, BlockchainId::Test).address;
+        assert!(!verify_message(&other, &sig, message));
+
+        // the same signature with the uncompressed header belongs to the uncompressed address
+        let mut uncompressed = sig;
+        uncompressed[0] -= 4;
+        assert!(!verify_message(&address, &uncompressed, message));
+        let pubkey = PublicKey::from(&key);
+        let uncompressed_address = Address {
+            hash160: Hash160::generate(&pubkey.inner.serialize_uncompressed()),
+            kind,
+        };
+        assert!(verify_message(
+            &uncompressed_address,
+            &uncompressed,
+            message
+        ));
+
+        let mut bad = sig;
+        bad[0] = 0;
+        assert!(!verify_message(&address, &bad, message));
+    }
+
+    /// The example in the README of bitcoinjs-message, which signs with the compressed key of
+    /// 1F3sAm6ZtwLAUnj7d38pGFxtP3RVEvtsbV and gives the base64 signature
+    /// H9L5yLFjti0QTHhPyFrZCT1V/MMnBtXKmoiKDZ78NDBjERki6ZTQZdSMCtkgoNmp17By9ItJr8o7ChX0XxY91nk=
+    #[test]
+    fn known_signature() {
+        let address = Address::from_str("1F3sAm6ZtwLAUnj7d38pGFxtP3RVEvtsbV").unwrap();
+        let message = b"This is an example of a signed message.";
+        let sig = hex!(
+            "1fd2f9c8b163b62d104c784fc85ad9093d55fcc32706d5ca9a888a0d9efc343063111922e994d065d48c"
+            "0ad920a0d9a9d7b072f48b49afca3b0a15f45f163dd679"
+        );
+        assert!(verify_message(&address, &sig, message));
+        let key = PublicKey::recover_from_compact(&signed_message_hash(message), &sig).unwrap();
+        assert_eq!(key.pubkey_hash(), address.hash160);
+        // signing is deterministic, so the same key gives the same signature
+        let wif = String::from("L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1");
+        let (private_key, _) = PrivateKey::from_wif(&wif).unwrap();
+        assert_eq!(sign_message(&private_key, message), sig);
+    }
+
+    #[test]
+    fn message_hash() {
+        // the prefix is preceded by its length, 24
+        let mut expected = vec![24];
+        expected.extend_from_slice(b"Bitcoin Signed Message:\n");
+        expected.push(5);
+        expected.extend_from_slice(b"hello");
+        assert_eq!(signed_message_hash(b"hello"), Hash::sha256d(&expected));
+    }
+}
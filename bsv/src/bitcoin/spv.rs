@@ -0,0 +1,160 @@
+use crate::bitcoin::header::merkle_parents;
+use crate::bitcoin::{BlockHeader, Hash, MerkleRoot, Tx, TxHash};
+use crate::{Error, Result};
+
+/// A proof that a transaction is included in a block, the path from the transaction to the merkle root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// The position of the transaction in the block.
+    pub index: u32,
+    /// The hash of the transaction.
+    pub txid: TxHash,
+    /// The sibling at each level of the merkle tree, starting with the sibling of the transaction.
+    pub branch: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Calculate the merkle root from the transaction and the branch.
+    ///
+    /// Returns None if the index is too large for the length of the branch.
+    pub fn root(&self) -> Option<MerkleRoot> {
+        if self.branch.len() < 32 && self.index >> self.branch.len() != 0 {
+            return None;
+        }
+        let mut hash = self.txid;
+        let mut data = [0u8; 64];
+        for (level, sibling) in self.branch.iter().enumerate() {
+            let (left, right) = if (self.index >> level) & 1 == 0 {
+                (&hash, sibling)
+            } else {
+                (sibling, &hash)
+            };
+            data[..32].copy_from_slice(&left.hash);
+            data[32..].copy_from_slice(&right.hash);
+            hash = Hash::sha256d(&data);
+        }
+        Some(hash)
+    }
+}
+
+/// Build the merkle proof for the transaction at `index` from the hashes of all of the transactions in
+/// the block, in order.
+///
+/// Returns None if the index is out of range.
+pub fn build_merkle_proof(txids: &[TxHash], index: usize) -> Option<MerkleProof> {
+    if index >= txids.len() {
+        return None;
+    }
+    let mut branch = Vec::new();
+    let mut level = txids.to_vec();
+    let mut pos = index;
+    while level.len() > 1 {
+        // a node without a sibling is paired with itself
+        branch.push(*level.get(pos ^ 1).unwrap_or(&level[pos]));
+        level = merkle_parents(&level);
+        pos /= 2;
+    }
+    Some(MerkleProof {
+        index: index as u32,
+        txid: txids[index],
+        branch,
+    })
+}
+
+/// Check that the proof leads to the given merkle root.
+pub fn verify_merkle_proof(proof: &MerkleProof, merkle_root: &MerkleRoot) -> Result<()> {
+    match proof.root() {
+        Some(root) if root == *merkle_root => Ok(()),
+        Some(root) => Err(Error::BadData(format!(
+            "merkle proof leads to {} not {}",
+            root, merkle_root
+        ))),
+        None => Err(Error::BadData(format!(
+            "merkle proof index {} is too large for {} levels",
+            proof.index,
+            proof.branch.len()
+        ))),
+    }
+}
+
+/// Verify that a transaction is included in the block with the given header.
+///
+/// The proof must be for the transaction, must lead to the merkle root in the header, and the header
+/// must satisfy its proof of work target. This does not check that the header is part of the best
+/// chain.
+pub fn verify_tx_inclusion(tx: &Tx, proof: &MerkleProof, header: &BlockHeader) -> Result<()> {
+    verify_txid_inclusion(&tx.hash(), proof, header)
+}
+
+/// Verify that the transaction with the given hash is included in the block with the given header.
+///
+/// See [verify_tx_inclusion()].
+pub fn verify_txid_inclusion(
+    txid: &TxHash,
+    proof: &MerkleProof,
+    header: &BlockHeader,
+) -> Result<()> {
+    if proof.txid != *txid {
+        return Err(Error::BadData(format!(
+            "merkle proof is for {} not {}",
+            proof.txid, txid
+        )));
+    }
+    verify_merkle_proof(proof, &header.merkle_root)?;
+    header.validate_pow()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{merkle_root, AsyncEncodable, Block};
+
+    fn block() -> Block {
+        let bin = std::fs::read(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .unwrap();
+        Block::from_binary_buf(&bin).unwrap()
+    }
+
+    #[test]
+    fn proofs() {
+        let block = block();
+        let txids: Vec<TxHash> = block.transactions.iter().map(|t| t.hash()).collect();
+        // 222 transactions, so the last one is paired with itself at the first level
+        for index in [0, 1, 100, 220, 221] {
+            let proof = build_merkle_proof(&txids, index).unwrap();
+            assert_eq!(proof.branch.len(), 8);
+            assert_eq!(proof.root(), Some(block.header.merkle_root));
+            verify_tx_inclusion(&block.transactions[index], &proof, &block.header).unwrap();
+        }
+        assert_eq!(build_merkle_proof(&txids, 222), None);
+        // a single transaction is its own root
+        let proof = build_merkle_proof(&txids[..1], 0).unwrap();
+        assert!(proof.branch.is_empty());
+        assert_eq!(proof.root(), Some(merkle_root(&txids[..1])));
+    }
+
+    #[test]
+    fn bad_proofs() {
+        let block = block();
+        let txids: Vec<TxHash> = block.transactions.iter().map(|t| t.hash()).collect();
+        let proof = build_merkle_proof(&txids, 100).unwrap();
+        // wrong transaction
+        assert!(verify_txid_inclusion(&txids[101], &proof, &block.header).is_err());
+        // wrong position
+        let mut moved = proof.clone();
+        moved.index = 101;
+        assert!(verify_txid_inclusion(&txids[100], &moved, &block.header).is_err());
+        moved.index = 100 + 256;
+        assert_eq!(moved.root(), None);
+        // tampered branch
+        let mut tampered = proof.clone();
+        tampered.branch[3] = Hash::sha256d(b"tampered");
+        assert!(verify_txid_inclusion(&txids[100], &tampered, &block.header).is_err());
+        // header without proof of work
+        let mut header = block.header.clone();
+        header.nonce += 1;
+        assert!(verify_txid_inclusion(&txids[100], &proof, &header).is_err());
+    }
+}
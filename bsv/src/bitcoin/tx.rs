@@ -1,15 +1,25 @@
+use crate::bitcoin::encoding::decode_hex;
 use crate::bitcoin::hash::Hash;
-use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable, Script};
+use crate::bitcoin::rules::{MAX_MONEY, MAX_TX_SIZE};
+use crate::bitcoin::{
+    varint_decode, varint_encode, varint_read, varint_size, Address, AsyncEncodable,
+    NonStandardReason, Script, ScriptTemplate, StandardnessPolicy,
+};
+use crate::util::{Amount, FeeRate};
 use async_trait::async_trait;
+use bytes::Buf;
 use hex::{FromHex, ToHex};
+use ring::digest::{self, SHA256};
 use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::collections::HashSet;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The TxHash is used to identify transactions.
 pub type TxHash = Hash;
 
 /// A Bitcoin transaction.
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Tx {
     /// transaction version number
     pub version: u32,
@@ -20,19 +30,209 @@ pub struct Tx {
 }
 
 impl Tx {
+    // maximum number of inputs or outputs to allocate space for before they have been read
+    const MAX_PREALLOCATE: usize = 1024;
+
     pub fn hash(&self) -> Hash {
         let v = self.to_binary_buf().unwrap();
         Hash::sha256d(&v)
     }
+
+    /// Returns true if this is a coinbase transaction, which has a single input with a null outpoint.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].outpoint.tx_hash == Hash::ZERO
+            && self.inputs[0].outpoint.index == u32::MAX
+    }
+
+    /// The number of signature operations in the scripts of the transaction.
+    ///
+    /// See [Script::sigop_count()].
+    pub fn sigop_count(&self) -> u64 {
+        self.inputs
+            .iter()
+            .map(|i| i.script.sigop_count())
+            .sum::<u64>()
+            + self
+                .outputs
+                .iter()
+                .map(|o| o.script.sigop_count())
+                .sum::<u64>()
+    }
+
+    /// The serialized size of the transaction broken down into inputs, outputs, and scripts.
+    pub fn size_breakdown(&self) -> TxSizeBreakdown {
+        TxSizeBreakdown {
+            inputs: varint_size(self.inputs.len() as u64)
+                + self.inputs.iter().map(|i| i.async_size()).sum::<usize>(),
+            outputs: varint_size(self.outputs.len() as u64)
+                + self.outputs.iter().map(|o| o.async_size()).sum::<usize>(),
+            input_scripts: self.inputs.iter().map(|i| i.script.len()).sum(),
+            output_scripts: self.outputs.iter().map(|o| o.script.len()).sum(),
+        }
+    }
+
+    /// Check the structure of the transaction.
+    ///
+    /// The transaction must have inputs and outputs, must not exceed the consensus size limit, must not
+    /// spend the same outpoint twice, and its outputs must not be worth more than 21 million BSV. A coinbase
+    /// must have a script of 2 to 100 bytes and no other transaction may spend the null outpoint. Scripts
+    /// and the outputs that are spent are not checked.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.inputs.is_empty() {
+            return Err(crate::Error::BadData("no inputs".to_string()));
+        }
+        if self.outputs.is_empty() {
+            return Err(crate::Error::BadData("no outputs".to_string()));
+        }
+        let size = self.async_size() as u64;
+        if size > MAX_TX_SIZE(false) {
+            return Err(crate::Error::BadData(format!(
+                "size {} exceeds maximum {}",
+                size,
+                MAX_TX_SIZE(false)
+            )));
+        }
+        let mut total = 0u64;
+        for (index, output) in self.outputs.iter().enumerate() {
+            total = total.saturating_add(output.value);
+            if output.value > MAX_MONEY || total > MAX_MONEY {
+                return Err(crate::Error::BadData(format!(
+                    "output {} value out of range",
+                    index
+                )));
+            }
+        }
+        let mut spent = HashSet::with_capacity(self.inputs.len());
+        for input in self.inputs.iter() {
+            if !spent.insert(&input.outpoint) {
+                return Err(crate::Error::BadData(format!(
+                    "duplicate input {}:{}",
+                    input.outpoint.tx_hash, input.outpoint.index
+                )));
+            }
+        }
+        if self.is_coinbase() {
+            let len = self.inputs[0].script.len();
+            if !(2..=100).contains(&len) {
+                return Err(crate::Error::BadData(format!(
+                    "coinbase script size {} out of range",
+                    len
+                )));
+            }
+        } else if self
+            .inputs
+            .iter()
+            .any(|i| i.outpoint.tx_hash == Hash::ZERO && i.outpoint.index == u32::MAX)
+        {
+            return Err(crate::Error::BadData(
+                "input spends null outpoint".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check whether the transaction is standard according to the given policy.
+    ///
+    /// Nodes do not relay non-standard transactions, so this should be checked before broadcasting. The
+    /// first rule violated is returned.
+    pub fn check_standard(&self, policy: &StandardnessPolicy) -> Result<(), NonStandardReason> {
+        let size = self.async_size() as u64;
+        if size > policy.max_tx_size {
+            return Err(NonStandardReason::TxTooLarge {
+                size,
+                max: policy.max_tx_size,
+            });
+        }
+        for (index, input) in self.inputs.iter().enumerate() {
+            if !input.script.is_push_only() {
+                return Err(NonStandardReason::InputNotPushOnly { index });
+            }
+        }
+        for (index, output) in self.outputs.iter().enumerate() {
+            if output.script.is_op_return() {
+                let size = output.script.len() as u64;
+                if size > policy.max_op_return_size {
+                    return Err(NonStandardReason::OpReturnTooLarge {
+                        index,
+                        size,
+                        max: policy.max_op_return_size,
+                    });
+                }
+            } else if output.value < policy.dust_limit {
+                return Err(NonStandardReason::DustOutput {
+                    index,
+                    value: output.value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The outputs whose scripts satisfy the predicate, with their indexes.
+    pub fn find_outputs<F>(&self, mut predicate: F) -> Vec<(u32, &TxOutput)>
+    where
+        F: FnMut(&Script) -> bool,
+    {
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| predicate(&o.script))
+            .map(|(i, o)| (i as u32, o))
+            .collect()
+    }
+
+    /// The outputs that pay to the address, with their indexes.
+    pub fn outputs_to_address(&self, address: &Address) -> Vec<(u32, &TxOutput)> {
+        self.find_outputs(|s| s.p2pkh_hash() == Some(&address.hash160.hash[..]))
+    }
+
+    /// The outputs whose scripts match the template, with their indexes.
+    pub fn outputs_matching_template(&self, template: ScriptTemplate) -> Vec<(u32, &TxOutput)> {
+        self.find_outputs(|s| template.matches(s))
+    }
+
+    /// The inputs that spend the outpoint, with their indexes.
+    ///
+    /// A valid transaction spends an outpoint at most once.
+    pub fn inputs_spending(&self, outpoint: &Outpoint) -> Vec<(u32, &TxInput)> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.outpoint == *outpoint)
+            .map(|(n, i)| (n as u32, i))
+            .collect()
+    }
+}
+
+/// The serialized size of a transaction, broken down by part.
+///
+/// The sizes of the inputs and outputs include the varint that precedes them, so the total size of the
+/// transaction is `8 + inputs + outputs`, the 8 bytes being the version and the lock time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxSizeBreakdown {
+    /// The size of the inputs, including the input count.
+    pub inputs: usize,
+    /// The size of the outputs, including the output count.
+    pub outputs: usize,
+    /// The size of the unlocking scripts of the inputs, excluding their length prefixes.
+    pub input_scripts: usize,
+    /// The size of the locking scripts of the outputs, excluding their length prefixes.
+    pub output_scripts: usize,
+}
+
+impl TxSizeBreakdown {
+    /// The total serialized size of the transaction.
+    pub fn total(&self) -> usize {
+        8 + self.inputs + self.outputs
+    }
 }
 
 impl FromHex for Tx {
     type Error = crate::Error;
 
     fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        let bytes = hex::decode(hex)?;
-        let tx = Tx::from_binary_buf(bytes.as_slice())?;
-        Ok(tx)
+        decode_hex(hex)
     }
 }
 
@@ -56,15 +256,22 @@ impl AsyncEncodable for Tx {
     {
         let version = reader.read_u32_le().await?;
         let num_inputs = varint_decode(reader).await?;
-        // todo: check size before allocation
-        let mut inputs = Vec::with_capacity(num_inputs as usize);
+        if num_inputs > MAX_TX_SIZE(false) / TxInput::MIN_SIZE as u64 {
+            let msg = format!("Too many inputs: {}", num_inputs);
+            return Err(crate::Error::BadData(msg));
+        }
+        // the count is not trusted, the vector will grow if the inputs are actually present
+        let mut inputs = Vec::with_capacity(min(num_inputs as usize, Tx::MAX_PREALLOCATE));
         for _i in 0..num_inputs {
             let input = TxInput::async_from_binary(reader).await?;
             inputs.push(input);
         }
         let num_outputs = varint_decode(reader).await?;
-        // todo: check size before allocation
-        let mut outputs = Vec::with_capacity(num_outputs as usize);
+        if num_outputs > MAX_TX_SIZE(false) / TxOutput::MIN_SIZE as u64 {
+            let msg = format!("Too many outputs: {}", num_outputs);
+            return Err(crate::Error::BadData(msg));
+        }
+        let mut outputs = Vec::with_capacity(min(num_outputs as usize, Tx::MAX_PREALLOCATE));
         for _i in 0..num_outputs {
             let output = TxOutput::async_from_binary(reader).await?;
             outputs.push(output);
@@ -108,6 +315,143 @@ impl AsyncEncodable for Tx {
     }
 }
 
+/// The identity and shape of a serialized transaction, found by [scan_tx()] without parsing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxSummary {
+    /// The txid, the sha256d of the serialized transaction.
+    pub txid: TxHash,
+    /// The serialized size of the transaction.
+    pub size: usize,
+    /// The number of inputs.
+    pub n_inputs: u64,
+    /// The number of outputs.
+    pub n_outputs: u64,
+    /// The sum of the output values, saturating rather than overflowing for invalid transactions.
+    pub total_out_value: u64,
+}
+
+/// Read a serialized transaction from the buffer, returning its [TxSummary].
+///
+/// The serialization is walked once, each byte being hashed as the buffer advances past it, and no
+/// [Tx] is built and no script is copied. The buffer is left at the end of the transaction, so a
+/// block can be indexed by calling this once for each of its transactions. The counts of inputs and
+/// outputs are limited in the same way as when a [Tx] is parsed.
+pub fn scan_tx(buf: &mut impl Buf) -> crate::Result<TxSummary> {
+    let mut reader = HashingReader::new(buf);
+    reader.skip(4)?;
+    let n_inputs = varint_read(&mut reader)?;
+    if n_inputs > MAX_TX_SIZE(false) / TxInput::MIN_SIZE as u64 {
+        let msg = format!("Too many inputs: {}", n_inputs);
+        return Err(crate::Error::BadData(msg));
+    }
+    for _ in 0..n_inputs {
+        reader.skip(Outpoint::SIZE as u64)?;
+        let script_len = varint_read(&mut reader)?;
+        reader.skip(script_len)?;
+        reader.skip(4)?;
+    }
+    let n_outputs = varint_read(&mut reader)?;
+    if n_outputs > MAX_TX_SIZE(false) / TxOutput::MIN_SIZE as u64 {
+        let msg = format!("Too many outputs: {}", n_outputs);
+        return Err(crate::Error::BadData(msg));
+    }
+    let mut total_out_value: u64 = 0;
+    for _ in 0..n_outputs {
+        let value = u64::from_le_bytes(reader.read_array()?);
+        total_out_value = total_out_value.saturating_add(value);
+        let script_len = varint_read(&mut reader)?;
+        reader.skip(script_len)?;
+    }
+    reader.skip(4)?;
+    let size = reader.read;
+    Ok(TxSummary {
+        txid: reader.finish(),
+        size,
+        n_inputs,
+        n_outputs,
+        total_out_value,
+    })
+}
+
+/// Reads from a [Buf], passing the bytes that are read to a sha256 hasher.
+///
+/// It is itself a [Buf], which hashes the bytes as it advances past them.
+struct HashingReader<'a, B: Buf> {
+    buf: &'a mut B,
+    sha256: digest::Context,
+    read: usize,
+}
+
+impl<'a, B: Buf> HashingReader<'a, B> {
+    fn new(buf: &'a mut B) -> HashingReader<'a, B> {
+        HashingReader {
+            buf,
+            sha256: digest::Context::new(&SHA256),
+            read: 0,
+        }
+    }
+
+    /// Move past `n` bytes, hashing them in place.
+    fn skip(&mut self, n: u64) -> crate::Result<()> {
+        if n > self.buf.remaining() as u64 {
+            return Err(crate::Error::DataTooSmall);
+        }
+        self.advance(n as usize);
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        if self.buf.remaining() < N {
+            return Err(crate::Error::DataTooSmall);
+        }
+        let mut bytes = [0; N];
+        self.copy_to_slice(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// The sha256d of the bytes that have been read.
+    fn finish(self) -> Hash {
+        let sha256 = self.sha256.finish();
+        let mut hash = [0; 32];
+        hash.copy_from_slice(digest::digest(&SHA256, sha256.as_ref()).as_ref());
+        Hash { hash }
+    }
+}
+
+impl<B: Buf> Buf for HashingReader<'_, B> {
+    fn remaining(&self) -> usize {
+        self.buf.remaining()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.buf.chunk()
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(
+            cnt <= self.buf.remaining(),
+            "advance past the end of the buffer"
+        );
+        self.read += cnt;
+        while cnt > 0 {
+            let chunk = self.buf.chunk();
+            let len = min(chunk.len(), cnt);
+            self.sha256.update(&chunk[..len]);
+            self.buf.advance(len);
+            cnt -= len;
+        }
+    }
+}
+
+/// The strategy used by [TxBuilder::select_inputs()] to choose which candidate outputs to spend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSelection {
+    /// Spend the candidates with the largest values first, minimizing the number of inputs.
+    LargestFirst,
+    /// Spend the candidates in the order given until the target is reached.
+    Accumulate,
+}
+
 /// A builder for transactions.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct TxBuilder {
@@ -115,6 +459,12 @@ pub struct TxBuilder {
     inputs: Vec<TxInput>,
     outputs: Vec<TxOutput>,
     lock_time: u32,
+    /// if set, outputs which are dust at this fee rate are rejected
+    dust_fee_rate: Option<FeeRate>,
+    /// the fee rate used when selecting inputs
+    fee_rate: FeeRate,
+    /// the script of the change output, if any
+    change_script: Option<Script>,
 }
 
 impl Default for TxBuilder {
@@ -124,15 +474,31 @@ impl Default for TxBuilder {
 }
 
 impl TxBuilder {
+    /// The fee rate used by [TxBuilder::select_inputs()] unless another is set.
+    pub const DEFAULT_FEE_RATE: FeeRate = FeeRate::from_sats_per_kb(100);
+
+    /// The estimated size of the unlocking script of a P2PKH input, which is a push of a DER encoded
+    /// signature plus sighash byte (at most 73 bytes) followed by a push of a compressed public key (34 bytes).
+    pub const P2PKH_UNLOCKING_SIZE: usize = 107;
+
     pub fn new() -> TxBuilder {
         TxBuilder {
             version: 1,
             inputs: vec![],
             outputs: vec![],
             lock_time: 0,
+            dust_fee_rate: None,
+            fee_rate: TxBuilder::DEFAULT_FEE_RATE,
+            change_script: None,
         }
     }
 
+    /// Reject outputs which are dust at the given fee rate when the transaction is built.
+    pub fn reject_dust(&mut self, fee_rate: FeeRate) -> &mut TxBuilder {
+        self.dust_fee_rate = Some(fee_rate);
+        self
+    }
+
     pub fn add_input(&mut self, input: &TxInput) -> &mut TxBuilder {
         self.inputs.push(input.clone());
         self
@@ -143,18 +509,129 @@ impl TxBuilder {
         self
     }
 
-    pub fn build(&self) -> Tx {
-        Tx {
+    /// Set the fee rate used to calculate the fee when selecting inputs.
+    pub fn set_fee_rate(&mut self, fee_rate: FeeRate) -> &mut TxBuilder {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Send any change to the given address when selecting inputs.
+    ///
+    /// This must be called before [TxBuilder::select_inputs()]. The change output is only added if it would
+    /// not be dust, otherwise the excess is added to the fee.
+    pub fn add_change_output(&mut self, address: &Address) -> &mut TxBuilder {
+        self.change_script = Some(address.locking_script());
+        self
+    }
+
+    /// Select inputs from the candidates to pay for the outputs and the fee.
+    ///
+    /// The candidates are unspent outputs together with their [Outpoint]s and are assumed to be P2PKH
+    /// outputs. The selected inputs are added with empty unlocking scripts, ready to be signed. The fee is
+    /// calculated from the estimated size of the signed transaction at the builder's fee rate. Any inputs
+    /// that were added before this call are assumed to have no value.
+    ///
+    /// Returns the fee that will be paid or an [Error::InsufficientFunds](crate::Error::InsufficientFunds)
+    /// error if the candidates are not sufficient.
+    pub fn select_inputs(
+        &mut self,
+        candidates: &[(Outpoint, TxOutput)],
+        strategy: CoinSelection,
+    ) -> crate::Result<Amount> {
+        let mut ordered: Vec<&(Outpoint, TxOutput)> = candidates.iter().collect();
+        if strategy == CoinSelection::LargestFirst {
+            ordered.sort_by_key(|c| std::cmp::Reverse(c.1.value));
+        }
+        let target: u64 = self.outputs.iter().map(|o| o.value).sum();
+        let mut selected = Vec::new();
+        let mut total = 0u64;
+        let mut fee = self.estimated_fee(0, false);
+        for candidate in ordered {
+            if total >= target + fee {
+                break;
+            }
+            selected.push(candidate);
+            total += candidate.1.value;
+            fee = self.estimated_fee(selected.len(), false);
+        }
+        if total < target + fee {
+            return Err(crate::Error::InsufficientFunds(target + fee - total));
+        }
+        let mut fee_paid = total - target;
+        if let Some(change_script) = &self.change_script {
+            let fee_with_change = self.estimated_fee(selected.len(), true);
+            if total >= target + fee_with_change {
+                let change = TxOutput::new(total - target - fee_with_change, change_script.clone());
+                if !change.is_dust(self.fee_rate) {
+                    self.outputs.push(change);
+                    fee_paid = fee_with_change;
+                }
+            }
+        }
+        for (outpoint, _) in selected {
+            self.inputs.push(TxInput::new(
+                outpoint.tx_hash,
+                outpoint.index,
+                Script::from(Vec::new()),
+                None,
+            ));
+        }
+        Ok(Amount::from_satoshis(fee_paid as i64))
+    }
+
+    // the fee for the estimated size of the signed transaction with additional P2PKH inputs and
+    // possibly a change output
+    fn estimated_fee(&self, extra_inputs: usize, with_change: bool) -> u64 {
+        let p2pkh_input_size = Outpoint::SIZE
+            + varint_size(Self::P2PKH_UNLOCKING_SIZE as u64)
+            + Self::P2PKH_UNLOCKING_SIZE
+            + 4;
+        let mut size = 8 + varint_size((self.inputs.len() + extra_inputs) as u64);
+        for input in self.inputs.iter() {
+            if input.script.is_empty() {
+                size += p2pkh_input_size;
+            } else {
+                size += input.async_size();
+            }
+        }
+        size += extra_inputs * p2pkh_input_size;
+        let mut n_outputs = self.outputs.len();
+        if with_change {
+            if let Some(change_script) = &self.change_script {
+                n_outputs += 1;
+                size += 8 + change_script.async_size();
+            }
+        }
+        size += varint_size(n_outputs as u64);
+        size += self.outputs.iter().map(|o| o.async_size()).sum::<usize>();
+        self.fee_rate.fee_for_size(size).satoshis as u64
+    }
+
+    /// Build the transaction.
+    ///
+    /// This fails if dust rejection has been enabled and one of the outputs is dust.
+    pub fn build(&self) -> crate::Result<Tx> {
+        if let Some(fee_rate) = self.dust_fee_rate {
+            for (i, output) in self.outputs.iter().enumerate() {
+                if output.is_dust(fee_rate) {
+                    let msg = format!("output {} with value {} is dust", i, output.value);
+                    return Err(crate::Error::BadArgument(msg));
+                }
+            }
+        }
+        Ok(Tx {
             version: self.version,
             inputs: self.inputs.clone(),
             outputs: self.outputs.clone(),
             lock_time: self.lock_time,
-        }
+        })
     }
 }
 
 /// An Outpoint is a reference to a specific output of a specific transaction.
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+///
+/// Outpoints are ordered by transaction hash and then by index.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct Outpoint {
     pub tx_hash: Hash,
     pub index: u32,
@@ -198,6 +675,9 @@ pub struct TxInput {
 }
 
 impl TxInput {
+    /// The minimum size of an encoded input, which has an empty script.
+    pub const MIN_SIZE: usize = Outpoint::SIZE + 1 + 4;
+
     /// Create a new TxInput.
     pub fn new(tx_hash: TxHash, index: u32, script: Script, sequence: Option<u32>) -> TxInput {
         let sequence = sequence.unwrap_or(u32::MAX);
@@ -248,10 +728,30 @@ pub struct TxOutput {
 }
 
 impl TxOutput {
+    /// The minimum size of an encoded output, which has an empty script.
+    pub const MIN_SIZE: usize = 8 + 1;
+
     /// Simple new function.
     pub fn new(value: u64, script: Script) -> TxOutput {
         TxOutput { value, script }
     }
+
+    /// Returns true if the output is dust at the given fee rate.
+    ///
+    /// An output is dust if its value is less than three times the fee needed to spend it, where spending it
+    /// requires both the output and an input of 148 bytes (the size of a typical P2PKH input). At the
+    /// conventional rate of 1000 sat/kB this makes a P2PKH output of less than 546 satoshis dust.
+    ///
+    /// Data carrier (OP_RETURN) outputs are unspendable and are never dust.
+    pub fn is_dust(&self, fee_rate: FeeRate) -> bool {
+        if self.script.is_op_return() {
+            return false;
+        }
+        let spend_size = self.async_size() + 148;
+        let threshold = 3 * fee_rate.fee_for_size(spend_size).satoshis;
+        // a value beyond i64::MAX is far above any threshold
+        i64::try_from(self.value).is_ok_and(|value| value < threshold)
+    }
 }
 
 #[async_trait]
@@ -283,7 +783,57 @@ impl AsyncEncodable for TxOutput {
 mod tests {
     use super::*;
     use crate::bitcoin::hash::Hash;
-    use crate::bitcoin::FromHex;
+    use crate::bitcoin::hash160::Hash160;
+    use crate::bitcoin::{BlockchainId, FromHex, KeyAddressKind, KeyPair};
+
+    #[test]
+    fn outpoint_ordering() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let outpoints: Vec<Outpoint> = (0..200)
+            .map(|_| {
+                let mut tx_hash = Hash::ZERO;
+                tx_hash.hash[31] = rng.gen_range(0..4);
+                Outpoint {
+                    tx_hash,
+                    index: rng.gen_range(0..4),
+                }
+            })
+            .collect();
+        for a in outpoints.iter() {
+            for b in outpoints.iter().take(50) {
+                assert_eq!(a.cmp(b), (a.tx_hash, a.index).cmp(&(b.tx_hash, b.index)));
+                assert_eq!(a.cmp(b) == std::cmp::Ordering::Equal, a == b);
+            }
+        }
+        let set: std::collections::BTreeSet<Outpoint> = outpoints.iter().cloned().collect();
+        let sorted: Vec<&Outpoint> = set.iter().collect();
+        assert!(sorted.windows(2).all(|w| w[0].tx_hash < w[1].tx_hash
+            || (w[0].tx_hash == w[1].tx_hash && w[0].index < w[1].index)));
+    }
+
+    /// Parse the coinbase transaction of the genesis block from hex
+    #[test]
+    fn tx_hex() {
+        let hex = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+        let tx = Tx::from_hex(hex).unwrap();
+        assert_eq!(
+            tx.hash(),
+            Hash::from_hex("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")
+                .unwrap()
+        );
+        assert_eq!(tx.encode_hex::<String>(), hex);
+        // trailing data, truncated data and bad hex are all rejected
+        assert!(matches!(
+            Tx::from_hex(format!("{}00", hex)),
+            Err(crate::Error::BadData(_))
+        ));
+        assert!(Tx::from_hex(&hex[..hex.len() - 2]).is_err());
+        assert!(matches!(
+            Tx::from_hex(&hex[..hex.len() - 1]),
+            Err(crate::Error::FromHexError(_))
+        ));
+    }
 
     /// Read a transaction from a byte array and check it
     #[test]
@@ -332,7 +882,9 @@ mod tests {
         let i = tx.inputs.first().unwrap();
         assert_eq!(
             i.outpoint.tx_hash,
-            Hash::from("755f816c02d01c9c0a2f80079132d7b05a1891dc0c860afc6b13e27adc2e058a")
+            Hash::from_hex_unwrap(
+                "755f816c02d01c9c0a2f80079132d7b05a1891dc0c860afc6b13e27adc2e058a"
+            )
         );
         assert_eq!(i.outpoint.index, 1);
         assert_eq!(tx.outputs.len(), 2);
@@ -360,10 +912,252 @@ mod tests {
         assert_eq!(tx2.hash(), tx_hash);
     }
 
+    #[test]
+    fn standard_tx() {
+        let (tx_bin, _tx_hash) = get_tx1();
+        let tx = Tx::from_binary_buf(tx_bin.as_slice()).unwrap();
+        assert_eq!(tx.check_standard(&StandardnessPolicy::default()), Ok(()));
+    }
+
+    #[test]
+    fn non_standard_tx() {
+        let (tx_bin, _tx_hash) = get_tx1();
+        let tx = Tx::from_binary_buf(tx_bin.as_slice()).unwrap();
+        let policy = StandardnessPolicy::default();
+
+        let small = StandardnessPolicy {
+            max_tx_size: 100,
+            ..policy.clone()
+        };
+        assert_eq!(
+            tx.check_standard(&small),
+            Err(NonStandardReason::TxTooLarge {
+                size: 211,
+                max: 100
+            })
+        );
+
+        let mut tx2 = tx.clone();
+        tx2.inputs[0].script = Script::from_hex("76a914").unwrap();
+        assert_eq!(
+            tx2.check_standard(&policy),
+            Err(NonStandardReason::InputNotPushOnly { index: 0 })
+        );
+
+        let mut tx2 = tx.clone();
+        tx2.outputs[1].value = 0;
+        assert_eq!(
+            tx2.check_standard(&policy),
+            Err(NonStandardReason::DustOutput { index: 1, value: 0 })
+        );
+
+        let small = StandardnessPolicy {
+            max_op_return_size: 5,
+            ..policy.clone()
+        };
+        assert_eq!(
+            tx.check_standard(&small),
+            Err(NonStandardReason::OpReturnTooLarge {
+                index: 0,
+                size: 10,
+                max: 5
+            })
+        );
+    }
+
+    #[test]
+    fn dust_outputs() {
+        let rate = FeeRate::from_sats_per_kb(1000);
+        let p2pkh = Script::from_hex("76a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac").unwrap();
+        assert!(TxOutput::new(545, p2pkh.clone()).is_dust(rate));
+        assert!(!TxOutput::new(546, p2pkh.clone()).is_dust(rate));
+        assert!(!TxOutput::new(1, p2pkh.clone()).is_dust(FeeRate::ZERO));
+        assert!(!TxOutput::new(u64::MAX, p2pkh.clone()).is_dust(rate));
+        let op_return = Script::from_hex("006a075354554b2e434f").unwrap();
+        assert!(!TxOutput::new(0, op_return).is_dust(rate));
+
+        let mut builder = TxBuilder::new();
+        builder.add_output(&TxOutput::new(545, p2pkh.clone()));
+        assert!(builder.build().is_ok());
+        assert!(builder.reject_dust(rate).build().is_err());
+        let mut builder = TxBuilder::new();
+        builder
+            .reject_dust(rate)
+            .add_output(&TxOutput::new(546, p2pkh));
+        assert!(builder.build().is_ok());
+    }
+
+    fn selection_fixture() -> (TxBuilder, Address) {
+        let address = KeyPair::from_seed(1, BlockchainId::Main).address;
+        let mut builder = TxBuilder::new();
+        builder
+            .set_fee_rate(FeeRate::from_sats_per_kb(1000))
+            .add_output(&TxOutput::new(30_000, address.locking_script()));
+        (builder, address)
+    }
+
+    fn candidates(values: &[u64]) -> Vec<(Outpoint, TxOutput)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                (
+                    Outpoint {
+                        tx_hash: Hash::sha256d(&[i as u8]),
+                        index: i as u32,
+                    },
+                    TxOutput::new(*v, Script::from(Vec::new())),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn select_largest_first() {
+        let (mut builder, address) = selection_fixture();
+        let c = candidates(&[10_000, 50_000, 20_000, 5_000]);
+        builder.add_change_output(&address);
+        let fee = builder
+            .select_inputs(&c, CoinSelection::LargestFirst)
+            .unwrap();
+        // 1 input, 2 outputs = 226 bytes
+        assert_eq!(fee, Amount::from_satoshis(226));
+        let tx = builder.build().unwrap();
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].outpoint, c[1].0);
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[1].value, 19_774);
+        assert_eq!(tx.outputs[1].script, address.locking_script());
+    }
+
+    #[test]
+    fn select_accumulate() {
+        let (mut builder, address) = selection_fixture();
+        let c = candidates(&[10_000, 50_000, 20_000, 5_000]);
+        builder.add_change_output(&address);
+        let fee = builder
+            .select_inputs(&c, CoinSelection::Accumulate)
+            .unwrap();
+        // 2 inputs, 2 outputs = 374 bytes
+        assert_eq!(fee, Amount::from_satoshis(374));
+        let tx = builder.build().unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.inputs[0].outpoint, c[0].0);
+        assert_eq!(tx.inputs[1].outpoint, c[1].0);
+        assert_eq!(tx.outputs[1].value, 29_626);
+    }
+
+    #[test]
+    fn select_no_change() {
+        // exact match, no change output is added
+        let (mut builder, address) = selection_fixture();
+        builder.add_change_output(&address);
+        let fee = builder
+            .select_inputs(&candidates(&[30_192]), CoinSelection::LargestFirst)
+            .unwrap();
+        assert_eq!(fee, Amount::from_satoshis(192));
+        assert_eq!(builder.build().unwrap().outputs.len(), 1);
+
+        // change would be dust, so it is added to the fee
+        let (mut builder, address) = selection_fixture();
+        builder.add_change_output(&address);
+        let fee = builder
+            .select_inputs(&candidates(&[30_500]), CoinSelection::LargestFirst)
+            .unwrap();
+        assert_eq!(fee, Amount::from_satoshis(500));
+        assert_eq!(builder.build().unwrap().outputs.len(), 1);
+    }
+
+    #[test]
+    fn select_insufficient_funds() {
+        let (mut builder, _) = selection_fixture();
+        let r = builder.select_inputs(&candidates(&[10_000, 5_000]), CoinSelection::LargestFirst);
+        match r {
+            Err(crate::Error::InsufficientFunds(shortfall)) => assert_eq!(shortfall, 15_340),
+            _ => panic!("expected insufficient funds"),
+        }
+        assert!(builder.build().unwrap().inputs.is_empty());
+    }
+
+    #[test]
+    fn find_outputs() {
+        let tx = Tx::from_binary_buf(&get_tx1().0).unwrap();
+        let address = Address {
+            hash160: Hash160 {
+                hash: hex::decode("54cba8da8701174e34aac2bb31d42a88e2c302d0")
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+            },
+            kind: KeyAddressKind::Main,
+        };
+        let found = tx.outputs_to_address(&address);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 1);
+        assert_eq!(found[0].1.value, 20_958_904);
+        let data = tx.outputs_matching_template(ScriptTemplate::OpReturn);
+        assert_eq!(data.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0]);
+        assert!(tx
+            .outputs_matching_template(ScriptTemplate::P2pk)
+            .is_empty());
+        let short = tx.find_outputs(|s| s.raw.len() < 20);
+        assert_eq!(short.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0]);
+
+        let spent = tx.inputs[0].outpoint.clone();
+        let found = tx.inputs_spending(&spent);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 0);
+        let other = Outpoint {
+            tx_hash: spent.tx_hash,
+            index: spent.index + 1,
+        };
+        assert!(tx.inputs_spending(&other).is_empty());
+    }
+
     fn get_tx1() -> (Vec<u8>, Hash) {
         let tx_hex = "01000000018a052edc7ae2136bfc0a860cdc91185ab0d7329107802f0a9c1cd0026c815f75010000006b483045022100e587ef1b4497a6694cad646cab468b6ece2fa98c7f49f9488611ca34eecebd1002205c4ea9066484bd1bffb7fdd7d84b5ae0ee6b7cdc20a8a513e41e420e0633b98841210262142850483b6728b8ecd299e4d0c8cf30ea0636f66205166814e52d73b64b4bffffffff0200000000000000000a006a075354554b2e434fb8ce3f01000000001976a91454cba8da8701174e34aac2bb31d42a88e2c302d088ac00000000";
         let tx_hash = "3abc31f8ff40ffb66d9037e156842fe782e6fa1ae728759263471c68660095f1";
         let tx_bin = hex::decode(tx_hex).unwrap();
         (tx_bin, Hash::from_hex(tx_hash).unwrap())
     }
+
+    proptest::proptest! {
+        #[test]
+        fn round_trip(tx in crate::testing::arb_tx()) {
+            let bin = tx.to_binary_buf().unwrap();
+            proptest::prop_assert_eq!(bin.len(), tx.async_size());
+            proptest::prop_assert_eq!(Tx::from_binary_buf(&bin).unwrap(), tx);
+        }
+    }
+
+    #[test]
+    fn scan_matches_parse() {
+        let raw = std::fs::read(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .unwrap();
+        let block = crate::bitcoin::Block::from_binary_buf(&raw).unwrap();
+        // the block has fewer than 253 transactions, so the count is a single byte
+        let mut buf = &raw[crate::bitcoin::BlockHeader::SIZE + 1..];
+        for tx in block.transactions.iter() {
+            let summary = scan_tx(&mut buf).unwrap();
+            assert_eq!(summary.txid, tx.hash());
+            assert_eq!(summary.size, tx.async_size());
+            assert_eq!(summary.n_inputs, tx.inputs.len() as u64);
+            assert_eq!(summary.n_outputs, tx.outputs.len() as u64);
+            assert_eq!(
+                summary.total_out_value,
+                tx.outputs.iter().map(|o| o.value).sum::<u64>()
+            );
+        }
+        assert!(buf.is_empty());
+
+        // a buffer that is not contiguous is hashed in pieces
+        let tx = &block.transactions[1];
+        let bin = tx.to_binary_buf().unwrap();
+        let (a, b) = bin.split_at(50);
+        let mut chained = Buf::chain(a, b);
+        assert_eq!(scan_tx(&mut chained).unwrap().txid, tx.hash());
+        assert!(scan_tx(&mut &bin[..bin.len() - 1]).is_err());
+    }
 }
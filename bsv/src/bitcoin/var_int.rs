@@ -1,3 +1,5 @@
+use bytes::{Buf, BufMut};
+use std::cmp::min;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The size of the value encoded as a varint.
@@ -12,16 +14,76 @@ pub fn varint_size(value: u64) -> usize {
 
 /// Decode a variable length integer from a byte stream, async version.
 pub async fn varint_decode<R: AsyncRead + Unpin + Send>(reader: &mut R) -> crate::Result<u64> {
-    let n0 = reader.read_u8().await.unwrap();
+    let n0 = reader.read_u8().await?;
     let v = match n0 {
-        0xff => reader.read_u64_le().await.unwrap(),
-        0xfe => reader.read_u32_le().await.unwrap() as u64,
-        0xfd => reader.read_u16_le().await.unwrap() as u64,
+        0xff => reader.read_u64_le().await?,
+        0xfe => reader.read_u32_le().await? as u64,
+        0xfd => reader.read_u16_le().await? as u64,
         _ => n0 as u64,
     };
     Ok(v)
 }
 
+/// Decode a variable length integer from a buffer.
+pub fn varint_read<B: Buf + ?Sized>(buf: &mut B) -> crate::Result<u64> {
+    if !buf.has_remaining() {
+        return Err(crate::Error::DataTooSmall);
+    }
+    let n0 = buf.get_u8();
+    let len = match n0 {
+        0xff => 8,
+        0xfe => 4,
+        0xfd => 2,
+        _ => return Ok(n0 as u64),
+    };
+    if buf.remaining() < len {
+        return Err(crate::Error::DataTooSmall);
+    }
+    Ok(buf.get_uint_le(len))
+}
+
+/// Encode a variable length integer into a buffer.
+pub fn varint_write<B: BufMut + ?Sized>(buf: &mut B, value: u64) {
+    match value {
+        0..=252 => buf.put_u8(value as u8),
+        253..=0xffff => {
+            buf.put_u8(0xfd);
+            buf.put_u16_le(value as u16);
+        }
+        0x10000..=0xffffffff => {
+            buf.put_u8(0xfe);
+            buf.put_u32_le(value as u32);
+        }
+        _ => {
+            buf.put_u8(0xff);
+            buf.put_u64_le(value);
+        }
+    }
+}
+
+/// Decode a variable length string from a byte stream, async version.
+///
+/// The string is encoded as a varint length followed by the UTF-8 bytes of the string. Strings longer
+/// than `max_size` bytes are rejected before any space is allocated for them.
+// used by the P2P messages
+#[cfg_attr(not(feature = "p2p"), allow(dead_code))]
+pub(crate) async fn varstr_decode<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+    max_size: u64,
+) -> crate::Result<String> {
+    let size = varint_decode(reader).await?;
+    if size > max_size {
+        return Err(crate::Error::DataTooLarge);
+    }
+    // only allocate space as the data arrives
+    let mut bytes = Vec::with_capacity(min(size, 1024) as usize);
+    let n = reader.take(size).read_to_end(&mut bytes).await?;
+    if n != size as usize {
+        return Err(crate::Error::DataTooSmall);
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
 /// Encode a variable length integer into a byte stream, async version.
 pub async fn varint_encode<W: AsyncWrite + Unpin + Send>(
     writer: &mut W,
@@ -73,6 +135,46 @@ mod tests {
         varint_encode(&mut v, n).await.unwrap();
         let j = varint_decode(&mut Cursor::new(&v)).await.unwrap();
         assert_eq!(j, n);
+        // the sync versions produce and accept the same encoding
+        let mut w: Vec<u8> = Vec::new();
+        varint_write(&mut w, n);
+        assert_eq!(w, v);
+        assert_eq!(varint_read(&mut &w[..]).unwrap(), n);
+    }
+
+    #[tokio::test]
+    async fn read_varstr() {
+        let v = [3u8, b'a', b'b', b'c'];
+        assert_eq!(varstr_decode(&mut Cursor::new(&v), 3).await.unwrap(), "abc");
+        assert!(matches!(
+            varstr_decode(&mut Cursor::new(&v), 2).await,
+            Err(crate::Error::DataTooLarge)
+        ));
+        assert!(matches!(
+            varstr_decode(&mut Cursor::new(&v[..3]), 3).await,
+            Err(crate::Error::DataTooSmall)
+        ));
+        // huge size with no data
+        let v = [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(varstr_decode(&mut Cursor::new(&v), u64::MAX).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_short() {
+        assert!(varint_decode(&mut Cursor::new(&[])).await.is_err());
+        assert!(varint_decode(&mut Cursor::new(&[0xfd, 1])).await.is_err());
+        assert!(varint_decode(&mut Cursor::new(&[0xfe, 1, 2, 3]))
+            .await
+            .is_err());
+        assert!(
+            varint_decode(&mut Cursor::new(&[0xff, 1, 2, 3, 4, 5, 6, 7]))
+                .await
+                .is_err()
+        );
+        assert!(varint_read(&mut &[][..]).is_err());
+        assert!(varint_read(&mut &[0xfd, 1][..]).is_err());
+        assert!(varint_read(&mut &[0xfe, 1, 2, 3][..]).is_err());
+        assert!(varint_read(&mut &[0xff, 1, 2, 3, 4, 5, 6, 7][..]).is_err());
     }
 
     #[tokio::test]
@@ -0,0 +1,193 @@
+use crate::bitcoin::{BlockHeader, Hash};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Add;
+
+/// The expected number of hashes needed to produce a block, or the sum of it over a chain of blocks.
+///
+/// The chain with the most accumulated work is the best chain. The work is a 256-bit unsigned number,
+/// held as four 64-bit limbs, least significant first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChainWork([u64; 4]);
+
+impl ChainWork {
+    pub const SIZE: usize = 32;
+    pub const ZERO: ChainWork = ChainWork([0; 4]);
+
+    /// The work of a block whose hash meets `target`, which is `2^256 / (target + 1)`.
+    pub fn from_target(target: &Hash) -> ChainWork {
+        let target = limbs(&target.hash);
+        // 2^256 does not fit, so calculate (2^256 - target - 1) / (target + 1) + 1 instead
+        let (divisor, overflow) = add(&target, &[1, 0, 0, 0]);
+        if overflow {
+            // the target is 2^256 - 1, which any hash meets
+            return ChainWork([1, 0, 0, 0]);
+        }
+        let quotient = div(&target.map(|l| !l), &divisor);
+        ChainWork(add(&quotient, &[1, 0, 0, 0]).0)
+    }
+
+    /// The work of the block, None if the target bits of the header are not valid.
+    pub fn from_header(header: &BlockHeader) -> Option<ChainWork> {
+        header.target().map(|t| ChainWork::from_target(&t))
+    }
+
+    /// Add two amounts of work, None if the result does not fit in 256 bits.
+    pub fn checked_add(&self, other: &ChainWork) -> Option<ChainWork> {
+        match add(&self.0, &other.0) {
+            (sum, false) => Some(ChainWork(sum)),
+            (_, true) => None,
+        }
+    }
+
+    /// The work as a big-endian number.
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().rev().enumerate() {
+            bytes[i * 8..(i + 1) * 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Read the work from a big-endian number.
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> ChainWork {
+        let mut work = [0u64; 4];
+        for (i, limb) in work.iter_mut().rev().enumerate() {
+            *limb = u64::from_be_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
+        }
+        ChainWork(work)
+    }
+}
+
+impl From<u128> for ChainWork {
+    fn from(value: u128) -> Self {
+        ChainWork([value as u64, (value >> 64) as u64, 0, 0])
+    }
+}
+
+impl Add for ChainWork {
+    type Output = ChainWork;
+
+    /// Add two amounts of work, panics on overflow. The total work of any real chain is far below
+    /// the limit.
+    fn add(self, rhs: ChainWork) -> ChainWork {
+        self.checked_add(&rhs).expect("chain work overflow")
+    }
+}
+
+impl Ord for ChainWork {
+    fn cmp(&self, other: &ChainWork) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for ChainWork {
+    fn partial_cmp(&self, other: &ChainWork) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for ChainWork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.to_be_bytes()))
+    }
+}
+
+/// The limbs of a little-endian 256-bit number.
+fn limbs(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0u64; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
+    }
+    limbs
+}
+
+fn add(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut sum = [0u64; 4];
+    let mut carry = false;
+    for i in 0..4 {
+        let (s, c1) = a[i].overflowing_add(b[i]);
+        let (s, c2) = s.overflowing_add(carry as u64);
+        sum[i] = s;
+        carry = c1 || c2;
+    }
+    (sum, carry)
+}
+
+fn sub(a: &mut [u64; 4], b: &[u64; 4]) {
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d, b1) = a[i].overflowing_sub(b[i]);
+        let (d, b2) = d.overflowing_sub(borrow as u64);
+        a[i] = d;
+        borrow = b1 || b2;
+    }
+}
+
+/// Binary long division, the divisor must not be zero.
+fn div(n: &[u64; 4], d: &[u64; 4]) -> [u64; 4] {
+    let mut q = [0u64; 4];
+    let mut r = [0u64; 4];
+    for i in (0..256).rev() {
+        // shift the next bit of the numerator into the remainder, which may then exceed 256 bits
+        let carry = r[3] >> 63;
+        for j in (1..4).rev() {
+            r[j] = (r[j] << 1) | (r[j - 1] >> 63);
+        }
+        r[0] = (r[0] << 1) | ((n[i / 64] >> (i % 64)) & 1);
+        if carry == 1 || r.iter().rev().cmp(d.iter().rev()) != Ordering::Less {
+            sub(&mut r, d);
+            q[i / 64] |= 1 << (i % 64);
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::BlockchainId;
+
+    #[test]
+    fn genesis_work() {
+        let main = BlockHeader::get_genesis(BlockchainId::Main);
+        assert_eq!(
+            ChainWork::from_header(&main),
+            Some(ChainWork::from(0x1_0001_0001))
+        );
+        let regtest = BlockHeader::get_genesis(BlockchainId::Regtest);
+        assert_eq!(ChainWork::from_header(&regtest), Some(ChainWork::from(2)));
+        // a target of 2^255 - 1 is met by half of the hashes
+        let mut target = Hash { hash: [0xff; 32] };
+        target.hash[31] = 0x7f;
+        assert_eq!(ChainWork::from_target(&target), ChainWork::from(2));
+        assert_eq!(
+            ChainWork::from_target(&Hash { hash: [0xff; 32] }),
+            ChainWork::from(1)
+        );
+        // the smallest target gives the most work
+        let mut one = Hash::ZERO;
+        one.hash[0] = 1;
+        let mut expected = [0u8; 32];
+        expected[0] = 0x80;
+        assert_eq!(
+            ChainWork::from_target(&one),
+            ChainWork::from_be_bytes(&expected)
+        );
+    }
+
+    #[test]
+    fn add_and_order() {
+        let a = ChainWork::from(u64::MAX as u128);
+        let b = a + ChainWork::from(1);
+        assert_eq!(b, ChainWork::from(1u128 << 64));
+        assert!(b > a);
+        assert_eq!(ChainWork::from_be_bytes(&b.to_be_bytes()), b);
+        assert_eq!(
+            b.to_string(),
+            "0000000000000000000000000000000000000000000000010000000000000000"
+        );
+        let max = ChainWork::from_be_bytes(&[0xff; 32]);
+        assert_eq!(max.checked_add(&ChainWork::from(1)), None);
+    }
+}
@@ -12,17 +12,34 @@
 //! not the network. The P2P network is just a means for software to communicate, it does not define
 //! the blockchain.
 //!
+//! * the `p2p` feature, which is enabled by default, builds the [p2p] module and the networking parts
+//! of tokio that it needs. Applications that only work with transactions, scripts and blocks can
+//! disable default features to leave them out.
+//!
+//! * the `key-generation` feature, also enabled by default, generates keys from the random number
+//! generator of the operating system. Without it and without `p2p` the [bitcoin] module has no OS
+//! dependencies and can be built for `wasm32-unknown-unknown`. The digests of `ring` still need
+//! `getrandom` there, which the application enables with its `js` feature.
+//!
 //! * the library will probably never support old versions of Bitcoin. Support for old versions is dead
 //! code and will be removed as quickly as possible.
 
 /// Functionality related to the core of Bitcoin SV. Transactions, Block Headers, etc.
 pub mod bitcoin;
 
-/// Functionality related to the Bitcoin SV peer-to-peer protocol and network.
+/// Functionality related to the Bitcoin SV peer-to-peer protocol and network, enabled by the `p2p`
+/// feature.
+#[cfg(feature = "p2p")]
 pub mod p2p;
 
 /// Utility functions.
 pub mod util;
 
+pub mod prelude;
+
+/// Property test strategies for transactions, scripts and blocks, enabled by the `test-utils` feature.
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
 mod result;
 pub use result::{Error, Result};
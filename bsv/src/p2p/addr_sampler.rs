@@ -0,0 +1,129 @@
+use crate::p2p::external_address::is_routable;
+use crate::p2p::messages::{NodeAddr, Services};
+use crate::p2p::peer_store::PeerStore;
+use async_trait::async_trait;
+use log::warn;
+use rand::seq::SliceRandom;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of the addresses of known peers, used to answer `getaddr` requests.
+#[async_trait]
+pub trait AddrSampler: Debug + Send + Sync {
+    /// Get the addresses of up to `max` peers that are worth sharing, each with the time it was
+    /// last seen.
+    async fn sample(&self, max: usize) -> Vec<NodeAddr>;
+}
+
+/// Samples the peers in a [PeerStore].
+///
+/// Only the routable addresses of peers that are not banned, and whose most recent connection
+/// attempts have not all failed, are shared. The timestamp of each address is the time of the last
+/// successful connection to the peer, and the peers are chosen at random so that repeated requests do
+/// not reveal the whole store.
+#[derive(Debug)]
+pub struct PeerStoreSampler {
+    store: Arc<dyn PeerStore>,
+}
+
+impl PeerStoreSampler {
+    /// Create a sampler of the peers in the store.
+    pub fn new(store: Arc<dyn PeerStore>) -> PeerStoreSampler {
+        PeerStoreSampler { store }
+    }
+}
+
+#[async_trait]
+impl AddrSampler for PeerStoreSampler {
+    async fn sample(&self, max: usize) -> Vec<NodeAddr> {
+        let peers = match self.store.list().await {
+            Ok(peers) => peers,
+            Err(e) => {
+                warn!("could not list peers to answer getaddr: {}", e);
+                return Vec::new();
+            }
+        };
+        let now = SystemTime::now();
+        let mut addrs: Vec<NodeAddr> = peers
+            .into_iter()
+            .filter(|(_, h)| !h.is_banned(now))
+            .filter_map(|(_, h)| {
+                let address = h.address.filter(|a| is_routable(&a.ip()))?;
+                // only peers that were connected to since their last failure are shared
+                let last = h.attempts.back().filter(|a| !a.outcome.is_failure())?;
+                let secs = last.timestamp.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some(NodeAddr {
+                    timestamp: secs as u32,
+                    services: h.last_session.map_or(Services::NONE, |s| s.services),
+                    ..NodeAddr::new(address.ip(), address.port())
+                })
+            })
+            .collect();
+        addrs.shuffle(&mut rand::thread_rng());
+        addrs.truncate(max);
+        addrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::peer_scoring::{ConnectionOutcome, PeerHistory, SessionStats};
+    use crate::p2p::peer_store::MemoryPeerStore;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn sample_accessible() {
+        let store = Arc::new(MemoryPeerStore::default());
+        let mut expected = Vec::new();
+        for (address, connected, failed) in [
+            ("8.8.8.8:8333", true, false),
+            ("8.8.4.4:8333", true, false),
+            ("10.0.0.1:8333", true, false),
+            ("1.1.1.1:8333", false, true),
+            ("9.9.9.9:8333", true, true),
+        ] {
+            let address: SocketAddr = address.parse().unwrap();
+            let id = store.create(address).await.unwrap();
+            let mut history = PeerHistory {
+                address: Some(address),
+                ..Default::default()
+            };
+            if connected {
+                history.record_session(&SessionStats {
+                    handshake_succeeded: true,
+                    ..Default::default()
+                });
+            }
+            if failed {
+                history.record_failure(ConnectionOutcome::Refused);
+            } else if is_routable(&address.ip()) {
+                expected.push(address);
+            }
+            store.put_batch(vec![(id, history)]).await.unwrap();
+        }
+        let banned: SocketAddr = "8.8.8.1:8333".parse().unwrap();
+        let id = store.create(banned).await.unwrap();
+        let mut history = PeerHistory::default();
+        history.record_session(&SessionStats {
+            handshake_succeeded: true,
+            ..Default::default()
+        });
+        history.ban(banned, SystemTime::now() + Duration::from_secs(60));
+        store.put_batch(vec![(id, history)]).await.unwrap();
+
+        let sampler = PeerStoreSampler::new(store);
+        let mut sampled: Vec<SocketAddr> = sampler
+            .sample(10)
+            .await
+            .iter()
+            .map(|a| SocketAddr::new(a.ip, a.port))
+            .collect();
+        sampled.sort();
+        expected.sort();
+        assert_eq!(sampled, expected);
+        assert_eq!(sampler.sample(1).await.len(), 1);
+    }
+}
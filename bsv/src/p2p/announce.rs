@@ -0,0 +1,109 @@
+use crate::bitcoin::TxHash;
+use crate::p2p::messages::{Inv, InvItem};
+use rand::Rng;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// The default maximum number of transactions in each inv message sent by the trickle.
+pub const DEFAULT_INV_BATCH_SIZE: usize = 1000;
+/// The default mean interval between flushes of the queued transaction announcements.
+pub const DEFAULT_INV_TRICKLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The transaction announcements waiting to be sent to a peer.
+///
+/// Announcing each transaction as soon as it is broadcast lets an observer that is connected to
+/// several nodes work out where a transaction started from the timing of the announcements. Instead
+/// the announcements are queued and sent together when the trickle timer fires, at intervals drawn
+/// from an exponential distribution (see [trickle_delay]). Block announcements are not queued.
+#[derive(Debug, Default)]
+pub(crate) struct AnnouncementQueue {
+    pending: Vec<TxHash>,
+    queued: HashSet<TxHash>,
+}
+
+impl AnnouncementQueue {
+    /// Queue a transaction, returning false if it is already queued.
+    pub fn push(&mut self, hash: TxHash) -> bool {
+        if !self.queued.insert(hash) {
+            return false;
+        }
+        self.pending.push(hash);
+        true
+    }
+
+    /// The number of queued transactions.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether there are no queued transactions.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Remove all of the queued transactions, in the order they were queued, as inv messages of at
+    /// most `batch_size` entries.
+    pub fn flush(&mut self, batch_size: usize) -> Vec<Inv> {
+        let batch_size = batch_size.clamp(1, Inv::MAX_INV_ENTRIES as usize);
+        self.queued.clear();
+        let invs = self
+            .pending
+            .chunks(batch_size)
+            .map(|chunk| Inv {
+                objects: chunk.iter().map(|h| InvItem::tx(*h)).collect(),
+            })
+            .collect();
+        self.pending.clear();
+        invs
+    }
+}
+
+/// The delay until the next flush of the queue, drawn from an exponential distribution with the
+/// given mean so that the flushes form a Poisson process.
+pub(crate) fn trickle_delay(mean: Duration) -> Duration {
+    // gen() is in [0, 1), so 1 - gen() is never 0
+    let u = 1.0 - rand::thread_rng().gen::<f64>();
+    // limit the tail, a delay of more than 8 times the mean is vanishingly rare anyway
+    mean.mul_f64((-u.ln()).min(8.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::Hash;
+
+    #[test]
+    fn queue_and_flush() {
+        let mut queue = AnnouncementQueue::default();
+        let hashes: Vec<TxHash> = (0..10u8).map(|i| Hash::sha256d(&[i])).collect();
+        for h in hashes.iter() {
+            assert!(queue.push(*h));
+        }
+        assert!(!queue.push(hashes[3]));
+        assert_eq!(queue.len(), 10);
+        let invs = queue.flush(4);
+        assert_eq!(
+            invs.iter().map(|i| i.objects.len()).collect::<Vec<_>>(),
+            vec![4, 4, 2]
+        );
+        let flushed: Vec<TxHash> = invs
+            .iter()
+            .flat_map(|i| i.objects.iter())
+            .map(|o| o.hash)
+            .collect();
+        assert_eq!(flushed, hashes);
+        assert_eq!(queue.len(), 0);
+        assert!(queue.flush(4).is_empty());
+        // can be queued again once it has been sent
+        assert!(queue.push(hashes[3]));
+    }
+
+    #[test]
+    fn delay_distribution() {
+        let mean = Duration::from_secs(2);
+        let delays: Vec<Duration> = (0..2000).map(|_| trickle_delay(mean)).collect();
+        assert!(delays.iter().all(|d| *d <= mean * 8));
+        let avg = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!(avg > Duration::from_millis(1700) && avg < Duration::from_millis(2300));
+    }
+}
@@ -0,0 +1,209 @@
+//! An audit trail of the decisions to ban and unban peers.
+//!
+//! Every ban and unban made by the [P2PManager](crate::p2p::P2PManager) is written to the
+//! [AuditSink] of its configuration as a [BanAuditEntry], so that the reason for a ban can be found
+//! long after it was made. The entry is written before the [PeerStore](crate::p2p::PeerStore) is
+//! updated, so it is not lost if the update fails.
+use crate::p2p::telemetry::{FIELD_ADDR, FIELD_EVENT, FIELD_PEER_ID, TARGET_BAN};
+use crate::Result;
+use log::{info, warn};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// What was decided about a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BanAction {
+    /// The peer was banned.
+    Ban,
+    /// The ban of the peer was lifted before it expired.
+    Unban,
+    /// The ban of the peer expired.
+    Expired,
+}
+
+impl fmt::Display for BanAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BanAction::Ban => "ban",
+            BanAction::Unban => "unban",
+            BanAction::Expired => "expired",
+        })
+    }
+}
+
+/// A single decision to ban or unban a peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BanAuditEntry {
+    /// When the decision was made.
+    pub time: SystemTime,
+    /// The id of the peer.
+    pub peer_id: Uuid,
+    /// The address of the peer, the ban applies to its IP address.
+    pub addr: SocketAddr,
+    /// What was decided.
+    pub action: BanAction,
+    /// Why it was decided.
+    pub reason: String,
+    /// The quality score of the peer before the decision, if it is known.
+    pub score_before: Option<f64>,
+    /// The quality score of the peer after the decision, if it is known.
+    pub score_after: Option<f64>,
+    /// When the ban expires, None if the peer is no longer banned.
+    pub expires: Option<SystemTime>,
+}
+
+impl BanAuditEntry {
+    /// The entry as a single line of JSON, with the times in milliseconds since the epoch.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "time": epoch_millis(self.time),
+            "peer_id": self.peer_id.to_string(),
+            "addr": self.addr.to_string(),
+            "action": self.action.to_string(),
+            "reason": self.reason,
+            "score_before": self.score_before,
+            "score_after": self.score_after,
+            "expires": self.expires.map(epoch_millis),
+        })
+        .to_string()
+    }
+}
+
+/// The `name=value` fields of the entry, as in the other structured log records, see
+/// [telemetry](crate::p2p::telemetry).
+impl fmt::Display for BanAuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}={} {}={} {}={} reason={:?}",
+            FIELD_PEER_ID,
+            self.peer_id,
+            FIELD_ADDR,
+            self.addr,
+            FIELD_EVENT,
+            self.action,
+            self.reason
+        )?;
+        if let Some(score) = self.score_before {
+            write!(f, " score_before={:.3}", score)?;
+        }
+        if let Some(score) = self.score_after {
+            write!(f, " score_after={:.3}", score)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, " expires={}", epoch_millis(expires))?;
+        }
+        Ok(())
+    }
+}
+
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Where the [BanAuditEntry]s are written.
+///
+/// Entries are written from the manager, so a sink should not block for long. A sink that fails to
+/// write an entry should log the failure itself, the decision stands either way.
+pub trait AuditSink: fmt::Debug + Send + Sync {
+    /// Append an entry to the audit trail.
+    fn record(&self, entry: &BanAuditEntry);
+}
+
+/// Writes the entries to the log at info level, with the target [TARGET_BAN].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogAuditSink;
+
+impl AuditSink for LogAuditSink {
+    fn record(&self, entry: &BanAuditEntry) {
+        info!(target: TARGET_BAN, "{}", entry);
+    }
+}
+
+/// Appends the entries to a file, one line of JSON each, see [BanAuditEntry::to_json()].
+///
+/// Each entry is flushed as it is written, so the file is complete up to the last decision even if
+/// the process is killed.
+#[derive(Debug)]
+pub struct JsonLinesAuditSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesAuditSink {
+    /// Create or append to the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonLinesAuditSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesAuditSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for JsonLinesAuditSink {
+    fn record(&self, entry: &BanAuditEntry) {
+        let mut file = self.file.lock().unwrap();
+        let line = entry.to_json() + "\n";
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            warn!("could not write ban audit entry, {}: {}", entry, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn json_lines() {
+        let path = std::env::temp_dir().join(format!("ban_audit_{}.jsonl", Uuid::new_v4()));
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let entry = BanAuditEntry {
+            time,
+            peer_id: Uuid::nil(),
+            addr: "192.0.2.1:8333".parse().unwrap(),
+            action: BanAction::Ban,
+            reason: "banned by operator".to_string(),
+            score_before: Some(0.5),
+            score_after: Some(0.5),
+            expires: Some(time + Duration::from_secs(3600)),
+        };
+        let unban = BanAuditEntry {
+            action: BanAction::Unban,
+            score_before: None,
+            score_after: None,
+            expires: None,
+            ..entry.clone()
+        };
+        // the file is appended to when it is opened again
+        JsonLinesAuditSink::open(&path).unwrap().record(&entry);
+        JsonLinesAuditSink::open(&path).unwrap().record(&unban);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["time"], 1_700_000_000_123u64);
+        assert_eq!(lines[0]["addr"], "192.0.2.1:8333");
+        assert_eq!(lines[0]["action"], "ban");
+        assert_eq!(lines[0]["score_before"], 0.5);
+        assert_eq!(lines[0]["expires"], 1_700_003_600_123u64);
+        assert_eq!(lines[1]["action"], "unban");
+        assert!(lines[1]["expires"].is_null());
+        assert_eq!(
+            entry.to_string(),
+            "peer_id=00000000-0000-0000-0000-000000000000 addr=192.0.2.1:8333 event=ban \
+             reason=\"banned by operator\" score_before=0.500 score_after=0.500 expires=1700003600123"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::bitcoin::{Hash, TxHash};
+use crate::p2p::envelope::{P2PEnvelope, P2PMessageChannelReceiver};
+use crate::p2p::messages::{InvType, P2PMessage, Reject};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+/// How the peers responded to the broadcast of a transaction, see
+/// [P2PManager::broadcast_tx_and_report()](crate::p2p::P2PManager::broadcast_tx_and_report).
+///
+/// A peer that does not have the transaction requests it with a `getdata` message after the
+/// announcement, and a peer that does not accept it may answer with a `reject` message. Peers that
+/// already have the transaction, and peers that drop it silently, do neither.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastReport {
+    /// The hash of the transaction.
+    pub hash: TxHash,
+    /// The peers that were connected when the transaction was announced.
+    pub announced_to: Vec<Uuid>,
+    /// The peers that requested the transaction.
+    pub requested_by: Vec<Uuid>,
+    /// The peers that rejected the transaction, with their reject message.
+    pub rejected_by: Vec<(Uuid, Reject)>,
+}
+
+impl BroadcastReport {
+    /// Start a report for a transaction announced to the peers.
+    pub fn new(hash: TxHash, announced_to: Vec<Uuid>) -> BroadcastReport {
+        BroadcastReport {
+            hash,
+            announced_to,
+            requested_by: Vec::new(),
+            rejected_by: Vec::new(),
+        }
+    }
+
+    /// Record a message from the data channel, returning true if it was a response to the broadcast.
+    pub fn observe(&mut self, envelope: &P2PEnvelope) -> bool {
+        match &envelope.message {
+            P2PMessage::GetData(inv)
+                if inv
+                    .objects
+                    .iter()
+                    .any(|i| i.obj_type == InvType::Tx && i.hash == self.hash) =>
+            {
+                if !self.requested_by.contains(&envelope.peer_id) {
+                    self.requested_by.push(envelope.peer_id);
+                }
+                true
+            }
+            P2PMessage::Reject(reject)
+                if reject.message == "tx"
+                    && Hash::from_slice(&reject.data).ok() == Some(self.hash) =>
+            {
+                if !self.rejected_by.iter().any(|(p, _)| *p == envelope.peer_id) {
+                    self.rejected_by.push((envelope.peer_id, reject.clone()));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the transaction was announced and every peer that it was announced to
+    /// rejected it.
+    pub fn is_rejected(&self) -> bool {
+        !self.announced_to.is_empty()
+            && self
+                .announced_to
+                .iter()
+                .all(|p| self.rejected_by.iter().any(|(r, _)| r == p))
+    }
+
+    /// Record the responses that arrive on the data channel within `wait`, stopping early if every
+    /// peer rejects the transaction.
+    pub async fn collect(&mut self, rx: &mut P2PMessageChannelReceiver, wait: Duration) {
+        let deadline = tokio::time::Instant::now() + wait;
+        while !self.is_rejected() {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(envelope)) => {
+                    self.observe(&envelope);
+                }
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::messages::{Inv, InvItem, REJECT_INSUFFICIENT_FEE};
+    use crate::p2p::ChannelConfig;
+    use std::sync::Arc;
+
+    fn from(peer_id: Uuid, message: P2PMessage) -> Arc<P2PEnvelope> {
+        let mut envelope = P2PEnvelope::new(message, &ChannelConfig::default());
+        envelope.peer_id = peer_id;
+        Arc::new(envelope)
+    }
+
+    fn reject(hash: TxHash) -> P2PMessage {
+        P2PMessage::Reject(Reject {
+            message: "tx".to_string(),
+            code: REJECT_INSUFFICIENT_FEE,
+            reason: "mempool min fee not met".to_string(),
+            data: hash.to_wire_bytes().to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn collect_responses() {
+        let hash = Hash::sha256d(b"tx");
+        let other = Hash::sha256d(b"other");
+        let peers = [Uuid::new_v4(), Uuid::new_v4()];
+        let getdata = |h| {
+            P2PMessage::GetData(Inv {
+                objects: vec![InvItem::tx(h)],
+            })
+        };
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        for envelope in [
+            from(peers[0], getdata(other)),
+            from(peers[0], getdata(hash)),
+            from(peers[0], getdata(hash)),
+            from(peers[1], reject(other)),
+            from(peers[1], reject(hash)),
+        ] {
+            tx.send(envelope).unwrap();
+        }
+        let mut report = BroadcastReport::new(hash, peers.to_vec());
+        report.collect(&mut rx, Duration::from_millis(50)).await;
+        assert_eq!(report.requested_by, vec![peers[0]]);
+        assert_eq!(report.rejected_by.len(), 1);
+        assert_eq!(report.rejected_by[0].1.code, REJECT_INSUFFICIENT_FEE);
+        assert!(!report.is_rejected());
+
+        // the wait ends as soon as the last peer rejects the transaction
+        tx.send(from(peers[0], reject(hash))).unwrap();
+        report.collect(&mut rx, Duration::from_secs(3600)).await;
+        assert!(report.is_rejected());
+        assert!(!BroadcastReport::new(hash, Vec::new()).is_rejected());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::util::epoch_millis;
+use crate::{Error, Result};
+use log::warn;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// The bytes at the start of a capture file.
+pub const CAPTURE_MAGIC: [u8; 8] = *b"bsvcap01";
+
+/// The number of records that can be waiting to be written before records are dropped.
+const TAP_QUEUE_SIZE: usize = 10_000;
+
+/// The direction in which bytes were sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Received from the peer.
+    Inbound,
+    /// Sent to the peer.
+    Outbound,
+}
+
+/// A chunk of bytes sent or received on a connection, as recorded in a capture file.
+///
+/// The chunks are the bytes as they were read from or written to the stream, so a chunk may contain
+/// part of a message or several messages. Use a [MessageFramer](crate::p2p::MessageFramer) or a
+/// [CaptureReader](crate::p2p::replay::CaptureReader) to recover the messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    /// The peer that the bytes were sent to or received from.
+    pub peer_id: Uuid,
+    /// When the bytes were sent or received, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Encode the record as it is stored in a capture file: the direction (0 for inbound, 1 for
+    /// outbound), the peer id, the timestamp, the length of the data as a little-endian u32, and the data.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(match self.direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        });
+        buf.extend_from_slice(self.peer_id.as_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.data);
+    }
+}
+
+enum TapCommand {
+    Record(CaptureRecord),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Records the bytes sent and received on connections to a capture file, for debugging.
+///
+/// The records are written by a separate task so that connections are not slowed down by the file.
+/// If the writer falls behind, records are dropped rather than blocking the connection, and the
+/// number dropped is available from [dropped()](MessageTap::dropped). A single tap can be shared by
+/// several connections, the records include the peer id.
+#[derive(Debug, Clone)]
+pub struct MessageTap {
+    sender: mpsc::Sender<TapCommand>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MessageTap {
+    /// Create or append to the capture file at `path` and start the writer task.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<MessageTap> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        if file.metadata().await?.len() == 0 {
+            file.write_all(&CAPTURE_MAGIC).await?;
+        }
+        let (sender, receiver) = mpsc::channel(TAP_QUEUE_SIZE);
+        tokio::spawn(MessageTap::writer(receiver, BufWriter::new(file)));
+        Ok(MessageTap {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Record bytes sent or received, without waiting.
+    pub fn record(&self, direction: Direction, peer_id: Uuid, data: &[u8]) {
+        let record = CaptureRecord {
+            direction,
+            peer_id,
+            timestamp: epoch_millis(),
+            data: data.to_vec(),
+        };
+        if self.sender.try_send(TapCommand::Record(record)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait until the records sent so far have been written to the file.
+    pub async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        let stopped = || Error::Internal("capture writer has stopped".to_string());
+        self.sender
+            .send(TapCommand::Flush(tx))
+            .await
+            .map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    /// The number of records that were dropped because the writer could not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The writer task, it ends when all of the taps have been dropped.
+    async fn writer(
+        mut receiver: mpsc::Receiver<TapCommand>,
+        mut file: BufWriter<tokio::fs::File>,
+    ) {
+        let mut buf = Vec::new();
+        while let Some(command) = receiver.recv().await {
+            let result = match command {
+                TapCommand::Record(record) => {
+                    buf.clear();
+                    record.encode(&mut buf);
+                    file.write_all(&buf).await
+                }
+                TapCommand::Flush(reply) => {
+                    let r = file.flush().await;
+                    let _ = reply.send(r.map_err(Error::from));
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                warn!("error writing capture file, capture stopped, error: {}", e);
+                return;
+            }
+        }
+        let _ = file.flush().await;
+    }
+}
+
+/// Wraps a stream, recording everything read from and written to it with a [MessageTap].
+pub(crate) struct TapStream<S> {
+    inner: S,
+    tap: MessageTap,
+    peer_id: Uuid,
+}
+
+impl<S> TapStream<S> {
+    pub(crate) fn new(inner: S, tap: MessageTap, peer_id: Uuid) -> TapStream<S> {
+        TapStream {
+            inner,
+            tap,
+            peer_id,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TapStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let r = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = r {
+            let data = &buf.filled()[before..];
+            if !data.is_empty() {
+                self.tap.record(Direction::Inbound, self.peer_id, data);
+            }
+        }
+        r
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TapStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let r = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = r {
+            if n > 0 {
+                self.tap
+                    .record(Direction::Outbound, self.peer_id, &buf[..n]);
+            }
+        }
+        r
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
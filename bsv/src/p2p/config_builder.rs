@@ -0,0 +1,38 @@
+/// Define a builder for a configuration struct, with a setter for each of the listed fields.
+///
+/// The builder starts from the default configuration, so that the defaults are only defined by the
+/// configuration itself, and `build()` returns the configuration once it passes `validate()`.
+macro_rules! config_builder {
+    (
+        $(#[$meta:meta])*
+        $builder:ident for $config:ident {
+            $($field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default)]
+        pub struct $builder {
+            config: $config,
+        }
+
+        impl $builder {
+            $(
+                #[doc = concat!("Set [", stringify!($config), "::", stringify!($field), "].")]
+                pub fn $field(&mut self, $field: $ty) -> &mut $builder {
+                    self.config.$field = $field;
+                    self
+                }
+            )*
+
+            /// Check the configuration, see
+            #[doc = concat!("[", stringify!($config), "::validate()],")]
+            /// and return it if it is valid.
+            pub fn build(&self) -> std::result::Result<$config, $crate::p2p::ConfigError> {
+                self.config.validate()?;
+                Ok(self.config.clone())
+            }
+        }
+    };
+}
+
+pub(crate) use config_builder;
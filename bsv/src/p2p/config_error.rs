@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// An inconsistent or out of range value in a [P2PManagerConfig](crate::p2p::P2PManagerConfig) or
+/// [ConnectionConfig](crate::p2p::ConnectionConfig).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `listen` is enabled but `connections_max` is zero, so every inbound connection would be refused.
+    ListenWithoutConnections,
+    /// `listen` is enabled with a `listen_port` of zero.
+    ZeroListenPort,
+    /// `connections_target` is larger than `connections_max`, the target can never be met.
+    TargetExceedsMax { target: u16, max: u16 },
+    /// `retries` is non-zero but `retry_delay` is zero, the retries would be attempted immediately.
+    ZeroRetryDelay { retries: u8 },
+    /// `max_recv_payload_size` is outside of the range that can be sent in a protoconf message.
+    RecvPayloadSizeOutOfRange { size: u64, min: u64, max: u64 },
+    /// `excessive_block_size` is zero, every block would be rejected.
+    ZeroExcessiveBlockSize,
+    /// `max_outbound_per_netgroup` is zero, no peer would ever be selected.
+    ZeroNetGroupLimit,
+    /// `inv_batch_size` is zero, no transaction could be announced.
+    ZeroInvBatchSize,
+    /// `operating_mode` is `FixedPeerList` but the fixed list, `initial_peers` and `bootstrap_peers`,
+    /// is empty.
+    EmptyFixedPeerList,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigError::*;
+        match self {
+            ListenWithoutConnections => write!(f, "listen is enabled but connections_max is 0"),
+            ZeroListenPort => write!(f, "listen is enabled but listen_port is 0"),
+            TargetExceedsMax { target, max } => write!(
+                f,
+                "connections_target {} exceeds connections_max {}",
+                target, max
+            ),
+            ZeroRetryDelay { retries } => {
+                write!(f, "retries is {} but retry_delay is 0", retries)
+            }
+            RecvPayloadSizeOutOfRange { size, min, max } => write!(
+                f,
+                "max_recv_payload_size {} is not between {} and {}",
+                size, min, max
+            ),
+            ZeroExcessiveBlockSize => write!(f, "excessive_block_size is 0"),
+            ZeroNetGroupLimit => write!(f, "max_outbound_per_netgroup is 0"),
+            ZeroInvBatchSize => write!(f, "inv_batch_size is 0"),
+            EmptyFixedPeerList => {
+                write!(
+                    f,
+                    "operating_mode is FixedPeerList but initial_peers and bootstrap_peers are empty"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -1,12 +1,35 @@
-use crate::bitcoin::BlockchainId;
 use crate::bitcoin::BlockchainId::Main;
+use crate::bitcoin::{BlockHash, BlockchainId, TxHash};
+use crate::p2p::addr_sampler::{AddrSampler, PeerStoreSampler};
+use crate::p2p::announce::{DEFAULT_INV_BATCH_SIZE, DEFAULT_INV_TRICKLE_INTERVAL};
+use crate::p2p::capture::MessageTap;
 use crate::p2p::channel::{ChannelConfig, PeerChannel};
+use crate::p2p::config_builder::config_builder;
+use crate::p2p::connector::{Connector, TcpConnector};
 use crate::p2p::envelope::{P2PMessageChannelReceiver, P2PMessageChannelSender};
-use crate::p2p::params::{DEFAULT_EXCESSIVE_BLOCK_SIZE, DEFAULT_MAX_RECV_PAYLOAD_SIZE};
+use crate::p2p::external_address::ExternalAddress;
+use crate::p2p::header_store::HeaderProvider;
+use crate::p2p::health::HealthConfig;
+use crate::p2p::mempool::MempoolResponder;
+use crate::p2p::messages::{BlockLocator, BlockStream, ChecksumPolicy, Services};
+use crate::p2p::params::{
+    DEFAULT_EXCESSIVE_BLOCK_SIZE, DEFAULT_MAX_RECV_PAYLOAD_SIZE, DEFAULT_MAX_TIME_OFFSET,
+    MIN_MAX_RECV_PAYLOAD_SIZE,
+};
 use crate::p2p::peer::PeerAddress;
-use crate::p2p::{P2PManagerConfig, ACTOR_CHANNEL_SIZE};
-use log::trace;
+use crate::p2p::peer_store::PeerStore;
+use crate::p2p::recent_tx::RecentTxCache;
+use crate::p2p::serve_cache::GetDataResponder;
+use crate::p2p::session::HandshakeLimits;
+use crate::p2p::slots::SlotGuard;
+use crate::p2p::throughput::MinThroughput;
+use crate::p2p::{ConfigError, P2PManagerConfig, ACTOR_CHANNEL_SIZE};
+use crate::util::FeeRate;
+use crate::{Error, Result};
+use log::{trace, warn};
+use serde::Deserialize;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
@@ -14,8 +37,10 @@ use uuid::Uuid;
 
 /// Configuration shared by all P2P Connections.
 ///
-/// This is the desired configuration.
-#[derive(Debug, Clone)]
+/// This is the desired configuration. It can be constructed with [ConnectionConfig::builder()] or
+/// deserialized in the same way as a [P2PManagerConfig].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConnectionConfig {
     /// The blockchain (mainnet, testnet, stn, regtest) to use.
     pub blockchain: BlockchainId,
@@ -23,7 +48,7 @@ pub struct ConnectionConfig {
     pub retries: u8,
     /// The delay between retries, in seconds. Default is 10 seconds.
     pub retry_delay: u16,
-    /// Should control messages be sent to the data channel?
+    /// Should control messages be sent to the data channel? Reject messages are always sent.
     pub send_control_messages: bool,
     /// The maximum payload size we want to receive, using protoconf.
     /// The default for this is DEFAULT_MAX_RECV_PAYLOAD_SIZE (200MB).
@@ -32,6 +57,72 @@ pub struct ConnectionConfig {
     /// The excessive block size. This is the maximum size of a block that we will accept.
     /// The default for this is DEFAULT_EXCESSIVE_BLOCK_SIZE (10GB).
     pub excessive_block_size: u64,
+    /// How to respond to mempool requests from the peer.
+    #[serde(skip)]
+    pub mempool_responder: MempoolResponder,
+    /// How to respond to getdata requests from the peer. Default is to only pass them to the data
+    /// channel.
+    #[serde(skip)]
+    pub getdata_responder: GetDataResponder,
+    /// The source of the addresses sent in response to a getaddr request from the peer, which is
+    /// answered once per connection. The request is not answered if None. Default is None.
+    #[serde(skip)]
+    pub addr_sampler: Option<Arc<dyn AddrSampler>>,
+    /// The source of the block hashes sent in response to a getblocks request from the peer. The
+    /// request is not answered if None. Default is None.
+    #[serde(skip)]
+    pub header_provider: Option<Arc<dyn HeaderProvider>>,
+    /// If a message does not start with the magic bytes, scan forward for them instead of closing
+    /// the connection. Some proxies insert extra bytes into the stream. Default is false.
+    pub magic_resync: bool,
+    /// The address on which other nodes can reach this node, shared by all connections.
+    #[serde(skip)]
+    pub external_address: Arc<ExternalAddress>,
+    /// Periodically advertise the external address to peers, if it is known. This should only be
+    /// enabled when listening for inbound connections. Default is false.
+    pub advertise_address: bool,
+    /// Do not announce transactions to a peer if their fee rate is below the minimum that the peer
+    /// set using a feefilter message. Default is false.
+    pub respect_fee_filter: bool,
+    /// Establishes the streams for outbound connections. Default is [TcpConnector].
+    #[serde(skip)]
+    pub connector: Arc<dyn Connector>,
+    /// Emit health events for each connection, if set. Default is None.
+    #[serde(skip)]
+    pub health: Option<HealthConfig>,
+    /// Shared by all connections to pass on each transaction only once, if set. Default is None.
+    #[serde(skip)]
+    pub recent_txs: Option<Arc<RecentTxCache>>,
+    /// Record the bytes sent and received on each connection to a capture file, if set. Default is None.
+    #[serde(skip)]
+    pub tap: Option<MessageTap>,
+    /// Refuse the handshake if the clock of the peer differs from ours by more than this. No limit
+    /// is applied if None. Default is 70 minutes.
+    #[serde(deserialize_with = "crate::util::duration::deserialize_option")]
+    pub max_time_offset: Option<Duration>,
+    /// When message checksums are calculated and verified. Default is
+    /// [ChecksumPolicy::NeverForExtended].
+    pub checksum_policy: ChecksumPolicy,
+    /// The maximum number of transactions in each inv message that announces the queued
+    /// transactions. Default is [DEFAULT_INV_BATCH_SIZE].
+    pub inv_batch_size: usize,
+    /// The mean interval at which queued transaction announcements are sent, the actual intervals
+    /// are randomized. Default is [DEFAULT_INV_TRICKLE_INTERVAL] (2 seconds).
+    #[serde(deserialize_with = "crate::util::duration::deserialize")]
+    pub inv_trickle_interval: Duration,
+    /// Refuse the handshake unless the peer offers all of these services, it may offer more.
+    /// Default is [Services::NONE].
+    pub required_services: Services,
+    /// Keep the settings negotiated with each peer in this store, and offer them as a hint when the
+    /// peer is next connected to, see [SessionSummary](crate::p2p::SessionSummary). Default is None.
+    #[serde(skip)]
+    pub peer_store: Option<Arc<dyn PeerStore>>,
+    /// Abandon reading a message, and close the connection, if its payload arrives more slowly
+    /// than this. Not limited if None. Default is [MinThroughput::default()].
+    pub min_throughput: Option<MinThroughput>,
+    /// The most that the peer may send before it completes the handshake. Default is
+    /// [HandshakeLimits::default()].
+    pub handshake_limits: HandshakeLimits,
 }
 
 impl ConnectionConfig {
@@ -44,8 +135,91 @@ impl ConnectionConfig {
             send_control_messages: false,
             max_recv_payload_size: DEFAULT_MAX_RECV_PAYLOAD_SIZE,
             excessive_block_size: DEFAULT_EXCESSIVE_BLOCK_SIZE,
+            mempool_responder: MempoolResponder::default(),
+            getdata_responder: GetDataResponder::default(),
+            addr_sampler: None,
+            header_provider: None,
+            magic_resync: false,
+            external_address: Arc::new(ExternalAddress::default()),
+            advertise_address: false,
+            respect_fee_filter: false,
+            connector: Arc::new(TcpConnector),
+            health: None,
+            recent_txs: None,
+            tap: None,
+            max_time_offset: Some(DEFAULT_MAX_TIME_OFFSET),
+            checksum_policy: ChecksumPolicy::default(),
+            inv_batch_size: DEFAULT_INV_BATCH_SIZE,
+            inv_trickle_interval: DEFAULT_INV_TRICKLE_INTERVAL,
+            required_services: Services::NONE,
+            peer_store: None,
+            min_throughput: Some(MinThroughput::default()),
+            handshake_limits: HandshakeLimits::default(),
         }
     }
+
+    /// Check that the configuration is consistent, returning the first problem found.
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.retries > 0 && self.retry_delay == 0 {
+            return Err(ConfigError::ZeroRetryDelay {
+                retries: self.retries,
+            });
+        }
+        let max = u32::MAX as u64;
+        if self.max_recv_payload_size < MIN_MAX_RECV_PAYLOAD_SIZE
+            || self.max_recv_payload_size > max
+        {
+            return Err(ConfigError::RecvPayloadSizeOutOfRange {
+                size: self.max_recv_payload_size,
+                min: MIN_MAX_RECV_PAYLOAD_SIZE,
+                max,
+            });
+        }
+        if self.excessive_block_size == 0 {
+            return Err(ConfigError::ZeroExcessiveBlockSize);
+        }
+        if self.inv_batch_size == 0 {
+            return Err(ConfigError::ZeroInvBatchSize);
+        }
+        Ok(())
+    }
+
+    /// A builder that starts from the default configuration for mainnet.
+    pub fn builder() -> ConnectionConfigBuilder {
+        ConnectionConfigBuilder::default()
+    }
+}
+
+config_builder! {
+    /// Builds a [ConnectionConfig], see [ConnectionConfig::builder()].
+    ConnectionConfigBuilder for ConnectionConfig {
+        blockchain: BlockchainId,
+        retries: u8,
+        retry_delay: u16,
+        send_control_messages: bool,
+        max_recv_payload_size: u64,
+        excessive_block_size: u64,
+        mempool_responder: MempoolResponder,
+        getdata_responder: GetDataResponder,
+        addr_sampler: Option<Arc<dyn AddrSampler>>,
+        header_provider: Option<Arc<dyn HeaderProvider>>,
+        magic_resync: bool,
+        external_address: Arc<ExternalAddress>,
+        advertise_address: bool,
+        respect_fee_filter: bool,
+        connector: Arc<dyn Connector>,
+        health: Option<HealthConfig>,
+        recent_txs: Option<Arc<RecentTxCache>>,
+        tap: Option<MessageTap>,
+        max_time_offset: Option<Duration>,
+        checksum_policy: ChecksumPolicy,
+        inv_batch_size: usize,
+        inv_trickle_interval: Duration,
+        required_services: Services,
+        peer_store: Option<Arc<dyn PeerStore>>,
+        min_throughput: Option<MinThroughput>,
+        handshake_limits: HandshakeLimits,
+    }
 }
 
 impl Default for ConnectionConfig {
@@ -61,6 +235,30 @@ impl From<&P2PManagerConfig> for ConnectionConfig {
         ConnectionConfig {
             blockchain: value.blockchain,
             send_control_messages: value.send_control_msgs,
+            mempool_responder: value.mempool_responder.clone(),
+            getdata_responder: value.getdata_responder.clone(),
+            // peers that ask for addresses are given a sample of the stored peers
+            addr_sampler: value
+                .peer_store
+                .clone()
+                .map(|store| Arc::new(PeerStoreSampler::new(store)) as Arc<dyn AddrSampler>),
+            header_provider: value.header_provider.clone(),
+            external_address: Arc::new(ExternalAddress::new(
+                value.external_address,
+                value.learn_external_address,
+            )),
+            advertise_address: value.listen,
+            respect_fee_filter: value.respect_fee_filter,
+            connector: value.connector.clone(),
+            health: value.health.clone(),
+            recent_txs: value
+                .deduplicate_txs
+                .then(|| Arc::new(RecentTxCache::default())),
+            max_time_offset: value.max_time_offset,
+            inv_batch_size: value.inv_batch_size,
+            inv_trickle_interval: value.inv_trickle_interval,
+            required_services: value.required_services,
+            peer_store: value.peer_store.clone(),
             ..Default::default()
         }
     }
@@ -109,6 +307,18 @@ impl Connection {
         peer: PeerAddress,
         config: Arc<ConnectionConfig>,
         data_channel: Option<P2PMessageChannelSender>,
+    ) -> (Connection, JoinHandle<()>) {
+        Connection::with_slot(peer, config, data_channel, None)
+    }
+
+    /// Create a connection that holds a connection slot, which is released when the connection ends.
+    ///
+    /// The owner may keep a reference to the slot to release it if the connection task panics.
+    pub fn with_slot(
+        peer: PeerAddress,
+        config: Arc<ConnectionConfig>,
+        data_channel: Option<P2PMessageChannelSender>,
+        slot: Option<Arc<SlotGuard>>,
     ) -> (Connection, JoinHandle<()>) {
         // actor channel
         let (tx, rx) = channel(ACTOR_CHANNEL_SIZE);
@@ -123,9 +333,9 @@ impl Connection {
         let p_c = peer.clone();
         let connection_id = Uuid::new_v4();
         let c_id2 = connection_id;
-        let j = tokio::spawn(
-            async move { ConnectionActor::new(rx, p_c, c_id2, config, d_chan2).await },
-        );
+        let j = tokio::spawn(async move {
+            ConnectionActor::new(rx, p_c, c_id2, config, d_chan2, slot).await
+        });
         (
             Connection {
                 peer,
@@ -142,6 +352,65 @@ impl Connection {
         self.data_channel.subscribe()
     }
 
+    /// Announce a transaction to the peer.
+    ///
+    /// The fee rate of the transaction is compared against the peer's fee filter, if enabled.
+    pub async fn announce_tx(&self, hash: TxHash, fee_rate: FeeRate) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::AnnounceTx(hash, fee_rate))
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
+    /// Announce a block to the peer, without waiting for the queued transaction announcements.
+    pub async fn announce_block(&self, hash: BlockHash) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::AnnounceBlock(hash))
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
+    /// Send a block to the peer from a stream of transactions.
+    ///
+    /// The transactions are encoded as they arrive from the stream. Blocks are only sent once the
+    /// handshake is complete, see [BlockStream] for the framing and checksum of large blocks.
+    pub async fn send_block(&self, block: BlockStream) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::SendBlock(block))
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
+    /// Ask the peer for the headers that follow the locator, with a getheaders message.
+    ///
+    /// The request is only sent once the handshake is complete, a request made before then is
+    /// dropped.
+    pub async fn request_headers(&self, locator: BlockLocator) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::RequestHeaders(locator))
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
+    /// Change the health configuration of the connection.
+    ///
+    /// The new interval takes effect immediately. Health events can not be enabled on a connection
+    /// that was started without them.
+    pub async fn update_health(&self, health: HealthConfig) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::UpdateHealth(health))
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
+    /// Pause the connection, it will not be re-established if it fails.
+    pub async fn pause(&self) -> Result<()> {
+        self.sender
+            .send(ConnectionControlMessage::Pause)
+            .await
+            .map_err(|_| Error::Internal("connection actor has stopped".to_string()))
+    }
+
     pub async fn close(&self) {
         self.sender
             .send(ConnectionControlMessage::Close)
@@ -150,9 +419,14 @@ impl Connection {
     }
 }
 
-pub enum ConnectionControlMessage {
-    Close, // close the connection
-    Pause, // pause the connection, i.e. dont re-connect if it fails
+pub(crate) enum ConnectionControlMessage {
+    Close,                        // close the connection
+    Pause,                        // pause the connection, i.e. dont re-connect if it fails
+    AnnounceTx(TxHash, FeeRate),  // announce a transaction to the peer
+    AnnounceBlock(BlockHash),     // announce a block to the peer
+    SendBlock(BlockStream),       // send a block to the peer
+    RequestHeaders(BlockLocator), // send a getheaders message to the peer
+    UpdateHealth(HealthConfig),   // change the health configuration
 }
 
 // The actor for a connection.
@@ -188,13 +462,13 @@ impl ConnectionActor {
         connection_id: Uuid,
         config: Arc<ConnectionConfig>,
         data_channel: P2PMessageChannelSender,
+        slot: Option<Arc<SlotGuard>>,
     ) {
         // make the first stream
-        let stream_config = Arc::new(RwLock::new(ChannelConfig::new(
-            &config,
-            &peer_address.peer_id,
-            &connection_id,
-        )));
+        let stream_config = Arc::new(RwLock::new(ChannelConfig {
+            slot,
+            ..ChannelConfig::new(&config, &peer_address.peer_id, &connection_id)
+        }));
         let (stream, join_handle) = PeerChannel::new(
             peer_address.clone(),
             stream_config.clone(),
@@ -234,6 +508,31 @@ impl ConnectionActor {
                         ConnectionControlMessage::Pause => {
                             self.paused = true;
                         }
+                        ConnectionControlMessage::AnnounceTx(hash, fee_rate) => {
+                            if let Err(e) = self.primary_stream.announce_tx(hash, fee_rate).await {
+                                warn!("failed to announce tx to peer: {}, error: {}", self.peer_address.peer_id, e);
+                            }
+                        }
+                        ConnectionControlMessage::AnnounceBlock(hash) => {
+                            if let Err(e) = self.primary_stream.announce_block(hash).await {
+                                warn!("failed to announce block to peer: {}, error: {}", self.peer_address.peer_id, e);
+                            }
+                        }
+                        ConnectionControlMessage::SendBlock(block) => {
+                            if let Err(e) = self.primary_stream.send_block(block).await {
+                                warn!("failed to send block to peer: {}, error: {}", self.peer_address.peer_id, e);
+                            }
+                        }
+                        ConnectionControlMessage::RequestHeaders(locator) => {
+                            if let Err(e) = self.primary_stream.request_headers(locator).await {
+                                warn!("failed to request headers from peer: {}, error: {}", self.peer_address.peer_id, e);
+                            }
+                        }
+                        ConnectionControlMessage::UpdateHealth(health) => {
+                            if let Err(e) = self.primary_stream.update_health(health).await {
+                                warn!("failed to update health config of peer: {}, error: {}", self.peer_address.peer_id, e);
+                            }
+                        }
                     }
                 }
             }
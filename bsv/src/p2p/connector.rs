@@ -0,0 +1,36 @@
+use crate::Result;
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A bidirectional byte stream over which the P2P protocol can run.
+///
+/// This is implemented for every type that implements the tokio IO traits, such as TCP streams,
+/// unix sockets, TLS streams and in-memory duplex pipes.
+pub trait PeerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> PeerStream for T {}
+
+/// Produces the stream for an outbound connection to a peer.
+///
+/// The connector is part of the [ConnectionConfig](crate::p2p::ConnectionConfig), replacing the
+/// default [TcpConnector] enables connections over other transports.
+#[async_trait]
+pub trait Connector: Debug + Send + Sync + 'static {
+    /// Establish a stream to the peer at the address.
+    async fn connect(&self, address: SocketAddr) -> Result<Box<dyn PeerStream>>;
+}
+
+/// Connects to peers directly using TCP.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpConnector;
+
+#[async_trait]
+impl Connector for TcpConnector {
+    async fn connect(&self, address: SocketAddr) -> Result<Box<dyn PeerStream>> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Box::new(stream))
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Error;
+use std::fmt;
+use std::io;
+
+// the errno values of "too many open files" for the process and the system, the same on Linux,
+// the BSDs and macOS
+#[cfg(unix)]
+const EMFILE: i32 = 24;
+#[cfg(unix)]
+const ENFILE: i32 = 23;
+
+/// Why a connection to a peer ended, derived from the error that ended it.
+///
+/// The operating system reports the end of a connection in several ways that call for different
+/// responses. Most mean that the peer or the network went away, and it is worth connecting again.
+/// Resource exhaustion, such as running out of file descriptors, is a problem of this process and
+/// not of the peer, so it must not be held against the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectCause {
+    /// The connection was closed by this node.
+    Local,
+    /// The peer closed the connection.
+    Closed,
+    /// The connection was reset or aborted.
+    Reset,
+    /// A write failed because the peer had closed the connection.
+    BrokenPipe,
+    /// The socket was no longer connected.
+    NotConnected,
+    /// The connection timed out.
+    TimedOut,
+    /// The peer refused the connection.
+    Refused,
+    /// The process or the system ran out of a resource, such as memory or file descriptors.
+    ResourceExhausted,
+    /// The peer broke the protocol.
+    Protocol,
+    /// Any other error.
+    Other,
+}
+
+impl DisconnectCause {
+    /// Classify the error that ended a connection.
+    pub fn classify(error: &Error) -> DisconnectCause {
+        match error {
+            Error::IOError(e) => DisconnectCause::from_io(e),
+            Error::StalledTransfer { .. }
+            | Error::OversizedMessage { .. }
+            | Error::HandshakeLimitExceeded { .. }
+            | Error::HandshakeTimeout(_)
+            | Error::InvalidInventory { .. }
+            | Error::BadData(_)
+            | Error::ChecksumMismatch => DisconnectCause::Protocol,
+            _ => DisconnectCause::Other,
+        }
+    }
+
+    /// Classify an IO error.
+    pub fn from_io(error: &io::Error) -> DisconnectCause {
+        #[cfg(unix)]
+        if matches!(error.raw_os_error(), Some(EMFILE | ENFILE)) {
+            return DisconnectCause::ResourceExhausted;
+        }
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => DisconnectCause::Closed,
+            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+                DisconnectCause::Reset
+            }
+            io::ErrorKind::BrokenPipe => DisconnectCause::BrokenPipe,
+            io::ErrorKind::NotConnected => DisconnectCause::NotConnected,
+            io::ErrorKind::TimedOut => DisconnectCause::TimedOut,
+            io::ErrorKind::ConnectionRefused => DisconnectCause::Refused,
+            io::ErrorKind::OutOfMemory => DisconnectCause::ResourceExhausted,
+            _ => DisconnectCause::Other,
+        }
+    }
+
+    /// Whether it is worth connecting to the peer again.
+    pub fn should_restart(&self) -> bool {
+        matches!(
+            self,
+            DisconnectCause::Closed
+                | DisconnectCause::Reset
+                | DisconnectCause::BrokenPipe
+                | DisconnectCause::NotConnected
+                | DisconnectCause::TimedOut
+                | DisconnectCause::Refused
+        )
+    }
+
+    /// Whether the cause is a problem of this process rather than of the peer, to be raised as an
+    /// alert for the whole node and not counted against the peer.
+    pub fn is_resource_exhaustion(&self) -> bool {
+        *self == DisconnectCause::ResourceExhausted
+    }
+}
+
+impl fmt::Display for DisconnectCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DisconnectCause::Local => "local",
+            DisconnectCause::Closed => "closed",
+            DisconnectCause::Reset => "reset",
+            DisconnectCause::BrokenPipe => "broken_pipe",
+            DisconnectCause::NotConnected => "not_connected",
+            DisconnectCause::TimedOut => "timed_out",
+            DisconnectCause::Refused => "refused",
+            DisconnectCause::ResourceExhausted => "resource_exhausted",
+            DisconnectCause::Protocol => "protocol",
+            DisconnectCause::Other => "other",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        use io::ErrorKind::*;
+        for (kind, cause, restart) in [
+            (UnexpectedEof, DisconnectCause::Closed, true),
+            (ConnectionReset, DisconnectCause::Reset, true),
+            (ConnectionAborted, DisconnectCause::Reset, true),
+            (BrokenPipe, DisconnectCause::BrokenPipe, true),
+            (NotConnected, DisconnectCause::NotConnected, true),
+            (TimedOut, DisconnectCause::TimedOut, true),
+            (ConnectionRefused, DisconnectCause::Refused, true),
+            (OutOfMemory, DisconnectCause::ResourceExhausted, false),
+            (PermissionDenied, DisconnectCause::Other, false),
+        ] {
+            let e = Error::from(io::Error::from(kind));
+            let c = DisconnectCause::classify(&e);
+            assert_eq!(c, cause, "{:?}", kind);
+            assert_eq!(c.should_restart(), restart, "{:?}", kind);
+            assert_eq!(
+                c.is_resource_exhaustion(),
+                kind == OutOfMemory,
+                "{:?}",
+                kind
+            );
+        }
+        #[cfg(unix)]
+        for errno in [EMFILE, ENFILE] {
+            let c = DisconnectCause::from_io(&io::Error::from_raw_os_error(errno));
+            assert!(c.is_resource_exhaustion());
+            assert!(!c.should_restart());
+        }
+        let e = Error::OversizedMessage {
+            command: "tx".to_string(),
+            size: 2,
+            max: 1,
+        };
+        assert_eq!(DisconnectCause::classify(&e), DisconnectCause::Protocol);
+        assert_eq!(DisconnectCause::BrokenPipe.to_string(), "broken_pipe");
+    }
+}
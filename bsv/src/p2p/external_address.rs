@@ -0,0 +1,206 @@
+use crate::p2p::messages::{Addr, NodeAddr, P2PMessage, Services};
+use log::warn;
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+
+/// The number of addresses reported by peers that are remembered.
+const MAX_REPORTS: usize = 32;
+/// The minimum number of matching reports needed before a learned address is used.
+const MIN_REPORTS: usize = 3;
+
+/// The address on which other nodes can reach us, advertised in version and addr messages.
+///
+/// The address is either configured or, if `learn` is true, learned from the `recv_addr` that peers
+/// include in their version messages. A learned address is the one reported by a majority of the recent
+/// handshakes. Addresses that are not routable on the public internet, such as private and loopback
+/// addresses, are never used.
+///
+/// A single instance is shared by all connections.
+#[derive(Debug, Default)]
+pub struct ExternalAddress {
+    configured: Option<SocketAddr>,
+    learn: bool,
+    reports: Mutex<VecDeque<SocketAddr>>,
+}
+
+impl ExternalAddress {
+    /// Create a new instance with an optional configured address.
+    pub fn new(configured: Option<SocketAddr>, learn: bool) -> ExternalAddress {
+        let configured = match configured {
+            Some(a) if !is_routable(&a.ip()) => {
+                warn!(
+                    "configured external address is not routable, ignoring: {}",
+                    a
+                );
+                None
+            }
+            a => a,
+        };
+        ExternalAddress {
+            configured,
+            learn,
+            reports: Mutex::new(VecDeque::with_capacity(MAX_REPORTS)),
+        }
+    }
+
+    /// Record the address of this node as reported by a peer.
+    pub fn report(&self, addr: SocketAddr) {
+        if !self.learn || !is_routable(&addr.ip()) {
+            return;
+        }
+        let mut reports = self.reports.lock().unwrap();
+        if reports.len() == MAX_REPORTS {
+            reports.pop_front();
+        }
+        reports.push_back(addr);
+    }
+
+    /// Get the external address, if it is known.
+    pub fn get(&self) -> Option<SocketAddr> {
+        if self.configured.is_some() {
+            return self.configured;
+        }
+        let reports = self.reports.lock().unwrap();
+        let mut best = None;
+        let mut best_count = 0;
+        for a in reports.iter() {
+            let count = reports.iter().filter(|b| *b == a).count();
+            if count > best_count {
+                best = Some(*a);
+                best_count = count;
+            }
+        }
+        if best_count >= MIN_REPORTS && best_count * 2 > reports.len() {
+            best
+        } else {
+            None
+        }
+    }
+
+    /// The address to include in the `tx_addr` field of our version message.
+    pub(crate) fn version_addr(&self, services: Services) -> NodeAddr {
+        match self.get() {
+            Some(a) => NodeAddr {
+                services,
+                ..NodeAddr::new(a.ip(), a.port())
+            },
+            None => NodeAddr::default(),
+        }
+    }
+
+    /// An addr message advertising the external address, if it is known.
+    pub(crate) fn addr_message(&self, services: Services) -> Option<P2PMessage> {
+        self.get().map(|a| {
+            P2PMessage::Addr(Addr {
+                addrs: vec![NodeAddr {
+                    services,
+                    ..NodeAddr::new(a.ip(), a.port())
+                }],
+            })
+        })
+    }
+}
+
+/// Returns true if the address could be reached from the public internet.
+pub fn is_routable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            !(v4.is_unspecified()
+                || v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                || o[0] == 0
+                // shared address space, RFC 6598
+                || (o[0] == 100 && (o[1] & 0xc0) == 64)
+                // reserved, RFC 1112
+                || o[0] >= 240)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_routable(&IpAddr::V4(v4));
+            }
+            let s = v6.segments();
+            !(v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                // unique local, RFC 4193
+                || (s[0] & 0xfe00) == 0xfc00
+                // link local
+                || (s[0] & 0xffc0) == 0xfe80
+                // documentation, RFC 3849
+                || (s[0] == 0x2001 && s[1] == 0x0db8))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn routable() {
+        for a in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "100.64.0.1",
+            "203.0.113.4",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert!(!is_routable(&a.parse().unwrap()), "{}", a);
+        }
+        for a in ["8.8.8.8", "100.128.0.1", "2a01:4f8::1", "::ffff:8.8.8.8"] {
+            assert!(is_routable(&a.parse().unwrap()), "{}", a);
+        }
+    }
+
+    #[test]
+    fn configured() {
+        let e = ExternalAddress::new(Some(addr("8.8.4.4:8333")), true);
+        e.report(addr("1.1.1.1:8333"));
+        assert_eq!(e.get(), Some(addr("8.8.4.4:8333")));
+        let e = ExternalAddress::new(Some(addr("192.168.0.2:8333")), false);
+        assert_eq!(e.get(), None);
+        assert_eq!(e.addr_message(Services::NONE), None);
+    }
+
+    #[test]
+    fn learned() {
+        let e = ExternalAddress::new(None, true);
+        e.report(addr("1.1.1.1:8333"));
+        e.report(addr("1.1.1.1:8333"));
+        assert_eq!(e.get(), None);
+        e.report(addr("127.0.0.1:8333"));
+        e.report(addr("2.2.2.2:8333"));
+        e.report(addr("1.1.1.1:8333"));
+        assert_eq!(e.get(), Some(addr("1.1.1.1:8333")));
+        // no longer a majority
+        e.report(addr("2.2.2.2:8333"));
+        e.report(addr("2.2.2.2:8333"));
+        assert_eq!(e.get(), None);
+        // not learning
+        let e = ExternalAddress::new(None, false);
+        for _ in 0..5 {
+            e.report(addr("1.1.1.1:8333"));
+        }
+        assert_eq!(e.get(), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! A fake peer for testing the P2P code deterministically without a real node.
+//!
+//! The [FakePeer] listens on a loopback port, accepts a single connection, completes the version/verack
+//! handshake, and then runs through a script of [FakePeerStep]s. It records every message it receives.
+//! It can also run over an in-memory stream, which unlike a socket works with a paused clock.
+use crate::bitcoin::BlockchainId;
+use crate::p2p::channel::ChannelConfig;
+use crate::p2p::connection::ConnectionConfig;
+use crate::p2p::messages::{P2PMessage, Version};
+use crate::p2p::PeerAddress;
+use crate::{Error, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// The maximum time allowed for the fake peer to complete its script.
+const FAKE_PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A step in the script of a [FakePeer].
+pub(crate) enum FakePeerStep {
+    /// Send a message to the peer.
+    Send(P2PMessage),
+    /// Send raw bytes to the peer, e.g. garbage.
+    SendRaw(Vec<u8>),
+    /// Read messages until one matches.
+    Expect(Box<dyn Fn(&P2PMessage) -> bool + Send + Sync>),
+    /// Do nothing for a while.
+    Silent(Duration),
+}
+
+impl FakePeerStep {
+    /// Read messages until one matches the predicate.
+    pub(crate) fn expect<F: Fn(&P2PMessage) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        FakePeerStep::Expect(Box::new(f))
+    }
+}
+
+/// A scripted peer listening on a loopback port.
+pub(crate) struct FakePeer {
+    /// The address on which the fake peer is listening.
+    pub(crate) address: SocketAddr,
+    handle: JoinHandle<Result<Vec<P2PMessage>>>,
+}
+
+impl FakePeer {
+    /// Start the fake peer, it will accept a single connection and then run the script.
+    pub(crate) async fn start(chain: BlockchainId, steps: Vec<FakePeerStep>) -> FakePeer {
+        FakePeer::start_with_handshake(chain, Vec::new(), steps).await
+    }
+
+    /// Start the fake peer, performing the `handshake` steps after it has sent its version and
+    /// before it sends its verack.
+    pub(crate) async fn start_with_handshake(
+        chain: BlockchainId,
+        handshake: Vec<FakePeerStep>,
+        steps: Vec<FakePeerStep>,
+    ) -> FakePeer {
+        let version = Version {
+            user_agent: "fake-peer".to_string(),
+            ..Default::default()
+        };
+        FakePeer::start_with_version(chain, version, handshake, steps).await
+    }
+
+    /// Start the fake peer, sending `version` as its version message.
+    pub(crate) async fn start_with_version(
+        chain: BlockchainId,
+        version: Version,
+        handshake: Vec<FakePeerStep>,
+        steps: Vec<FakePeerStep>,
+    ) -> FakePeer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            FakePeer::run(stream, chain, version, handshake, steps).await
+        });
+        FakePeer { address, handle }
+    }
+
+    /// Start the fake peer on one end of an in-memory stream, returning the other end, which is
+    /// connected to the fake peer. The address of the fake peer is a documentation address.
+    pub(crate) fn start_over_duplex(
+        chain: BlockchainId,
+        handshake: Vec<FakePeerStep>,
+        steps: Vec<FakePeerStep>,
+    ) -> (FakePeer, DuplexStream) {
+        let (ours, theirs) = tokio::io::duplex(1 << 16);
+        let version = Version {
+            user_agent: "fake-peer".to_string(),
+            ..Default::default()
+        };
+        let handle =
+            tokio::spawn(
+                async move { FakePeer::run(ours, chain, version, handshake, steps).await },
+            );
+        let address = "192.0.2.1:8333".parse().unwrap();
+        (FakePeer { address, handle }, theirs)
+    }
+
+    /// Get a [PeerAddress] that can be used to connect to the fake peer.
+    pub(crate) fn peer_address(&self) -> PeerAddress {
+        PeerAddress::new(self.address)
+    }
+
+    /// Wait for the fake peer to complete its script, returning all of the messages it received.
+    pub(crate) async fn finish(self) -> Result<Vec<P2PMessage>> {
+        match timeout(FAKE_PEER_TIMEOUT, self.handle).await {
+            Ok(r) => r.unwrap(),
+            Err(_) => Err(Error::Internal("fake peer timed out".to_string())),
+        }
+    }
+
+    async fn run<S: AsyncRead + AsyncWrite + Send>(
+        stream: S,
+        chain: BlockchainId,
+        version: Version,
+        handshake: Vec<FakePeerStep>,
+        steps: Vec<FakePeerStep>,
+    ) -> Result<Vec<P2PMessage>> {
+        let config = ChannelConfig::new(
+            &ConnectionConfig::default_for(chain),
+            &Uuid::new_v4(),
+            &Uuid::new_v4(),
+        );
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let mut received = Vec::new();
+        // the connecting peer always sends the version first
+        Self::expect(&mut reader, &config, &mut received, &|m| {
+            matches!(m, P2PMessage::Version(_))
+        })
+        .await?;
+        Self::send(&mut writer, &config, P2PMessage::Version(version)).await?;
+        for step in handshake {
+            Self::step(&mut reader, &mut writer, &config, &mut received, step).await?;
+        }
+        Self::send(&mut writer, &config, P2PMessage::Verack).await?;
+        // the verack may already have been read by a handshake step
+        if !received.contains(&P2PMessage::Verack) {
+            Self::expect(&mut reader, &config, &mut received, &|m| {
+                matches!(m, P2PMessage::Verack)
+            })
+            .await?;
+        }
+        for step in steps {
+            Self::step(&mut reader, &mut writer, &config, &mut received, step).await?;
+        }
+        Ok(received)
+    }
+
+    async fn step<R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin + Send>(
+        reader: &mut R,
+        writer: &mut W,
+        config: &ChannelConfig,
+        received: &mut Vec<P2PMessage>,
+        step: FakePeerStep,
+    ) -> Result<()> {
+        match step {
+            FakePeerStep::Send(msg) => Self::send(writer, config, msg).await?,
+            FakePeerStep::SendRaw(bytes) => writer.write_all(&bytes).await?,
+            FakePeerStep::Expect(f) => Self::expect(reader, config, received, f.as_ref()).await?,
+            FakePeerStep::Silent(d) => tokio::time::sleep(d).await,
+        }
+        Ok(())
+    }
+
+    async fn send<W: AsyncWrite + Unpin + Send>(
+        writer: &mut W,
+        config: &ChannelConfig,
+        msg: P2PMessage,
+    ) -> Result<()> {
+        msg.write(writer, config).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn expect<R: AsyncRead + Unpin + Send>(
+        reader: &mut R,
+        config: &ChannelConfig,
+        received: &mut Vec<P2PMessage>,
+        f: &(dyn Fn(&P2PMessage) -> bool + Send + Sync),
+    ) -> Result<()> {
+        loop {
+            let msg = P2PMessage::read(reader, config).await?;
+            let matched = f(&msg);
+            received.push(msg);
+            if matched {
+                return Ok(());
+            }
+        }
+    }
+}
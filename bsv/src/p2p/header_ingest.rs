@@ -0,0 +1,358 @@
+use crate::bitcoin::{BlockHash, BlockHeader};
+use crate::p2p::SessionStats;
+use crate::Result;
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// The default maximum number of headers held while waiting for their parent.
+pub const DEFAULT_MAX_ORPHANS: usize = 10_000;
+
+/// The destination of the headers, normally the header store.
+pub trait HeaderSink {
+    /// Returns true if the header is already known.
+    fn contains(&self, hash: &BlockHash) -> bool;
+
+    /// Add a header whose parent is known. An error indicates that the header is invalid.
+    fn apply(&mut self, header: &BlockHeader) -> Result<()>;
+}
+
+/// The headers received from a peer, by outcome.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    /// Headers that were new and were applied.
+    pub useful: u64,
+    /// Headers that were already known or already waiting for their parent.
+    pub duplicate: u64,
+    /// Headers that were rejected by the sink, or that descend from a rejected header.
+    pub invalid: u64,
+    /// Headers that were evicted from the orphan pool without their parent ever arriving.
+    pub evicted: u64,
+}
+
+impl IngestStats {
+    /// The fraction of the headers received from the peer that were useful, None if nothing was received.
+    pub fn useful_ratio(&self) -> Option<f64> {
+        let total = self.useful + self.duplicate + self.invalid + self.evicted;
+        if total == 0 {
+            None
+        } else {
+            Some(self.useful as f64 / total as f64)
+        }
+    }
+
+    /// Add these statistics to the statistics of a session, for peer scoring.
+    ///
+    /// Useful headers count as data served and invalid headers as protocol violations. Duplicates are not
+    /// penalized, they are expected when several peers answer at the same time.
+    pub fn add_to_session(&self, session: &mut SessionStats) {
+        session.bytes_served = session
+            .bytes_served
+            .saturating_add(self.useful * BlockHeader::SIZE as u64);
+        session.protocol_violations = session
+            .protocol_violations
+            .saturating_add(self.invalid.min(u32::MAX as u64) as u32);
+    }
+}
+
+/// A header waiting for its parent.
+struct Orphan {
+    header: BlockHeader,
+    peer: Uuid,
+}
+
+/// Sits in front of the header store during sync, applying headers in order and exactly once.
+///
+/// When several peers answer a `getheaders` at the same time, batches arrive duplicated and out of order.
+/// HeaderIngest drops headers that are already known and holds headers whose parent is not yet known in
+/// an orphan pool. When the parent is applied, the orphans that follow it are applied too. The orphan pool
+/// is bounded, when it is full the oldest orphans are evicted, so a peer sending a chain that never
+/// connects can not use unbounded memory.
+///
+/// The outcome of every header is counted per peer, see [stats()](HeaderIngest::stats).
+pub struct HeaderIngest {
+    max_orphans: usize,
+    /// orphans by hash
+    orphans: HashMap<BlockHash, Orphan>,
+    /// orphan hashes by the hash of their parent
+    children: HashMap<BlockHash, Vec<BlockHash>>,
+    /// orphan hashes in the order they were added, may contain hashes that have since been removed
+    order: VecDeque<BlockHash>,
+    stats: HashMap<Uuid, IngestStats>,
+}
+
+impl HeaderIngest {
+    /// Create a new HeaderIngest that will hold at most `max_orphans` headers waiting for their parent.
+    pub fn new(max_orphans: usize) -> HeaderIngest {
+        HeaderIngest {
+            max_orphans,
+            orphans: HashMap::new(),
+            children: HashMap::new(),
+            order: VecDeque::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Process a batch of headers received from a peer. Returns the number of headers applied to the
+    /// sink, which may include orphans received earlier.
+    pub fn ingest<S: HeaderSink>(
+        &mut self,
+        peer: Uuid,
+        headers: &[BlockHeader],
+        sink: &mut S,
+    ) -> usize {
+        let mut applied = 0;
+        for header in headers {
+            let hash = header.hash();
+            if sink.contains(&hash) || self.orphans.contains_key(&hash) {
+                self.stats.entry(peer).or_default().duplicate += 1;
+            } else if sink.contains(&header.prev_hash) {
+                applied += self.apply(peer, header, hash, sink);
+            } else {
+                self.add_orphan(peer, header.clone(), hash);
+            }
+        }
+        applied
+    }
+
+    /// The statistics of the headers received from a peer.
+    pub fn stats(&self, peer: &Uuid) -> IngestStats {
+        self.stats.get(peer).copied().unwrap_or_default()
+    }
+
+    /// Remove and return the statistics of a peer, e.g. when it disconnects.
+    pub fn take_stats(&mut self, peer: &Uuid) -> IngestStats {
+        self.stats.remove(peer).unwrap_or_default()
+    }
+
+    /// The number of headers waiting for their parent.
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Apply a header and then any orphans that descend from it.
+    fn apply<S: HeaderSink>(
+        &mut self,
+        peer: Uuid,
+        header: &BlockHeader,
+        hash: BlockHash,
+        sink: &mut S,
+    ) -> usize {
+        let mut applied = 0;
+        let mut pending = vec![(peer, header.clone(), hash)];
+        while let Some((peer, header, hash)) = pending.pop() {
+            if let Err(e) = sink.apply(&header) {
+                warn!("header {} from peer {} rejected: {}", hash, peer, e);
+                self.stats.entry(peer).or_default().invalid += 1;
+                self.drop_descendants(hash);
+                continue;
+            }
+            self.stats.entry(peer).or_default().useful += 1;
+            applied += 1;
+            for child in self.children.remove(&hash).unwrap_or_default() {
+                if let Some(o) = self.orphans.remove(&child) {
+                    pending.push((o.peer, o.header, child));
+                }
+            }
+        }
+        self.compact_order();
+        applied
+    }
+
+    /// Remove the orphans that descend from an invalid header, they can never be applied.
+    fn drop_descendants(&mut self, hash: BlockHash) {
+        let mut pending = vec![hash];
+        while let Some(hash) = pending.pop() {
+            for child in self.children.remove(&hash).unwrap_or_default() {
+                if let Some(o) = self.orphans.remove(&child) {
+                    self.stats.entry(o.peer).or_default().invalid += 1;
+                    pending.push(child);
+                }
+            }
+        }
+    }
+
+    fn add_orphan(&mut self, peer: Uuid, header: BlockHeader, hash: BlockHash) {
+        if self.max_orphans == 0 {
+            self.stats.entry(peer).or_default().evicted += 1;
+            return;
+        }
+        while self.orphans.len() >= self.max_orphans {
+            self.evict_oldest();
+        }
+        self.children
+            .entry(header.prev_hash)
+            .or_default()
+            .push(hash);
+        self.orphans.insert(hash, Orphan { header, peer });
+        self.order.push_back(hash);
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some(hash) = self.order.pop_front() {
+            if let Some(o) = self.orphans.remove(&hash) {
+                if let Some(siblings) = self.children.get_mut(&o.header.prev_hash) {
+                    siblings.retain(|h| *h != hash);
+                    if siblings.is_empty() {
+                        self.children.remove(&o.header.prev_hash);
+                    }
+                }
+                self.stats.entry(o.peer).or_default().evicted += 1;
+                return;
+            }
+        }
+    }
+
+    /// Remove the hashes of orphans that have been applied from the eviction order, so that it does not
+    /// grow without bound.
+    fn compact_order(&mut self) {
+        if self.order.len() > 2 * self.orphans.len() + 64 {
+            let orphans = &self.orphans;
+            self.order.retain(|h| orphans.contains_key(h));
+        }
+    }
+}
+
+impl Default for HeaderIngest {
+    fn default() -> Self {
+        HeaderIngest::new(DEFAULT_MAX_ORPHANS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::Hash;
+    use crate::Error;
+    use std::collections::HashSet;
+
+    /// A header store that records the order in which headers are applied.
+    struct TestStore {
+        known: HashSet<BlockHash>,
+        applied: Vec<BlockHash>,
+    }
+
+    impl TestStore {
+        fn new(genesis: &BlockHeader) -> TestStore {
+            TestStore {
+                known: HashSet::from([genesis.hash()]),
+                applied: Vec::new(),
+            }
+        }
+    }
+
+    impl HeaderSink for TestStore {
+        fn contains(&self, hash: &BlockHash) -> bool {
+            self.known.contains(hash)
+        }
+
+        fn apply(&mut self, header: &BlockHeader) -> Result<()> {
+            if header.nonce == u32::MAX {
+                return Err(Error::BadData("bad header".to_string()));
+            }
+            assert!(self.known.contains(&header.prev_hash));
+            self.known.insert(header.hash());
+            self.applied.push(header.hash());
+            Ok(())
+        }
+    }
+
+    /// A chain of headers starting from `parent`.
+    fn chain(parent: &BlockHeader, len: usize, salt: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for i in 0..len {
+            let prev_hash = headers.last().unwrap_or(parent).hash();
+            headers.push(BlockHeader {
+                prev_hash,
+                timestamp: i as u32,
+                nonce: salt,
+                ..Default::default()
+            });
+        }
+        headers
+    }
+
+    #[test]
+    fn interleaved_peers() {
+        let genesis = BlockHeader::default();
+        let headers = chain(&genesis, 20, 1);
+        let mut store = TestStore::new(&genesis);
+        let mut ingest = HeaderIngest::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(ingest.ingest(a, &headers[0..8], &mut store), 8);
+        // overlaps with the first batch
+        assert_eq!(ingest.ingest(b, &headers[5..12], &mut store), 4);
+        // a gap, held until the parent arrives
+        assert_eq!(ingest.ingest(a, &headers[15..20], &mut store), 0);
+        assert_eq!(ingest.orphan_count(), 5);
+        // out of order within the batch
+        let batch = [
+            headers[14].clone(),
+            headers[12].clone(),
+            headers[13].clone(),
+        ];
+        assert_eq!(ingest.ingest(b, &batch, &mut store), 8);
+        assert_eq!(ingest.orphan_count(), 0);
+        assert_eq!(ingest.ingest(b, &headers[12..16], &mut store), 0);
+
+        let expected: Vec<BlockHash> = headers.iter().map(|h| h.hash()).collect();
+        assert_eq!(store.applied, expected);
+        let stats_a = ingest.stats(&a);
+        assert_eq!((stats_a.useful, stats_a.duplicate), (13, 0));
+        let stats_b = ingest.stats(&b);
+        assert_eq!((stats_b.useful, stats_b.duplicate), (7, 3 + 4));
+        assert_eq!(stats_a.useful_ratio(), Some(1.0));
+        assert!(stats_b.useful_ratio().unwrap() < 1.0);
+    }
+
+    #[test]
+    fn unconnected_chain() {
+        let genesis = BlockHeader::default();
+        let fake_parent = BlockHeader {
+            prev_hash: Hash::sha256d(b"nowhere"),
+            ..Default::default()
+        };
+        let fake = chain(&fake_parent, 1000, 2);
+        let real = chain(&genesis, 10, 3);
+        let mut store = TestStore::new(&genesis);
+        let mut ingest = HeaderIngest::new(100);
+        let (bad, good) = (Uuid::new_v4(), Uuid::new_v4());
+        for batch in fake.chunks(50) {
+            ingest.ingest(bad, batch, &mut store);
+            assert!(ingest.orphan_count() <= 100);
+        }
+        assert!(ingest.order.len() <= 100);
+        assert_eq!(ingest.stats(&bad).evicted, 900);
+        assert_eq!(ingest.ingest(good, &real, &mut store), 10);
+        assert_eq!(ingest.stats(&bad).useful_ratio(), Some(0.0));
+
+        let mut session = SessionStats::default();
+        ingest.take_stats(&good).add_to_session(&mut session);
+        assert_eq!(session.bytes_served, 800);
+        assert_eq!(ingest.stats(&good), IngestStats::default());
+    }
+
+    #[test]
+    fn invalid_header() {
+        let genesis = BlockHeader::default();
+        let mut headers = chain(&genesis, 3, 4);
+        headers[1] = BlockHeader {
+            nonce: u32::MAX,
+            ..headers[1].clone()
+        };
+        headers[2].prev_hash = headers[1].hash();
+        let mut store = TestStore::new(&genesis);
+        let mut ingest = HeaderIngest::default();
+        let peer = Uuid::new_v4();
+        // the child arrives first and waits for its invalid parent
+        assert_eq!(ingest.ingest(peer, &headers[2..], &mut store), 0);
+        assert_eq!(ingest.ingest(peer, &headers[..2], &mut store), 1);
+        let stats = ingest.stats(&peer);
+        // the child is dropped with its parent
+        assert_eq!((stats.useful, stats.invalid), (1, 2));
+        assert_eq!(ingest.orphan_count(), 0);
+        let mut session = SessionStats::default();
+        stats.add_to_session(&mut session);
+        assert_eq!(session.protocol_violations, 2);
+    }
+}
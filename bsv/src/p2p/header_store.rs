@@ -0,0 +1,905 @@
+use crate::bitcoin::{AsyncEncodable, BlockHash, BlockHeader, BlockchainId, ChainWork, Hash};
+use crate::p2p::messages::{BlockLocator, Headers};
+use crate::p2p::params::PROTOCOL_VERSION;
+use crate::p2p::HeaderSink;
+use crate::{Error, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::RwLock;
+use tokio::sync::broadcast;
+
+/// The bytes at the start of a header store file.
+const HEADER_STORE_MAGIC: [u8; 8] = *b"bsvhdrs1";
+/// The size of a record: the header, the cumulative work of the chain ending at the header, and a checksum.
+const RECORD_SIZE: usize = BlockHeader::SIZE + ChainWork::SIZE + 4;
+/// The number of [ChainEvent]s that a subscriber can fall behind before it misses events.
+const CHAIN_EVENT_CHANNEL_SIZE: usize = 1000;
+/// The number of headers imported between progress reports.
+const IMPORT_PROGRESS_INTERVAL: u64 = 1000;
+
+/// A change of the best chain of a [FileHeaderStore].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// A header was added to the tip of the best chain.
+    Extended { new_tip: BlockHash, height: u32 },
+    /// A branch with more work replaced the tip of the best chain.
+    ///
+    /// The blocks in `disconnected` are no longer in the best chain, they are listed from the old tip
+    /// down to the fork, the order in which they should be rolled back. The blocks in `connected` are
+    /// now in the best chain, they are listed from the fork up to the new tip, the order in which they
+    /// should be applied.
+    Reorged {
+        old_tip: BlockHash,
+        new_tip: BlockHash,
+        /// The height of the last block that is in both chains.
+        fork_height: u32,
+        disconnected: Vec<BlockHash>,
+        connected: Vec<BlockHash>,
+    },
+}
+
+/// The most block hashes sent in answer to a `getblocks` message.
+pub const MAX_GETBLOCKS_HASHES: usize = 500;
+
+/// A source of the best chain, used to answer `getblocks` requests from peers.
+///
+/// The [FileHeaderStore] is a provider. A store that is also being extended can be shared with the
+/// connections in a [RwLock], which is held for the whole of each request.
+pub trait HeaderProvider: Debug + Send + Sync {
+    /// The height of the best chain, the genesis block is at height 0.
+    fn height(&self) -> u32;
+    /// The hash of the block at `height` in the best chain.
+    fn hash_at(&self, height: u32) -> Option<BlockHash>;
+    /// The height of a block, which need not be in the best chain.
+    fn height_of(&self, hash: &BlockHash) -> Option<u32>;
+
+    /// The hash of the tip of the best chain.
+    fn tip(&self) -> BlockHash {
+        self.hash_at(self.height()).unwrap()
+    }
+
+    /// The height of the first locator hash that is in the best chain, the genesis block if none is.
+    fn fork_height(&self, locator: &BlockLocator) -> u32 {
+        locator
+            .block_locator_hashes
+            .iter()
+            .find_map(|hash| {
+                let height = self.height_of(hash)?;
+                (self.hash_at(height) == Some(*hash)).then_some(height)
+            })
+            .unwrap_or(0)
+    }
+
+    /// The hashes of the best chain to send in answer to a getblocks message.
+    ///
+    /// The hashes start after the fork point of the locator, see [fork_height()](Self::fork_height),
+    /// and end before the stop hash or after `max` hashes. Returns an error if the locator is not
+    /// valid, see [BlockLocator::validate()].
+    fn locate_blocks(&self, locator: &BlockLocator, max: usize) -> Result<Vec<BlockHash>> {
+        locator.validate()?;
+        let mut hashes = Vec::new();
+        for height in self.fork_height(locator) + 1..=self.height() {
+            let Some(hash) = self.hash_at(height) else {
+                break;
+            };
+            if hash == locator.hash_stop || hashes.len() >= max {
+                break;
+            }
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IndexEntry {
+    offset: u64,
+    prev_hash: BlockHash,
+    height: u32,
+    work: ChainWork,
+}
+
+/// A header store that keeps the headers in an append-only file.
+///
+/// Every header is appended to the file together with the cumulative work of the chain that ends with
+/// it. Only an index is held in memory: the position of each header in the file, its height and its
+/// work, and the hashes of the best chain by height. The best chain is the chain with the most work,
+/// the first one received wins a tie.
+///
+/// When the store is opened the file is replayed to rebuild the index, so the best chain is the same
+/// after a restart. A record that is incomplete or does not check out, such as one that was being
+/// written when the process crashed, is truncated together with everything after it.
+#[derive(Debug)]
+pub struct FileHeaderStore {
+    file: File,
+    /// the end of the valid records, where the next record is written
+    end: u64,
+    index: HashMap<BlockHash, IndexEntry>,
+    /// the hashes of the best chain, by height
+    best_chain: Vec<BlockHash>,
+    events: broadcast::Sender<ChainEvent>,
+}
+
+impl FileHeaderStore {
+    /// Open the header store at `path`, creating it with the genesis header of `chain` if necessary.
+    pub fn open<P: AsRef<Path>>(path: P, chain: BlockchainId) -> Result<FileHeaderStore> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut store = FileHeaderStore {
+            file,
+            end: 0,
+            index: HashMap::new(),
+            best_chain: Vec::new(),
+            events: broadcast::channel(CHAIN_EVENT_CHANNEL_SIZE).0,
+        };
+        let genesis = BlockHeader::get_genesis(chain);
+        store.replay()?;
+        match store.best_chain.first() {
+            Some(hash) if *hash != genesis.hash() => {
+                return Err(Error::BadData(format!(
+                    "header store does not start with the genesis header of {:?}",
+                    chain
+                )));
+            }
+            Some(_) => {}
+            None => {
+                store.file.set_len(0)?;
+                store.file.write_all(&HEADER_STORE_MAGIC)?;
+                store.end = HEADER_STORE_MAGIC.len() as u64;
+                let work = ChainWork::from_header(&genesis).unwrap();
+                store.append(&genesis, Hash::ZERO, 0, work)?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Subscribe to the changes of the best chain made by headers applied after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// The hash of the last header of the best chain.
+    pub fn tip(&self) -> BlockHash {
+        *self.best_chain.last().unwrap()
+    }
+
+    /// The height of the best chain, the genesis header is at height 0.
+    pub fn height(&self) -> u32 {
+        (self.best_chain.len() - 1) as u32
+    }
+
+    /// The total work of the best chain.
+    pub fn tip_work(&self) -> ChainWork {
+        self.index[&self.tip()].work
+    }
+
+    /// The hash of the header at `height` in the best chain.
+    pub fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        self.best_chain.get(height as usize).copied()
+    }
+
+    /// The height of a header, which need not be in the best chain.
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.index.get(hash).map(|e| e.height)
+    }
+
+    /// The total work of the chain ending with the header.
+    pub fn chain_work(&self, hash: &BlockHash) -> Option<ChainWork> {
+        self.index.get(hash).map(|e| e.work)
+    }
+
+    /// Read a header from the file.
+    pub fn header(&self, hash: &BlockHash) -> Result<Option<BlockHeader>> {
+        let Some(entry) = self.index.get(hash) else {
+            return Ok(None);
+        };
+        let mut buf = [0u8; BlockHeader::SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut buf)?;
+        Ok(Some(BlockHeader::from_binary_buf(&buf)?))
+    }
+
+    /// A locator of the best chain, to ask a peer for the headers that follow it.
+    ///
+    /// The locator holds the ten most recent hashes and then hashes that are exponentially further
+    /// apart, ending with the genesis hash, so that a peer on a different branch still finds a recent
+    /// common ancestor.
+    pub fn locator(&self) -> BlockLocator {
+        let mut hashes = Vec::new();
+        let mut height = self.height() as usize;
+        let mut step = 1;
+        loop {
+            hashes.push(self.best_chain[height]);
+            if height == 0 {
+                break;
+            }
+            if hashes.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        BlockLocator {
+            version: PROTOCOL_VERSION,
+            block_locator_hashes: hashes,
+            hash_stop: BlockLocator::HASH_STOP,
+        }
+    }
+
+    /// The headers of the best chain to send in answer to a getheaders message.
+    ///
+    /// The headers start after the first locator hash that is in the best chain, or after the genesis
+    /// header if there is none. At most [Headers::MAX_HEADERS] are returned, fewer if the stop hash is
+    /// reached first. Returns an error if the locator is not valid, see [BlockLocator::validate()].
+    pub fn locate_headers(&self, locator: &BlockLocator) -> Result<Vec<BlockHeader>> {
+        locator.validate()?;
+        let start = self.fork_height(locator);
+        let mut headers = Vec::new();
+        for hash in self.best_chain[start as usize + 1..]
+            .iter()
+            .take(Headers::MAX_HEADERS as usize)
+        {
+            headers.push(self.header(hash)?.unwrap());
+            if *hash == locator.hash_stop {
+                break;
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Write the headers of the best chain from `start_height` to `end_height`, inclusive, as
+    /// concatenated 80-byte headers in height order. This is the flat format of header archives such
+    /// as the headers.bin files used by SPV wallets.
+    ///
+    /// Returns the number of headers written.
+    pub fn export_range<W: Write>(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        writer: &mut W,
+    ) -> Result<u64> {
+        if start_height > end_height || end_height > self.height() {
+            return Err(Error::BadArgument(format!(
+                "can not export heights {} to {}, the best chain has height {}",
+                start_height,
+                end_height,
+                self.height()
+            )));
+        }
+        for hash in &self.best_chain[start_height as usize..=end_height as usize] {
+            let header = self.header(hash)?.unwrap();
+            writer.write_all(&header.to_binary_buf()?)?;
+        }
+        Ok((end_height - start_height + 1) as u64)
+    }
+
+    /// Import concatenated 80-byte headers, in the format written by [export_range()](Self::export_range).
+    ///
+    /// Each header is checked and added in the same way as a header received from a peer, see
+    /// [HeaderSink::apply()], and headers that are already in the store are skipped. The number of
+    /// headers read so far is passed to `progress` every thousand headers and at the end.
+    ///
+    /// Returns the number of headers added. An invalid header, or data that ends part way through
+    /// a header, stops the import with an error that gives the offset of the header in the data.
+    /// The headers before it remain in the store.
+    pub fn import_headers<R: Read>(
+        &mut self,
+        reader: R,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        let mut reader = BufReader::new(reader);
+        let mut buf = [0u8; BlockHeader::SIZE];
+        let mut offset = 0u64;
+        let mut read = 0u64;
+        let mut added = 0u64;
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if n < buf.len() {
+                return Err(Error::BadData(format!(
+                    "truncated header at offset {}, {} of {} bytes",
+                    offset,
+                    n,
+                    BlockHeader::SIZE
+                )));
+            }
+            let header = BlockHeader::from_binary_buf(&buf)?;
+            if !self.contains(&header.hash()) {
+                self.apply(&header).map_err(|e| {
+                    Error::BadData(format!("invalid header at offset {}: {}", offset, e))
+                })?;
+                added += 1;
+            }
+            offset += n as u64;
+            read += 1;
+            if read.is_multiple_of(IMPORT_PROGRESS_INTERVAL) {
+                progress(read);
+            }
+        }
+        if !read.is_multiple_of(IMPORT_PROGRESS_INTERVAL) {
+            progress(read);
+        }
+        Ok(added)
+    }
+
+    /// Flush the written headers to the disk.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Rebuild the index from the file, truncating any records that can not be used.
+    fn replay(&mut self) -> Result<()> {
+        let len = self.file.metadata()?.len();
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut magic = [0u8; 8];
+        match reader.read_exact(&mut magic) {
+            Ok(()) if magic == HEADER_STORE_MAGIC => {}
+            Ok(()) => return Err(Error::BadData("not a header store".to_string())),
+            // an empty file, or the magic was not completely written
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let mut offset = magic.len() as u64;
+        let mut record = [0u8; RECORD_SIZE];
+        let mut records = Vec::new();
+        loop {
+            match reader.read_exact(&mut record) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            match Self::decode(&record) {
+                Some(r) => records.push((offset, r)),
+                None => break,
+            }
+            offset += RECORD_SIZE as u64;
+        }
+        drop(reader);
+        for (offset, (header, work)) in records {
+            let hash = header.hash();
+            // the stored work must be the work of the parent plus the work of the header
+            let own_work = ChainWork::from_header(&header);
+            let height = match self.index.get(&header.prev_hash) {
+                Some(parent)
+                    if own_work.and_then(|w| parent.work.checked_add(&w)) == Some(work) =>
+                {
+                    parent.height + 1
+                }
+                None if self.index.is_empty() && own_work == Some(work) => 0,
+                _ => {
+                    self.truncate(offset, len)?;
+                    return Ok(());
+                }
+            };
+            self.insert(hash, header.prev_hash, offset, height, work);
+            self.end = offset + RECORD_SIZE as u64;
+        }
+        if self.end != 0 && self.end != len {
+            self.truncate(self.end, len)?;
+        }
+        Ok(())
+    }
+
+    /// Decode a record, None if the checksum does not match.
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<(BlockHeader, ChainWork)> {
+        let (data, checksum) = record.split_at(RECORD_SIZE - 4);
+        if Hash::sha256d(data).hash[..4] != *checksum {
+            return None;
+        }
+        let header = BlockHeader::from_binary_buf(&data[..BlockHeader::SIZE]).ok()?;
+        let work = ChainWork::from_be_bytes(data[BlockHeader::SIZE..].try_into().unwrap());
+        Some((header, work))
+    }
+
+    fn truncate(&mut self, offset: u64, len: u64) -> Result<()> {
+        warn!(
+            "truncating {} bytes of damaged records from the header store",
+            len - offset
+        );
+        self.file.set_len(offset)?;
+        self.end = offset;
+        Ok(())
+    }
+
+    /// Write a header to the end of the file and add it to the index.
+    fn append(
+        &mut self,
+        header: &BlockHeader,
+        prev_hash: BlockHash,
+        height: u32,
+        work: ChainWork,
+    ) -> Result<Option<ChainEvent>> {
+        let mut record = header.to_binary_buf()?;
+        record.extend_from_slice(&work.to_be_bytes());
+        let checksum = Hash::sha256d(&record);
+        record.extend_from_slice(&checksum.hash[..4]);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&record)?;
+        let offset = self.end;
+        self.end += RECORD_SIZE as u64;
+        Ok(self.insert(header.hash(), prev_hash, offset, height, work))
+    }
+
+    /// Add a header to the index, making its chain the best chain if it has more work.
+    ///
+    /// Returns the change to the best chain, if there was one.
+    fn insert(
+        &mut self,
+        hash: BlockHash,
+        prev_hash: BlockHash,
+        offset: u64,
+        height: u32,
+        work: ChainWork,
+    ) -> Option<ChainEvent> {
+        self.index.insert(
+            hash,
+            IndexEntry {
+                offset,
+                prev_hash,
+                height,
+                work,
+            },
+        );
+        if self.best_chain.is_empty() {
+            self.best_chain.push(hash);
+            return None;
+        }
+        if work <= self.tip_work() {
+            return None;
+        }
+        // walk back to the best chain, then replace the part of the best chain after the fork
+        let mut branch = Vec::new();
+        let mut h = hash;
+        loop {
+            let entry = &self.index[&h];
+            if self.best_chain.get(entry.height as usize) == Some(&h) {
+                break;
+            }
+            branch.push(h);
+            h = entry.prev_hash;
+        }
+        let old_tip = self.tip();
+        let fork_height = height as usize - branch.len();
+        let disconnected: Vec<BlockHash> = self.best_chain.drain(fork_height + 1..).rev().collect();
+        branch.reverse();
+        self.best_chain.extend(branch.iter().copied());
+        Some(if disconnected.is_empty() {
+            ChainEvent::Extended {
+                new_tip: hash,
+                height,
+            }
+        } else {
+            ChainEvent::Reorged {
+                old_tip,
+                new_tip: hash,
+                fork_height: fork_height as u32,
+                disconnected,
+                connected: branch,
+            }
+        })
+    }
+}
+
+/// Fill the buffer, returning fewer bytes only at the end of the data.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(r) => n += r,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(n)
+}
+
+impl HeaderSink for FileHeaderStore {
+    fn contains(&self, hash: &BlockHash) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    /// Check the proof of work of the header and append it to the store, notifying the subscribers
+    /// if the best chain changed.
+    fn apply(&mut self, header: &BlockHeader) -> Result<()> {
+        let hash = header.hash();
+        if self.index.contains_key(&hash) {
+            return Ok(());
+        }
+        let Some(parent) = self.index.get(&header.prev_hash).copied() else {
+            return Err(Error::BadData(format!(
+                "parent {} of header {} is not known",
+                header.prev_hash, hash
+            )));
+        };
+        header.validate_pow()?;
+        let work = ChainWork::from_header(header)
+            .and_then(|w| parent.work.checked_add(&w))
+            .ok_or_else(|| Error::BadData("chain work overflow".to_string()))?;
+        if let Some(event) = self.append(header, header.prev_hash, parent.height + 1, work)? {
+            // there may be no subscribers
+            let _ = self.events.send(event);
+        }
+        Ok(())
+    }
+}
+
+impl HeaderProvider for FileHeaderStore {
+    fn height(&self) -> u32 {
+        FileHeaderStore::height(self)
+    }
+
+    fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        FileHeaderStore::hash_at(self, height)
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        FileHeaderStore::height_of(self, hash)
+    }
+}
+
+impl<T: HeaderProvider> HeaderProvider for RwLock<T> {
+    fn height(&self) -> u32 {
+        self.read().unwrap().height()
+    }
+
+    fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        self.read().unwrap().hash_at(height)
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.read().unwrap().height_of(hash)
+    }
+
+    fn tip(&self) -> BlockHash {
+        self.read().unwrap().tip()
+    }
+
+    /// Locate the blocks while holding the lock, so that the chain does not change part way through.
+    fn locate_blocks(&self, locator: &BlockLocator, max: usize) -> Result<Vec<BlockHash>> {
+        self.read().unwrap().locate_blocks(locator, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    /// Removes the file when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> TempFile {
+            TempFile(std::env::temp_dir().join(format!("bsv-headers-{}.bin", Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Mine a chain of regtest headers starting from `parent`.
+    fn mine(parent: &BlockHeader, len: usize, salt: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for i in 0..len {
+            let mut header = BlockHeader {
+                version: salt,
+                prev_hash: headers.last().unwrap_or(parent).hash(),
+                timestamp: i as u32,
+                bits: 0x207f_ffff,
+                ..Default::default()
+            };
+            while header.validate_pow().is_err() {
+                header.nonce += 1;
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn apply_all(store: &mut FileHeaderStore, headers: &[BlockHeader]) {
+        for h in headers {
+            store.apply(h).unwrap();
+        }
+    }
+
+    #[test]
+    fn sync_restart_continue() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let headers = mine(&genesis, 3000, 1);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.tip(), genesis.hash());
+        apply_all(&mut store, &headers[..2000]);
+        assert_eq!(store.height(), 2000);
+        drop(store);
+
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.height(), 2000);
+        assert_eq!(store.tip(), headers[1999].hash());
+        assert_eq!(store.tip_work(), ChainWork::from(2 * 2001));
+        assert_eq!(store.hash_at(1000), Some(headers[999].hash()));
+        assert_eq!(
+            store.header(&headers[500].hash()).unwrap(),
+            Some(headers[500].clone())
+        );
+        // continue where it left off
+        apply_all(&mut store, &headers[2000..]);
+        drop(store);
+
+        let store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.height(), 3000);
+        assert_eq!(store.tip(), headers[2999].hash());
+        // a store for another chain can not be opened
+        assert!(FileHeaderStore::open(&path.0, BlockchainId::Main).is_err());
+    }
+
+    #[test]
+    fn best_chain_by_work() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let a = mine(&genesis, 5, 1);
+        let b = mine(&a[1], 4, 2);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        apply_all(&mut store, &a);
+        // a branch with equal work does not replace the best chain
+        apply_all(&mut store, &b[..3]);
+        assert_eq!(store.tip(), a[4].hash());
+        apply_all(&mut store, &b[3..]);
+        assert_eq!(store.tip(), b[3].hash());
+        assert_eq!(store.height(), 6);
+        assert_eq!(store.hash_at(2), Some(a[1].hash()));
+        assert_eq!(store.hash_at(3), Some(b[0].hash()));
+        assert_eq!(store.height_of(&a[4].hash()), Some(5));
+        assert!(store.apply(&mine(&b[3], 1, 3)[0]).is_ok());
+        // a header whose parent is not known is rejected
+        let orphan = mine(&mine(&genesis, 1, 4)[0], 1, 4);
+        assert!(store.apply(&orphan[0]).is_err());
+        let best: Vec<_> = (0..=store.height()).map(|h| store.hash_at(h)).collect();
+        drop(store);
+
+        let store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        let replayed: Vec<_> = (0..=store.height()).map(|h| store.hash_at(h)).collect();
+        assert_eq!(replayed, best);
+        assert!(store.contains(&a[4].hash()));
+    }
+
+    #[test]
+    fn chain_events() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let a = mine(&genesis, 3, 1);
+        let b = mine(&a[0], 3, 2);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        let mut events = store.subscribe();
+        apply_all(&mut store, &a);
+        apply_all(&mut store, &b);
+        let next = mine(&b[2], 1, 3);
+        apply_all(&mut store, &next);
+
+        let mut received = Vec::new();
+        while let Ok(e) = events.try_recv() {
+            received.push(e);
+        }
+        let extended = |h: &BlockHeader, height| ChainEvent::Extended {
+            new_tip: h.hash(),
+            height,
+        };
+        assert_eq!(
+            received,
+            vec![
+                extended(&a[0], 1),
+                extended(&a[1], 2),
+                extended(&a[2], 3),
+                ChainEvent::Reorged {
+                    old_tip: a[2].hash(),
+                    new_tip: b[2].hash(),
+                    fork_height: 1,
+                    disconnected: vec![a[2].hash(), a[1].hash()],
+                    connected: vec![b[0].hash(), b[1].hash(), b[2].hash()],
+                },
+                extended(&next[0], 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn serve_locator() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let headers = mine(&genesis, 2100, 1);
+        let side = mine(&headers[3], 2, 2);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        apply_all(&mut store, &headers);
+        apply_all(&mut store, &side);
+        let locator = |hashes: Vec<BlockHash>, hash_stop| BlockLocator {
+            version: PROTOCOL_VERSION,
+            block_locator_hashes: hashes,
+            hash_stop,
+        };
+
+        // unknown and side chain hashes are skipped, the headers follow the newest known hash
+        let l = locator(
+            vec![
+                Hash::sha256d(b"unknown"),
+                side[1].hash(),
+                headers[2090].hash(),
+                headers[5].hash(),
+            ],
+            BlockLocator::HASH_STOP,
+        );
+        assert_eq!(store.locate_headers(&l).unwrap(), headers[2091..].to_vec());
+        // the response ends at the stop hash
+        let l = locator(vec![headers[5].hash()], headers[8].hash());
+        assert_eq!(store.locate_headers(&l).unwrap(), headers[6..9].to_vec());
+        // the response is limited, and starts after genesis if nothing is known
+        let l = locator(vec![], BlockLocator::HASH_STOP);
+        let served = store.locate_headers(&l).unwrap();
+        assert_eq!(served.len(), Headers::MAX_HEADERS as usize);
+        assert_eq!(served[0], headers[0]);
+        // nothing follows the tip
+        let l = locator(vec![headers[2099].hash()], BlockLocator::HASH_STOP);
+        assert!(store.locate_headers(&l).unwrap().is_empty());
+        // an oversized locator is refused
+        let l = locator(vec![headers[0].hash(); 102], BlockLocator::HASH_STOP);
+        assert!(store.locate_headers(&l).is_err());
+        assert!(store.locate_blocks(&l, MAX_GETBLOCKS_HASHES).is_err());
+
+        // getblocks is answered with hashes, which end before the stop hash
+        let hashes = |headers: &[BlockHeader]| headers.iter().map(|h| h.hash()).collect::<Vec<_>>();
+        let l = locator(vec![side[1].hash(), headers[5].hash()], headers[8].hash());
+        assert_eq!(
+            store.locate_blocks(&l, MAX_GETBLOCKS_HASHES).unwrap(),
+            hashes(&headers[6..8])
+        );
+        let l = locator(vec![], BlockLocator::HASH_STOP);
+        assert_eq!(
+            store.locate_blocks(&l, MAX_GETBLOCKS_HASHES).unwrap(),
+            hashes(&headers[..500])
+        );
+        let shared = RwLock::new(store);
+        let l = locator(vec![headers[2000].hash()], BlockLocator::HASH_STOP);
+        assert_eq!(
+            shared.locate_blocks(&l, MAX_GETBLOCKS_HASHES).unwrap(),
+            hashes(&headers[2001..])
+        );
+        assert_eq!(HeaderProvider::tip(&shared), headers[2099].hash());
+        let store = shared.into_inner().unwrap();
+
+        // the locator of the store starts at the tip and ends with genesis
+        let l = store.locator();
+        assert_eq!(
+            l.block_locator_hashes[..10],
+            store.best_chain[2091..]
+                .iter()
+                .rev()
+                .copied()
+                .collect::<Vec<_>>()[..]
+        );
+        assert_eq!(l.block_locator_hashes.last(), Some(&genesis.hash()));
+        assert!(l.block_locator_hashes.len() < 30);
+        // a store that is behind is answered with the headers that it is missing
+        let behind_path = TempFile::new();
+        let mut behind = FileHeaderStore::open(&behind_path.0, BlockchainId::Regtest).unwrap();
+        apply_all(&mut behind, &headers[..5]);
+        assert_eq!(
+            store.locate_headers(&behind.locator()).unwrap(),
+            headers[5..2005].to_vec()
+        );
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let headers = mine(&genesis, 5000, 1);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        apply_all(&mut store, &headers);
+        let mut exported = Vec::new();
+        assert_eq!(store.export_range(0, 5000, &mut exported).unwrap(), 5001);
+        assert_eq!(exported.len(), 5001 * BlockHeader::SIZE);
+        assert_eq!(
+            &exported[..BlockHeader::SIZE],
+            &genesis.to_binary_buf().unwrap()[..]
+        );
+        assert!(store.export_range(10, 5001, &mut Vec::new()).is_err());
+        assert!(store.export_range(10, 9, &mut Vec::new()).is_err());
+
+        let copy_path = TempFile::new();
+        let mut copy = FileHeaderStore::open(&copy_path.0, BlockchainId::Regtest).unwrap();
+        let mut reports = Vec::new();
+        // the genesis header is already in the new store
+        let added = copy
+            .import_headers(&exported[..], |n| reports.push(n))
+            .unwrap();
+        assert_eq!(added, 5000);
+        assert_eq!(reports, vec![1000, 2000, 3000, 4000, 5000, 5001]);
+        assert_eq!(copy.tip(), store.tip());
+        assert_eq!(copy.tip_work(), store.tip_work());
+        assert_eq!(copy.height(), 5000);
+        // importing again adds nothing
+        assert_eq!(copy.import_headers(&exported[..], |_| {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn import_rejects_bad_data() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let headers = mine(&genesis, 10, 1);
+        let mut data: Vec<u8> = headers
+            .iter()
+            .flat_map(|h| h.to_binary_buf().unwrap())
+            .collect();
+        data.truncate(data.len() - 30);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        let e = store.import_headers(&data[..], |_| {}).unwrap_err();
+        assert!(
+            e.to_string().contains("truncated header at offset 720"),
+            "{}",
+            e
+        );
+        // the complete headers were imported
+        assert_eq!(store.height(), 9);
+
+        // a header that does not connect
+        let path = TempFile::new();
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        let e = store
+            .import_headers(&data[BlockHeader::SIZE..], |_| {})
+            .unwrap_err();
+        assert!(
+            e.to_string().contains("invalid header at offset 0"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn damaged_tail_truncated() {
+        let path = TempFile::new();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let headers = mine(&genesis, 12, 1);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        apply_all(&mut store, &headers[..10]);
+        drop(store);
+        let len = std::fs::metadata(&path.0).unwrap().len();
+
+        // a partially written record
+        let mut file = OpenOptions::new().append(true).open(&path.0).unwrap();
+        file.write_all(&[0xab; RECORD_SIZE / 2]).unwrap();
+        drop(file);
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.height(), 10);
+        assert_eq!(std::fs::metadata(&path.0).unwrap().len(), len);
+        store.apply(&headers[10]).unwrap();
+        drop(store);
+
+        // a record that was corrupted is dropped with everything after it
+        let mut data = std::fs::read(&path.0).unwrap();
+        let last = data.len() - RECORD_SIZE;
+        data[last + 5] ^= 0xff;
+        std::fs::write(&path.0, &data).unwrap();
+        let mut store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.height(), 10);
+        apply_all(&mut store, &headers[10..]);
+        drop(store);
+        let store = FileHeaderStore::open(&path.0, BlockchainId::Regtest).unwrap();
+        assert_eq!(store.height(), 12);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::p2p::peer_scoring::RTT_ALPHA;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::Sender;
+use uuid::Uuid;
+
+/// The maximum number of unanswered pings that are remembered.
+const MAX_PENDING_PINGS: usize = 8;
+
+/// Configuration of the health events emitted by each connection.
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// How often a [HealthEvent::PeerHealth] event is emitted and a keepalive ping is sent.
+    pub interval: Duration,
+    /// A [HealthEvent::RttExceeded] event is emitted when a ping takes longer than this.
+    pub rtt_threshold: Duration,
+    /// A [HealthEvent::PingUnanswered] event is emitted when a ping has been waiting for longer than
+    /// this. This is checked every `interval`.
+    pub unanswered_after: Duration,
+    /// The channel to which the events are sent.
+    pub events: Sender<HealthEvent>,
+}
+
+impl HealthConfig {
+    /// Create a configuration with the default thresholds, an interval of one minute, an RTT
+    /// threshold of two seconds, and pings unanswered after thirty seconds.
+    pub fn new(events: Sender<HealthEvent>) -> HealthConfig {
+        HealthConfig {
+            interval: Duration::from_secs(60),
+            rtt_threshold: Duration::from_secs(2),
+            unanswered_after: Duration::from_secs(30),
+            events,
+        }
+    }
+}
+
+/// An event describing the health of a connection to a peer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthEvent {
+    /// Periodic statistics of the connection.
+    PeerHealth {
+        peer_id: Uuid,
+        /// The exponentially weighted moving average of the ping round-trip time.
+        rtt_ewma: Option<Duration>,
+        /// The number of messages received since the last event.
+        messages_in_window: u64,
+        /// The number of payload bytes received since the last event.
+        bytes_in_window: u64,
+        /// The number of pings waiting for a pong.
+        pending_pings: usize,
+    },
+    /// A ping took longer than the threshold to be answered.
+    RttExceeded {
+        peer_id: Uuid,
+        rtt: Duration,
+        threshold: Duration,
+    },
+    /// A ping has not been answered. This is emitted once until a pong is received.
+    PingUnanswered {
+        peer_id: Uuid,
+        outstanding: Duration,
+    },
+}
+
+/// Tracks the health of a single connection and produces its [HealthEvent]s.
+///
+/// The tracker is owned by the connection, so recording a message does not take any locks. The
+/// current time is passed in so that the tracker can be driven by a test clock.
+#[derive(Debug, Clone)]
+pub struct HealthTracker {
+    peer_id: Uuid,
+    interval: Duration,
+    rtt_threshold: Duration,
+    unanswered_after: Duration,
+    window_start: Instant,
+    messages: u64,
+    bytes: u64,
+    rtt_ewma: Option<Duration>,
+    /// The nonces of the pings waiting for a pong and when they were sent, oldest first.
+    pending: VecDeque<(u64, Instant)>,
+    unanswered_reported: bool,
+}
+
+impl HealthTracker {
+    /// Create a tracker whose first window starts at `now`.
+    pub fn new(peer_id: Uuid, config: &HealthConfig, now: Instant) -> HealthTracker {
+        HealthTracker {
+            peer_id,
+            interval: config.interval,
+            rtt_threshold: config.rtt_threshold,
+            unanswered_after: config.unanswered_after,
+            window_start: now,
+            messages: 0,
+            bytes: 0,
+            rtt_ewma: None,
+            pending: VecDeque::new(),
+            unanswered_reported: false,
+        }
+    }
+
+    /// Apply a new configuration, returning the names of the settings that changed.
+    ///
+    /// The current window and the outstanding pings are kept, the new thresholds apply to them.
+    pub fn update(&mut self, config: &HealthConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.interval != config.interval {
+            self.interval = config.interval;
+            changed.push("interval");
+        }
+        if self.rtt_threshold != config.rtt_threshold {
+            self.rtt_threshold = config.rtt_threshold;
+            changed.push("rtt_threshold");
+        }
+        if self.unanswered_after != config.unanswered_after {
+            self.unanswered_after = config.unanswered_after;
+            // report the oldest ping again if it is still waiting by the new threshold
+            self.unanswered_reported = false;
+            changed.push("unanswered_after");
+        }
+        changed
+    }
+
+    /// The average round-trip time of the pings that have been answered, None if there are none.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt_ewma
+    }
+
+    /// Record a message received from the peer.
+    pub fn record_message(&mut self, payload_size: usize) {
+        self.messages += 1;
+        self.bytes += payload_size as u64;
+    }
+
+    /// Record a ping sent to the peer.
+    pub fn ping_sent(&mut self, nonce: u64, now: Instant) {
+        if self.pending.len() == MAX_PENDING_PINGS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((nonce, now));
+    }
+
+    /// Record a pong received from the peer, returning an event if the round-trip time exceeded the
+    /// threshold. Pongs that do not match a ping are ignored.
+    pub fn pong_received(&mut self, nonce: u64, now: Instant) -> Option<HealthEvent> {
+        let pos = self.pending.iter().position(|(n, _)| *n == nonce)?;
+        let (_, sent) = self.pending.remove(pos).unwrap();
+        let rtt = now.saturating_duration_since(sent);
+        self.rtt_ewma = Some(match self.rtt_ewma {
+            None => rtt,
+            Some(avg) => avg.mul_f64(1.0 - RTT_ALPHA) + rtt.mul_f64(RTT_ALPHA),
+        });
+        self.unanswered_reported = false;
+        (rtt > self.rtt_threshold).then_some(HealthEvent::RttExceeded {
+            peer_id: self.peer_id,
+            rtt,
+            threshold: self.rtt_threshold,
+        })
+    }
+
+    /// Produce the events that are due at `now`.
+    ///
+    /// A [HealthEvent::PingUnanswered] event comes first if the oldest ping has been waiting too long,
+    /// followed by the [HealthEvent::PeerHealth] event if the interval has elapsed, which starts a new
+    /// window.
+    pub fn poll(&mut self, now: Instant) -> Vec<HealthEvent> {
+        let mut events = Vec::new();
+        if let Some((_, sent)) = self.pending.front() {
+            let outstanding = now.saturating_duration_since(*sent);
+            if outstanding >= self.unanswered_after && !self.unanswered_reported {
+                self.unanswered_reported = true;
+                events.push(HealthEvent::PingUnanswered {
+                    peer_id: self.peer_id,
+                    outstanding,
+                });
+            }
+        }
+        if now.saturating_duration_since(self.window_start) >= self.interval {
+            events.push(HealthEvent::PeerHealth {
+                peer_id: self.peer_id,
+                rtt_ewma: self.rtt_ewma,
+                messages_in_window: self.messages,
+                bytes_in_window: self.bytes,
+                pending_pings: self.pending.len(),
+            });
+            self.window_start = now;
+            self.messages = 0;
+            self.bytes = 0;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_in_order() {
+        let (tx, _rx) = tokio::sync::broadcast::channel(10);
+        let config = HealthConfig {
+            interval: Duration::from_secs(10),
+            rtt_threshold: Duration::from_millis(500),
+            unanswered_after: Duration::from_secs(5),
+            events: tx,
+        };
+        let peer_id = Uuid::new_v4();
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let mut t = HealthTracker::new(peer_id, &config, start);
+        let mut events = Vec::new();
+
+        t.record_message(100);
+        t.record_message(50);
+        events.extend(t.poll(at(5.0)));
+        assert!(events.is_empty());
+        t.ping_sent(1, at(10.0));
+        events.extend(t.poll(at(10.0)));
+        t.record_message(10);
+        // a slow pong produces an event immediately
+        events.extend(t.pong_received(1, at(10.8)));
+        // an unknown pong is ignored
+        assert_eq!(t.pong_received(99, at(11.0)), None);
+        t.ping_sent(2, at(20.0));
+        events.extend(t.poll(at(20.0)));
+        // the ping is reported once
+        events.extend(t.poll(at(30.0)));
+        events.extend(t.poll(at(31.0)));
+        events.extend(t.poll(at(40.0)));
+
+        let health =
+            |rtt_ms: Option<u64>, messages, bytes, pending_pings| HealthEvent::PeerHealth {
+                peer_id,
+                rtt_ewma: rtt_ms.map(Duration::from_millis),
+                messages_in_window: messages,
+                bytes_in_window: bytes,
+                pending_pings,
+            };
+        assert_eq!(
+            events,
+            vec![
+                health(None, 2, 150, 1),
+                HealthEvent::RttExceeded {
+                    peer_id,
+                    rtt: Duration::from_millis(800),
+                    threshold: Duration::from_millis(500),
+                },
+                health(Some(800), 1, 10, 1),
+                HealthEvent::PingUnanswered {
+                    peer_id,
+                    outstanding: Duration::from_secs(10),
+                },
+                health(Some(800), 0, 0, 1),
+                health(Some(800), 0, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn pending_pings_bounded() {
+        let (tx, _rx) = tokio::sync::broadcast::channel(10);
+        let config = HealthConfig::new(tx);
+        let start = Instant::now();
+        let mut t = HealthTracker::new(Uuid::new_v4(), &config, start);
+        for nonce in 0..20 {
+            t.ping_sent(nonce, start);
+        }
+        assert_eq!(t.pending.len(), MAX_PENDING_PINGS);
+        // the oldest pings were dropped
+        assert!(t.pong_received(0, start).is_none());
+        assert!(t.pending.iter().any(|(n, _)| *n == 19));
+        // a fast pong does not exceed the threshold
+        assert!(t
+            .pong_received(19, start + Duration::from_millis(10))
+            .is_none());
+        assert_eq!(t.rtt_ewma, Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn update_thresholds() {
+        let (tx, _rx) = tokio::sync::broadcast::channel(10);
+        let config = HealthConfig::new(tx);
+        let start = Instant::now();
+        let mut t = HealthTracker::new(Uuid::new_v4(), &config, start);
+        assert!(t.update(&config).is_empty());
+        t.ping_sent(1, start);
+        assert!(t.pong_received(1, start + Duration::from_secs(1)).is_none());
+        let faster = HealthConfig {
+            interval: Duration::from_secs(5),
+            rtt_threshold: Duration::from_millis(500),
+            ..config
+        };
+        assert_eq!(t.update(&faster), vec!["interval", "rtt_threshold"]);
+        t.ping_sent(2, start);
+        assert!(t.pong_received(2, start + Duration::from_secs(1)).is_some());
+        // the current window ends at the new interval
+        assert_eq!(t.poll(start + Duration::from_secs(5)).len(), 1);
+    }
+}
@@ -1,20 +1,62 @@
-use crate::bitcoin::BlockchainId;
+use crate::bitcoin::{BlockHash, BlockchainId, TxHash};
+use crate::p2p::announce::{DEFAULT_INV_BATCH_SIZE, DEFAULT_INV_TRICKLE_INTERVAL};
+use crate::p2p::ban_audit::{AuditSink, BanAction, BanAuditEntry, LogAuditSink};
+use crate::p2p::broadcast::BroadcastReport;
+use crate::p2p::config_builder::config_builder;
+use crate::p2p::config_error::ConfigError;
 use crate::p2p::connection::{Connection, ConnectionConfig};
+use crate::p2p::connector::{Connector, TcpConnector};
 use crate::p2p::envelope::{P2PMessageChannelReceiver, P2PMessageChannelSender};
+use crate::p2p::header_store::HeaderProvider;
+use crate::p2p::health::HealthConfig;
 use crate::p2p::manager::P2PManagerState::{Paused, Running};
 use crate::p2p::manager::P2PMgrCallMessage::ReplyState;
-use crate::p2p::peer::PeerAddress;
+use crate::p2p::mempool::MempoolResponder;
+use crate::p2p::messages::{BlockLocator, BlockStream, Services};
+use crate::p2p::params::{NetworkParams, DEFAULT_MAX_TIME_OFFSET};
+use crate::p2p::peer::{AddressFamilyPolicy, PeerAddress};
+use crate::p2p::peer_scoring::{select_peers, PeerHistory};
+use crate::p2p::peer_store::{PeerStore, PeerWriteBehind};
+use crate::p2p::serve_cache::GetDataResponder;
+use crate::p2p::slots::{ConnectionSlots, SlotCounts, SlotGuard};
 use crate::p2p::ACTOR_CHANNEL_SIZE;
 use crate::result::InternalError;
-use crate::Result;
+use crate::util::FeeRate;
+use crate::{Error, Result};
+use log::{info, warn};
 use minactor::{create_actor, Actor, ActorRef, Control};
-use std::collections::HashMap;
-use std::net::IpAddr;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::Sender;
 use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// The interval between sweeps of the connections and bans, see [P2PMgrSendMessage::Sweep].
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The fraction of the peers selected to connect to that have not been tried before, see
+/// [select_peers()].
+const SELECTION_EXPLORATION: f64 = 0.25;
+
+/// The interval between writes of the updated peer histories to the store, see [PeerWriteBehind].
+const PEER_STORE_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The number of updated peer histories that are written to the store without waiting for the interval.
+const PEER_STORE_MAX_PENDING: usize = 256;
 
 /// Configuration for the P2PManager.
-#[derive(Debug, Clone)]
+///
+/// Use [P2PManagerConfig::builder()] to construct a configuration, or deserialize it from a
+/// configuration file. Fields that are missing take their default values, durations can be given as
+/// strings such as `"30s"` (see [parse_duration()](crate::util::parse_duration)), and the fields that
+/// hold channels or trait objects can not be deserialized and are left at their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct P2PManagerConfig {
     /// The blockchain (mainnet, testnet, stn, regtest) to use.
     pub blockchain: BlockchainId,
@@ -34,14 +76,92 @@ pub struct P2PManagerConfig {
     ///
     /// If this is false, then all peers must be manually added.
     pub add_peers: bool,
+    /// Look up the DNS seeds of the blockchain at start and connect to the nodes they return. The
+    /// seeds are host names that resolve to the addresses of nodes that accept connections. This
+    /// requires [add_peers](P2PManagerConfig::add_peers).
+    pub query_dns_seeds: bool,
     /// Initial list of peers to which connections should be established.
     ///
-    /// Note that if start_paused is true then this list is not processed.
+    /// Connections are made until `connections_target` is met, to peers chosen from the list by their
+    /// quality scores with at most `max_outbound_per_netgroup` connections in each
+    /// [NetGroup](crate::p2p::NetGroup). Note that if start_paused is true then this list is not
+    /// processed.
+    ///
+    /// In the [FixedPeerList](OperatingMode::FixedPeerList) mode these, with the
+    /// [bootstrap peers](P2PManagerConfig::bootstrap_peers), are the only peers that are connected to.
     pub initial_peers: Vec<PeerAddress>,
+    /// Addresses of reliable nodes, such as the operator's own, that are connected to before the
+    /// [initial peers](P2PManagerConfig::initial_peers) in any operating mode.
+    ///
+    /// At start they are added to the [peer_store](P2PManagerConfig::peer_store) and marked as
+    /// [bootstrap](crate::p2p::PeerHistory::bootstrap) peers. In the
+    /// [FixedPeerList](OperatingMode::FixedPeerList) mode they are part of the fixed list, and an
+    /// initial peer at the same address refers to the same peer.
+    pub bootstrap_peers: Vec<SocketAddr>,
+    /// Whether connections are restricted to a fixed list of peers, this can be changed with
+    /// [P2PManager::set_operating_mode()].
+    pub operating_mode: OperatingMode,
+    /// The channel to which [ControlEvent]s are sent, if any.
+    #[serde(skip)]
+    pub control_events: Option<Sender<ControlEvent>>,
+    /// The maximum number of selected peers in a single [NetGroup](crate::p2p::NetGroup). Peers
+    /// added with [P2PManager::add_peer()] are not limited.
+    pub max_outbound_per_netgroup: u16,
     /// If true then start in the paused state.
     pub start_paused: bool,
-    /// Send control messages to the data channel.
+    /// Send control messages to the data channel. Reject messages are always sent.
     pub send_control_msgs: bool,
+    /// How to respond to mempool requests from peers.
+    #[serde(skip)]
+    pub mempool_responder: MempoolResponder,
+    /// How to respond to getdata requests from peers. The connections share the
+    /// [ServeCache](crate::p2p::ServeCache) of the responder, see [GetDataResponder::new()].
+    #[serde(skip)]
+    pub getdata_responder: GetDataResponder,
+    /// The source of the block hashes sent in response to getblocks requests from peers, which are
+    /// not answered if None. Default is None.
+    #[serde(skip)]
+    pub header_provider: Option<Arc<dyn HeaderProvider>>,
+    /// The address on which other nodes can reach this node, advertised to peers.
+    pub external_address: Option<SocketAddr>,
+    /// Learn the external address from the address that peers report for this node, if it is
+    /// not configured.
+    pub learn_external_address: bool,
+    /// Do not announce transactions to peers whose fee filter is higher than the fee rate of the
+    /// transaction.
+    pub respect_fee_filter: bool,
+    /// Establishes the streams for outbound connections, for example over TLS or a proxy.
+    #[serde(skip)]
+    pub connector: Arc<dyn Connector>,
+    /// Emit health events for each connection, see [HealthConfig].
+    #[serde(skip)]
+    pub health: Option<HealthConfig>,
+    /// Pass on only the first announcement and the first copy of each transaction to the data channel,
+    /// across all connections. Disable this to receive every copy from every peer.
+    pub deduplicate_txs: bool,
+    /// Where the history of peers is kept, including bans. Bans are only held in memory if this is None.
+    ///
+    /// The manager and its connections write to the store through a [PeerWriteBehind], which the
+    /// manager flushes when it stops.
+    #[serde(skip)]
+    pub peer_store: Option<Arc<dyn PeerStore>>,
+    /// Where the decisions to ban and unban peers are recorded, by default the log.
+    #[serde(skip)]
+    pub ban_audit: Arc<dyn AuditSink>,
+    /// Refuse connections to peers whose clock differs from ours by more than this, if set.
+    #[serde(deserialize_with = "crate::util::duration::deserialize_option")]
+    pub max_time_offset: Option<Duration>,
+    /// The maximum number of transactions announced in each inv message.
+    pub inv_batch_size: usize,
+    /// The mean interval at which broadcast transactions are announced to each peer. Announcements
+    /// are queued and sent together at randomized intervals, rather than as soon as they are broadcast.
+    #[serde(deserialize_with = "crate::util::duration::deserialize")]
+    pub inv_trickle_interval: Duration,
+    /// Only complete the handshake with peers that offer at least these services.
+    pub required_services: Services,
+    /// The address families of the peers that are connected to, set this to
+    /// [V6Only](AddressFamilyPolicy::V6Only) on a host that only has IPv6 connectivity.
+    pub address_family_policy: AddressFamilyPolicy,
 }
 
 impl P2PManagerConfig {
@@ -54,11 +174,162 @@ impl P2PManagerConfig {
             connections_target: 8,
             connections_max: None,
             add_peers: true,
+            query_dns_seeds: false,
             initial_peers: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            operating_mode: OperatingMode::Normal,
+            control_events: None,
+            max_outbound_per_netgroup: 2,
             start_paused: false,
             send_control_msgs: false,
+            mempool_responder: MempoolResponder::default(),
+            getdata_responder: GetDataResponder::default(),
+            header_provider: None,
+            external_address: None,
+            learn_external_address: false,
+            respect_fee_filter: false,
+            connector: Arc::new(TcpConnector),
+            health: None,
+            deduplicate_txs: true,
+            peer_store: None,
+            ban_audit: Arc::new(LogAuditSink),
+            max_time_offset: Some(DEFAULT_MAX_TIME_OFFSET),
+            inv_batch_size: DEFAULT_INV_BATCH_SIZE,
+            inv_trickle_interval: DEFAULT_INV_TRICKLE_INTERVAL,
+            required_services: Services::NONE,
+            address_family_policy: AddressFamilyPolicy::Both,
         }
     }
+
+    /// Check that the configuration is consistent, returning the first problem found.
+    ///
+    /// This includes the [ConnectionConfig] that is derived from this configuration.
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.listen {
+            if self.connections_max == Some(0) {
+                return Err(ConfigError::ListenWithoutConnections);
+            }
+            if self.listen_port == Some(0) {
+                return Err(ConfigError::ZeroListenPort);
+            }
+        }
+        if self.max_outbound_per_netgroup == 0 {
+            return Err(ConfigError::ZeroNetGroupLimit);
+        }
+        if self.operating_mode == OperatingMode::FixedPeerList
+            && self.initial_peers.is_empty()
+            && self.bootstrap_peers.is_empty()
+        {
+            return Err(ConfigError::EmptyFixedPeerList);
+        }
+        if let Some(max) = self.connections_max {
+            if self.connections_target > max {
+                return Err(ConfigError::TargetExceedsMax {
+                    target: self.connections_target,
+                    max,
+                });
+            }
+        }
+        ConnectionConfig::from(self).validate()
+    }
+
+    /// A builder that starts from the default configuration for mainnet.
+    pub fn builder() -> P2PManagerConfigBuilder {
+        P2PManagerConfigBuilder::default()
+    }
+}
+
+config_builder! {
+    /// Builds a [P2PManagerConfig], see [P2PManagerConfig::builder()].
+    P2PManagerConfigBuilder for P2PManagerConfig {
+        blockchain: BlockchainId,
+        listen: bool,
+        listen_port: Option<u16>,
+        connections_target: u16,
+        connections_max: Option<u16>,
+        add_peers: bool,
+        query_dns_seeds: bool,
+        initial_peers: Vec<PeerAddress>,
+        bootstrap_peers: Vec<SocketAddr>,
+        operating_mode: OperatingMode,
+        control_events: Option<Sender<ControlEvent>>,
+        max_outbound_per_netgroup: u16,
+        start_paused: bool,
+        send_control_msgs: bool,
+        mempool_responder: MempoolResponder,
+        getdata_responder: GetDataResponder,
+        header_provider: Option<Arc<dyn HeaderProvider>>,
+        external_address: Option<SocketAddr>,
+        learn_external_address: bool,
+        respect_fee_filter: bool,
+        connector: Arc<dyn Connector>,
+        health: Option<HealthConfig>,
+        deduplicate_txs: bool,
+        peer_store: Option<Arc<dyn PeerStore>>,
+        ban_audit: Arc<dyn AuditSink>,
+        max_time_offset: Option<Duration>,
+        inv_batch_size: usize,
+        inv_trickle_interval: Duration,
+        required_services: Services,
+        address_family_policy: AddressFamilyPolicy,
+    }
+}
+
+/// Which peers the [P2PManager] connects to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatingMode {
+    /// Connect to the initial peers, to added peers, and to discovered peers if
+    /// [add_peers](P2PManagerConfig::add_peers) is set.
+    #[default]
+    Normal,
+    /// Connect only to the peers in a fixed list, refusing any other peer.
+    FixedPeerList,
+}
+
+impl fmt::Display for OperatingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            OperatingMode::Normal => "normal",
+            OperatingMode::FixedPeerList => "fixed_peer_list",
+        })
+    }
+}
+
+/// An event describing a change made to the [P2PManager], sent to
+/// [P2PManagerConfig::control_events].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlEvent {
+    /// The operating mode was changed by [P2PManager::set_operating_mode()].
+    OperatingModeChanged {
+        from: OperatingMode,
+        to: OperatingMode,
+        /// The peers that were disconnected because the new mode does not permit them.
+        disconnected: Vec<PeerAddress>,
+    },
+    /// A connection ended without being closed by the P2PManager, and was removed by a sweep.
+    ConnectionLost {
+        peer: PeerAddress,
+        reason: ConnectionLostReason,
+    },
+}
+
+/// Why a connection ended without being closed by the [P2PManager].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionLostReason {
+    /// The connection task ended normally.
+    Closed,
+    /// The connection task panicked.
+    Crashed,
+}
+
+impl fmt::Display for ConnectionLostReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionLostReason::Closed => "closed",
+            ConnectionLostReason::Crashed => "crashed",
+        })
+    }
 }
 
 impl Default for P2PManagerConfig {
@@ -101,6 +372,8 @@ pub struct P2PManager {
     actor: ActorRef<P2PManagerActor>,
     /// The data channel
     data_channel: P2PMessageChannelSender,
+    /// The connection slots, shared with the actor
+    slots: ConnectionSlots,
 }
 
 impl P2PManager {
@@ -109,18 +382,23 @@ impl P2PManager {
     /// This returns the P2PManager and a tokio join handle to the P2PManager actor.
     ///
     /// The join handle should be awaited at termination to ensure that the P2PManager is stopped in a normal fashion.
-    pub async fn new(config: P2PManagerConfig) -> (P2PManager, JoinHandle<()>) {
+    ///
+    /// Returns an error if the configuration is not valid, see [P2PManagerConfig::validate()].
+    pub async fn new(config: P2PManagerConfig) -> Result<(P2PManager, JoinHandle<()>)> {
+        config.validate()?;
         let (data_tx, _data_rx) = tokio::sync::broadcast::channel(ACTOR_CHANNEL_SIZE);
         let d_tx2 = data_tx.clone();
-        let actor = P2PManagerActor::new(config, d_tx2);
+        let slots = ConnectionSlots::new(config.connections_max.map_or(usize::MAX, usize::from));
+        let actor = P2PManagerActor::new(config, d_tx2, slots.clone());
         let (a_ref, j) = create_actor(actor).await.unwrap();
-        (
+        Ok((
             P2PManager {
                 data_channel: data_tx,
                 actor: a_ref,
+                slots,
             },
             j,
-        )
+        ))
     }
 
     /// Subscribe to the data channel.
@@ -151,6 +429,204 @@ impl P2PManager {
         Ok(())
     }
 
+    /// Announce a transaction to all connected peers.
+    ///
+    /// The announcement is queued on each connection and sent with the other queued transactions
+    /// after a random delay, see [P2PManagerConfig::inv_trickle_interval].
+    ///
+    /// If [P2PManagerConfig::respect_fee_filter] is set then the transaction is not announced to peers
+    /// whose fee filter is higher than `fee_rate`.
+    pub async fn broadcast_tx(&self, hash: TxHash, fee_rate: FeeRate) -> Result<()> {
+        self.actor
+            .send(P2PMgrSendMessage::BroadcastTx(hash, fee_rate))
+            .await?;
+        Ok(())
+    }
+
+    /// Announce a transaction to all connected peers, as [broadcast_tx()](Self::broadcast_tx), and
+    /// report how they respond within `wait`.
+    ///
+    /// The transaction must be available to the peers that request it, see
+    /// [P2PManagerConfig::getdata_responder]. The wait ends early if every peer rejects it.
+    pub async fn broadcast_tx_and_report(
+        &self,
+        hash: TxHash,
+        fee_rate: FeeRate,
+        wait: Duration,
+    ) -> Result<BroadcastReport> {
+        let mut rx = self.subscribe();
+        let peers = self.connected_peers().await?;
+        self.broadcast_tx(hash, fee_rate).await?;
+        let mut report = BroadcastReport::new(hash, peers.iter().map(|p| p.peer_id).collect());
+        report.collect(&mut rx, wait).await;
+        Ok(report)
+    }
+
+    /// Announce a block to all connected peers.
+    ///
+    /// Unlike transactions, blocks are announced immediately.
+    pub async fn announce_block(&self, hash: BlockHash) -> Result<()> {
+        self.actor
+            .send(P2PMgrSendMessage::AnnounceBlock(hash))
+            .await?;
+        Ok(())
+    }
+
+    /// Send a block to a connected peer, encoding the transactions as they arrive from the stream.
+    ///
+    /// The block is dropped, with a warning, if there is no connection to the peer.
+    pub async fn send_block(&self, peer_id: Uuid, block: BlockStream) -> Result<()> {
+        self.actor
+            .send(P2PMgrSendMessage::SendBlock(peer_id, block))
+            .await?;
+        Ok(())
+    }
+
+    /// Ask a connected peer for the headers that follow the locator, see
+    /// [FileHeaderStore::locator()](crate::p2p::FileHeaderStore::locator). The headers arrive on the
+    /// data channel as a [Headers](crate::p2p::P2PMessage::Headers) message.
+    ///
+    /// The request is dropped, with a warning, if there is no connection to the peer, and it is not
+    /// sent if the handshake with the peer has not completed.
+    pub async fn request_headers(&self, peer_id: Uuid, locator: BlockLocator) -> Result<()> {
+        self.actor
+            .send(P2PMgrSendMessage::RequestHeaders(peer_id, locator))
+            .await?;
+        Ok(())
+    }
+
+    /// Connect to a peer, unless there is already a connection to its IP address.
+    ///
+    /// Returns an error without starting a connection if the P2PManager is paused, if the peer is
+    /// banned, if its address family is not allowed by [P2PManagerConfig::address_family_policy],
+    /// or if all of the connection slots are in use.
+    pub async fn add_peer(&self, peer: PeerAddress) -> Result<()> {
+        let address = peer.address;
+        let r = self.actor.call(P2PMgrCallMessage::AddPeer(peer)).await?;
+        match r? {
+            P2PMgrCallMessage::ReplyAddPeer(None) => Ok(()),
+            P2PMgrCallMessage::ReplyAddPeer(Some(ConnectRefused::NoSlot)) => {
+                Err(Error::NoConnectionSlot)
+            }
+            P2PMgrCallMessage::ReplyAddPeer(Some(reason)) => Err(Error::BadArgument(format!(
+                "not connecting to {}: {:?}",
+                address, reason
+            ))),
+            _ => panic!("should never get here"),
+        }
+    }
+
+    /// Change the operating mode.
+    ///
+    /// Switching to [FixedPeerList](OperatingMode::FixedPeerList) requires the list of peers,
+    /// disconnects every peer whose IP address is not in it, and then connects to the peers in the
+    /// list. Switching to [Normal](OperatingMode::Normal) keeps the existing connections, must not
+    /// be given a list, and connects to the [bootstrap](P2PManagerConfig::bootstrap_peers) and
+    /// [initial peers](P2PManagerConfig::initial_peers). No connections are made while the
+    /// P2PManager is paused. A [ControlEvent::OperatingModeChanged] is sent when the mode has been
+    /// changed.
+    pub async fn set_operating_mode(
+        &self,
+        mode: OperatingMode,
+        fixed_peers: Option<Vec<PeerAddress>>,
+    ) -> Result<()> {
+        match (mode, &fixed_peers) {
+            (OperatingMode::FixedPeerList, Some(peers)) if !peers.is_empty() => {}
+            (OperatingMode::FixedPeerList, _) => {
+                return Err(Error::BadArgument(
+                    "a fixed peer list is required".to_string(),
+                ))
+            }
+            (OperatingMode::Normal, Some(_)) => {
+                return Err(Error::BadArgument(
+                    "a fixed peer list can only be given with FixedPeerList".to_string(),
+                ))
+            }
+            (OperatingMode::Normal, None) => {}
+        }
+        let r = self
+            .actor
+            .call(P2PMgrCallMessage::SetOperatingMode(mode, fixed_peers))
+            .await?;
+        match r? {
+            P2PMgrCallMessage::ReplyOperatingMode => Ok(()),
+            _ => panic!("should never get here"),
+        }
+    }
+
+    /// The number of connections that are pending and established.
+    ///
+    /// A connection is pending from when it is started until its handshake completes.
+    pub fn connection_counts(&self) -> SlotCounts {
+        self.slots.counts()
+    }
+
+    /// An estimate of how far the network clock is ahead of ours, in seconds.
+    ///
+    /// This is the median of the differences between the timestamps in the version messages of the
+    /// connected peers and our clock, None if no peer has completed the handshake.
+    pub fn network_time_offset(&self) -> Option<i64> {
+        self.slots.median_time_offset()
+    }
+
+    /// Ban a peer for the given duration, closing the connection to it if there is one.
+    ///
+    /// The ban applies to the IP address of the peer, it is taken from the active connection if there
+    /// is one and from the [PeerStore] otherwise. If a store is configured then the ban is recorded in
+    /// the history of the peer, which is created if the peer is not in the store yet. Connections
+    /// made after this returns are checked against the ban.
+    ///
+    /// The `reason` is written to the [AuditSink](crate::p2p::AuditSink) of the configuration.
+    /// Returns an error if the peer is neither connected nor in the store.
+    pub async fn ban_peer(
+        &self,
+        peer_id: Uuid,
+        duration: Duration,
+        reason: impl Into<String>,
+    ) -> Result<()> {
+        let r = self
+            .actor
+            .call(P2PMgrCallMessage::BanPeer(peer_id, duration, reason.into()))
+            .await?;
+        match r? {
+            P2PMgrCallMessage::ReplyBanned(true) => Ok(()),
+            P2PMgrCallMessage::ReplyBanned(false) => {
+                Err(Error::BadArgument(format!("unknown peer: {}", peer_id)))
+            }
+            _ => panic!("should never get here"),
+        }
+    }
+
+    /// Lift the ban of a peer before it expires, so that it can be connected to again.
+    ///
+    /// The ban is removed from the history of the peer in the [PeerStore], if there is one. The
+    /// `reason` is written to the [AuditSink](crate::p2p::AuditSink) of the configuration. Returns
+    /// an error if the peer is not banned.
+    pub async fn unban_peer(&self, peer_id: Uuid, reason: impl Into<String>) -> Result<()> {
+        let r = self
+            .actor
+            .call(P2PMgrCallMessage::UnbanPeer(peer_id, reason.into()))
+            .await?;
+        match r? {
+            P2PMgrCallMessage::ReplyUnbanned(true) => Ok(()),
+            P2PMgrCallMessage::ReplyUnbanned(false) => Err(Error::BadArgument(format!(
+                "peer is not banned: {}",
+                peer_id
+            ))),
+            _ => panic!("should never get here"),
+        }
+    }
+
+    /// Get the peers to which there is a connection.
+    pub async fn connected_peers(&self) -> Result<Vec<PeerAddress>> {
+        let r = self.actor.call(P2PMgrCallMessage::GetPeers).await?;
+        if let P2PMgrCallMessage::ReplyPeers(peers) = r? {
+            Ok(peers)
+        } else {
+            panic!("should never get here");
+        }
+    }
+
     /// Get the current state of the P2PManager.
     pub async fn get_state(&self) -> Result<P2PManagerState> {
         let r = self.actor.call(P2PMgrCallMessage::GetState).await?;
@@ -178,6 +654,41 @@ enum P2PMgrSendMessage {
     Pause,
     /// Resume the P2PManager after it has been paused.
     Resume,
+    /// Announce a transaction to all peers.
+    BroadcastTx(TxHash, FeeRate),
+    /// Announce a block to all peers.
+    AnnounceBlock(BlockHash),
+    /// Send a block to a peer.
+    SendBlock(Uuid, BlockStream),
+    /// Send a getheaders message to a peer.
+    RequestHeaders(Uuid, BlockLocator),
+    /// Addresses of nodes that were found by the DNS query, to connect to if more connections are needed.
+    Discovered(Vec<SocketAddr>),
+    /// Remove the connections that have ended unexpectedly and the bans that have expired. This is
+    /// sent periodically by a sub-task.
+    Sweep,
+}
+
+/// The reason that a connection to a peer was not started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectRefused {
+    Paused,
+    Banned,
+    NoSlot,
+    AddressFamily,
+    NotInFixedList,
+}
+
+impl fmt::Display for ConnectRefused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConnectRefused::Paused => "paused",
+            ConnectRefused::Banned => "banned",
+            ConnectRefused::NoSlot => "no connection slot",
+            ConnectRefused::AddressFamily => "address family not allowed",
+            ConnectRefused::NotInFixedList => "not in the fixed peer list",
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -186,6 +697,26 @@ enum P2PMgrCallMessage {
     GetState,
     /// Reply to GetState call.
     ReplyState(P2PManagerState),
+    /// Ban a peer for a duration, for the given reason.
+    BanPeer(Uuid, Duration, String),
+    /// Reply to BanPeer call, false if the peer is not known.
+    ReplyBanned(bool),
+    /// Lift the ban of a peer, for the given reason.
+    UnbanPeer(Uuid, String),
+    /// Reply to UnbanPeer call, false if the peer is not banned.
+    ReplyUnbanned(bool),
+    /// Get the connected peers.
+    GetPeers,
+    /// Reply to GetPeers call.
+    ReplyPeers(Vec<PeerAddress>),
+    /// Connect to a peer.
+    AddPeer(PeerAddress),
+    /// Reply to AddPeer call, the reason if the connection was not started.
+    ReplyAddPeer(Option<ConnectRefused>),
+    /// Change the operating mode, with the fixed list for FixedPeerList.
+    SetOperatingMode(OperatingMode, Option<Vec<PeerAddress>>),
+    /// Reply to SetOperatingMode call.
+    ReplyOperatingMode,
 }
 
 /// The P2PManager initiates and manages P2P connections.
@@ -198,14 +729,75 @@ struct P2PManagerActor {
     /// configuration for connections
     connection_config: Arc<ConnectionConfig>,
     // current connections
-    connections: HashMap<u64, (Connection, JoinHandle<()>)>,
+    connections: HashMap<u64, ActiveConnection>,
     /// index of IP -> connection id
     ip_index: HashMap<IpAddr, u64>,
+    /// the bans, by IP address
+    bans: HashMap<IpAddr, Ban>,
+    /// limits the number of connections
+    slots: ConnectionSlots,
+    /// the current operating mode
+    mode: OperatingMode,
+    /// the IP addresses of the fixed peers, used in the FixedPeerList mode
+    fixed_ips: HashSet<IpAddr>,
+    /// the bootstrap peers, with their ids in the peer store once it has been updated
+    bootstrap: Vec<PeerAddress>,
+    /// the task that periodically triggers a sweep of the connections
+    sweep_handle: Option<JoinHandle<()>>,
+    /// buffers the writes to the peer store, it is also the store in the configuration
+    store_writer: Option<PeerWriteBehind<dyn PeerStore>>,
+    /// the task that writes to the peer store
+    store_writer_handle: Option<JoinHandle<()>>,
+}
+
+/// A ban held by the P2PManager.
+struct Ban {
+    /// The peer that was banned.
+    peer_id: Uuid,
+    /// The address of the peer when it was banned.
+    address: SocketAddr,
+    /// When the ban expires.
+    until: SystemTime,
+}
+
+/// A connection started by the P2PManager.
+struct ActiveConnection {
+    connection: Connection,
+    /// The task of the connection, which only ends when it is closed or when it panics.
+    handle: JoinHandle<()>,
+    /// The slot of the connection, which is released by the manager if the connection crashes.
+    slot: Arc<SlotGuard>,
 }
 
 impl P2PManagerActor {
-    fn new(config: P2PManagerConfig, data_channel: P2PMessageChannelSender) -> Self {
+    fn new(
+        mut config: P2PManagerConfig,
+        data_channel: P2PMessageChannelSender,
+        slots: ConnectionSlots,
+    ) -> Self {
+        // the manager and the connections share the write-behind layer in place of the store
+        let (store_writer, store_writer_handle) = match config.peer_store.take() {
+            Some(store) => {
+                let (writer, j) =
+                    PeerWriteBehind::new(store, PEER_STORE_FLUSH_INTERVAL, PEER_STORE_MAX_PENDING);
+                config.peer_store = Some(Arc::new(writer.clone()));
+                (Some(writer), Some(j))
+            }
+            None => (None, None),
+        };
         let connection_config = Arc::new(ConnectionConfig::from(&config));
+        let mode = config.operating_mode;
+        let bootstrap: Vec<PeerAddress> = config
+            .bootstrap_peers
+            .iter()
+            .map(|a| PeerAddress::new(*a))
+            .collect();
+        let fixed_ips = config
+            .initial_peers
+            .iter()
+            .chain(bootstrap.iter())
+            .map(|p| p.ip())
+            .collect();
         P2PManagerActor {
             config,
             state: P2PManagerState::Starting,
@@ -214,34 +806,434 @@ impl P2PManagerActor {
             connection_config,
             connections: HashMap::new(),
             ip_index: HashMap::new(),
+            bans: HashMap::new(),
+            slots,
+            mode,
+            fixed_ips,
+            bootstrap,
+            sweep_handle: None,
+            store_writer,
+            store_writer_handle,
+        }
+    }
+
+    /// The bootstrap peers followed by the initial peers, leaving out initial peers at the address
+    /// of a bootstrap peer.
+    fn configured_peers(&self) -> Vec<PeerAddress> {
+        let mut peers = self.bootstrap.clone();
+        for p in self.config.initial_peers.iter() {
+            if !peers.iter().any(|b| b.address == p.address) {
+                peers.push(p.clone());
+            }
         }
+        peers
     }
 
-    /// Initiate a connection to a peer.
-    async fn connect(&mut self, p: PeerAddress) {
+    /// Add the bootstrap peers to the store, or find them there, and mark them.
+    async fn store_bootstrap_peers(&mut self) {
+        let Some(store) = &self.config.peer_store else {
+            return;
+        };
+        for p in self.bootstrap.iter_mut() {
+            let address = p.address;
+            let marked = match store.find_or_create(address).await {
+                Ok(peer_id) => {
+                    p.peer_id = peer_id;
+                    let update = Box::new(move |h: &mut PeerHistory| {
+                        h.address = Some(address);
+                        h.bootstrap = true;
+                    });
+                    store.update(&peer_id, update).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = marked {
+                warn!("could not add bootstrap peer {} to store: {}", address, e);
+            }
+        }
+    }
+
+    /// Initiate a connection to a peer, if it is not banned and a connection slot is available.
+    async fn connect(&mut self, p: PeerAddress) -> std::result::Result<(), ConnectRefused> {
+        if !self.config.address_family_policy.allows(&p.ip()) {
+            return Err(ConnectRefused::AddressFamily);
+        }
+        if !self.permits(&p.ip()) {
+            return Err(ConnectRefused::NotInFixedList);
+        }
+        if self.is_banned(&p.ip()) {
+            info!("not connecting to banned peer {}", p.address);
+            return Err(ConnectRefused::Banned);
+        }
         if let std::collections::hash_map::Entry::Vacant(e) = self.ip_index.entry(p.ip()) {
-            let (c, j) = Connection::new(
+            let slot = Arc::new(self.slots.reserve().ok_or(ConnectRefused::NoSlot)?);
+            let (connection, handle) = Connection::with_slot(
                 p.clone(),
                 self.connection_config.clone(),
                 Some(self.data_channel.clone()),
+                Some(slot.clone()),
+            );
+            self.connections.insert(
+                self.next_c_id,
+                ActiveConnection {
+                    connection,
+                    handle,
+                    slot,
+                },
             );
-            self.connections.insert(self.next_c_id, (c, j));
             e.insert(self.next_c_id);
             self.next_c_id += 1
         }
+        Ok(())
     }
 
-    async fn disconnect(&mut self, p: PeerAddress) {
-        if let Some(c_id) = self.ip_index.get(&p.ip()) {
-            if let Some((c, j)) = self.connections.remove(c_id) {
-                c.close().await;
-                j.await.expect("Connection failed");
+    /// Connect to candidates until the connection target is met.
+    ///
+    /// The candidates are chosen by [select_peers()], weighted by the quality scores in the store
+    /// and keeping the number of connections in each [NetGroup](crate::p2p::NetGroup) within the
+    /// limit. The bootstrap peers among them are chosen before the others.
+    async fn select(&mut self, candidates: Vec<PeerAddress>) {
+        self.sweep().await;
+        let mut eligible = Vec::with_capacity(candidates.len());
+        for p in candidates {
+            if self.ip_index.contains_key(&p.ip())
+                || !self.config.address_family_policy.allows(&p.ip())
+                || !self.permits(&p.ip())
+                || self.is_banned(&p.ip())
+            {
+                continue;
+            }
+            eligible.push(p);
+        }
+        let (bootstrap, others): (Vec<PeerAddress>, Vec<PeerAddress>) = eligible
+            .into_iter()
+            .partition(|p| self.bootstrap.iter().any(|b| b.address == p.address));
+        let mut connected: Vec<PeerAddress> = self
+            .connections
+            .values()
+            .map(|c| c.connection.peer.clone())
+            .collect();
+        for tier in [bootstrap, others] {
+            let wanted =
+                usize::from(self.config.connections_target).saturating_sub(connected.len());
+            if wanted == 0 {
+                break;
+            }
+            let mut scored = Vec::with_capacity(tier.len());
+            for p in tier {
+                let score = self
+                    .stored_history(&p.peer_id)
+                    .await
+                    .and_then(|h| h.quality_score);
+                scored.push((p, score));
+            }
+            let selected = select_peers(
+                &scored,
+                &connected,
+                wanted,
+                SELECTION_EXPLORATION,
+                usize::from(self.config.max_outbound_per_netgroup),
+                &mut rand::thread_rng(),
+            );
+            for p in selected {
+                let address = p.address;
+                match self.connect(p.clone()).await {
+                    Ok(()) => connected.push(p),
+                    Err(reason) => warn!("not connecting to peer {}: {}", address, reason),
+                }
+            }
+        }
+    }
+
+    async fn disconnect(&mut self, p: &PeerAddress) {
+        if let Some(c_id) = self.ip_index.remove(&p.ip()) {
+            if let Some(c) = self.connections.remove(&c_id) {
+                c.connection.close().await;
+                c.handle.await.expect("Connection failed");
+            }
+        }
+    }
+
+    fn find_connection(&self, peer_id: &Uuid) -> Option<&Connection> {
+        self.connections
+            .values()
+            .map(|c| &c.connection)
+            .find(|c| c.peer.peer_id == *peer_id)
+    }
+
+    /// Returns true if the operating mode permits a connection to the IP address.
+    fn permits(&self, ip: &IpAddr) -> bool {
+        match self.mode {
+            OperatingMode::Normal => true,
+            OperatingMode::FixedPeerList => self.fixed_ips.contains(ip),
+        }
+    }
+
+    /// Switch to a new operating mode, closing the connections that it does not permit.
+    async fn set_mode(&mut self, mode: OperatingMode, fixed_peers: Option<Vec<PeerAddress>>) {
+        let from = self.mode;
+        self.mode = mode;
+        let candidates = match fixed_peers {
+            Some(peers) => peers,
+            None => self.configured_peers(),
+        };
+        self.fixed_ips = match mode {
+            OperatingMode::Normal => HashSet::new(),
+            OperatingMode::FixedPeerList => candidates.iter().map(|p| p.ip()).collect(),
+        };
+        let excluded: Vec<PeerAddress> = self
+            .connections
+            .values()
+            .map(|c| &c.connection.peer)
+            .filter(|p| !self.permits(&p.ip()))
+            .cloned()
+            .collect();
+        for p in excluded.iter() {
+            self.disconnect(p).await;
+        }
+        if self.state == Running {
+            self.select(candidates).await;
+        }
+        info!(
+            "operating mode changed from {} to {}, {} peers disconnected",
+            from,
+            mode,
+            excluded.len()
+        );
+        if let Some(events) = &self.config.control_events {
+            // there may be no receivers
+            let _ = events.send(ControlEvent::OperatingModeChanged {
+                from,
+                to: mode,
+                disconnected: excluded,
+            });
+        }
+    }
+
+    /// Whether the IP address is banned. An expired ban is not removed here, but by
+    /// [sweep()](Self::sweep).
+    fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans
+            .get(ip)
+            .is_some_and(|ban| SystemTime::now() < ban.until)
+    }
+
+    /// Read the history of a peer from the store, None if there is no store or the read fails.
+    async fn stored_history(&self, peer_id: &Uuid) -> Option<PeerHistory> {
+        let store = self.config.peer_store.as_ref()?;
+        match store.get(peer_id).await {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("could not read peer {} from store: {}", peer_id, e);
+                None
+            }
+        }
+    }
+
+    /// Ban a peer, returning false if it is neither connected nor in the store.
+    ///
+    /// The ban is added before the connection is closed and the store is updated, all within a single
+    /// message of the actor, so a connection to the same address that is requested concurrently is
+    /// either closed here or refused by [connect()](Self::connect).
+    async fn ban(&mut self, peer_id: Uuid, duration: Duration, reason: String) -> bool {
+        let until = SystemTime::now() + duration;
+        let connected = self
+            .connections
+            .values()
+            .map(|c| &c.connection.peer)
+            .find(|p| p.peer_id == peer_id)
+            .cloned();
+        let stored = self.stored_history(&peer_id).await;
+        let address = match (&connected, &stored) {
+            (Some(p), _) => p.address,
+            (None, Some(h)) if h.address.is_some() => h.address.unwrap(),
+            _ => return false,
+        };
+        let ban = Ban {
+            peer_id,
+            address,
+            until,
+        };
+        self.bans.insert(address.ip(), ban);
+        if let Some(p) = connected {
+            self.disconnect(&p).await;
+        }
+        let mut history = stored.unwrap_or_default();
+        let score_before = history.quality_score;
+        history.ban(address, until);
+        self.config.ban_audit.record(&BanAuditEntry {
+            time: SystemTime::now(),
+            peer_id,
+            addr: address,
+            action: BanAction::Ban,
+            reason,
+            score_before,
+            score_after: history.quality_score,
+            expires: Some(until),
+        });
+        info!("banned peer {} {}", peer_id, history.summary());
+        if let Some(store) = &self.config.peer_store {
+            // the channel may update the history as it closes, so the ban is applied to the stored
+            // history rather than written over it
+            let update = Box::new(move |h: &mut PeerHistory| h.ban(address, until));
+            if let Err(e) = store.update(&peer_id, update).await {
+                warn!("could not store ban of peer {}: {}", peer_id, e);
+            }
+        }
+        self.flush_store().await;
+        true
+    }
+
+    /// Lift the ban of a peer, returning false if it is not banned.
+    async fn unban(&mut self, peer_id: Uuid, reason: String) -> bool {
+        let now = SystemTime::now();
+        let stored = self.stored_history(&peer_id).await;
+        let held = self
+            .bans
+            .iter()
+            .find(|(_, b)| b.peer_id == peer_id && now < b.until)
+            .map(|(ip, b)| (*ip, b.address));
+        let address = match (held, &stored) {
+            (Some((ip, address)), _) => {
+                self.bans.remove(&ip);
+                address
+            }
+            (None, Some(h)) if h.is_banned(now) && h.address.is_some() => h.address.unwrap(),
+            _ => return false,
+        };
+        let score = stored.as_ref().and_then(|h| h.quality_score);
+        self.config.ban_audit.record(&BanAuditEntry {
+            time: now,
+            peer_id,
+            addr: address,
+            action: BanAction::Unban,
+            reason,
+            score_before: score,
+            score_after: score,
+            expires: None,
+        });
+        // a peer that is not in the store is not added to it
+        if let (Some(store), true) = (&self.config.peer_store, stored.is_some()) {
+            let update = Box::new(|h: &mut PeerHistory| h.banned_until = None);
+            if let Err(e) = store.update(&peer_id, update).await {
+                warn!("could not store unban of peer {}: {}", peer_id, e);
             }
         }
+        self.flush_store().await;
+        info!("unbanned peer {} at {}", peer_id, address);
+        true
     }
 
-    // start task to query the dns servers and find peers
-    fn start_dns_query(&self) {} // todo
+    /// Write the queued updates of the peer histories to the store, rather than waiting for the
+    /// next flush. Used for decisions of the operator, which should not be lost.
+    async fn flush_store(&self) {
+        if let Some(writer) = &self.store_writer {
+            if let Err(e) = writer.flush().await {
+                warn!("could not write peers to store: {}", e);
+            }
+        }
+    }
+
+    /// Remove the connections whose tasks have ended without being closed by the manager, and the
+    /// bans that have expired.
+    ///
+    /// A connection task that panics never returns its slot through the normal path, so the slot is
+    /// released here, and the connection is removed from the maps, keeping them consistent with the
+    /// connections that are actually running. An expired ban is recorded in the audit trail at the
+    /// time it expired.
+    async fn sweep(&mut self) {
+        let now = SystemTime::now();
+        let expired: Vec<IpAddr> = self
+            .bans
+            .iter()
+            .filter(|(_, ban)| ban.until <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in expired {
+            let ban = self.bans.remove(&ip).unwrap();
+            self.config.ban_audit.record(&BanAuditEntry {
+                time: ban.until,
+                peer_id: ban.peer_id,
+                addr: ban.address,
+                action: BanAction::Expired,
+                reason: "ban expired".to_string(),
+                score_before: None,
+                score_after: None,
+                expires: None,
+            });
+        }
+        let ended: Vec<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, c)| c.handle.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ended {
+            let c = self.connections.remove(&id).unwrap();
+            c.slot.release();
+            let reason = match c.handle.await {
+                Err(e) if e.is_panic() => ConnectionLostReason::Crashed,
+                _ => ConnectionLostReason::Closed,
+            };
+            let peer = c.connection.peer;
+            warn!("connection to peer {} {}", peer, reason);
+            if let Some(events) = &self.config.control_events {
+                let _ = events.send(ControlEvent::ConnectionLost { peer, reason });
+            }
+        }
+        let connections = &self.connections;
+        self.ip_index.retain(|_, id| connections.contains_key(id));
+    }
+
+    /// Start a task that resolves the DNS seeds and sends the addresses found to the actor.
+    fn start_dns_query(&self, self_ref: ActorRef<Self>) {
+        let params = NetworkParams::from(self.config.blockchain);
+        tokio::spawn(async move {
+            let mut found = Vec::new();
+            for seed in params.dns_seeds {
+                match tokio::net::lookup_host((*seed, params.port)).await {
+                    Ok(addresses) => {
+                        let before = found.len();
+                        for address in addresses {
+                            if !found.contains(&address) {
+                                found.push(address);
+                            }
+                        }
+                        info!(
+                            "dns seed {} returned {} new nodes",
+                            seed,
+                            found.len() - before
+                        );
+                    }
+                    Err(e) => warn!("could not resolve dns seed {}: {}", seed, e),
+                }
+            }
+            found.shuffle(&mut rand::thread_rng());
+            let _ = self_ref.send(P2PMgrSendMessage::Discovered(found)).await;
+        });
+    }
+
+    /// Connect to discovered nodes, using the ids of the peers in the store if there is one.
+    async fn add_discovered(&mut self, addresses: Vec<SocketAddr>) {
+        if self.state != Running || !self.config.add_peers {
+            return;
+        }
+        let mut candidates = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let peer = match &self.config.peer_store {
+                Some(store) => match store.find_or_create(address).await {
+                    Ok(peer_id) => PeerAddress { peer_id, address },
+                    Err(e) => {
+                        warn!("could not add peer {} to store: {}", address, e);
+                        PeerAddress::new(address)
+                    }
+                },
+                None => PeerAddress::new(address),
+            };
+            candidates.push(peer);
+        }
+        self.select(candidates).await;
+    }
 }
 
 /// The P2PManagerActor is an Actor from minactor.
@@ -250,17 +1242,34 @@ impl Actor for P2PManagerActor {
     type CallMessage = P2PMgrCallMessage;
     type ErrorType = InternalError;
 
-    async fn on_initialization(&mut self, _self_ref: ActorRef<Self>) -> Control {
-        // todo: if config.add_peers then start process to find dns peers
+    async fn on_initialization(&mut self, self_ref: ActorRef<Self>) -> Control {
+        // stores written before addresses were unique may hold several records of a peer
+        if let Some(store) = &self.config.peer_store {
+            if let Err(e) = store.dedup().await {
+                warn!("could not merge duplicate peers in store: {}", e);
+            }
+        }
+        self.store_bootstrap_peers().await;
         if self.config.start_paused {
             self.state = Paused;
         } else {
             self.state = Running;
-            let initial_peers = self.config.initial_peers.clone();
-            for p in initial_peers {
-                self.connect(p).await;
-            }
+            let peers = self.configured_peers();
+            self.select(peers).await;
         }
+        if self.config.add_peers && self.config.query_dns_seeds {
+            self.start_dns_query(self_ref.clone());
+        }
+        self.sweep_handle = Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if self_ref.send(P2PMgrSendMessage::Sweep).await.is_err() {
+                    break;
+                }
+            }
+        }));
         Control::Ok
     }
 
@@ -272,6 +1281,53 @@ impl Actor for P2PManagerActor {
             P2PMgrSendMessage::Resume => {
                 self.state = Running;
             }
+            P2PMgrSendMessage::BroadcastTx(hash, fee_rate) => {
+                for c in self.connections.values().map(|c| &c.connection) {
+                    if let Err(e) = c.announce_tx(hash, fee_rate).await {
+                        warn!(
+                            "failed to announce tx to peer: {}, error: {}",
+                            c.peer.peer_id, e
+                        );
+                    }
+                }
+            }
+            P2PMgrSendMessage::AnnounceBlock(hash) => {
+                for c in self.connections.values().map(|c| &c.connection) {
+                    if let Err(e) = c.announce_block(hash).await {
+                        warn!(
+                            "failed to announce block to peer: {}, error: {}",
+                            c.peer.peer_id, e
+                        );
+                    }
+                }
+            }
+            P2PMgrSendMessage::Sweep => self.sweep().await,
+            P2PMgrSendMessage::Discovered(addresses) => self.add_discovered(addresses).await,
+            P2PMgrSendMessage::RequestHeaders(peer_id, locator) => {
+                match self.find_connection(&peer_id) {
+                    Some(c) => {
+                        if let Err(e) = c.request_headers(locator).await {
+                            warn!(
+                                "failed to request headers from peer: {}, error: {}",
+                                peer_id, e
+                            );
+                        }
+                    }
+                    None => warn!("not requesting headers, no connection to peer: {}", peer_id),
+                }
+            }
+            P2PMgrSendMessage::SendBlock(peer_id, block) => match self.find_connection(&peer_id) {
+                Some(c) => {
+                    if let Err(e) = c.send_block(block).await {
+                        warn!("failed to send block to peer: {}, error: {}", peer_id, e);
+                    }
+                }
+                None => warn!(
+                    "not sending block {}, no connection to peer: {}",
+                    block.hash(),
+                    peer_id
+                ),
+            },
         }
         Control::Ok
     }
@@ -285,6 +1341,35 @@ impl Actor for P2PManagerActor {
     ) {
         match msg {
             P2PMgrCallMessage::GetState => (Control::Ok, Ok(ReplyState(self.state.clone()))),
+            P2PMgrCallMessage::BanPeer(peer_id, duration, reason) => {
+                let banned = self.ban(peer_id, duration, reason).await;
+                (Control::Ok, Ok(P2PMgrCallMessage::ReplyBanned(banned)))
+            }
+            P2PMgrCallMessage::UnbanPeer(peer_id, reason) => {
+                let unbanned = self.unban(peer_id, reason).await;
+                (Control::Ok, Ok(P2PMgrCallMessage::ReplyUnbanned(unbanned)))
+            }
+            P2PMgrCallMessage::AddPeer(p) => {
+                let refused = if self.state == Running {
+                    self.connect(p).await.err()
+                } else {
+                    Some(ConnectRefused::Paused)
+                };
+                (Control::Ok, Ok(P2PMgrCallMessage::ReplyAddPeer(refused)))
+            }
+            P2PMgrCallMessage::SetOperatingMode(mode, fixed_peers) => {
+                self.set_mode(mode, fixed_peers).await;
+                (Control::Ok, Ok(P2PMgrCallMessage::ReplyOperatingMode))
+            }
+            P2PMgrCallMessage::GetPeers => {
+                self.sweep().await;
+                let peers = self
+                    .connections
+                    .values()
+                    .map(|c| c.connection.peer.clone())
+                    .collect();
+                (Control::Ok, Ok(P2PMgrCallMessage::ReplyPeers(peers)))
+            }
             _ => {
                 panic!("should never get here");
             }
@@ -292,10 +1377,23 @@ impl Actor for P2PManagerActor {
     }
 
     async fn on_shutdown(&mut self) -> Control {
-        for (_, (c, j)) in self.connections.drain() {
-            c.close().await;
+        if let Some(j) = self.sweep_handle.take() {
+            j.abort();
+        }
+        self.sweep().await;
+        for (_, c) in self.connections.drain() {
+            c.connection.close().await;
             // todo: remove expect
-            j.await.expect("Connection failed");
+            c.handle.await.expect("Connection failed");
+        }
+        // the connections have stored their sessions, write them with the other queued updates
+        if let Some(writer) = self.store_writer.take() {
+            if let Err(e) = writer.shutdown().await {
+                warn!("could not write peers to store: {}", e);
+            }
+        }
+        if let Some(j) = self.store_writer_handle.take() {
+            let _ = j.await;
         }
         self.state = P2PManagerState::Stopped;
         Control::Ok
@@ -306,14 +1404,833 @@ impl Actor for P2PManagerActor {
 mod tests {
     use super::*;
     use crate::bitcoin::BlockchainId::Main;
+    use crate::p2p::{ChecksumPolicy, MinThroughput, NetGroup};
 
     #[tokio::test]
     async fn start_stop_test() {
-        let (h, j) = P2PManager::new(P2PManagerConfig::default(Main)).await;
+        let (h, j) = P2PManager::new(P2PManagerConfig::default(Main))
+            .await
+            .unwrap();
         let s = h.get_state().await;
         assert!(s.is_ok());
         assert_eq!(s.unwrap(), Running);
         let _ = h.stop().await;
         j.await.expect("P2PManager failed");
     }
+
+    #[tokio::test]
+    async fn connect_to_initial_peer() {
+        use crate::p2p::fake_peer::{FakePeer, FakePeerStep};
+        use crate::p2p::messages::P2PMessage;
+
+        let steps = vec![FakePeerStep::expect(|m| {
+            matches!(m, P2PMessage::SendHeaders)
+        })];
+        let peer = FakePeer::start(Main, steps).await;
+        let config = P2PManagerConfig::builder()
+            .initial_peers(vec![peer.peer_address()])
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        let received = peer.finish().await.unwrap();
+        assert!(matches!(received[0], P2PMessage::Version(_)));
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    #[test]
+    fn validate_config() {
+        let base = P2PManagerConfig::default(Main);
+        let cases: Vec<(P2PManagerConfig, Option<ConfigError>)> = vec![
+            (base.clone(), None),
+            (
+                P2PManagerConfig {
+                    connections_max: Some(8),
+                    listen_port: Some(18333),
+                    ..base.clone()
+                },
+                None,
+            ),
+            (
+                P2PManagerConfig {
+                    listen: false,
+                    connections_target: 0,
+                    connections_max: Some(0),
+                    listen_port: Some(0),
+                    ..base.clone()
+                },
+                None,
+            ),
+            (
+                P2PManagerConfig {
+                    connections_target: 0,
+                    connections_max: Some(0),
+                    ..base.clone()
+                },
+                Some(ConfigError::ListenWithoutConnections),
+            ),
+            (
+                P2PManagerConfig {
+                    listen_port: Some(0),
+                    ..base.clone()
+                },
+                Some(ConfigError::ZeroListenPort),
+            ),
+            (
+                P2PManagerConfig {
+                    connections_target: 9,
+                    connections_max: Some(8),
+                    ..base.clone()
+                },
+                Some(ConfigError::TargetExceedsMax { target: 9, max: 8 }),
+            ),
+            (
+                P2PManagerConfig {
+                    max_outbound_per_netgroup: 0,
+                    ..base.clone()
+                },
+                Some(ConfigError::ZeroNetGroupLimit),
+            ),
+            (
+                P2PManagerConfig {
+                    operating_mode: OperatingMode::FixedPeerList,
+                    ..base.clone()
+                },
+                Some(ConfigError::EmptyFixedPeerList),
+            ),
+            (
+                P2PManagerConfig {
+                    operating_mode: OperatingMode::FixedPeerList,
+                    bootstrap_peers: vec!["192.0.2.1:8333".parse().unwrap()],
+                    ..base.clone()
+                },
+                None,
+            ),
+        ];
+        for (i, (config, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(config.validate().err(), expected, "case {}", i);
+        }
+
+        let base = ConnectionConfig::default_for(Main);
+        let cases: Vec<(ConnectionConfig, Option<ConfigError>)> = vec![
+            (base.clone(), None),
+            (
+                ConnectionConfig {
+                    retries: 0,
+                    retry_delay: 0,
+                    max_recv_payload_size: u32::MAX as u64,
+                    ..base.clone()
+                },
+                None,
+            ),
+            (
+                ConnectionConfig {
+                    retry_delay: 0,
+                    ..base.clone()
+                },
+                Some(ConfigError::ZeroRetryDelay { retries: 5 }),
+            ),
+            (
+                ConnectionConfig {
+                    max_recv_payload_size: u32::MAX as u64 + 1,
+                    ..base.clone()
+                },
+                Some(ConfigError::RecvPayloadSizeOutOfRange {
+                    size: u32::MAX as u64 + 1,
+                    min: 1_048_576,
+                    max: u32::MAX as u64,
+                }),
+            ),
+            (
+                ConnectionConfig {
+                    max_recv_payload_size: 1000,
+                    ..base.clone()
+                },
+                Some(ConfigError::RecvPayloadSizeOutOfRange {
+                    size: 1000,
+                    min: 1_048_576,
+                    max: u32::MAX as u64,
+                }),
+            ),
+            (
+                ConnectionConfig {
+                    excessive_block_size: 0,
+                    ..base.clone()
+                },
+                Some(ConfigError::ZeroExcessiveBlockSize),
+            ),
+            (
+                ConnectionConfig {
+                    inv_batch_size: 0,
+                    ..base.clone()
+                },
+                Some(ConfigError::ZeroInvBatchSize),
+            ),
+        ];
+        for (i, (config, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(config.validate().err(), expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn load_config() {
+        let text = r#"
+            blockchain = "test"
+            listen = false
+            connections_target = 4
+            connections_max = 6
+            initial_peers = [
+                { address = "203.0.113.7:18333", peer_id = "67e55044-10b1-426f-9247-bb680e5fe0c8" },
+            ]
+            operating_mode = "fixed_peer_list"
+            max_outbound_per_netgroup = 1
+            external_address = "198.51.100.1:18333"
+            max_time_offset = "none"
+            inv_batch_size = 500
+            inv_trickle_interval = "1m30s"
+            required_services = "NETWORK | BLOOM"
+            address_family_policy = "v4_only"
+        "#;
+        let loaded: P2PManagerConfig = toml::from_str(text).unwrap();
+        let peer = PeerAddress {
+            peer_id: Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap(),
+            address: "203.0.113.7:18333".parse().unwrap(),
+        };
+        let built = P2PManagerConfig::builder()
+            .blockchain(BlockchainId::Test)
+            .listen(false)
+            .connections_target(4)
+            .connections_max(Some(6))
+            .initial_peers(vec![peer])
+            .operating_mode(OperatingMode::FixedPeerList)
+            .max_outbound_per_netgroup(1)
+            .external_address(Some("198.51.100.1:18333".parse().unwrap()))
+            .max_time_offset(None)
+            .inv_batch_size(500)
+            .inv_trickle_interval(Duration::from_secs(90))
+            .required_services(Services::NETWORK | Services::BLOOM)
+            .address_family_policy(AddressFamilyPolicy::V4Only)
+            .build()
+            .unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", built));
+        assert!(loaded.validate().is_ok());
+
+        // missing fields take their defaults
+        let loaded: P2PManagerConfig = toml::from_str("").unwrap();
+        let built = P2PManagerConfig::builder().build().unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", built));
+
+        let text = r#"
+            retries = 3
+            retry_delay = 2
+            checksum_policy = { skip_above = 1000000 }
+            max_time_offset = 600
+            min_throughput = { min_bytes = 100, interval = "5s" }
+        "#;
+        let loaded: ConnectionConfig = toml::from_str(text).unwrap();
+        let built = ConnectionConfig::builder()
+            .retries(3)
+            .retry_delay(2)
+            .checksum_policy(ChecksumPolicy::SkipAbove(1_000_000))
+            .max_time_offset(Some(Duration::from_secs(600)))
+            .min_throughput(Some(MinThroughput {
+                min_bytes: 100,
+                interval: Duration::from_secs(5),
+                ..Default::default()
+            }))
+            .build()
+            .unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", built));
+
+        // unknown fields are an error, as are invalid values
+        assert!(toml::from_str::<P2PManagerConfig>("connection_target = 4").is_err());
+        assert!(toml::from_str::<P2PManagerConfig>("inv_trickle_interval = \"2x\"").is_err());
+        // the builder validates
+        assert_eq!(
+            P2PManagerConfig::builder()
+                .connections_target(9)
+                .connections_max(Some(8))
+                .build()
+                .err(),
+            Some(ConfigError::TargetExceedsMax { target: 9, max: 8 })
+        );
+    }
+
+    /// Refuses every connection, the connections remain registered with the manager.
+    #[derive(Debug)]
+    struct RefusingConnector;
+
+    #[async_trait::async_trait]
+    impl Connector for RefusingConnector {
+        async fn connect(&self, _address: SocketAddr) -> Result<Box<dyn crate::p2p::PeerStream>> {
+            Err(Error::Internal("refused".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn netgroup_diversity() {
+        let initial_peers: Vec<PeerAddress> = (1..=5)
+            .flat_map(|i| {
+                [
+                    PeerAddress::new(format!("10.1.0.{}:8333", i).parse().unwrap()),
+                    PeerAddress::new(format!("10.2.{}.1:8333", i).parse().unwrap()),
+                ]
+            })
+            .collect();
+        for limit in [1, 2, 3] {
+            let config = P2PManagerConfig::builder()
+                .connections_target(6)
+                .max_outbound_per_netgroup(limit)
+                .initial_peers(initial_peers.clone())
+                .connector(Arc::new(RefusingConnector))
+                .build()
+                .unwrap();
+            let (h, j) = P2PManager::new(config).await.unwrap();
+            let peers = h.connected_peers().await.unwrap();
+            assert_eq!(peers.len(), 2 * limit as usize);
+            let mut groups: HashMap<NetGroup, u16> = HashMap::new();
+            for p in peers.iter() {
+                *groups.entry(p.netgroup()).or_default() += 1;
+            }
+            assert_eq!(groups.len(), 2);
+            assert!(groups.values().all(|c| *c == limit));
+            // a manually added peer is not limited
+            let manual = PeerAddress::new("10.1.9.9:8333".parse().unwrap());
+            h.add_peer(manual).await.unwrap();
+            h.stop().await.unwrap();
+            j.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn address_family_policy() {
+        let initial_peers: Vec<PeerAddress> = [
+            "10.1.0.1:8333",
+            "[2001:db8:1::1]:8333",
+            "[::ffff:10.2.0.1]:8333",
+            "[2001:db8:2::1]:8333",
+        ]
+        .iter()
+        .map(|s| PeerAddress::new(s.parse().unwrap()))
+        .collect();
+        for policy in [
+            AddressFamilyPolicy::V4Only,
+            AddressFamilyPolicy::V6Only,
+            AddressFamilyPolicy::Both,
+        ] {
+            let config = P2PManagerConfig::builder()
+                .initial_peers(initial_peers.clone())
+                .address_family_policy(policy)
+                .connector(Arc::new(RefusingConnector))
+                .build()
+                .unwrap();
+            let (h, j) = P2PManager::new(config).await.unwrap();
+            let peers = h.connected_peers().await.unwrap();
+            let expected = match policy {
+                AddressFamilyPolicy::V4Only => 2,
+                AddressFamilyPolicy::V6Only => 2,
+                AddressFamilyPolicy::Both => 4,
+            };
+            assert_eq!(peers.len(), expected, "{:?}", policy);
+            assert!(peers.iter().all(|p| policy.allows(&p.ip())));
+            // a manually added peer of the wrong family is refused
+            let other = if policy == AddressFamilyPolicy::V4Only {
+                "[2001:db8:3::1]:8333"
+            } else {
+                "10.3.0.1:8333"
+            };
+            let other = PeerAddress::new(other.parse().unwrap());
+            let r = h.add_peer(other.clone()).await;
+            assert_eq!(r.is_ok(), policy.allows(&other.ip()), "{:?}", policy);
+            h.stop().await.unwrap();
+            j.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn operating_mode_transitions() {
+        let peers: Vec<PeerAddress> = (1..=4)
+            .map(|i| PeerAddress::new(format!("10.{}.0.1:8333", i).parse().unwrap()))
+            .collect();
+        let manual = PeerAddress::new("10.9.0.1:8333".parse().unwrap());
+        let extra = PeerAddress::new("10.5.0.1:8333".parse().unwrap());
+        let (events_tx, mut events) = tokio::sync::broadcast::channel(8);
+        let config = P2PManagerConfig::builder()
+            .initial_peers(peers.clone())
+            .control_events(Some(events_tx))
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        h.add_peer(manual.clone()).await.unwrap();
+        let ips = |peers: Vec<PeerAddress>| {
+            let mut ips: Vec<IpAddr> = peers.iter().map(|p| p.ip()).collect();
+            ips.sort();
+            ips
+        };
+        assert_eq!(h.connected_peers().await.unwrap().len(), 5);
+
+        // invalid combinations are refused without changing anything
+        assert!(h
+            .set_operating_mode(OperatingMode::FixedPeerList, None)
+            .await
+            .is_err());
+        assert!(h
+            .set_operating_mode(OperatingMode::FixedPeerList, Some(vec![]))
+            .await
+            .is_err());
+        assert!(h
+            .set_operating_mode(OperatingMode::Normal, Some(peers.clone()))
+            .await
+            .is_err());
+        assert!(events.try_recv().is_err());
+
+        // normal to fixed keeps the listed peers, matched by IP address, and connects to the others
+        let fixed = vec![
+            PeerAddress::new(peers[0].address),
+            peers[1].clone(),
+            extra.clone(),
+        ];
+        h.set_operating_mode(OperatingMode::FixedPeerList, Some(fixed))
+            .await
+            .unwrap();
+        assert_eq!(
+            ips(h.connected_peers().await.unwrap()),
+            ips(vec![peers[0].clone(), peers[1].clone(), extra.clone()])
+        );
+        match events.try_recv().unwrap() {
+            ControlEvent::OperatingModeChanged {
+                from,
+                to,
+                disconnected,
+            } => {
+                assert_eq!(from, OperatingMode::Normal);
+                assert_eq!(to, OperatingMode::FixedPeerList);
+                assert_eq!(
+                    ips(disconnected),
+                    ips(vec![peers[2].clone(), peers[3].clone(), manual.clone()])
+                );
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert!(h.add_peer(manual.clone()).await.is_err());
+
+        // fixed to normal keeps every connection and reconnects to the initial peers
+        h.set_operating_mode(OperatingMode::Normal, None)
+            .await
+            .unwrap();
+        let mut expected = peers.clone();
+        expected.push(extra);
+        assert_eq!(ips(h.connected_peers().await.unwrap()), ips(expected));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ControlEvent::OperatingModeChanged {
+                from: OperatingMode::FixedPeerList,
+                to: OperatingMode::Normal,
+                disconnected: vec![],
+            }
+        );
+        h.add_peer(manual).await.unwrap();
+        h.stop().await.unwrap();
+        j.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn start_with_fixed_peers() {
+        let fixed = PeerAddress::new("10.1.0.1:8333".parse().unwrap());
+        let config = P2PManagerConfig::builder()
+            .initial_peers(vec![fixed.clone()])
+            .operating_mode(OperatingMode::FixedPeerList)
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        assert_eq!(h.connected_peers().await.unwrap(), vec![fixed]);
+        let other = PeerAddress::new("10.2.0.1:8333".parse().unwrap());
+        assert!(matches!(
+            h.add_peer(other).await,
+            Err(Error::BadArgument(_))
+        ));
+        h.stop().await.unwrap();
+        j.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ban_peer_not_in_store() {
+        use crate::p2p::fake_peer::{FakePeer, FakePeerStep};
+        use crate::p2p::MemoryPeerStore;
+
+        let peer = FakePeer::start(Main, vec![FakePeerStep::Silent(Duration::from_secs(5))]).await;
+        let address = peer.peer_address();
+        let store = Arc::new(MemoryPeerStore::default());
+        let config = P2PManagerConfig::builder()
+            .initial_peers(vec![address.clone()])
+            .peer_store(Some(store.clone()))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        assert_eq!(h.connected_peers().await.unwrap(), vec![address.clone()]);
+        h.ban_peer(address.peer_id, Duration::from_secs(3600), "test")
+            .await
+            .unwrap();
+        assert!(h.connected_peers().await.unwrap().is_empty());
+        // the record was created from the address of the connection
+        let history = store.get(&address.peer_id).await.unwrap().unwrap();
+        assert_eq!(history.address, Some(address.address));
+        assert!(history.is_banned(SystemTime::now()));
+        // a peer that is neither connected nor stored can not be banned
+        assert!(matches!(
+            h.ban_peer(Uuid::new_v4(), Duration::from_secs(3600), "test")
+                .await,
+            Err(Error::BadArgument(_))
+        ));
+        // the banned address is refused, even with a different peer id
+        assert!(h.add_peer(PeerAddress::new(address.address)).await.is_err());
+        assert!(h.connected_peers().await.unwrap().is_empty());
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    #[tokio::test]
+    async fn dedup_store_on_start() {
+        use crate::p2p::{MemoryPeerStore, PeerStore};
+
+        let store = Arc::new(MemoryPeerStore::default());
+        let address: SocketAddr = "192.0.2.1:8333".parse().unwrap();
+        let history = PeerHistory {
+            address: Some(address),
+            ..PeerHistory::default()
+        };
+        store
+            .put_batch(vec![
+                (Uuid::new_v4(), history.clone()),
+                (Uuid::new_v4(), history),
+            ])
+            .await
+            .unwrap();
+        let config = P2PManagerConfig::builder()
+            .peer_store(Some(store.clone()))
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        // calls are handled once the actor has initialized
+        assert_eq!(h.get_state().await.unwrap(), Paused);
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    #[tokio::test]
+    async fn bootstrap_peers() {
+        use crate::p2p::{MemoryPeerStore, PeerStore};
+
+        let bootstrap: Vec<SocketAddr> = ["10.1.0.1:8333", "10.2.0.1:8333"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let initial = PeerAddress::new("10.3.0.1:8333".parse().unwrap());
+        let store = Arc::new(MemoryPeerStore::default());
+        let config = P2PManagerConfig::builder()
+            .connections_target(2)
+            .bootstrap_peers(bootstrap.clone())
+            .initial_peers(vec![initial])
+            .peer_store(Some(store.clone()))
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        let peers = h.connected_peers().await.unwrap();
+        // the marks are written to the store when the manager stops
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+        let stored = store.list().await.unwrap();
+        assert_eq!(stored.len(), 2);
+        for (_, history) in stored.iter() {
+            assert!(bootstrap.contains(&history.address.unwrap()));
+            assert!(history.bootstrap);
+        }
+        // the bootstrap peers are selected before the initial peers, with their ids in the store
+        let mut selected: Vec<Uuid> = peers.iter().map(|p| p.peer_id).collect();
+        let mut expected: Vec<Uuid> = stored.iter().map(|(id, _)| *id).collect();
+        selected.sort();
+        expected.sort();
+        assert_eq!(selected, expected);
+
+        // a fixed list can refer to a bootstrap peer without adding it to the store again
+        let config = P2PManagerConfig::builder()
+            .bootstrap_peers(bootstrap.clone())
+            .initial_peers(vec![PeerAddress::new(bootstrap[0])])
+            .operating_mode(OperatingMode::FixedPeerList)
+            .peer_store(Some(store.clone()))
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        assert_eq!(h.connected_peers().await.unwrap().len(), 2);
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    /// Keeps the audit entries in memory.
+    #[derive(Debug, Default)]
+    struct MemoryAuditSink(std::sync::Mutex<Vec<BanAuditEntry>>);
+
+    impl AuditSink for MemoryAuditSink {
+        fn record(&self, entry: &BanAuditEntry) {
+            self.0.lock().unwrap().push(entry.clone());
+        }
+    }
+
+    /// A store whose updates fail.
+    #[derive(Debug, Default)]
+    struct ReadOnlyStore(crate::p2p::MemoryPeerStore);
+
+    #[async_trait::async_trait]
+    impl PeerStore for ReadOnlyStore {
+        async fn get(&self, peer_id: &Uuid) -> Result<Option<crate::p2p::PeerHistory>> {
+            self.0.get(peer_id).await
+        }
+        async fn put_batch(&self, _: Vec<(Uuid, crate::p2p::PeerHistory)>) -> Result<()> {
+            Err(Error::Internal("read only".to_string()))
+        }
+        async fn create(&self, address: SocketAddr) -> Result<Uuid> {
+            self.0.create(address).await
+        }
+        async fn merge(&self, survivor: &Uuid, duplicate: &Uuid) -> Result<()> {
+            self.0.merge(survivor, duplicate).await
+        }
+        async fn list(&self) -> Result<Vec<(Uuid, crate::p2p::PeerHistory)>> {
+            self.0.list().await
+        }
+    }
+
+    #[tokio::test]
+    async fn ban_audit() {
+        let store = Arc::new(ReadOnlyStore::default());
+        let audit = Arc::new(MemoryAuditSink::default());
+        let addresses: Vec<SocketAddr> = ["192.0.2.1:8333", "192.0.2.2:8333"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut ids = Vec::new();
+        for address in addresses.iter() {
+            ids.push(store.create(*address).await.unwrap());
+        }
+        let config = P2PManagerConfig::builder()
+            .peer_store(Some(store.clone()))
+            .ban_audit(audit.clone())
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        // the bans are recorded although the store can not be updated
+        h.ban_peer(ids[0], Duration::from_secs(3600), "spam")
+            .await
+            .unwrap();
+        h.ban_peer(ids[1], Duration::from_millis(100), "invalid blocks")
+            .await
+            .unwrap();
+        h.unban_peer(ids[0], "false positive").await.unwrap();
+        assert!(matches!(
+            h.unban_peer(ids[0], "false positive").await,
+            Err(Error::BadArgument(_))
+        ));
+        // the expired ban is recorded by the sweep when the manager stops
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+
+        let entries = audit.0.lock().unwrap().clone();
+        let summary: Vec<(BanAction, Uuid, SocketAddr)> = entries
+            .iter()
+            .map(|e| (e.action, e.peer_id, e.addr))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (BanAction::Ban, ids[0], addresses[0]),
+                (BanAction::Ban, ids[1], addresses[1]),
+                (BanAction::Unban, ids[0], addresses[0]),
+                (BanAction::Expired, ids[1], addresses[1]),
+            ]
+        );
+        let expires = entries[0].expires.unwrap().duration_since(entries[0].time);
+        assert!(expires.unwrap() >= Duration::from_secs(3599));
+        let reasons: Vec<&str> = entries.iter().map(|e| e.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            vec!["spam", "invalid blocks", "false positive", "ban expired"]
+        );
+        assert!(entries[2..].iter().all(|e| e.expires.is_none()));
+        // the expiry is recorded at the time the ban expired, not when it was noticed
+        assert_eq!(Some(entries[3].time), entries[1].expires);
+    }
+
+    #[tokio::test]
+    async fn connection_slots() {
+        use crate::p2p::fake_peer::{FakePeer, FakePeerStep};
+
+        let peer = FakePeer::start(Main, vec![FakePeerStep::Silent(Duration::from_secs(5))]).await;
+        let address = peer.peer_address();
+        let config = P2PManagerConfig::builder()
+            .connections_target(1)
+            .connections_max(Some(1))
+            .initial_peers(vec![address.clone()])
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        // the slot moves from pending to established when the handshake completes
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while h.connection_counts().established == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            h.connection_counts(),
+            SlotCounts {
+                pending: 0,
+                established: 1
+            }
+        );
+        // no slot is left for another peer
+        let other = PeerAddress::new("127.0.0.2:8333".parse().unwrap());
+        assert!(matches!(
+            h.add_peer(other).await,
+            Err(Error::NoConnectionSlot)
+        ));
+        assert_eq!(h.connected_peers().await.unwrap(), vec![address.clone()]);
+        // closing the connection releases the slot
+        h.ban_peer(address.peer_id, Duration::from_secs(60), "test")
+            .await
+            .unwrap();
+        assert_eq!(h.connection_counts(), SlotCounts::default());
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    #[tokio::test]
+    async fn network_time_offset() {
+        use crate::p2p::fake_peer::{FakePeer, FakePeerStep};
+        use crate::p2p::Version;
+        use crate::util::epoch_secs;
+
+        let version = Version {
+            timestamp: epoch_secs() + 300,
+            ..Version::default()
+        };
+        let steps = vec![FakePeerStep::Silent(Duration::from_secs(5))];
+        let peer = FakePeer::start_with_version(Main, version, Vec::new(), steps).await;
+        let address = peer.peer_address();
+        let config = P2PManagerConfig::builder()
+            .connections_target(1)
+            .initial_peers(vec![address.clone()])
+            .build()
+            .unwrap();
+        let (h, j) = P2PManager::new(config).await.unwrap();
+        assert_eq!(h.network_time_offset(), None);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while h.connection_counts().established == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let offset = h.network_time_offset().unwrap();
+        assert!((298..=302).contains(&offset), "offset: {}", offset);
+        // the offset is forgotten when the connection closes
+        h.ban_peer(address.peer_id, Duration::from_secs(60), "test")
+            .await
+            .unwrap();
+        assert_eq!(h.network_time_offset(), None);
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+    }
+
+    #[tokio::test]
+    async fn ban_races_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // accept the connections and hold them open
+        let accept = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((s, _)) = listener.accept().await {
+                streams.push(s);
+            }
+        });
+        let (h, j) = P2PManager::new(P2PManagerConfig::default(Main))
+            .await
+            .unwrap();
+        for _ in 0..10 {
+            let peer = PeerAddress::new(SocketAddr::from(([127, 0, 0, 1], port)));
+            h.add_peer(peer.clone()).await.unwrap();
+            let (banned, added) = tokio::join!(
+                h.ban_peer(peer.peer_id, Duration::from_millis(200), "test"),
+                h.add_peer(PeerAddress::new(peer.address))
+            );
+            banned.unwrap();
+            // refused if the ban was handled first
+            assert!(matches!(added, Ok(()) | Err(Error::BadArgument(_))));
+            // whichever was handled first, no connection survives the ban
+            assert!(h.connected_peers().await.unwrap().is_empty());
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+        let _ = h.stop().await;
+        j.await.expect("P2PManager failed");
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn new_rejects_invalid_config() {
+        let config = P2PManagerConfig {
+            connections_target: 10,
+            connections_max: Some(4),
+            ..P2PManagerConfig::default(Main)
+        };
+        match P2PManager::new(config).await {
+            Err(crate::Error::ConfigError(e)) => {
+                assert_eq!(e, ConfigError::TargetExceedsMax { target: 10, max: 4 })
+            }
+            _ => panic!("expected a configuration error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_crashed_connection() {
+        let (events_tx, mut events) = tokio::sync::broadcast::channel(8);
+        let config = P2PManagerConfig::builder()
+            .control_events(Some(events_tx))
+            .connector(Arc::new(RefusingConnector))
+            .build()
+            .unwrap();
+        let (data_tx, _data_rx) = tokio::sync::broadcast::channel(ACTOR_CHANNEL_SIZE);
+        let slots = ConnectionSlots::new(8);
+        let mut actor = P2PManagerActor::new(config, data_tx, slots.clone());
+        let peer = PeerAddress::new("10.1.0.1:8333".parse().unwrap());
+        actor.connect(peer.clone()).await.unwrap();
+        assert_eq!(slots.counts().total(), 1);
+        // replace the connection task with one that panics, without releasing the slot
+        let c = actor.connections.values_mut().next().unwrap();
+        c.connection.close().await;
+        let crashed = tokio::spawn(async { panic!("connection crashed") });
+        std::mem::replace(&mut c.handle, crashed).await.unwrap();
+        while !c.handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(slots.counts().total(), 1);
+        actor.sweep().await;
+        assert_eq!(slots.counts(), SlotCounts::default());
+        assert!(actor.connections.is_empty());
+        assert!(actor.ip_index.is_empty());
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ControlEvent::ConnectionLost {
+                peer,
+                reason: ConnectionLostReason::Crashed
+            }
+        );
+    }
 }
@@ -0,0 +1,78 @@
+use crate::bitcoin::{Tx, TxHash};
+use crate::p2p::messages::Inv;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A source of transactions that can be served to peers.
+pub trait TxProvider: Send + Sync {
+    /// Get the hashes of the transactions in the mempool, returning at most `limit` hashes.
+    fn mempool_hashes(&self, limit: usize) -> Vec<TxHash>;
+
+    /// Get a transaction by its hash.
+    fn get_tx(&self, hash: &TxHash) -> Option<Tx>;
+}
+
+/// What to do with a `mempool` request when there is no [TxProvider].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolPolicy {
+    /// Do not respond.
+    Ignore,
+    /// Respond with an empty inv message.
+    EmptyInv,
+}
+
+/// Determines how a peer connection responds to `mempool` requests.
+///
+/// When there is a [TxProvider], a `mempool` request is answered with an inv message listing up to
+/// `max_entries` transactions. Producing the list is expensive, so requests from a peer that arrive within
+/// `min_interval` of the previous response are ignored.
+#[derive(Clone)]
+pub struct MempoolResponder {
+    /// The source of the transactions.
+    pub provider: Option<Arc<dyn TxProvider>>,
+    /// What to do when there is no provider.
+    pub policy: MempoolPolicy,
+    /// The maximum number of transactions to include in the response.
+    pub max_entries: usize,
+    /// The minimum time between responses to a peer.
+    pub min_interval: Duration,
+}
+
+impl MempoolResponder {
+    /// Create a responder that answers requests using the provider.
+    pub fn new(provider: Arc<dyn TxProvider>) -> MempoolResponder {
+        MempoolResponder {
+            provider: Some(provider),
+            ..Default::default()
+        }
+    }
+
+    /// The number of entries to request from the provider.
+    pub(crate) fn limit(&self) -> usize {
+        self.max_entries.min(Inv::MAX_INV_ENTRIES as usize)
+    }
+}
+
+impl Default for MempoolResponder {
+    /// By default there is no provider and requests are ignored.
+    fn default() -> Self {
+        MempoolResponder {
+            provider: None,
+            policy: MempoolPolicy::Ignore,
+            max_entries: Inv::MAX_INV_ENTRIES as usize,
+            min_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl fmt::Debug for MempoolResponder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MempoolResponder")
+            .field("provider", &self.provider.is_some())
+            .field("policy", &self.policy)
+            .field("max_entries", &self.max_entries)
+            .field("min_interval", &self.min_interval)
+            .finish()
+    }
+}
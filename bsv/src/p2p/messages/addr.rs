@@ -67,3 +67,70 @@ impl fmt::Display for Addr {
         write!(f, "Addr(n={}, [{}])", self.addrs.len(), addrs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::messages::Services;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn read_bytes() {
+        // the example addr payload from the protocol documentation
+        let b = hex::decode(
+            "01\
+             e215104d\
+             0100000000000000\
+             00000000000000000000ffff0a000001\
+             208d",
+        )
+        .unwrap();
+        let a = Addr::from_binary_buf(&b).unwrap();
+        assert_eq!(a.addrs.len(), 1);
+        let n = &a.addrs[0];
+        assert_eq!(n.timestamp, 1_292_899_810);
+        assert_eq!(n.services, Services::NETWORK);
+        assert_eq!(n.ip, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(n.port, 8333);
+        assert_eq!(a.async_size(), b.len());
+        assert_eq!(a.to_binary_buf().unwrap(), b);
+    }
+
+    #[test]
+    fn write_read() {
+        let addrs: Vec<NodeAddr> = (0..Addr::MAX_ADDR_COUNT as u32)
+            .map(|i| NodeAddr {
+                timestamp: 1_700_000_000 + i,
+                services: Services(i as u64),
+                ip: Ipv4Addr::from(i).into(),
+                port: i as u16,
+            })
+            .collect();
+        let a = Addr { addrs };
+        let b = a.to_binary_buf().unwrap();
+        // every entry has a timestamp
+        assert_eq!(b.len(), 3 + 1000 * NodeAddr::SIZE);
+        assert_eq!(b.len(), a.async_size());
+        assert_eq!(Addr::from_binary_buf(&b).unwrap(), a);
+        let empty = Addr { addrs: vec![] };
+        assert_eq!(
+            Addr::from_binary_buf(&empty.to_binary_buf().unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn too_many() {
+        let mut a = Addr {
+            addrs: vec![NodeAddr::default(); Addr::MAX_ADDR_COUNT as usize + 1],
+        };
+        assert!(a.to_binary_buf().is_err());
+        a.addrs.pop();
+        let mut b = a.to_binary_buf().unwrap();
+        // change the count to 1001 and add another entry
+        b[1..3].copy_from_slice(&1001u16.to_le_bytes());
+        let entry = b[3..3 + NodeAddr::SIZE].to_vec();
+        b.extend_from_slice(&entry);
+        assert!(Addr::from_binary_buf(&b).is_err());
+    }
+}
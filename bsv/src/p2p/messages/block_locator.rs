@@ -1,5 +1,8 @@
 use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable, Hash};
+use crate::p2p::messages::MAX_PREALLOCATE;
+use crate::p2p::params::MIN_SUPPORTED_PROTOCOL_VERSION;
 use async_trait::async_trait;
+use std::cmp::min;
 use std::fmt;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -16,6 +19,28 @@ pub struct BlockLocator {
 
 impl BlockLocator {
     pub const HASH_STOP: Hash = Hash::ZERO;
+    /// The maximum number of locator hashes accepted from a peer, not including the stop hash.
+    pub const MAX_LOCATOR_HASHES: u64 = 101;
+
+    /// Check that a locator received from a peer can be served.
+    ///
+    /// The number of hashes is limited so that a peer can not make us look up an unbounded number of
+    /// hashes, and the version must be one that we support.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.block_locator_hashes.len() as u64 > BlockLocator::MAX_LOCATOR_HASHES {
+            return Err(crate::Error::BadData(format!(
+                "Too many locator hashes: {}",
+                self.block_locator_hashes.len()
+            )));
+        }
+        if self.version < MIN_SUPPORTED_PROTOCOL_VERSION {
+            return Err(crate::Error::BadData(format!(
+                "Unsupported locator version: {}",
+                self.version
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -25,8 +50,13 @@ impl AsyncEncodable for BlockLocator {
         Self: Sized,
     {
         let version = reader.read_u32_le().await?;
-        let num_hashes = varint_decode(reader).await? as usize;
-        let mut block_locator_hashes = Vec::with_capacity(num_hashes);
+        let num_hashes = varint_decode(reader).await?;
+        if num_hashes > BlockLocator::MAX_LOCATOR_HASHES {
+            let msg = format!("Too many locator hashes: {}", num_hashes);
+            return Err(crate::Error::BadData(msg));
+        }
+        let mut block_locator_hashes =
+            Vec::with_capacity(min(num_hashes, MAX_PREALLOCATE as u64) as usize);
         for _ in 0..num_hashes {
             block_locator_hashes.push(Hash::async_from_binary(reader).await?);
         }
@@ -77,3 +107,42 @@ impl fmt::Display for BlockLocator {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::params::PROTOCOL_VERSION;
+
+    fn locator(n: usize) -> BlockLocator {
+        BlockLocator {
+            version: PROTOCOL_VERSION,
+            block_locator_hashes: (0..n).map(|i| Hash::sha256d(&i.to_le_bytes())).collect(),
+            hash_stop: BlockLocator::HASH_STOP,
+        }
+    }
+
+    #[test]
+    fn locator_length_limit() {
+        let max = locator(BlockLocator::MAX_LOCATOR_HASHES as usize);
+        assert!(max.validate().is_ok());
+        let bin = max.to_binary_buf().unwrap();
+        assert_eq!(BlockLocator::from_binary_buf(&bin).unwrap(), max);
+
+        let too_many = locator(BlockLocator::MAX_LOCATOR_HASHES as usize + 1);
+        assert!(too_many.validate().is_err());
+        let bin = too_many.to_binary_buf().unwrap();
+        assert!(matches!(
+            BlockLocator::from_binary_buf(&bin),
+            Err(crate::Error::BadData(_))
+        ));
+    }
+
+    #[test]
+    fn locator_version() {
+        let old = BlockLocator {
+            version: MIN_SUPPORTED_PROTOCOL_VERSION - 1,
+            ..locator(1)
+        };
+        assert!(old.validate().is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::bitcoin::{varint_size, AsyncEncodable, Block, BlockHeader, Hash, Tx};
+use crate::p2p::channel::ChannelConfig;
+use crate::p2p::messages::messages::commands::BLOCK;
+use crate::p2p::messages::messages::ZERO_CHECKSUM;
+use crate::p2p::messages::msg_header::P2PMessageHeader;
+use crate::{Error, Result};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::Stream;
+
+/// A block message whose transactions are produced by a stream, so that a block can be sent to a
+/// peer without holding the whole block in memory.
+///
+/// The size of the payload goes in the message header, which is sent before any transaction, so the
+/// caller must supply it up front. It decides the framing: a payload larger than 4GB is sent with
+/// the extended message header, which peers accept from protocol version 70016.
+///
+/// The checksum is also in the header. Extended messages carry a zero checksum, as do messages whose
+/// checksum is skipped by the [ChecksumPolicy](crate::p2p::ChecksumPolicy). Otherwise the payload is
+/// encoded into a buffer to calculate the checksum before it is sent, so use a policy such as
+/// [SkipAbove](crate::p2p::ChecksumPolicy::SkipAbove) to stream large blocks.
+///
+/// Clones share the stream of transactions, which can only be written once. Writing a clone of a
+/// block that has already been written is an error.
+#[derive(Clone)]
+pub struct BlockStream {
+    header: BlockHeader,
+    tx_count: u64,
+    payload_size: u64,
+    txs: Arc<Mutex<Option<TxStream>>>,
+}
+
+type TxStream = Pin<Box<dyn Stream<Item = Tx> + Send>>;
+
+impl BlockStream {
+    /// Create a block message from a header and a stream of `tx_count` transactions, which encode
+    /// to a block of `payload_size` bytes.
+    pub fn new<S>(header: BlockHeader, tx_count: u64, payload_size: u64, txs: S) -> BlockStream
+    where
+        S: Stream<Item = Tx> + Send + 'static,
+    {
+        BlockStream {
+            header,
+            tx_count,
+            payload_size,
+            txs: Arc::new(Mutex::new(Some(Box::pin(txs)))),
+        }
+    }
+
+    /// The hash of the block.
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    /// The size of the block message payload, as supplied by the caller.
+    pub fn payload_size(&self) -> u64 {
+        self.payload_size
+    }
+
+    /// Write the block message, header and payload.
+    ///
+    /// An error is returned if the transactions do not match the count or the payload size, which
+    /// may only be detected after some of the message has been written. The stream to the peer is
+    /// then unusable and should be closed.
+    pub async fn write<W: AsyncWrite + Unpin + Send>(
+        self,
+        writer: &mut W,
+        config: &ChannelConfig,
+    ) -> Result<()> {
+        let minimum = (self.header.async_size() + varint_size(self.tx_count)) as u64;
+        if self.payload_size < minimum {
+            return Err(Error::BadArgument(format!(
+                "payload size {} is too small for a block of {} transactions",
+                self.payload_size, self.tx_count
+            )));
+        }
+        let extended = self.payload_size > 0xffffffff;
+        if extended && config.protocol_version < 70016 {
+            return Err(Error::BadData("payload too large".to_string()));
+        }
+        let txs = self.txs.lock().unwrap().take().ok_or_else(|| {
+            Error::BadArgument(format!("block {} has already been written", self.hash()))
+        })?;
+        let header = P2PMessageHeader {
+            magic: config.magic,
+            command: BLOCK,
+            payload_size: self.payload_size,
+            checksum: ZERO_CHECKSUM,
+        };
+        let written = if extended || !config.checksum_policy.on_send(self.payload_size) {
+            header.async_to_binary(writer).await?;
+            Block::write_streaming(&self.header, self.tx_count, txs, writer).await?
+        } else {
+            let mut buf = Vec::with_capacity(self.payload_size as usize);
+            let written =
+                Block::write_streaming(&self.header, self.tx_count, txs, &mut buf).await?;
+            if written == self.payload_size {
+                let header = P2PMessageHeader {
+                    checksum: Hash::sha256d(&buf).hash[..4].try_into().unwrap(),
+                    ..header
+                };
+                header.async_to_binary(writer).await?;
+                writer.write_all(&buf).await?;
+            }
+            written
+        };
+        if written != self.payload_size {
+            return Err(Error::BadArgument(format!(
+                "block payload is {} bytes, expected {}",
+                written, self.payload_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BlockStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockStream")
+            .field("header", &self.header)
+            .field("tx_count", &self.tx_count)
+            .field("payload_size", &self.payload_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Blocks are equal if they are clones of each other, streams cannot be compared.
+impl PartialEq for BlockStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.txs, &other.txs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{Outpoint, Script, TxInput, TxOutput};
+    use crate::p2p::messages::P2PMessage;
+    use crate::p2p::ChecksumPolicy;
+    use std::io::Cursor;
+
+    fn block() -> Block {
+        let transactions: Vec<Tx> = (0..20u32)
+            .map(|i| Tx {
+                version: 1,
+                inputs: vec![TxInput {
+                    outpoint: Outpoint {
+                        tx_hash: Hash::sha256d(&i.to_le_bytes()),
+                        index: i,
+                    },
+                    script: Script::from(vec![1; 10]),
+                    sequence: u32::MAX,
+                }],
+                outputs: vec![TxOutput::new(1000, Script::from(vec![2; 25]))],
+                lock_time: 0,
+            })
+            .collect();
+        Block {
+            header: BlockHeader::default(),
+            transactions,
+        }
+    }
+
+    fn stream_of(block: &Block, payload_size: u64) -> BlockStream {
+        BlockStream::new(
+            block.header.clone(),
+            block.transactions.len() as u64,
+            payload_size,
+            tokio_stream::iter(block.transactions.clone()),
+        )
+    }
+
+    #[tokio::test]
+    async fn same_bytes_as_block_message() {
+        let block = block();
+        let size = block.async_size() as u64;
+        for policy in [ChecksumPolicy::AlwaysVerify, ChecksumPolicy::SkipAbove(100)] {
+            let config = ChannelConfig {
+                checksum_policy: policy,
+                ..Default::default()
+            };
+            let mut expected = Vec::new();
+            P2PMessage::Block(block.clone())
+                .write(&mut expected, &config)
+                .await
+                .unwrap();
+            let mut v = Vec::new();
+            stream_of(&block, size)
+                .write(&mut v, &config)
+                .await
+                .unwrap();
+            assert_eq!(v, expected, "{:?}", policy);
+            let msg = P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap();
+            assert_eq!(msg, P2PMessage::Block(block.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn wrong_sizes() {
+        let block = block();
+        let size = block.async_size() as u64;
+        let config = ChannelConfig::default();
+        // a checksummed message is not sent if the size is wrong
+        let mut v = Vec::new();
+        assert!(stream_of(&block, size + 1)
+            .write(&mut v, &config)
+            .await
+            .is_err());
+        assert!(v.is_empty());
+        assert!(stream_of(&block, 10).write(&mut v, &config).await.is_err());
+        let short = BlockStream::new(
+            block.header.clone(),
+            block.transactions.len() as u64 + 1,
+            size + 1,
+            tokio_stream::iter(block.transactions.clone()),
+        );
+        assert!(short.write(&mut v, &config).await.is_err());
+        // a large block needs the extended header
+        let config = ChannelConfig {
+            protocol_version: 70015,
+            ..Default::default()
+        };
+        assert!(stream_of(&block, 1 << 33)
+            .write(&mut v, &config)
+            .await
+            .is_err());
+        assert!(v.is_empty());
+    }
+
+    #[tokio::test]
+    async fn written_once() {
+        let block = block();
+        let config = ChannelConfig::default();
+        let stream = stream_of(&block, block.async_size() as u64);
+        let clone = stream.clone();
+        assert_eq!(stream, clone);
+        assert_ne!(stream, stream_of(&block, block.async_size() as u64));
+        let mut v = Vec::new();
+        stream.write(&mut v, &config).await.unwrap();
+        let written = v.len();
+        assert!(clone.write(&mut v, &config).await.is_err());
+        assert_eq!(v.len(), written);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::bitcoin::AsyncEncodable;
+use crate::util::FeeRate;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Fee filter message.
+///
+/// The peer asks not to be sent inv messages for transactions with a fee rate below this minimum.
+///
+/// Specification: <https://github.com/bitcoin/bips/blob/master/bip-0133.mediawiki>
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct FeeFilter {
+    /// The minimum fee rate of transactions that should be announced to the peer.
+    pub min_fee_rate: FeeRate,
+}
+
+impl FeeFilter {
+    /// Size of the feefilter payload in bytes
+    pub const SIZE: usize = 8;
+
+    pub fn new(min_fee_rate: FeeRate) -> FeeFilter {
+        FeeFilter { min_fee_rate }
+    }
+}
+
+#[async_trait]
+impl AsyncEncodable for FeeFilter {
+    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        let sats_per_kb = reader.read_u64_le().await?;
+        Ok(FeeFilter::new(FeeRate::from_sats_per_kb(sats_per_kb)))
+    }
+
+    async fn async_to_binary<W: AsyncWrite + Unpin + Send>(
+        &self,
+        writer: &mut W,
+    ) -> crate::Result<()> {
+        writer.write_u64_le(self.min_fee_rate.sats_per_kb).await?;
+        Ok(())
+    }
+
+    fn async_size(&self) -> usize {
+        Self::SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_bytes() {
+        let b = hex::decode("e803000000000000").unwrap();
+        let f = FeeFilter::from_binary_buf(b.as_slice()).unwrap();
+        assert_eq!(f.min_fee_rate, FeeRate::from_sats_per_kb(1000));
+        assert_eq!(f.to_binary_buf().unwrap(), b);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::bitcoin::AsyncEncodable;
+use crate::p2p::channel::ChannelConfig;
+use crate::p2p::messages::messages::commands::EXTMSG;
+use crate::p2p::messages::msg_header::P2PMessageHeader;
+use crate::p2p::messages::P2PMessage;
+use crate::{Error, Result};
+use bytes::{Buf, BytesMut};
+use futures::executor::block_on;
+use log::warn;
+use std::io::Cursor;
+
+/// Splits a stream of bytes into P2P messages.
+///
+/// The [P2PMessage::read()] function reads a message from an async reader using `read_exact`, which
+/// requires the reader to be positioned at the start of a message. The MessageFramer instead accepts
+/// bytes in chunks of any size using [push_bytes()](MessageFramer::push_bytes) and produces messages
+/// using [next_message()](MessageFramer::next_message) once they are complete. A chunk may contain
+/// part of a message, or several messages, so the framer can be used with buffered IO or to parse
+/// recorded data.
+///
+/// If the bytes do not start with the magic bytes of the blockchain then the framer scans forward until it
+/// finds them, discarding the bytes in between.
+pub struct MessageFramer {
+    config: ChannelConfig,
+    buffer: BytesMut,
+}
+
+impl MessageFramer {
+    /// Create a new MessageFramer, the configuration supplies the magic bytes and the size limits.
+    ///
+    /// Frames are only parsed once they are complete, so the minimum throughput is not applied.
+    pub fn new(config: ChannelConfig) -> MessageFramer {
+        MessageFramer {
+            config: ChannelConfig {
+                min_throughput: None,
+                ..config
+            },
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Add bytes received from the stream.
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The number of bytes that have been received but not yet returned as part of a message.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Get the next complete message, returns None if more bytes are needed.
+    ///
+    /// An error is returned when bytes are discarded to find the next magic bytes or when a complete
+    /// message can not be decoded. In both cases the framer skips over the bad bytes, so this function
+    /// can be called again to get the following messages.
+    pub fn next_message(&mut self) -> Option<Result<P2PMessage>> {
+        if let Some(skipped) = self.sync_to_magic() {
+            warn!("skipped {} bytes looking for magic", skipped);
+            return Some(Err(Error::BadData(format!(
+                "skipped {} bytes looking for magic",
+                skipped
+            ))));
+        }
+        let header = match self.peek_header()? {
+            Ok(h) => h,
+            Err(e) => {
+                // skip over the magic so that the next call will look for the next message
+                self.buffer.advance(self.config.magic.len());
+                return Some(Err(e));
+            }
+        };
+        let frame_size = header.async_size() as u64 + header.payload_size;
+        if (self.buffer.len() as u64) < frame_size {
+            return None;
+        }
+        let frame = self.buffer.split_to(frame_size as usize);
+        Some(block_on(P2PMessage::read(
+            &mut Cursor::new(&frame[..]),
+            &self.config,
+        )))
+    }
+
+    /// Discard bytes until the buffer starts with the magic bytes, or with a partial copy of them.
+    ///
+    /// Returns the number of bytes discarded, or None if no bytes were discarded.
+    fn sync_to_magic(&mut self) -> Option<usize> {
+        let magic = self.config.magic;
+        let skip = match self.buffer.windows(magic.len()).position(|w| w == magic) {
+            Some(pos) => pos,
+            None => {
+                // keep any trailing bytes that could be the start of the magic
+                let keep = (1..magic.len())
+                    .rev()
+                    .find(|n| self.buffer.ends_with(&magic[..*n]))
+                    .unwrap_or(0);
+                self.buffer.len().saturating_sub(keep)
+            }
+        };
+        if skip == 0 {
+            return None;
+        }
+        self.buffer.advance(skip);
+        Some(skip)
+    }
+
+    /// Decode the header at the start of the buffer, returns None if more bytes are needed.
+    fn peek_header(&self) -> Option<Result<P2PMessageHeader>> {
+        if self.buffer.len() < P2PMessageHeader::STANDARD_SIZE {
+            return None;
+        }
+        if self.buffer[4..16] == EXTMSG && self.buffer.len() < P2PMessageHeader::EXTENDED_SIZE {
+            return None;
+        }
+        let header = match P2PMessageHeader::from_binary_buf(&self.buffer) {
+            Ok(h) => h,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(header.validate(&self.config).map(|_| header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{Hash, Script, Tx, TxInput, TxOutput};
+    use crate::p2p::messages::{Ping, Protoconf};
+
+    fn messages() -> Vec<P2PMessage> {
+        let tx = Tx {
+            version: 1,
+            inputs: vec![TxInput::new(Hash::ZERO, 3, Script::from(vec![1; 20]), None)],
+            outputs: vec![TxOutput::new(1000, Script::from(vec![2; 25]))],
+            lock_time: 0,
+        };
+        // not a version message, decoding those sets the timestamps of the addresses to the current time
+        vec![
+            P2PMessage::Protoconf(Protoconf::default()),
+            P2PMessage::Verack,
+            P2PMessage::Ping(Ping::new(7)),
+            P2PMessage::Tx(tx),
+            P2PMessage::Mempool,
+        ]
+    }
+
+    fn encode(msgs: &[P2PMessage], config: &ChannelConfig) -> Vec<u8> {
+        let mut v = Vec::new();
+        for m in msgs {
+            block_on(m.write(&mut v, config)).unwrap();
+        }
+        v
+    }
+
+    fn drain(framer: &mut MessageFramer, out: &mut Vec<P2PMessage>) {
+        while let Some(r) = framer.next_message() {
+            out.push(r.unwrap());
+        }
+    }
+
+    #[test]
+    fn split_at_every_boundary() {
+        let config = ChannelConfig::default();
+        let msgs = messages();
+        let bin = encode(&msgs, &config);
+        for i in 0..=bin.len() {
+            let mut framer = MessageFramer::new(config.clone());
+            let mut out = Vec::new();
+            framer.push_bytes(&bin[..i]);
+            drain(&mut framer, &mut out);
+            framer.push_bytes(&bin[i..]);
+            drain(&mut framer, &mut out);
+            assert_eq!(out, msgs, "split at {}", i);
+            assert_eq!(framer.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let config = ChannelConfig::default();
+        let msgs = messages();
+        let bin = encode(&msgs, &config);
+        for size in 1..=P2PMessageHeader::STANDARD_SIZE + 1 {
+            let mut framer = MessageFramer::new(config.clone());
+            let mut out = Vec::new();
+            for chunk in bin.chunks(size) {
+                framer.push_bytes(chunk);
+                drain(&mut framer, &mut out);
+            }
+            assert_eq!(out, msgs, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let config = ChannelConfig::default();
+        let msgs = messages();
+        let mut bin = encode(&msgs[..2], &config);
+        // garbage including a partial magic
+        bin.extend_from_slice(&[0xde, 0xad, config.magic[0], config.magic[1], 0xbe, 0xef]);
+        bin.extend(encode(&msgs[2..], &config));
+        for i in 0..=bin.len() {
+            let mut framer = MessageFramer::new(config.clone());
+            let mut out = Vec::new();
+            let mut errors = 0;
+            for chunk in [&bin[..i], &bin[i..]] {
+                framer.push_bytes(chunk);
+                while let Some(r) = framer.next_message() {
+                    match r {
+                        Ok(m) => out.push(m),
+                        Err(_) => errors += 1,
+                    }
+                }
+            }
+            assert_eq!(out, msgs, "split at {}", i);
+            assert!(errors >= 1, "split at {}", i);
+        }
+    }
+
+    #[test]
+    fn bad_header() {
+        let config = ChannelConfig::default();
+        let mut bin = encode(&[P2PMessage::Verack], &config);
+        // payload size larger than allowed
+        bin[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        bin.extend(encode(&[P2PMessage::Mempool], &config));
+        let mut framer = MessageFramer::new(config);
+        framer.push_bytes(&bin);
+        assert!(matches!(framer.next_message(), Some(Err(_))));
+        // the rest of the bad header is skipped
+        assert!(matches!(framer.next_message(), Some(Err(_))));
+        assert_eq!(framer.next_message().unwrap().unwrap(), P2PMessage::Mempool);
+        assert!(framer.next_message().is_none());
+    }
+}
@@ -10,13 +10,23 @@ pub struct Headers {
     pub headers: Vec<BlockHeader>,
 }
 
+impl Headers {
+    /// Maximum number of headers allowed in a Headers message
+    pub const MAX_HEADERS: u64 = 2000;
+}
+
 #[async_trait]
 impl AsyncEncodable for Headers {
     async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        let num_headers = varint_decode(reader).await? as usize;
+        let num_headers = varint_decode(reader).await?;
+        if num_headers > Headers::MAX_HEADERS {
+            let msg = format!("Too many headers: {}", num_headers);
+            return Err(crate::Error::BadData(msg));
+        }
+        let num_headers = num_headers as usize;
         let mut headers = Vec::with_capacity(num_headers);
         for _ in 0..num_headers {
             headers.push(BlockHeader::async_from_binary(reader).await?);
@@ -53,3 +63,37 @@ impl fmt::Display for Headers {
         write!(f, "Headers(n={}, [{}])", self.headers.len(), out_str)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{BlockchainId, TrickleReader, TrickleWriter};
+
+    #[tokio::test]
+    async fn trickle_headers() {
+        let headers = Headers {
+            headers: vec![
+                BlockHeader::get_genesis(BlockchainId::Main),
+                BlockHeader::get_genesis(BlockchainId::Test),
+            ],
+        };
+        let mut writer = TrickleWriter::default();
+        headers.async_to_binary(&mut writer).await.unwrap();
+        assert_eq!(writer.data.len(), headers.async_size());
+        let h2 = Headers::async_from_binary(&mut TrickleReader::new(&writer.data))
+            .await
+            .unwrap();
+        assert_eq!(h2, headers);
+    }
+
+    #[tokio::test]
+    async fn too_many_headers() {
+        let mut v = Vec::new();
+        varint_encode(&mut v, Headers::MAX_HEADERS + 1)
+            .await
+            .unwrap();
+        assert!(Headers::async_from_binary(&mut TrickleReader::new(&v))
+            .await
+            .is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable, Hash};
+use crate::bitcoin::{
+    varint_decode, varint_encode, varint_size, AsyncEncodable, BlockHash, Hash, TxHash,
+};
+use crate::p2p::messages::MAX_PREALLOCATE;
+use crate::{Error, Result};
 use async_trait::async_trait;
+use std::cmp::min;
 use std::fmt;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -10,32 +15,91 @@ pub struct Inv {
     pub objects: Vec<InvItem>,
 }
 
+impl Inv {
+    /// The maximum number of entries in an inventory message.
+    pub const MAX_INV_ENTRIES: u64 = 50_000;
+
+    /// Read the payload of an `inv`, `getdata` or `notfound` message, which share this format.
+    ///
+    /// The errors name the `command` that the payload belongs to, and the entry that was invalid.
+    pub async fn read<R: AsyncRead + Unpin + Send>(reader: &mut R, command: &str) -> Result<Inv> {
+        let num_objects = varint_decode(reader).await?;
+        if num_objects > Inv::MAX_INV_ENTRIES {
+            return Err(InventoryError::TooManyEntries {
+                count: num_objects,
+                max: Inv::MAX_INV_ENTRIES,
+            }
+            .for_command(command));
+        }
+        let num_objects = num_objects as usize;
+        let mut objects = Vec::with_capacity(min(num_objects, MAX_PREALLOCATE));
+        for index in 0..num_objects {
+            let obj_type = reader.read_u32_le().await?;
+            let hash = Hash::async_from_binary(reader).await?;
+            let Ok(obj_type) = InvType::try_from(obj_type) else {
+                return Err(InventoryError::UnknownType { index, obj_type }.for_command(command));
+            };
+            objects.push(InvItem { obj_type, hash });
+        }
+        Ok(Inv { objects })
+    }
+
+    /// Check that the inventory can be sent as the payload of a `command` message.
+    pub fn check(&self, command: &str) -> Result<()> {
+        if self.objects.len() as u64 > Inv::MAX_INV_ENTRIES {
+            return Err(InventoryError::TooManyEntries {
+                count: self.objects.len() as u64,
+                max: Inv::MAX_INV_ENTRIES,
+            }
+            .for_command(command));
+        }
+        Ok(())
+    }
+}
+
+/// Why the inventory of an `inv`, `getdata` or `notfound` message is invalid, see
+/// [Error::InvalidInventory].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InventoryError {
+    /// There are more entries than [Inv::MAX_INV_ENTRIES].
+    TooManyEntries { count: u64, max: u64 },
+    /// The entry at `index` has a type that is not known.
+    UnknownType { index: usize, obj_type: u32 },
+}
+
+impl InventoryError {
+    fn for_command(self, command: &str) -> Error {
+        Error::InvalidInventory {
+            command: command.to_string(),
+            error: self,
+        }
+    }
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InventoryError::TooManyEntries { count, max } => {
+                write!(f, "{} entries exceeds the maximum of {}", count, max)
+            }
+            InventoryError::UnknownType { index, obj_type } => {
+                write!(f, "entry {} has unknown type {}", index, obj_type)
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl AsyncEncodable for Inv {
-    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> crate::Result<Self>
+    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> Result<Self>
     where
         Self: Sized,
     {
-        let num_objects = varint_decode(reader).await? as usize;
-        // if num_objects > MAX_INV_ENTRIES {
-        //     let msg = format!("Num objects exceeded maximum: {}", num_objects);
-        //     return Err(crate::Error::BadData(msg));
-        // }
-        let mut objects = Vec::with_capacity(num_objects);
-        for _ in 0..num_objects {
-            objects.push(InvItem::async_from_binary(reader).await?);
-        }
-        Ok(Inv { objects })
+        Inv::read(reader, "inv").await
     }
 
-    async fn async_to_binary<W: AsyncWrite + Unpin + Send>(
-        &self,
-        writer: &mut W,
-    ) -> crate::Result<()> {
-        // if self.objects.len() as u64 > MAX_INV_ENTRIES {
-        //     let msg = format!("Too many objects: {}", self.objects.len());
-        //     return Err(crate::Error::BadData(msg));
-        // }
+    async fn async_to_binary<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
+        self.check("inv")?;
         varint_encode(writer, self.objects.len() as u64).await?;
         for object in self.objects.iter() {
             object.async_to_binary(writer).await?;
@@ -63,7 +127,7 @@ impl fmt::Display for Inv {
 }
 
 /// Inventory item types
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub enum InvType {
     /// May be ignored
     InvError = 0,
@@ -78,19 +142,35 @@ pub enum InvType {
 impl TryFrom<u32> for InvType {
     type Error = crate::Error;
 
-    fn try_from(value: u32) -> Result<Self, Self::Error> {
+    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
         match value {
             0 => Ok(InvType::InvError),
             1 => Ok(InvType::Tx),
             2 => Ok(InvType::Block),
             4 => Ok(InvType::CompactBlock),
-            _ => Err(crate::Error::BadData("Invalid inventory type".to_string())),
+            _ => Err(crate::Error::BadData(format!(
+                "Invalid inventory type: {}",
+                value
+            ))),
+        }
+    }
+}
+
+impl From<&InvType> for u32 {
+    fn from(value: &InvType) -> Self {
+        match value {
+            InvType::InvError => 0,
+            InvType::Tx => 1,
+            InvType::Block => 2,
+            InvType::CompactBlock => 4,
         }
     }
 }
 
 /// An inventory item, one element from a vector of inventory items.
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+///
+/// Items are ordered by type and then by hash.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub struct InvItem {
     /// Type of object
     pub obj_type: InvType,
@@ -101,6 +181,22 @@ pub struct InvItem {
 impl InvItem {
     /// Size of the inventory item in bytes
     pub const SIZE: usize = 36;
+
+    /// An inventory item for a transaction.
+    pub fn tx(hash: TxHash) -> InvItem {
+        InvItem {
+            obj_type: InvType::Tx,
+            hash,
+        }
+    }
+
+    /// An inventory item for a block.
+    pub fn block(hash: BlockHash) -> InvItem {
+        InvItem {
+            obj_type: InvType::Block,
+            hash,
+        }
+    }
 }
 
 impl fmt::Display for InvType {
@@ -132,12 +228,7 @@ impl AsyncEncodable for InvItem {
         &self,
         writer: &mut W,
     ) -> crate::Result<()> {
-        match self.obj_type {
-            InvType::InvError => writer.write_u32_le(0).await?,
-            InvType::Tx => writer.write_u32_le(1).await?,
-            InvType::Block => writer.write_u32_le(2).await?,
-            InvType::CompactBlock => writer.write_u32_le(4).await?,
-        }
+        writer.write_u32_le(u32::from(&self.obj_type)).await?;
         self.hash.async_to_binary(writer).await
     }
 
@@ -151,3 +242,107 @@ impl fmt::Display for InvItem {
         write!(f, "({}, {})", self.obj_type, self.hash)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::AsyncEncodable;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn inv_item_round_trip() {
+        let hash = Hash::sha256d(b"inv");
+        let items = [
+            InvItem::tx(hash),
+            InvItem::block(hash),
+            InvItem {
+                obj_type: InvType::CompactBlock,
+                hash,
+            },
+        ];
+        for item in items {
+            let mut v = Vec::new();
+            item.async_to_binary(&mut v).await.unwrap();
+            assert_eq!(v.len(), InvItem::SIZE);
+            assert_eq!(
+                u32::from_le_bytes(v[..4].try_into().unwrap()),
+                u32::from(&item.obj_type)
+            );
+            let decoded = InvItem::async_from_binary(&mut Cursor::new(&v))
+                .await
+                .unwrap();
+            assert_eq!(decoded, item);
+        }
+        assert_eq!(InvItem::tx(hash).to_string(), format!("(Tx, {})", hash));
+    }
+
+    #[tokio::test]
+    async fn unknown_inv_type() {
+        for value in [3u32, 5, 0x40000001] {
+            let mut v = value.to_le_bytes().to_vec();
+            v.extend_from_slice(&[0u8; 32]);
+            let r = InvItem::async_from_binary(&mut Cursor::new(&v)).await;
+            assert!(matches!(r, Err(crate::Error::BadData(_))));
+        }
+        // an unknown type fails the whole message
+        let mut v = vec![2u8];
+        InvItem::tx(Hash::ZERO)
+            .async_to_binary(&mut v)
+            .await
+            .unwrap();
+        v.extend_from_slice(&3u32.to_le_bytes());
+        v.extend_from_slice(&[0u8; 32]);
+        let r = Inv::read(&mut Cursor::new(&v), "getdata").await;
+        match r {
+            Err(Error::InvalidInventory { command, error }) => {
+                assert_eq!(command, "getdata");
+                assert_eq!(
+                    error,
+                    InventoryError::UnknownType {
+                        index: 1,
+                        obj_type: 3
+                    }
+                );
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn inv_bounds() {
+        let max = Inv {
+            objects: (0..Inv::MAX_INV_ENTRIES)
+                .map(|i| InvItem::tx(Hash::sha256d(&i.to_le_bytes())))
+                .collect(),
+        };
+        let mut v = Vec::new();
+        max.async_to_binary(&mut v).await.unwrap();
+        assert_eq!(v.len(), max.async_size());
+        assert_eq!(Inv::read(&mut Cursor::new(&v), "inv").await.unwrap(), max);
+
+        // one more entry is refused when writing and when reading
+        let mut over = max.clone();
+        over.objects.push(InvItem::block(Hash::ZERO));
+        let too_many = InventoryError::TooManyEntries {
+            count: Inv::MAX_INV_ENTRIES + 1,
+            max: Inv::MAX_INV_ENTRIES,
+        };
+        assert!(matches!(
+            over.check("notfound"),
+            Err(Error::InvalidInventory { command, error }) if command == "notfound" && error == too_many
+        ));
+        assert!(over.async_to_binary(&mut Vec::new()).await.is_err());
+        let mut v = Vec::new();
+        crate::bitcoin::varint_encode(&mut v, Inv::MAX_INV_ENTRIES + 1)
+            .await
+            .unwrap();
+        let r = Inv::read(&mut Cursor::new(&v), "inv").await;
+        assert!(matches!(
+            r,
+            Err(Error::InvalidInventory { error, .. }) if error == too_many
+        ));
+        // the count is not trusted, a short payload fails without allocating for it
+        let r = Inv::read(&mut Cursor::new(&[0xfd, 0x50, 0xc3]), "inv").await;
+        assert!(matches!(r, Err(Error::IOError(_))));
+    }
+}
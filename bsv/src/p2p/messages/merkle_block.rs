@@ -1,7 +1,9 @@
 use crate::bitcoin::{
     varint_decode, varint_encode, varint_size, AsyncEncodable, BlockHeader, Hash,
 };
+use crate::p2p::messages::MAX_PREALLOCATE;
 use async_trait::async_trait;
+use std::cmp::min;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// A block header and partial merkle tree for SPV nodes to validate transactions
@@ -25,13 +27,13 @@ impl AsyncEncodable for MerkleBlock {
     {
         let header = BlockHeader::async_from_binary(reader).await?;
         let total_transactions = reader.read_u32_le().await?;
-        let num_hashes = varint_decode(reader).await? as usize;
-        let mut hashes = Vec::with_capacity(num_hashes);
+        let num_hashes = varint_decode(reader).await?;
+        let mut hashes = Vec::with_capacity(min(num_hashes, MAX_PREALLOCATE as u64) as usize);
         for _ in 0..num_hashes {
             hashes.push(Hash::async_from_binary(reader).await?);
         }
-        let num_flags = varint_decode(reader).await? as usize;
-        let mut flags = Vec::with_capacity(num_flags);
+        let num_flags = varint_decode(reader).await?;
+        let mut flags = Vec::with_capacity(min(num_flags, MAX_PREALLOCATE as u64) as usize);
         for _ in 0..num_flags {
             flags.push(reader.read_u8().await?);
         }
@@ -1,15 +1,16 @@
 pub use self::commands::PROTOCONF;
-use crate::bitcoin::{AsyncEncodable, Hash, Tx};
+use crate::bitcoin::{varint_size, AsyncEncodable, Block, Hash, Tx};
 use crate::p2p::channel::ChannelConfig;
 use crate::p2p::messages::addr::Addr;
-use crate::p2p::messages::block::Block;
+
 use crate::p2p::messages::block_locator::BlockLocator;
+use crate::p2p::messages::fee_filter::FeeFilter;
 use crate::p2p::messages::headers::Headers;
-use crate::p2p::messages::inv::Inv;
+use crate::p2p::messages::inv::{Inv, InvType};
 use crate::p2p::messages::merkle_block::MerkleBlock;
 use crate::p2p::messages::messages::commands::{
-    ADDR, BLOCK, GETADDR, GETBLOCKS, GETDATA, GETHEADERS, HEADERS, INV, MEMPOOL, MERKLEBLOCK,
-    NOTFOUND, PING, PONG, REJECT, SENDCMPCT, SENDHEADERS, TX, VERACK, VERSION,
+    ADDR, BLOCK, FEEFILTER, GETADDR, GETBLOCKS, GETDATA, GETHEADERS, HEADERS, INV, MEMPOOL,
+    MERKLEBLOCK, NOTFOUND, PING, PONG, REJECT, SENDCMPCT, SENDHEADERS, TX, VERACK, VERSION,
 };
 use crate::p2p::messages::messages::P2PMessageType::{ConnectionControl, Data};
 use crate::p2p::messages::msg_header::P2PMessageHeader;
@@ -17,16 +18,21 @@ use crate::p2p::messages::protoconf::Protoconf;
 use crate::p2p::messages::reject::Reject;
 use crate::p2p::messages::send_cmpct::SendCmpct;
 use crate::p2p::messages::{Ping, Version};
+use crate::p2p::telemetry::{record_error, ErrorKind};
+use crate::p2p::throughput::StallGuard;
 use crate::{Error, Result};
 use log::{trace, warn};
+use ring::digest::{self, SHA256};
 use std::fmt;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
 // based on code imported from rust-sv but substantially modified
 
-// I wont be implementing the FEEFILTER related messages. These aren't scalable. As unknown messages,
-// they will be ignored if received.
+// The FEEFILTER message is decoded so that we can avoid announcing transactions that the peer will ignore,
+// but we dont send it. Fee filtering isn't scalable.
 //
 // The Bitcoin P2P protocol is a message based protocol, where each message is a chunk of contiguous data.
 // At first glance, the messages appear to represent a single fact or a collection of independent facts. However, on
@@ -73,21 +79,30 @@ pub mod commands {
     /// [Addr command](https://en.bitcoin.it/wiki/Protocol_documentation#addr)
     pub const ADDR: [u8; 12] = *b"addr\0\0\0\0\0\0\0\0";
 
-    /// [Alert command](https://en.bitcoin.it/wiki/Protocol_documentation#alert) (deprecated)
+    /// [Alert command](https://en.bitcoin.it/wiki/Protocol_documentation#alert) (deprecated, received
+    /// as [P2PMessage::Unknown](super::P2PMessage::Unknown))
     pub const ALERT: [u8; 12] = *b"alert\0\0\0\0\0\0\0";
 
     /// [Block command](https://en.bitcoin.it/wiki/Protocol_documentation#block)
     pub const BLOCK: [u8; 12] = *b"block\0\0\0\0\0\0\0";
 
     /// [Block transaction command](https://en.bitcoin.it/wiki/Protocol_documentation#blocktxn)
+    ///
+    /// Compact blocks are not supported, this and the other compact block commands are received as
+    /// [P2PMessage::Unknown](super::P2PMessage::Unknown).
     pub const BLOCKTXN: [u8; 12] = *b"blocktxn\0\0\0\0";
 
     /// [Compact block command](https://en.bitcoin.it/wiki/Protocol_documentation#cmpctblock)
     pub const CMPCTBLOCK: [u8; 12] = *b"cmpctblock\0\0";
 
     /// [Extended Message Header](https://github.com/bitcoin-sv-specs/protocol/blob/master/p2p/large_messages.md)
+    ///
+    /// This is not a message, it marks a header that is followed by the real command and a 64-bit size.
     pub const EXTMSG: [u8; 12] = *b"extmsg\0\0\0\0\0\0";
 
+    /// [Fee filter command](https://en.bitcoin.it/wiki/Protocol_documentation#feefilter)
+    pub const FEEFILTER: [u8; 12] = *b"feefilter\0\0\0";
+
     /// [Inventory command](https://en.bitcoin.it/wiki/Protocol_documentation#inv)
     pub const INV: [u8; 12] = *b"inv\0\0\0\0\0\0\0\0\0";
 
@@ -151,6 +166,7 @@ pub mod commands {
 pub enum P2PMessage {
     Addr(Addr),
     Block(Block),
+    FeeFilter(FeeFilter),
     GetAddr,
     GetBlocks(BlockLocator),
     GetData(Inv),
@@ -178,22 +194,49 @@ impl P2PMessage {
         reader: &mut R,
         comms_config: &ChannelConfig,
     ) -> Result<Self> {
-        let header = P2PMessageHeader::async_from_binary(reader).await?;
+        let skipped =
+            P2PMessageHeader::read_magic(reader, &comms_config.magic, comms_config.magic_resync)
+                .await?;
+        if skipped > 0 {
+            record_error(ErrorKind::ProtocolAnomaly);
+            warn!("skipped {} bytes before the magic", skipped);
+        }
+        let header = P2PMessageHeader::read_after_magic(reader, comms_config.magic).await?;
         trace!("P2PMessage::read() - header: {:?}", header);
         header.validate(comms_config)?;
+        let mut guard = StallGuard::new(reader, comms_config.min_throughput);
+        match Self::read_payload(&mut guard, &header, comms_config).await {
+            Err(_) if guard.is_stalled() => Err(Error::StalledTransfer {
+                command: header.command_str().trim_end_matches('\0').to_string(),
+                received: guard.received(),
+                expected: header.payload_size,
+            }),
+            r => r,
+        }
+    }
+
+    /// Read the payload of a message whose header has been read and validated.
+    async fn read_payload<R: AsyncRead + Unpin + Send>(
+        reader: &mut R,
+        header: &P2PMessageHeader,
+        comms_config: &ChannelConfig,
+    ) -> Result<Self> {
+        let verify = comms_config.checksum_policy.on_receive(header);
+        let reader = &mut ChecksumReader::new(reader, verify);
         // payload size has been checked for max limit in header.validate()
         let msg = match header.command {
             ADDR => P2PMessage::Addr(Addr::async_from_binary(reader).await?),
             BLOCK => P2PMessage::Block(Block::async_from_binary(reader).await?),
+            FEEFILTER => P2PMessage::FeeFilter(FeeFilter::async_from_binary(reader).await?),
             GETADDR => P2PMessage::GetAddr,
             GETBLOCKS => P2PMessage::GetBlocks(BlockLocator::async_from_binary(reader).await?),
-            GETDATA => P2PMessage::GetData(Inv::async_from_binary(reader).await?),
+            GETDATA => P2PMessage::GetData(Inv::read(reader, "getdata").await?),
             GETHEADERS => P2PMessage::GetHeaders(BlockLocator::async_from_binary(reader).await?),
             HEADERS => P2PMessage::Headers(Headers::async_from_binary(reader).await?),
-            INV => P2PMessage::Inv(Inv::async_from_binary(reader).await?),
+            INV => P2PMessage::Inv(Inv::read(reader, "inv").await?),
             MEMPOOL => P2PMessage::Mempool,
             MERKLEBLOCK => P2PMessage::MerkleBlock(MerkleBlock::async_from_binary(reader).await?),
-            NOTFOUND => P2PMessage::NotFound(Inv::async_from_binary(reader).await?),
+            NOTFOUND => P2PMessage::NotFound(Inv::read(reader, "notfound").await?),
             PING => P2PMessage::Ping(Ping::async_from_binary(reader).await?),
             PONG => P2PMessage::Pong(Ping::async_from_binary(reader).await?),
             PROTOCONF => P2PMessage::Protoconf(Protoconf::async_from_binary(reader).await?),
@@ -207,27 +250,20 @@ impl P2PMessage {
                 if header.payload_size == 0 {
                     trace!(
                         "received unknown command={:?} with empty payload",
-                        std::str::from_utf8(&header.command).unwrap()
+                        header.command_str()
                     );
-                    P2PMessage::Unknown(
-                        format!(
-                            "Unknown command: {}",
-                            std::str::from_utf8(&header.command).unwrap()
-                        ),
-                        0,
-                    )
+                    P2PMessage::Unknown(format!("Unknown command: {}", header.command_str()), 0)
                 } else {
-                    let mut v = vec![0u8; header.payload_size as usize];
-                    reader.read_exact(&mut v).await?;
+                    Self::discard(reader, header.payload_size).await?;
                     trace!(
                         "received unknown command={:?} with payload size: {}",
-                        std::str::from_utf8(&header.command).unwrap(),
+                        header.command_str(),
                         header.payload_size
                     );
                     P2PMessage::Unknown(
                         format!(
                             "Unknown command: {:?}, payload size {}",
-                            std::str::from_utf8(&header.command).unwrap(),
+                            header.command_str(),
                             header.payload_size
                         ),
                         header.payload_size as usize,
@@ -240,18 +276,27 @@ impl P2PMessage {
                 // todo: 70016 version message is larger. dont report on it. remove this when we support 70016
                 warn!(
                     "received larger payload than msg: command={:?}, payload size={}, msg size={}",
-                    std::str::from_utf8(&header.command).unwrap(),
+                    header.command_str(),
                     header.payload_size,
                     msg.size()
                 );
             }
             // we've read less bytes than the payload size, we need to read the rest and discard it
-            let mut v = vec![0u8; header.payload_size as usize - msg.size()];
-            reader.read_exact(&mut v).await?;
+            Self::discard(reader, header.payload_size - msg.size() as u64).await?;
         }
+        reader.verify(header)?;
         Ok(msg)
     }
 
+    /// Read and discard bytes from the reader without buffering them all in memory.
+    async fn discard<R: AsyncRead + Unpin + Send>(reader: &mut R, size: u64) -> Result<()> {
+        let n = tokio::io::copy(&mut reader.take(size), &mut tokio::io::sink()).await?;
+        if n != size {
+            return Err(Error::DataTooSmall);
+        }
+        Ok(())
+    }
+
     /// Writes a Bitcoin P2P message with its payload to bytes
     pub async fn write<W: AsyncWrite + Unpin + Send>(
         &self,
@@ -261,9 +306,13 @@ impl P2PMessage {
         match self {
             P2PMessage::Addr(p) => self.write_with_payload(writer, ADDR, config, p).await,
             P2PMessage::Block(p) => self.write_with_payload(writer, BLOCK, config, p).await,
+            P2PMessage::FeeFilter(p) => self.write_with_payload(writer, FEEFILTER, config, p).await,
             P2PMessage::GetAddr => self.write_without_payload(writer, GETADDR, config).await,
             P2PMessage::GetBlocks(p) => self.write_with_payload(writer, GETBLOCKS, config, p).await,
-            P2PMessage::GetData(p) => self.write_with_payload(writer, GETDATA, config, p).await,
+            P2PMessage::GetData(p) => {
+                p.check("getdata")?;
+                self.write_with_payload(writer, GETDATA, config, p).await
+            }
             P2PMessage::GetHeaders(p) => {
                 self.write_with_payload(writer, GETHEADERS, config, p).await
             }
@@ -274,7 +323,10 @@ impl P2PMessage {
                 self.write_with_payload(writer, MERKLEBLOCK, config, p)
                     .await
             }
-            P2PMessage::NotFound(p) => self.write_with_payload(writer, NOTFOUND, config, p).await,
+            P2PMessage::NotFound(p) => {
+                p.check("notfound")?;
+                self.write_with_payload(writer, NOTFOUND, config, p).await
+            }
             P2PMessage::Ping(p) => self.write_with_payload(writer, PING, config, p).await,
             P2PMessage::Pong(p) => self.write_with_payload(writer, PONG, config, p).await,
             P2PMessage::Protoconf(p) => self.write_with_payload(writer, PROTOCONF, config, p).await,
@@ -294,11 +346,40 @@ impl P2PMessage {
         }
     }
 
+    /// Get the command of the message, as used in the message header.
+    pub fn command(&self) -> &'static str {
+        match self {
+            P2PMessage::Addr(_) => "addr",
+            P2PMessage::Block(_) => "block",
+            P2PMessage::FeeFilter(_) => "feefilter",
+            P2PMessage::GetAddr => "getaddr",
+            P2PMessage::GetBlocks(_) => "getblocks",
+            P2PMessage::GetData(_) => "getdata",
+            P2PMessage::GetHeaders(_) => "getheaders",
+            P2PMessage::Headers(_) => "headers",
+            P2PMessage::Inv(_) => "inv",
+            P2PMessage::Mempool => "mempool",
+            P2PMessage::MerkleBlock(_) => "merkleblock",
+            P2PMessage::NotFound(_) => "notfound",
+            P2PMessage::Ping(_) => "ping",
+            P2PMessage::Pong(_) => "pong",
+            P2PMessage::Protoconf(_) => "protoconf",
+            P2PMessage::Reject(_) => "reject",
+            P2PMessage::SendHeaders => "sendheaders",
+            P2PMessage::SendCmpct(_) => "sendcmpct",
+            P2PMessage::Tx(_) => "tx",
+            P2PMessage::Verack => "verack",
+            P2PMessage::Version(_) => "version",
+            P2PMessage::Unknown(_, _) => "unknown",
+        }
+    }
+
     /// Get the size of the payload of the message
     pub fn size(&self) -> usize {
         match self {
             P2PMessage::Addr(p) => p.async_size(),
             P2PMessage::Block(p) => p.async_size(),
+            P2PMessage::FeeFilter(p) => p.async_size(),
             P2PMessage::GetAddr => 0,
             P2PMessage::GetBlocks(p) => p.async_size(),
             P2PMessage::GetData(p) => p.async_size(),
@@ -334,8 +415,7 @@ impl P2PMessage {
             payload_size: 0,
             checksum: NO_CHECKSUM,
         };
-        let v = header.to_binary_buf()?;
-        let _ = writer.write(&v).await?;
+        header.async_to_binary(writer).await?;
         Ok(())
     }
 
@@ -366,7 +446,22 @@ impl P2PMessage {
             payload.async_to_binary(writer).await?;
             return Ok(());
         }
-        let buf = payload.to_binary_buf()?;
+        let payload_size = payload.async_size() as u64;
+        if !config.checksum_policy.on_send(payload_size) {
+            // stream the payload, there is no need to hold it in memory
+            let header = P2PMessageHeader {
+                magic: config.magic,
+                command,
+                payload_size,
+                checksum: ZERO_CHECKSUM,
+            };
+            header.async_to_binary(writer).await?;
+            payload.async_to_binary(writer).await?;
+            return Ok(());
+        }
+        // encode asynchronously, this may be called from within another executor
+        let mut buf = Vec::with_capacity(payload.async_size());
+        payload.async_to_binary(&mut buf).await?;
         let hash = Hash::sha256d(&buf);
         let header = P2PMessageHeader {
             magic: config.magic,
@@ -375,16 +470,139 @@ impl P2PMessage {
             checksum: hash.hash[..4].try_into().unwrap(),
         };
         header.async_to_binary(writer).await?;
-        let _ = writer.write(&buf).await?;
+        writer.write_all(&buf).await?;
+        Ok(())
+    }
+
+    /// Prepare the message for a peer that accepts payloads of at most `max` bytes.
+    ///
+    /// A message that fits is returned as it is. The list messages, `inv`, `getdata`, `notfound`,
+    /// `addr` and `headers`, are split into several messages that each fit, keeping the items in
+    /// their original order, and each also within the item limit of its command. Any other message
+    /// that does not fit results in [Error::MessageTooLargeForPeer].
+    pub fn split_for_peer(self, max: u64) -> Result<Vec<P2PMessage>> {
+        let size = self.size() as u64;
+        let too_large = Error::MessageTooLargeForPeer {
+            command: self.command().to_string(),
+            size,
+            max,
+        };
+        let split = match self {
+            P2PMessage::Inv(p) => chunk_items(p.objects, max, Inv::MAX_INV_ENTRIES)
+                .map(|c| c.map(|objects| P2PMessage::Inv(Inv { objects })).collect()),
+            P2PMessage::GetData(p) => chunk_items(p.objects, max, Inv::MAX_INV_ENTRIES).map(|c| {
+                c.map(|objects| P2PMessage::GetData(Inv { objects }))
+                    .collect()
+            }),
+            P2PMessage::NotFound(p) => chunk_items(p.objects, max, Inv::MAX_INV_ENTRIES).map(|c| {
+                c.map(|objects| P2PMessage::NotFound(Inv { objects }))
+                    .collect()
+            }),
+            P2PMessage::Addr(p) => chunk_items(p.addrs, max, Addr::MAX_ADDR_COUNT)
+                .map(|c| c.map(|addrs| P2PMessage::Addr(Addr { addrs })).collect()),
+            P2PMessage::Headers(p) => chunk_items(p.headers, max, Headers::MAX_HEADERS).map(|c| {
+                c.map(|headers| P2PMessage::Headers(Headers { headers }))
+                    .collect()
+            }),
+            msg if size <= max => Some(vec![msg]),
+            _ => None,
+        };
+        split.ok_or(too_large)
+    }
+
+    /// Write a `tx` or `block` message whose payload is already encoded, such as one held by a
+    /// [ServeCache](crate::p2p::ServeCache).
+    pub(crate) async fn write_encoded<W: AsyncWrite + Unpin + Send>(
+        writer: &mut W,
+        obj_type: InvType,
+        payload: &[u8],
+        config: &ChannelConfig,
+    ) -> Result<()> {
+        let command = match obj_type {
+            InvType::Tx => TX,
+            InvType::Block => BLOCK,
+            _ => {
+                return Err(Error::BadArgument(format!(
+                    "can not write an encoded {} message",
+                    obj_type
+                )))
+            }
+        };
+        let payload_size = payload.len() as u64;
+        let extended = payload_size > 0xffffffff;
+        if extended && (command != BLOCK || config.protocol_version < 70016) {
+            return Err(Error::BadData("payload too large".to_string()));
+        }
+        let checksum = if extended || !config.checksum_policy.on_send(payload_size) {
+            ZERO_CHECKSUM
+        } else {
+            Hash::sha256d(payload).hash[..4].try_into().unwrap()
+        };
+        let header = P2PMessageHeader {
+            magic: config.magic,
+            command,
+            payload_size,
+            checksum,
+        };
+        header.async_to_binary(writer).await?;
+        writer.write_all(payload).await?;
         Ok(())
     }
 }
 
+/// Passes the payload of a message through from a reader, calculating its checksum if enabled.
+struct ChecksumReader<'a, R> {
+    reader: &'a mut R,
+    context: Option<digest::Context>,
+}
+
+impl<'a, R> ChecksumReader<'a, R> {
+    fn new(reader: &'a mut R, enabled: bool) -> Self {
+        ChecksumReader {
+            reader,
+            context: enabled.then(|| digest::Context::new(&SHA256)),
+        }
+    }
+
+    /// Check the checksum of the bytes that have been read against the checksum in the header.
+    fn verify(&mut self, header: &P2PMessageHeader) -> Result<()> {
+        let Some(context) = self.context.take() else {
+            return Ok(());
+        };
+        let hash = digest::digest(&SHA256, context.finish().as_ref());
+        if hash.as_ref()[..4] != header.checksum {
+            warn!(
+                "checksum mismatch for {} message",
+                header.command_str().trim_end_matches('\0')
+            );
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let start = buf.filled().len();
+        let this = &mut *self;
+        let r = Pin::new(&mut *this.reader).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(context)) = (&r, &mut this.context) {
+            context.update(&buf.filled()[start..]);
+        }
+        r
+    }
+}
+
 impl fmt::Debug for P2PMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             P2PMessage::Addr(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::Block(p) => f.write_str(&format!("{:#?}", p)),
+            P2PMessage::FeeFilter(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::GetAddr => f.write_str("GetAddr"),
             P2PMessage::GetBlocks(p) => f
                 .debug_struct("GetBlocks")
@@ -422,7 +640,8 @@ impl fmt::Display for P2PMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             P2PMessage::Addr(p) => f.write_str(&format!("{}", p)),
-            P2PMessage::Block(p) => f.write_str(&format!("{:#?}", p)),
+            P2PMessage::Block(p) => f.write_str(&format!("{:?}", p)),
+            P2PMessage::FeeFilter(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::GetAddr => f.write_str("GetAddr"),
             P2PMessage::GetBlocks(p) => f
                 .debug_struct("GetBlocks")
@@ -448,7 +667,7 @@ impl fmt::Display for P2PMessage {
             P2PMessage::Reject(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::SendCmpct(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::SendHeaders => f.write_str("SendHeaders"),
-            P2PMessage::Tx(p) => f.write_str(&format!("{:#?}", p)),
+            P2PMessage::Tx(p) => f.write_str(&format!("{:?}", p)),
             P2PMessage::Verack => f.write_str("Verack"),
             P2PMessage::Version(p) => f.write_str(&format!("{:#?}", p)),
             P2PMessage::Unknown(p, _size) => f.write_str(&format!("{:#?}", p)),
@@ -456,6 +675,40 @@ impl fmt::Display for P2PMessage {
     }
 }
 
+/// Split the items of a list message into chunks that each encode, as a varint count followed by
+/// the items, in at most `max` bytes and hold at most `limit` items. None if an item does not fit
+/// on its own.
+fn chunk_items<T: AsyncEncodable>(
+    items: Vec<T>,
+    max: u64,
+    limit: u64,
+) -> Option<impl Iterator<Item = Vec<T>>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_size = 0;
+    for item in items {
+        let item_size = item.async_size() as u64;
+        let n = chunk.len() as u64 + 1;
+        if n > limit || varint_size(n) as u64 + chunk_size + item_size > max {
+            if chunk.is_empty() {
+                return None;
+            }
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_size = 0;
+            if varint_size(1) as u64 + item_size > max {
+                return None;
+            }
+        }
+        chunk_size += item_size;
+        chunk.push(item);
+    }
+    // an empty list is still sent as one message
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+    Some(chunks.into_iter())
+}
+
 /// We define several different types of P2P Messages
 /// These types will be expanded as I flesh out the implementation
 pub enum P2PMessageType {
@@ -470,6 +723,7 @@ impl From<&P2PMessage> for P2PMessageType {
         match value {
             P2PMessage::Addr(_) => Data,
             P2PMessage::Block(_) => Data,
+            P2PMessage::FeeFilter(_) => ConnectionControl,
             P2PMessage::GetAddr => Data,
             P2PMessage::GetBlocks(_) => Data,
             P2PMessage::GetData(_) => Data,
@@ -498,6 +752,7 @@ impl From<Arc<P2PMessage>> for P2PMessageType {
         match *value {
             P2PMessage::Addr(_) => Data,
             P2PMessage::Block(_) => Data,
+            P2PMessage::FeeFilter(_) => ConnectionControl,
             P2PMessage::GetAddr => Data,
             P2PMessage::GetBlocks(_) => Data,
             P2PMessage::GetData(_) => Data,
@@ -525,11 +780,14 @@ impl From<Arc<P2PMessage>> for P2PMessageType {
 mod tests {
     use super::*;
     use crate::bitcoin::{BlockHeader, Hash, Outpoint, Script, Tx, TxInput, TxOutput};
-    use crate::p2p::messages::inv::{InvItem, InvType};
+    use crate::p2p::messages::inv::{InvItem, InvType, InventoryError};
+    use crate::p2p::messages::messages::commands::{ALERT, BLOCKTXN, CMPCTBLOCK, GETBLOCKTXN};
     use crate::p2p::messages::reject::REJECT_INVALID;
+    use crate::p2p::messages::ChecksumPolicy;
     use crate::p2p::messages::NodeAddr;
+    use crate::p2p::messages::Services;
     use crate::p2p::params::PROTOCOL_VERSION;
-    use crate::util::epoch_secs;
+    use crate::util::{epoch_secs, FeeRate};
     use hex::FromHex;
     use std::io::Cursor;
     use std::net::{IpAddr, Ipv6Addr};
@@ -544,7 +802,7 @@ mod tests {
         let mut v = Vec::new();
         let a = NodeAddr {
             timestamp: 700,
-            services: 900,
+            services: Services(900),
             ip: IpAddr::from(Ipv6Addr::from([
                 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 9, 8, 7, 6, 5,
             ])),
@@ -565,10 +823,10 @@ mod tests {
         let p = Block {
             header: BlockHeader {
                 version: 0x00000001,
-                prev_hash: Hash::from(
+                prev_hash: Hash::from_hex_unwrap(
                     "abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234",
                 ),
-                merkle_root: Hash::from(
+                merkle_root: Hash::from_hex_unwrap(
                     "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
                 ),
                 timestamp: 0x4dd7f5c7,
@@ -580,7 +838,7 @@ mod tests {
                     version: 0x44556677,
                     inputs: vec![TxInput {
                         outpoint: Outpoint {
-                            tx_hash: Hash::from(
+                            tx_hash: Hash::from_hex_unwrap(
                                 "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
                             ),
                             index: 3,
@@ -598,7 +856,7 @@ mod tests {
                     version: 0x99881122,
                     inputs: vec![TxInput {
                         outpoint: Outpoint {
-                            tx_hash: Hash::from(
+                            tx_hash: Hash::from_hex_unwrap(
                                 "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
                             ),
                             index: 4,
@@ -639,10 +897,14 @@ mod tests {
         let p = BlockLocator {
             version: 567,
             block_locator_hashes: vec![
-                Hash::from("2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3"),
-                Hash::from("abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234"),
+                Hash::from_hex_unwrap(
+                    "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
+                ),
+                Hash::from_hex_unwrap(
+                    "abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234",
+                ),
             ],
-            hash_stop: Hash::from(
+            hash_stop: Hash::from_hex_unwrap(
                 "0b5a8ca2ce1e9a761ae41fa7dcf93973c81851b38e20a7e8f3756f02cdc8e66f",
             ),
         };
@@ -660,7 +922,7 @@ mod tests {
         let p = Inv {
             objects: vec![InvItem {
                 obj_type: InvType::Tx,
-                hash: Hash::from(
+                hash: Hash::from_hex_unwrap(
                     "0b5a8ca2ce1e9a761ae41fa7dcf93973c81851b38e20a7e8f3756f02cdc8e66f",
                 ),
             }],
@@ -679,10 +941,14 @@ mod tests {
         let p = BlockLocator {
             version: 345,
             block_locator_hashes: vec![
-                Hash::from("2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3"),
-                Hash::from("abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234"),
+                Hash::from_hex_unwrap(
+                    "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
+                ),
+                Hash::from_hex_unwrap(
+                    "abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234",
+                ),
             ],
-            hash_stop: Hash::from(
+            hash_stop: Hash::from_hex_unwrap(
                 "0b5a8ca2ce1e9a761ae41fa7dcf93973c81851b38e20a7e8f3756f02cdc8e66f",
             ),
         };
@@ -715,7 +981,7 @@ mod tests {
         let p = Inv {
             objects: vec![InvItem {
                 obj_type: InvType::Tx,
-                hash: Hash::from(
+                hash: Hash::from_hex_unwrap(
                     "00000000000000000538178e5c48e51e271e009c31d3854886d29328fa0aa037",
                 ),
             }],
@@ -759,8 +1025,12 @@ mod tests {
             },
             total_transactions: 14,
             hashes: vec![
-                Hash::from("2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3"),
-                Hash::from("abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234"),
+                Hash::from_hex_unwrap(
+                    "2b12fcf1b09288fcaff797d71e950e71ae42b91e8bdb2304758dfcffc2b620e3",
+                ),
+                Hash::from_hex_unwrap(
+                    "abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234abcd1234",
+                ),
             ],
             flags: vec![24, 125, 199],
         };
@@ -778,7 +1048,7 @@ mod tests {
         let p = Inv {
             objects: vec![InvItem {
                 obj_type: InvType::Tx,
-                hash: Hash::from(
+                hash: Hash::from_hex_unwrap(
                     "0b5a8ca2ce1e9a761ae41fa7dcf93973c81851b38e20a7e8f3756f02cdc8e66f",
                 ),
             }],
@@ -792,6 +1062,17 @@ mod tests {
             m
         );
 
+        // FeeFilter
+        let mut v = Vec::new();
+        let m = P2PMessage::FeeFilter(FeeFilter::new(FeeRate::from_sats_per_kb(500)));
+        m.write(&mut v, &config).await.unwrap();
+        assert_eq!(
+            P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap(),
+            m
+        );
+
         // Ping
         let mut v = Vec::new();
         let p = Ping { nonce: 7890 };
@@ -816,6 +1097,17 @@ mod tests {
             m
         );
 
+        // Protoconf
+        let mut v = Vec::new();
+        let m = P2PMessage::Protoconf(Protoconf::new(4_000_000));
+        m.write(&mut v, &config).await.unwrap();
+        assert_eq!(
+            P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap(),
+            m
+        );
+
         // Reject
         let mut v = Vec::new();
         let p = Reject {
@@ -865,7 +1157,7 @@ mod tests {
             version: 0x44556677,
             inputs: vec![TxInput {
                 outpoint: Outpoint {
-                    tx_hash: Hash::from(
+                    tx_hash: Hash::from_hex_unwrap(
                         "0b5a8ca2ce1e9a761ae41fa7dcf93973c81851b38e20a7e8f3756f02cdc8e66f",
                     ),
                     index: 3,
@@ -903,7 +1195,7 @@ mod tests {
         let mut v = Vec::new();
         let p = Version {
             version: PROTOCOL_VERSION,
-            services: 77,
+            services: Services(77),
             timestamp: epoch_secs(),
             recv_addr: NodeAddr {
                 ..Default::default()
@@ -926,6 +1218,324 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn inventory_errors_name_the_command() {
+        let config = ChannelConfig::default();
+        let inv = Inv {
+            objects: vec![InvItem::tx(Hash::ZERO), InvItem::block(Hash::ZERO)],
+        };
+        let messages = [
+            ("inv", P2PMessage::Inv(inv.clone())),
+            ("getdata", P2PMessage::GetData(inv.clone())),
+            ("notfound", P2PMessage::NotFound(inv)),
+        ];
+        for (name, m) in messages {
+            let mut v = Vec::new();
+            m.write(&mut v, &config).await.unwrap();
+            assert_eq!(
+                P2PMessage::read(&mut Cursor::new(&v), &config)
+                    .await
+                    .unwrap(),
+                m
+            );
+            // give the second entry an unknown type
+            v[24 + 1 + InvItem::SIZE] = 9;
+            match P2PMessage::read(&mut Cursor::new(&v), &config).await {
+                Err(Error::InvalidInventory { command, error }) => {
+                    assert_eq!(command, name);
+                    assert_eq!(
+                        error,
+                        InventoryError::UnknownType {
+                            index: 1,
+                            obj_type: 9
+                        }
+                    );
+                }
+                r => panic!("{}: unexpected result {:?}", name, r),
+            }
+        }
+    }
+
+    /// The position of the variant in the enum. The match has no wildcard, so adding a variant does
+    /// not compile until it is added here, and then the `commands` test fails until it has a sample.
+    fn variant(m: &P2PMessage) -> usize {
+        match m {
+            P2PMessage::Addr(_) => 0,
+            P2PMessage::Block(_) => 1,
+            P2PMessage::FeeFilter(_) => 2,
+            P2PMessage::GetAddr => 3,
+            P2PMessage::GetBlocks(_) => 4,
+            P2PMessage::GetData(_) => 5,
+            P2PMessage::GetHeaders(_) => 6,
+            P2PMessage::Headers(_) => 7,
+            P2PMessage::Inv(_) => 8,
+            P2PMessage::Mempool => 9,
+            P2PMessage::MerkleBlock(_) => 10,
+            P2PMessage::NotFound(_) => 11,
+            P2PMessage::Ping(_) => 12,
+            P2PMessage::Pong(_) => 13,
+            P2PMessage::Protoconf(_) => 14,
+            P2PMessage::Reject(_) => 15,
+            P2PMessage::SendHeaders => 16,
+            P2PMessage::SendCmpct(_) => 17,
+            P2PMessage::Tx(_) => 18,
+            P2PMessage::Verack => 19,
+            P2PMessage::Version(_) => 20,
+            P2PMessage::Unknown(_, _) => 21,
+        }
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let config = ChannelConfig::default();
+        let header = BlockHeader::from_binary_buf(&Vec::from_hex("00405324d8facaf19ce3efc5f6b3fbdc1cb1f5369a56c3de3e50280300000000000000002742bdb5930e5bf24be6e7521ceeecf6d3199871e2a6438f54cb5fd95d3f5139a38d90653c5808186eac9b4c").unwrap()).unwrap();
+        let hash = Hash::sha256d(b"commands");
+        let inv = Inv {
+            objects: vec![InvItem::tx(hash)],
+        };
+        let locator = BlockLocator {
+            version: PROTOCOL_VERSION,
+            block_locator_hashes: vec![hash],
+            hash_stop: Hash::ZERO,
+        };
+        let tx = Tx {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![TxOutput::new(5, Script::from(vec![0x51]))],
+            lock_time: 0,
+        };
+        let samples = vec![
+            P2PMessage::Addr(Addr {
+                addrs: vec![NodeAddr::default()],
+            }),
+            P2PMessage::Block(Block {
+                header: header.clone(),
+                transactions: vec![tx.clone()],
+            }),
+            P2PMessage::FeeFilter(FeeFilter::new(FeeRate::from_sats_per_kb(1))),
+            P2PMessage::GetAddr,
+            P2PMessage::GetBlocks(locator.clone()),
+            P2PMessage::GetData(inv.clone()),
+            P2PMessage::GetHeaders(locator),
+            P2PMessage::Headers(Headers {
+                headers: vec![header.clone()],
+            }),
+            P2PMessage::Inv(inv.clone()),
+            P2PMessage::Mempool,
+            P2PMessage::MerkleBlock(MerkleBlock {
+                header,
+                total_transactions: 1,
+                hashes: vec![hash],
+                flags: vec![1],
+            }),
+            P2PMessage::NotFound(inv),
+            P2PMessage::Ping(Ping::new(1)),
+            P2PMessage::Pong(Ping::new(2)),
+            P2PMessage::Protoconf(Protoconf::new(2_000_000)),
+            P2PMessage::Reject(Reject {
+                message: "tx".to_string(),
+                code: REJECT_INVALID,
+                reason: "bad".to_string(),
+                data: hash.hash.to_vec(),
+            }),
+            P2PMessage::SendHeaders,
+            P2PMessage::SendCmpct(SendCmpct {
+                enable: 0,
+                version: 1,
+            }),
+            P2PMessage::Tx(tx),
+            P2PMessage::Verack,
+            P2PMessage::Version(Version::default()),
+        ];
+        // every variant except Unknown, which can not be written, has a sample
+        let mut variants: Vec<usize> = samples.iter().map(variant).collect();
+        variants.sort();
+        variants.dedup();
+        assert_eq!(variants, (0..21).collect::<Vec<_>>());
+
+        for m in samples {
+            let mut v = Vec::new();
+            m.write(&mut v, &config).await.unwrap();
+            // the command in the header is the one reported by the message
+            let command = std::str::from_utf8(&v[4..16]).unwrap();
+            assert_eq!(command.trim_end_matches('\0'), m.command());
+            assert_eq!(m.size(), v.len() - 24, "{}", m.command());
+            let read = P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap();
+            assert_eq!(read, m);
+        }
+
+        // the commands that have constants but are not supported
+        for command in [ALERT, BLOCKTXN, CMPCTBLOCK, GETBLOCKTXN] {
+            let mut v = Vec::new();
+            let header = P2PMessageHeader {
+                magic: config.magic,
+                command,
+                payload_size: 0,
+                checksum: NO_CHECKSUM,
+            };
+            header.async_to_binary(&mut v).await.unwrap();
+            let read = P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap();
+            assert!(matches!(read, P2PMessage::Unknown(_, 0)), "{:?}", read);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_unknown() {
+        let config = ChannelConfig::default();
+        // the command is not valid UTF-8 and the payload is discarded
+        for command in [*b"unknowncmd\0\0", [0xff; 12]] {
+            let header = P2PMessageHeader {
+                magic: config.magic,
+                command,
+                payload_size: 5,
+                checksum: Hash::sha256d(&[1, 2, 3, 4, 5]).hash[..4]
+                    .try_into()
+                    .unwrap(),
+            };
+            let mut v = header.to_binary_buf().unwrap();
+            v.extend_from_slice(&[1, 2, 3, 4, 5]);
+            v.extend(P2PMessageHeader::default().to_binary_buf().unwrap());
+            let mut cursor = Cursor::new(&v);
+            let m = P2PMessage::read(&mut cursor, &config).await.unwrap();
+            assert!(matches!(m, P2PMessage::Unknown(_, 5)));
+            assert_eq!(cursor.position(), 29);
+            // a short payload is an error
+            let mut cursor = Cursor::new(&v[..27]);
+            assert!(P2PMessage::read(&mut cursor, &config).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn checksum_policy() {
+        let config = ChannelConfig::default();
+        let ping = P2PMessage::Ping(Ping::new(5));
+        let mut v = Vec::new();
+        ping.write(&mut v, &config).await.unwrap();
+        assert_ne!(v[20..24], ZERO_CHECKSUM);
+        assert_eq!(
+            P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .unwrap(),
+            ping
+        );
+        // a corrupted payload is detected
+        v[24] ^= 1;
+        assert!(matches!(
+            P2PMessage::read(&mut Cursor::new(&v), &config).await,
+            Err(Error::ChecksumMismatch)
+        ));
+        let always = ChannelConfig {
+            checksum_policy: ChecksumPolicy::AlwaysVerify,
+            ..config.clone()
+        };
+        assert!(P2PMessage::read(&mut Cursor::new(&v), &always)
+            .await
+            .is_err());
+
+        // above the limit, a zero checksum is sent and the checksum is not verified
+        let skip = ChannelConfig {
+            checksum_policy: ChecksumPolicy::SkipAbove(4),
+            ..config.clone()
+        };
+        assert!(P2PMessage::read(&mut Cursor::new(&v), &skip).await.is_ok());
+        let mut v = Vec::new();
+        ping.write(&mut v, &skip).await.unwrap();
+        assert_eq!(v[20..24], ZERO_CHECKSUM);
+        assert_eq!(
+            P2PMessage::read(&mut Cursor::new(&v), &skip).await.unwrap(),
+            ping
+        );
+        assert!(P2PMessage::read(&mut Cursor::new(&v), &config)
+            .await
+            .is_err());
+        // messages without a payload are unaffected
+        let mut v = Vec::new();
+        P2PMessage::Verack.write(&mut v, &skip).await.unwrap();
+        assert_eq!(v[20..24], NO_CHECKSUM);
+        P2PMessage::read(&mut Cursor::new(&v), &always)
+            .await
+            .unwrap();
+    }
+
+    /// Records the largest single write.
+    #[derive(Default)]
+    struct MaxWriteRecorder {
+        max_write: usize,
+        total: usize,
+    }
+
+    impl AsyncWrite for MaxWriteRecorder {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.max_write = self.max_write.max(buf.len());
+            self.total += buf.len();
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn skipped_checksum_streams_payload() {
+        let bin = std::fs::read(
+            "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin",
+        )
+        .unwrap();
+        let block = P2PMessage::Block(Block::from_binary_buf(&bin).unwrap());
+        let mut buffered = MaxWriteRecorder::default();
+        block
+            .write(&mut buffered, &ChannelConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(buffered.max_write, bin.len());
+        let skip = ChannelConfig {
+            checksum_policy: ChecksumPolicy::SkipAbove(1000),
+            ..ChannelConfig::default()
+        };
+        let mut streamed = MaxWriteRecorder::default();
+        block.write(&mut streamed, &skip).await.unwrap();
+        assert_eq!(streamed.total, buffered.total);
+        // the payload was never assembled in a single buffer
+        assert!(streamed.max_write < bin.len() / 2);
+    }
+
+    #[tokio::test]
+    async fn read_oversized_counts() {
+        let config = ChannelConfig::default();
+        // counts that are much larger than the payload must not be trusted
+        for (command, payload) in [
+            (GETHEADERS, "0100000000ffffffffffffffff"),
+            (INV, "ffffffffffffffffff"),
+            (VERSION, "7f11010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ffffffffffffffffff"),
+        ] {
+            let payload = Vec::from_hex(payload).unwrap();
+            let header = P2PMessageHeader {
+                magic: config.magic,
+                command,
+                payload_size: payload.len() as u64,
+                checksum: ZERO_CHECKSUM,
+            };
+            let mut v = header.to_binary_buf().unwrap();
+            v.extend(payload);
+            assert!(P2PMessage::read(&mut Cursor::new(&v), &config)
+                .await
+                .is_err());
+        }
+    }
+
     // #[test]
     // #[should_panic]
     // fn write_other_errors() {
@@ -954,4 +1564,92 @@ mod tests {
     //         assert!(false);
     //     }
     // }
+
+    #[tokio::test]
+    async fn read_resync() {
+        let mut config = ChannelConfig::default();
+        let mut v = vec![0x01, 0x02, config.magic[0]];
+        P2PMessage::Ping(Ping::new(5))
+            .write(&mut v, &config)
+            .await
+            .unwrap();
+        // fails without resync
+        assert!(P2PMessage::read(&mut Cursor::new(&v), &config)
+            .await
+            .is_err());
+        config.magic_resync = true;
+        let before = crate::p2p::telemetry::error_counts().protocol_anomaly;
+        let m = P2PMessage::read(&mut Cursor::new(&v), &config)
+            .await
+            .unwrap();
+        assert_eq!(m, P2PMessage::Ping(Ping::new(5)));
+        assert!(crate::p2p::telemetry::error_counts().protocol_anomaly > before);
+        // the scan is bounded
+        let mut v = vec![0; P2PMessageHeader::MAX_RESYNC_BYTES + 1];
+        P2PMessage::Verack.write(&mut v, &config).await.unwrap();
+        assert!(P2PMessage::read(&mut Cursor::new(&v), &config)
+            .await
+            .is_err());
+        let mut v = vec![0; P2PMessageHeader::MAX_RESYNC_BYTES];
+        P2PMessage::Verack.write(&mut v, &config).await.unwrap();
+        let m = P2PMessage::read(&mut Cursor::new(&v), &config)
+            .await
+            .unwrap();
+        assert_eq!(m, P2PMessage::Verack);
+    }
+
+    #[test]
+    fn split_for_peer() {
+        let items: Vec<InvItem> = (0..10u8)
+            .map(|i| InvItem::tx(Hash::sha256d(&[i])))
+            .collect();
+        // exactly at the limit the message is not split
+        let max = (1 + 10 * InvItem::SIZE) as u64;
+        let inv = P2PMessage::Inv(Inv {
+            objects: items.clone(),
+        });
+        assert_eq!(inv.clone().split_for_peer(max).unwrap(), vec![inv.clone()]);
+        // one byte less and the last item moves to a second message
+        let split = inv.clone().split_for_peer(max - 1).unwrap();
+        let sizes: Vec<usize> = split.iter().map(|m| m.size()).collect();
+        assert_eq!(sizes, vec![1 + 9 * InvItem::SIZE, 1 + InvItem::SIZE]);
+        let rejoined: Vec<InvItem> = split
+            .into_iter()
+            .flat_map(|m| match m {
+                P2PMessage::Inv(inv) => inv.objects,
+                m => panic!("unexpected message {:?}", m),
+            })
+            .collect();
+        assert_eq!(rejoined, items);
+        // the item limit of the command also applies
+        let many = P2PMessage::GetData(Inv {
+            objects: vec![items[0].clone(); Inv::MAX_INV_ENTRIES as usize + 1],
+        });
+        assert_eq!(many.split_for_peer(u64::MAX).unwrap().len(), 2);
+        // not even one item fits
+        assert!(inv.split_for_peer(InvItem::SIZE as u64).is_err());
+
+        // a transaction can not be split
+        let tx = Tx {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![TxOutput {
+                value: 1,
+                script: Script::from(vec![0; 5_000]),
+            }],
+            lock_time: 0,
+        };
+        let size = tx.async_size() as u64;
+        match P2PMessage::Tx(tx).split_for_peer(4_000) {
+            Err(Error::MessageTooLargeForPeer {
+                command,
+                size: s,
+                max,
+            }) => {
+                assert_eq!(command, "tx");
+                assert_eq!((s, max), (size, 4_000));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
 }
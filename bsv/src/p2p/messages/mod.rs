@@ -1,6 +1,8 @@
 mod addr;
-mod block;
 mod block_locator;
+mod block_stream;
+mod fee_filter;
+mod framer;
 mod headers;
 mod inv;
 mod merkle_block;
@@ -11,13 +13,35 @@ mod ping;
 mod protoconf;
 mod reject;
 mod send_cmpct;
+mod services;
 mod version;
 
+/// The maximum number of items to allocate space for before they have been read.
+///
+/// Counts in messages are received from the peer and can not be trusted, collections will grow if
+/// the items are actually present.
+const MAX_PREALLOCATE: usize = 1024;
+
 // the individual P2P messages
+pub use addr::Addr;
+pub use block_locator::BlockLocator;
+pub use block_stream::BlockStream;
+pub use fee_filter::FeeFilter;
+pub use headers::Headers;
+pub use inv::{Inv, InvItem, InvType, InventoryError};
+pub use merkle_block::MerkleBlock;
 pub use node_addr::NodeAddr;
 pub use ping::Ping;
 pub use protoconf::Protoconf;
-pub use version::Version;
+pub use reject::{
+    Reject, REJECT_CHECKPOINT, REJECT_DUPLICATE, REJECT_DUST, REJECT_INSUFFICIENT_FEE,
+    REJECT_INVALID, REJECT_MALFORMED, REJECT_NONSTANDARD, REJECT_OBSOLETE,
+};
+pub use send_cmpct::SendCmpct;
+pub use services::Services;
+pub use version::{Version, NODE_NONE};
 
 // P2P message
+pub use framer::MessageFramer;
 pub use messages::{P2PMessage, P2PMessageType};
+pub use msg_header::{max_size_for_command, ChecksumPolicy};
@@ -1,10 +1,16 @@
-use crate::bitcoin::AsyncEncodable;
+use crate::bitcoin::{varint_size, AsyncEncodable};
 use crate::p2p::channel::ChannelConfig;
-use crate::p2p::messages::messages::commands::{BLOCK, EXTMSG};
-use crate::p2p::messages::messages::PROTOCONF;
+use crate::p2p::messages::messages::commands::{
+    ADDR, BLOCK, EXTMSG, FEEFILTER, GETADDR, GETBLOCKS, GETDATA, GETHEADERS, HEADERS, INV, MEMPOOL,
+    NOTFOUND, PING, PONG, REJECT, SENDCMPCT, SENDHEADERS, TX, VERACK, VERSION,
+};
+use crate::p2p::messages::messages::{PROTOCONF, ZERO_CHECKSUM};
 use crate::p2p::messages::protoconf::MAX_PROTOCONF_SIZE;
+use crate::p2p::messages::{Addr, BlockLocator, Headers, Inv, InvItem, NodeAddr, Reject, Version};
+use crate::p2p::params::MAX_TX_SIZE;
 use crate::{Error, Result};
 use async_trait::async_trait;
+use serde::Deserialize;
 use std::fmt;
 use std::str;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -32,63 +38,56 @@ impl P2PMessageHeader {
     pub const STANDARD_SIZE: usize = 24;
     /// Size of the extended message header in bytes
     pub const EXTENDED_SIZE: usize = 44;
+    /// The maximum number of bytes skipped when scanning for the magic bytes.
+    pub const MAX_RESYNC_BYTES: usize = 1024;
 
     /// Returns true if the header is in extended format.
     pub fn is_extended(&self) -> bool {
         self.payload_size >= 0xffffffff && self.command == BLOCK
     }
 
-    /// Checks if the header is valid
+    /// Returns the command as a string, for logging.
     ///
-    /// `magic` - Expected magic bytes for the network
-    /// `max_size` - Max size in bytes for the payload
-    pub fn validate(&self, config: &ChannelConfig) -> Result<()> {
-        if self.magic != config.magic {
-            // todo: ban
-            let msg = format!(
-                "Bad magic: {:02x},{:02x},{:02x},{:02x}",
-                self.magic[0], self.magic[1], self.magic[2], self.magic[3]
-            );
-            return Err(Error::BadData(msg));
-        }
-        if self.command == PROTOCONF {
-            // strange exception for protoconf messages
-            return if self.payload_size > MAX_PROTOCONF_SIZE {
-                // todo: ban score
-                let msg = format!("Bad size for protoconf message: {:?}", self.payload_size);
-                Err(Error::BadData(msg))
-            } else {
-                Ok(())
-            };
-        }
-        if self.command == BLOCK {
-            // normal payload size limit does not apply to block messages
-            return if self.payload_size > config.excessive_block_size {
-                // todo: ban score
-                let msg = format!("Bad size for block message: {:?}", self.payload_size);
-                Err(Error::BadData(msg))
-            } else {
-                Ok(())
-            };
-        }
-        if self.payload_size > config.max_recv_payload_size {
-            // todo: ban score
-            let msg = format!("Bad size: {:?}", self.payload_size);
-            return Err(Error::BadData(msg));
-        }
-        Ok(())
+    /// The command is received from the peer and may not be valid UTF-8, invalid sequences are replaced.
+    pub fn command_str(&self) -> String {
+        String::from_utf8_lossy(&self.command).into_owned()
     }
-}
 
-#[async_trait]
-impl AsyncEncodable for P2PMessageHeader {
-    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> Result<Self>
-    where
-        Self: Sized,
-    {
-        // read standard header
-        let mut magic = vec![0u8; 4];
+    /// Read the magic bytes that start a message and check them against the expected magic.
+    ///
+    /// This is checked before the rest of the header is read. If the magic does not match and `resync` is
+    /// true then the stream is scanned forward a byte at a time, up to [MAX_RESYNC_BYTES](Self::MAX_RESYNC_BYTES),
+    /// until the magic is found. Otherwise an error is returned. On success the reader is positioned after
+    /// the magic and the number of bytes skipped is returned.
+    pub async fn read_magic<R: AsyncRead + Unpin + Send>(
+        reader: &mut R,
+        expected: &[u8; 4],
+        resync: bool,
+    ) -> Result<usize> {
+        let mut magic = [0u8; 4];
         reader.read_exact(&mut magic).await?;
+        let mut skipped = 0;
+        while magic != *expected {
+            if !resync || skipped >= P2PMessageHeader::MAX_RESYNC_BYTES {
+                let msg = format!(
+                    "Bad magic: {:02x},{:02x},{:02x},{:02x}, skipped {} bytes",
+                    magic[0], magic[1], magic[2], magic[3], skipped
+                );
+                return Err(Error::BadData(msg));
+            }
+            magic.rotate_left(1);
+            magic[3] = reader.read_u8().await?;
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+
+    /// Read the remainder of the header, after the magic bytes.
+    pub(crate) async fn read_after_magic<R: AsyncRead + Unpin + Send>(
+        reader: &mut R,
+        magic: [u8; 4],
+    ) -> Result<P2PMessageHeader> {
+        // read the rest of the standard header
         let mut command = vec![0u8; 12];
         reader.read_exact(&mut command).await?;
         let mut payload_size: u64 = reader.read_u32_le().await? as u64;
@@ -110,13 +109,129 @@ impl AsyncEncodable for P2PMessageHeader {
             }
         }
         Ok(P2PMessageHeader {
-            magic: magic.try_into().unwrap(),
+            magic,
             command: command.try_into().unwrap(),
             payload_size,
             checksum: checksum.try_into().unwrap(),
         })
     }
 
+    /// Checks if the header is valid
+    ///
+    /// The magic bytes must be those of the network and the payload must be no larger than
+    /// [max_size_for_command()] allows for the command.
+    pub fn validate(&self, config: &ChannelConfig) -> Result<()> {
+        if self.magic != config.magic {
+            // todo: ban
+            let msg = format!(
+                "Bad magic: {:02x},{:02x},{:02x},{:02x}",
+                self.magic[0], self.magic[1], self.magic[2], self.magic[3]
+            );
+            return Err(Error::BadData(msg));
+        }
+        let max = max_size_for_command(&self.command, config);
+        if self.payload_size > max {
+            return Err(Error::OversizedMessage {
+                command: self.command_str().trim_end_matches('\0').to_string(),
+                size: self.payload_size,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The largest payload that a peer may send with the command, in bytes.
+///
+/// The messages with a fixed layout, or a limit on their number of entries, are bounded by the
+/// largest valid encoding, whatever has been negotiated. Only the `tx` message and unknown commands
+/// are governed by the `max_recv_payload_size` that we announce in our protoconf, and the `block`
+/// message by the `excessive_block_size`.
+pub fn max_size_for_command(command: &[u8; 12], config: &ChannelConfig) -> u64 {
+    let hashes = |n: u64| (varint_size(n) + n as usize * 32) as u64;
+    match *command {
+        VERACK | GETADDR | MEMPOOL | SENDHEADERS => 0,
+        PING | PONG | FEEFILTER => 8,
+        SENDCMPCT => 9,
+        PROTOCONF => MAX_PROTOCONF_SIZE,
+        // version, services, timestamp, two addresses, nonce, user agent, start height and relay
+        VERSION => {
+            let user_agent = Version::MAX_USER_AGENT_SIZE;
+            4 + 8 + 8 + 26 + 26 + 8 + varint_size(user_agent) as u64 + user_agent + 4 + 1
+        }
+        ADDR => {
+            (varint_size(Addr::MAX_ADDR_COUNT) + Addr::MAX_ADDR_COUNT as usize * NodeAddr::SIZE)
+                as u64
+        }
+        INV | GETDATA | NOTFOUND => {
+            let n = Inv::MAX_INV_ENTRIES;
+            (varint_size(n) + n as usize * InvItem::SIZE) as u64
+        }
+        GETBLOCKS | GETHEADERS => 4 + hashes(BlockLocator::MAX_LOCATOR_HASHES) + 32,
+        // each header is followed by an empty transaction count
+        HEADERS => (varint_size(Headers::MAX_HEADERS) + Headers::MAX_HEADERS as usize * 81) as u64,
+        REJECT => {
+            2 * (varint_size(Reject::MAX_STRING_SIZE) as u64 + Reject::MAX_STRING_SIZE) + 1 + 32
+        }
+        BLOCK => config.excessive_block_size,
+        TX => config.max_recv_payload_size.min(MAX_TX_SIZE),
+        _ => config.max_recv_payload_size,
+    }
+}
+
+/// When the checksum of a message payload is calculated on send and verified on receipt.
+///
+/// The checksum is the first 4 bytes of the double SHA256 of the payload, which for a large block
+/// costs as much as hashing all of its transactions. Extended format messages, described in
+/// [P2P Large Message Support](https://github.com/bitcoin-sv-specs/protocol/blob/master/p2p/large_messages.md),
+/// always carry a zero checksum. When the checksum of a payload is skipped, a zero checksum is sent
+/// and the received checksum is ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumPolicy {
+    /// Calculate and verify the checksum of every standard format message, and verify the checksum
+    /// of a received extended format message if it is not zero.
+    AlwaysVerify,
+    /// Skip the checksum of payloads larger than this number of bytes and of extended format messages.
+    SkipAbove(u64),
+    /// Skip the checksum of extended format messages only.
+    #[default]
+    NeverForExtended,
+}
+
+impl ChecksumPolicy {
+    /// Whether the checksum is calculated when sending a standard format message with a payload of
+    /// `payload_size` bytes. Extended format messages are always sent with a zero checksum.
+    pub fn on_send(&self, payload_size: u64) -> bool {
+        match self {
+            ChecksumPolicy::SkipAbove(max) => payload_size <= *max,
+            _ => true,
+        }
+    }
+
+    /// Whether the checksum of a message with the header is verified when it is received.
+    pub fn on_receive(&self, header: &P2PMessageHeader) -> bool {
+        match self {
+            _ if header.is_extended() => {
+                *self == ChecksumPolicy::AlwaysVerify && header.checksum != ZERO_CHECKSUM
+            }
+            ChecksumPolicy::SkipAbove(max) => header.payload_size <= *max,
+            _ => true,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncEncodable for P2PMessageHeader {
+    async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).await?;
+        P2PMessageHeader::read_after_magic(reader, magic).await
+    }
+
     async fn async_to_binary<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
         // do we need to write an extended header?
         if self.is_extended() {
@@ -194,7 +309,7 @@ mod tests {
         let h = P2PMessageHeader {
             magic,
             command: *b"verack\0\0\0\0\0\0",
-            payload_size: 88,
+            payload_size: 0,
             checksum: [0x12, 0x34, 0x56, 0x78],
         };
         let mut config = ChannelConfig::default();
@@ -209,4 +324,87 @@ mod tests {
         // Bad size
         assert!(h.validate(&bad_config).is_err());
     }
+
+    #[test]
+    fn command_limits() {
+        let config = ChannelConfig::default();
+        let header = |command: [u8; 12], payload_size: u64| P2PMessageHeader {
+            magic: config.magic,
+            command,
+            payload_size,
+            checksum: ZERO_CHECKSUM,
+        };
+        match header(PING, 1024).validate(&config) {
+            Err(Error::OversizedMessage { command, size, max }) => {
+                assert_eq!(command, "ping");
+                assert_eq!((size, max), (1024, 8));
+            }
+            r => panic!("expected an oversized message, got {:?}", r),
+        }
+        assert!(header(GETHEADERS, 1024).validate(&config).is_ok());
+        assert!(header(VERACK, 0).validate(&config).is_ok());
+        assert!(header(VERACK, 1).validate(&config).is_err());
+        let inv = max_size_for_command(&INV, &config);
+        assert!(header(INV, inv).validate(&config).is_ok());
+        assert!(header(INV, inv + 1).validate(&config).is_err());
+
+        // a larger protoconf limit applies to transactions and unknown commands only
+        let mut raised = config.clone();
+        raised.max_recv_payload_size = config.max_recv_payload_size * 2;
+        let unknown = *b"unknown\0\0\0\0\0";
+        for command in [TX, unknown] {
+            assert!(
+                max_size_for_command(&command, &raised) > max_size_for_command(&command, &config)
+            );
+        }
+        for command in [PING, VERSION, ADDR, INV, GETHEADERS, HEADERS, REJECT, BLOCK] {
+            assert_eq!(
+                max_size_for_command(&command, &raised),
+                max_size_for_command(&command, &config)
+            );
+        }
+        // transactions are never larger than the consensus limit
+        raised.max_recv_payload_size = u32::MAX as u64;
+        assert_eq!(max_size_for_command(&TX, &raised), MAX_TX_SIZE);
+    }
+
+    #[test]
+    fn checksum_policy() {
+        let small = P2PMessageHeader {
+            command: BLOCK,
+            payload_size: 1000,
+            checksum: ZERO_CHECKSUM,
+            ..Default::default()
+        };
+        let extended = P2PMessageHeader {
+            payload_size: 0x1_0000_0000,
+            ..small.clone()
+        };
+        let signed_extended = P2PMessageHeader {
+            checksum: [1, 2, 3, 4],
+            ..extended.clone()
+        };
+        let always = ChecksumPolicy::AlwaysVerify;
+        assert!(always.on_send(u32::MAX as u64));
+        assert!(always.on_receive(&small));
+        assert!(!always.on_receive(&extended));
+        assert!(always.on_receive(&signed_extended));
+
+        let skip = ChecksumPolicy::SkipAbove(1000);
+        assert!(skip.on_send(1000));
+        assert!(!skip.on_send(1001));
+        assert!(skip.on_receive(&small));
+        let large = P2PMessageHeader {
+            payload_size: 1001,
+            ..small.clone()
+        };
+        assert!(!skip.on_receive(&large));
+        assert!(!skip.on_receive(&signed_extended));
+
+        let default = ChecksumPolicy::default();
+        assert_eq!(default, ChecksumPolicy::NeverForExtended);
+        assert!(default.on_send(u32::MAX as u64));
+        assert!(default.on_receive(&large));
+        assert!(!default.on_receive(&signed_extended));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::bitcoin::AsyncEncodable;
+use crate::p2p::messages::Services;
 use crate::util::epoch_secs_u32;
 use async_trait::async_trait;
 use std::fmt;
@@ -15,7 +16,7 @@ pub struct NodeAddr {
     /// Timestamp of the address
     pub timestamp: u32,
     /// Services flags for the node
-    pub services: u64,
+    pub services: Services,
     /// IP address for the node
     pub ip: IpAddr,
     /// Port for Bitcoin P2P communication
@@ -30,7 +31,7 @@ impl NodeAddr {
     pub fn new(ip: IpAddr, port: u16) -> NodeAddr {
         NodeAddr {
             timestamp: epoch_secs_u32(),
-            services: 0,
+            services: Services::NONE,
             ip,
             port,
         }
@@ -41,7 +42,7 @@ impl Default for NodeAddr {
     fn default() -> NodeAddr {
         NodeAddr {
             timestamp: epoch_secs_u32(),
-            services: 0,
+            services: Services::NONE,
             ip: IpAddr::from([0; 16]),
             port: 0,
         }
@@ -55,7 +56,7 @@ impl AsyncEncodable for NodeAddr {
         Self: Sized,
     {
         let timestamp = reader.read_u32_le().await?;
-        let services = reader.read_u64_le().await?;
+        let services = Services(reader.read_u64_le().await?);
         let mut ip_bin = [0u8; 16];
         reader.read_exact(&mut ip_bin).await?; // big endian order
         let ip;
@@ -81,7 +82,7 @@ impl AsyncEncodable for NodeAddr {
         writer: &mut W,
     ) -> crate::Result<()> {
         writer.write_u32_le(self.timestamp).await?;
-        writer.write_u64_le(self.services).await?;
+        writer.write_u64_le(self.services.0).await?;
         match self.ip {
             IpAddr::V4(v4) => {
                 writer
@@ -133,7 +134,7 @@ mod tests {
         .unwrap();
         let a = NodeAddr::from_binary_buf(b.as_slice()).unwrap();
         assert_eq!(a.timestamp, 1_704_625_247);
-        assert_eq!(a.services, 37);
+        assert_eq!(a.services, Services(37));
         assert_eq!(a.ip, "45.50.191.251".parse::<Ipv4Addr>().unwrap());
         assert_eq!(a.port, 56787);
     }
@@ -142,7 +143,7 @@ mod tests {
     fn write_read() {
         let a = NodeAddr {
             timestamp: 1_704_625_247,
-            services: 1,
+            services: Services::NETWORK,
             ip: IpAddr::from([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]),
             port: 123,
         };
@@ -150,4 +151,35 @@ mod tests {
         assert_eq!(v.len(), NodeAddr::SIZE);
         assert_eq!(NodeAddr::from_binary_buf(v.as_slice()).unwrap(), a);
     }
+
+    #[test]
+    fn ipv6() {
+        // a native address is written as is, and is not confused with a mapped IPv4 address
+        let native: IpAddr = "2001:db8:85a3::8a2e:370:7334".parse().unwrap();
+        let a = NodeAddr {
+            timestamp: 1_704_625_247,
+            services: Services::NETWORK,
+            ip: native,
+            port: 8333,
+        };
+        let v = a.to_binary_buf().unwrap();
+        // ip then port, both big endian
+        assert_eq!(
+            hex::encode(&v[12..]),
+            "20010db885a3000000008a2e03707334208d"
+        );
+        assert_eq!(NodeAddr::from_binary_buf(&v).unwrap(), a);
+        // a mapped address is written the same way as the IPv4 address, and read back as IPv4
+        let mapped = NodeAddr {
+            ip: "::ffff:45.50.191.251".parse().unwrap(),
+            ..a.clone()
+        };
+        let v4 = NodeAddr {
+            ip: "45.50.191.251".parse().unwrap(),
+            ..a
+        };
+        let v = mapped.to_binary_buf().unwrap();
+        assert_eq!(v, v4.to_binary_buf().unwrap());
+        assert_eq!(NodeAddr::from_binary_buf(&v).unwrap(), v4);
+    }
 }
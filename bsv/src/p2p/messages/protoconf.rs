@@ -1,4 +1,4 @@
-use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable};
+use crate::bitcoin::{varint_decode, varint_encode, varint_size, varstr_decode, AsyncEncodable};
 use async_trait::async_trait;
 use log::warn;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -65,11 +65,7 @@ impl AsyncEncodable for Protoconf {
             warn!("Protoconf has more than 2 entries, ignoring extra entries.");
         }
         let max_recv_payload_length = reader.read_u32_le().await?;
-        let string_size = varint_decode(reader).await?;
-        // todo: check size of string
-        let mut string_bytes = vec![0; string_size as usize];
-        reader.read_exact(&mut string_bytes).await?;
-        let stream_policies = String::from_utf8(string_bytes)?;
+        let stream_policies = varstr_decode(reader, MAX_PROTOCONF_SIZE).await?;
         Ok(Protoconf {
             max_recv_payload_length,
             stream_policies,
@@ -1,16 +1,11 @@
-use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable};
+use crate::bitcoin::{varint_encode, varint_size, varstr_decode, AsyncEncodable};
 use async_trait::async_trait;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-// Message rejection error codes
-pub const REJECT_MALFORMED: u8 = 0x01;
-pub const REJECT_INVALID: u8 = 0x10;
-pub const REJECT_OBSOLETE: u8 = 0x11;
-pub const REJECT_DUPLICATE: u8 = 0x12;
-pub const REJECT_NONSTANDARD: u8 = 0x40;
-pub const REJECT_DUST: u8 = 0x41;
-pub const REJECT_INSUFFICIENT_FEE: u8 = 0x42;
-pub const REJECT_CHECKPOINT: u8 = 0x43;
+pub use crate::bitcoin::{
+    REJECT_CHECKPOINT, REJECT_DUPLICATE, REJECT_DUST, REJECT_INSUFFICIENT_FEE, REJECT_INVALID,
+    REJECT_MALFORMED, REJECT_NONSTANDARD, REJECT_OBSOLETE,
+};
 
 /// Rejected message
 #[derive(Default, PartialEq, Eq, Hash, Clone, Debug)]
@@ -27,21 +22,20 @@ pub struct Reject {
     pub data: Vec<u8>,
 }
 
+impl Reject {
+    /// The maximum size of the message and reason strings that will be accepted.
+    pub const MAX_STRING_SIZE: u64 = 1_000;
+}
+
 #[async_trait]
 impl AsyncEncodable for Reject {
     async fn async_from_binary<R: AsyncRead + Unpin + Send>(reader: &mut R) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        let str_size = varint_decode(reader).await? as usize;
-        let mut str_bytes = vec![0; str_size];
-        reader.read_exact(&mut str_bytes).await?;
-        let message = String::from_utf8(str_bytes)?;
+        let message = varstr_decode(reader, Reject::MAX_STRING_SIZE).await?;
         let code = reader.read_u8().await?;
-        let reason_size = varint_decode(reader).await? as usize;
-        let mut reason_bytes = vec![0; reason_size];
-        reader.read_exact(&mut reason_bytes).await?;
-        let reason = String::from_utf8(reason_bytes)?;
+        let reason = varstr_decode(reader, Reject::MAX_STRING_SIZE).await?;
         let mut data = vec![];
         if message == *"block" || message == *"tx" {
             data = vec![0_u8; 32];
@@ -14,6 +14,23 @@ pub struct SendCmpct {
 impl SendCmpct {
     /// Size of the SendCmpct payload in bytes
     pub const SIZE: usize = 9;
+    /// The version of compact blocks defined by BIP 152.
+    pub const VERSION: u64 = 1;
+
+    /// A sendcmpct for version 1 compact blocks, asking for high-bandwidth mode if `high_bandwidth`
+    /// is true.
+    pub fn new(high_bandwidth: bool) -> SendCmpct {
+        SendCmpct {
+            enable: u8::from(high_bandwidth),
+            version: SendCmpct::VERSION,
+        }
+    }
+
+    /// Whether the sender asks for new blocks to be announced as compact blocks, without a
+    /// preceding inv or headers.
+    pub fn high_bandwidth(&self) -> bool {
+        self.enable != 0
+    }
 
     /// Returns whether compact blocks should be used
     pub fn use_cmpctblock(&self) -> bool {
@@ -0,0 +1,253 @@
+use crate::{Error, Result};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use std::str::FromStr;
+
+/// The service flags that a node advertises in its version message and in addr messages.
+///
+/// The flags are displayed by name, separated by `|`, with any bits that are not known shown in hex,
+/// for example `NETWORK | BLOOM | 0x100000`. The same form can be parsed, which is convenient for
+/// configuration files.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Services(pub u64);
+
+impl Services {
+    /// No services, the node is not a full node. Used for SPV wallets.
+    pub const NONE: Services = Services(0);
+    /// The node is a full node and can serve the full block chain.
+    pub const NETWORK: Services = Services(1);
+    /// The node can respond to getutxo requests.
+    pub const GETUTXO: Services = Services(1 << 1);
+    /// The node supports bloom filtered connections.
+    pub const BLOOM: Services = Services(1 << 2);
+    /// The node supports Xtreme Thinblocks.
+    pub const XTHIN: Services = Services(1 << 4);
+    /// The node follows the Bitcoin Cash rules, signalled by the node software that split from it.
+    pub const BITCOIN_CASH: Services = Services(1 << 5);
+    /// The node can serve the recent blocks, the last 288 blocks.
+    pub const NETWORK_LIMITED: Services = Services(1 << 10);
+
+    const NAMED: [(&'static str, Services); 6] = [
+        ("NETWORK", Services::NETWORK),
+        ("GETUTXO", Services::GETUTXO),
+        ("BLOOM", Services::BLOOM),
+        ("XTHIN", Services::XTHIN),
+        ("BITCOIN_CASH", Services::BITCOIN_CASH),
+        ("NETWORK_LIMITED", Services::NETWORK_LIMITED),
+    ];
+
+    /// The raw flags.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether all of the flags in `other` are set, an empty set is always present.
+    pub fn has(&self, other: Services) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set the flags in `other`.
+    pub fn insert(&mut self, other: Services) {
+        self.0 |= other.0;
+    }
+
+    /// Clear the flags in `other`.
+    pub fn remove(&mut self, other: Services) {
+        self.0 &= !other.0;
+    }
+
+    /// Check that the services include all of the `required` services.
+    pub fn check_required(&self, required: Services) -> Result<()> {
+        if self.has(required) {
+            Ok(())
+        } else {
+            Err(Error::BadData(format!(
+                "peer does not offer the required services, missing: {}",
+                Services(required.0 & !self.0)
+            )))
+        }
+    }
+}
+
+impl From<u64> for Services {
+    fn from(bits: u64) -> Self {
+        Services(bits)
+    }
+}
+
+impl From<Services> for u64 {
+    fn from(services: Services) -> Self {
+        services.0
+    }
+}
+
+impl BitOr for Services {
+    type Output = Services;
+
+    fn bitor(self, rhs: Services) -> Services {
+        Services(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Services {
+    fn bitor_assign(&mut self, rhs: Services) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Services {
+    type Output = Services;
+
+    fn bitand(self, rhs: Services) -> Services {
+        Services(self.0 & rhs.0)
+    }
+}
+
+impl fmt::Display for Services {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("NONE");
+        }
+        let mut parts = Vec::new();
+        let mut unknown = self.0;
+        for (name, flag) in Services::NAMED {
+            if self.has(flag) {
+                parts.push(name.to_string());
+                unknown &= !flag.0;
+            }
+        }
+        if unknown != 0 {
+            parts.push(format!("{:#x}", unknown));
+        }
+        f.write_str(&parts.join(" | "))
+    }
+}
+
+impl fmt::Debug for Services {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Services({})", self)
+    }
+}
+
+impl FromStr for Services {
+    type Err = Error;
+
+    /// Parse flag names and hex values separated by `|`, the form produced by [Display](fmt::Display).
+    /// Names are not case sensitive.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut services = Services::NONE;
+        for part in s.split('|').map(str::trim) {
+            if part.eq_ignore_ascii_case("NONE") {
+                continue;
+            }
+            if let Some(hex) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+                let bits = u64::from_str_radix(hex, 16)
+                    .map_err(|_| Error::BadArgument(format!("invalid service flags: {}", part)))?;
+                services.insert(Services(bits));
+                continue;
+            }
+            let flag = Services::NAMED
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(part))
+                .map(|(_, flag)| *flag)
+                .ok_or_else(|| Error::BadArgument(format!("unknown service flag: {}", part)))?;
+            services.insert(flag);
+        }
+        Ok(services)
+    }
+}
+
+impl<'de> Deserialize<'de> for Services {
+    /// Services are deserialized from the form accepted by [FromStr], or from the raw flags.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct ServicesVisitor;
+
+        impl Visitor<'_> for ServicesVisitor {
+            type Value = Services;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("service flags such as \"NETWORK | BLOOM\" or a number")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Services, E> {
+                Ok(Services(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Services, E> {
+                u64::try_from(v)
+                    .map(Services)
+                    .map_err(|_| E::custom("negative service flags"))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Services, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ServicesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_parse() {
+        let cases = [
+            (Services::NONE, "NONE"),
+            (Services::NETWORK, "NETWORK"),
+            (Services(37), "NETWORK | BLOOM | BITCOIN_CASH"),
+            (
+                Services::NETWORK | Services::BLOOM | Services::NETWORK_LIMITED,
+                "NETWORK | BLOOM | NETWORK_LIMITED",
+            ),
+            (
+                Services((1 << 63) | (1 << 40) | 1),
+                "NETWORK | 0x8000010000000000",
+            ),
+            (Services(1 << 20), "0x100000"),
+        ];
+        for (services, s) in cases {
+            assert_eq!(services.to_string(), s);
+            assert_eq!(s.parse::<Services>().unwrap(), services);
+        }
+        assert_eq!(format!("{:?}", Services(5)), "Services(NETWORK | BLOOM)");
+        assert_eq!(
+            "bloom|network | 0x2".parse::<Services>().unwrap(),
+            Services(7)
+        );
+        assert!("NETWORK | FAST".parse::<Services>().is_err());
+        assert!("0xzz".parse::<Services>().is_err());
+        assert!("NETWORK |".parse::<Services>().is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let mut s = Services::NETWORK;
+        s.insert(Services::BLOOM);
+        assert_eq!(s, Services(5));
+        assert!(s.has(Services::BLOOM));
+        assert!(s.has(Services::NETWORK | Services::BLOOM));
+        assert!(!s.has(Services::NETWORK | Services::GETUTXO));
+        assert!(s.has(Services::NONE));
+        s.remove(Services::NETWORK);
+        assert_eq!(s, Services::BLOOM);
+        assert_eq!(u64::from(s), 4);
+    }
+
+    #[test]
+    fn required_is_subset() {
+        let offered = Services::NETWORK | Services::BLOOM | Services(1 << 50);
+        // more services than required is fine, equality is not needed
+        assert!(offered.check_required(Services::NETWORK).is_ok());
+        assert!(offered.check_required(Services::NONE).is_ok());
+        assert!(offered.check_required(offered).is_ok());
+        let e = offered
+            .check_required(Services::NETWORK | Services::NETWORK_LIMITED)
+            .unwrap_err();
+        assert!(e.to_string().contains("NETWORK_LIMITED"), "{}", e);
+        assert!(Services::NONE.check_required(Services::NETWORK).is_err());
+    }
+}
@@ -1,18 +1,20 @@
-use crate::bitcoin::{varint_decode, varint_encode, varint_size, AsyncEncodable};
+use crate::bitcoin::{varint_encode, varint_size, varstr_decode, AsyncEncodable};
 use crate::p2p::messages::node_addr::NodeAddr;
+use crate::p2p::messages::Services;
 use crate::p2p::params::{MIN_SUPPORTED_PROTOCOL_VERSION, PROTOCOL_VERSION};
 use crate::util::{epoch_secs, epoch_secs_u32};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use log::warn;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// Service flag that node is not a full node. Used for SPV wallets.
-pub const NODE_NONE: u64 = 0;
+pub const NODE_NONE: Services = Services::NONE;
 
 /// Service flag that node is a full node and implements all protocol features
-pub const NODE_NETWORK: u64 = 1;
+pub const NODE_NETWORK: Services = Services::NETWORK;
 
 /// Version payload defining a node's capabilities
 /// todo: add support for message streams: https://github.com/bitcoin-sv-specs/protocol/blob/master/p2p/multistreams.md
@@ -21,7 +23,7 @@ pub struct Version {
     /// The protocol version being used by the node.
     pub version: u32,
     /// Bitfield of features to be enabled for this connection.
-    pub services: u64,
+    pub services: Services,
     /// Time since the Unix epoch in seconds.
     pub timestamp: i64,
     /// Network address of the node receiving this message.
@@ -43,6 +45,9 @@ pub struct Version {
 }
 
 impl Version {
+    /// The maximum size of the user agent string, the same limit as the node software.
+    pub const MAX_USER_AGENT_SIZE: u64 = 256;
+
     /// Checks if the version message is valid
     pub fn validate(&self) -> Result<()> {
         if self.version < MIN_SUPPORTED_PROTOCOL_VERSION {
@@ -53,22 +58,35 @@ impl Version {
         } else if self.version > PROTOCOL_VERSION {
             warn!("unknown protocol version: {}", self.version);
         }
-        if (self.timestamp - epoch_secs()).abs() > 2 * 60 * 60 {
-            return Err(Error::BadData(format!(
-                "Timestamp too old: {}",
-                self.timestamp
-            )));
-        }
         Ok(())
     }
 
+    /// The difference between the timestamp of the message and our clock, in seconds. This is
+    /// positive if the clock of the sender is ahead of ours.
+    pub fn time_offset(&self) -> i64 {
+        self.timestamp - epoch_secs()
+    }
+
+    /// Check that the timestamp is within `max_offset` of our clock, returning the offset. No limit
+    /// is applied if `max_offset` is None.
+    pub fn check_time_offset(&self, max_offset: Option<Duration>) -> Result<i64> {
+        let offset = self.time_offset();
+        match max_offset {
+            Some(max) if offset.unsigned_abs() > max.as_secs() => Err(Error::BadData(format!(
+                "peer clock differs from ours by {} seconds, timestamp: {}",
+                offset, self.timestamp
+            ))),
+            _ => Ok(offset),
+        }
+    }
+
     // the version message does not include the timestamp in the addr, so we have our own function to read the
     // addr structure here
     async fn read_version_addr<R: AsyncReadExt + Unpin + Send>(reader: &mut R) -> Result<NodeAddr>
     where
         NodeAddr: Sized,
     {
-        let services = reader.read_u64_le().await?;
+        let services = Services(reader.read_u64_le().await?);
         let mut ip_bin = [0u8; 16];
         reader.read_exact(&mut ip_bin).await?; // big endian order
         let ip = if ip_bin[0..12] == [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255] {
@@ -94,7 +112,7 @@ impl Version {
         node_addr: &NodeAddr,
         writer: &mut W,
     ) -> Result<()> {
-        writer.write_u64_le(node_addr.services).await?;
+        writer.write_u64_le(node_addr.services.0).await?;
         match node_addr.ip {
             IpAddr::V4(v4) => {
                 writer
@@ -134,16 +152,12 @@ impl AsyncEncodable for Version {
         Self: Sized,
     {
         let version = reader.read_u32_le().await?;
-        let services = reader.read_u64_le().await?;
+        let services = Services(reader.read_u64_le().await?);
         let timestamp = reader.read_i64_le().await?;
         let recv_addr = Version::read_version_addr(reader).await?;
         let tx_addr = Version::read_version_addr(reader).await?;
         let nonce = reader.read_u64_le().await?;
-        let user_agent_size = varint_decode(reader).await?;
-        // todo: check size before allocation
-        let mut user_agent_bytes = vec![0; user_agent_size as usize];
-        reader.read_exact(&mut user_agent_bytes).await?;
-        let user_agent = String::from_utf8(user_agent_bytes)?;
+        let user_agent = varstr_decode(reader, Version::MAX_USER_AGENT_SIZE).await?;
         let start_height = reader.read_i32_le().await?;
         let relay = reader.read_u8().await? == 0x01;
         Ok(Version {
@@ -161,7 +175,7 @@ impl AsyncEncodable for Version {
 
     async fn async_to_binary<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
         writer.write_u32_le(self.version).await?;
-        writer.write_u64_le(self.services).await?;
+        writer.write_u64_le(self.services.0).await?;
         writer.write_i64_le(self.timestamp).await?;
         Version::write_version_addr(&self.recv_addr, writer).await?;
         Version::write_version_addr(&self.tx_addr, writer).await?;
@@ -193,12 +207,12 @@ mod tests {
         let b = hex::decode("7f1101002500000000000000f2d2d25a00000000000000000000000000000000000000000000ffff2d32bffbdd1725000000000000000000000000000000000000000000000000008d501d3bb5369deb242f426974636f696e204142433a302e31362e30284542382e303b20626974636f7265292f6606080001".as_bytes()).unwrap();
         let v = Version::from_binary_buf(b.as_slice()).unwrap();
         assert_eq!(v.version, 70015);
-        assert_eq!(v.services, 37);
+        assert_eq!(v.services, Services(37));
         assert_eq!(v.timestamp, 1523766002);
-        assert_eq!(v.recv_addr.services, 0);
+        assert_eq!(v.recv_addr.services, Services::NONE);
         assert_eq!(v.recv_addr.ip, IpAddr::V4(Ipv4Addr::new(45, 50, 191, 251)));
         assert_eq!(v.recv_addr.port, 56599);
-        assert_eq!(v.tx_addr.services, 37);
+        assert_eq!(v.tx_addr.services, Services(37));
         assert_eq!(v.tx_addr.ip, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
         assert_eq!(v.tx_addr.port, 0);
         assert_eq!(v.nonce, 16977786322265395341);
@@ -211,7 +225,7 @@ mod tests {
     async fn write_read() {
         let m = Version {
             version: MIN_SUPPORTED_PROTOCOL_VERSION,
-            services: 77,
+            services: Services(77),
             timestamp: 1234,
             recv_addr: NodeAddr {
                 ..Default::default()
@@ -233,7 +247,7 @@ mod tests {
     fn validate() {
         let m = Version {
             version: MIN_SUPPORTED_PROTOCOL_VERSION,
-            services: 77,
+            services: Services(77),
             timestamp: epoch_secs(),
             recv_addr: NodeAddr {
                 ..Default::default()
@@ -254,11 +268,28 @@ mod tests {
             ..m.clone()
         };
         assert!(m2.validate().is_err());
-        // Bad timestamp
-        let m3 = Version {
-            timestamp: 0,
+    }
+
+    #[test]
+    fn time_offset() {
+        let max = Some(Duration::from_secs(70 * 60));
+        let m = Version {
+            timestamp: epoch_secs() + 60,
+            ..Version::default()
+        };
+        assert!((59..=61).contains(&m.check_time_offset(max).unwrap()));
+        let behind = Version {
+            timestamp: epoch_secs() - 71 * 60,
+            ..m.clone()
+        };
+        assert!(behind.check_time_offset(max).is_err());
+        assert!(behind.check_time_offset(None).unwrap() < -70 * 60);
+        // a skewed timestamp is not a problem with the message itself
+        let ahead = Version {
+            timestamp: epoch_secs() + 71 * 60,
             ..m.clone()
         };
-        assert!(m3.validate().is_err());
+        assert!(ahead.validate().is_ok());
+        assert!(ahead.check_time_offset(max).is_err());
     }
 }
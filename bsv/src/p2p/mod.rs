@@ -2,18 +2,81 @@
 //!
 //! Although this network is going to be superseded by the Mandala Upgrade, it will continue to play
 //! an important role until all users have upgraded.
+mod addr_sampler;
+mod announce;
+mod ban_audit;
+mod broadcast;
+mod capture;
 mod channel;
+mod config_builder;
+mod config_error;
 mod connection;
+mod connector;
+mod disconnect;
 mod envelope;
+mod external_address;
+#[cfg(test)]
+mod fake_peer;
+mod header_ingest;
+mod header_store;
+mod health;
 mod listener;
 mod manager;
+mod mempool;
 mod messages;
 mod params;
 mod peer;
+mod peer_scoring;
+mod peer_store;
+mod recent_tx;
+pub mod replay;
+mod serve_cache;
+mod session;
+mod slots;
+pub mod telemetry;
+mod throughput;
 
-pub use self::connection::{Connection, ConnectionConfig, ConnectionControlMessage};
-pub use self::manager::{P2PManager, P2PManagerConfig};
-pub use self::peer::PeerAddress;
+pub use self::addr_sampler::{AddrSampler, PeerStoreSampler};
+pub use self::announce::{DEFAULT_INV_BATCH_SIZE, DEFAULT_INV_TRICKLE_INTERVAL};
+pub use self::ban_audit::{AuditSink, BanAction, BanAuditEntry, JsonLinesAuditSink, LogAuditSink};
+pub use self::broadcast::BroadcastReport;
+pub use self::capture::{CaptureRecord, Direction, MessageTap, CAPTURE_MAGIC};
+pub use self::channel::ChannelConfig;
+pub use self::config_error::ConfigError;
+pub use self::connection::{Connection, ConnectionConfig, ConnectionConfigBuilder};
+pub use self::connector::{Connector, PeerStream, TcpConnector};
+pub use self::disconnect::DisconnectCause;
+pub use self::external_address::{is_routable, ExternalAddress};
+pub use self::header_ingest::{HeaderIngest, HeaderSink, IngestStats, DEFAULT_MAX_ORPHANS};
+pub use self::header_store::{ChainEvent, FileHeaderStore, HeaderProvider, MAX_GETBLOCKS_HASHES};
+pub use self::health::{HealthConfig, HealthEvent, HealthTracker};
+pub use self::manager::{
+    ConnectionLostReason, ControlEvent, OperatingMode, P2PManager, P2PManagerConfig,
+    P2PManagerConfigBuilder,
+};
+pub use self::mempool::{MempoolPolicy, MempoolResponder, TxProvider};
+pub use self::messages::{
+    max_size_for_command, Addr, BlockLocator, BlockStream, ChecksumPolicy, FeeFilter, Headers, Inv,
+    InvItem, InvType, InventoryError, MerkleBlock, MessageFramer, NodeAddr, P2PMessage,
+    P2PMessageType, Ping, Protoconf, Reject, SendCmpct, Services, Version, REJECT_CHECKPOINT,
+    REJECT_DUPLICATE, REJECT_DUST, REJECT_INSUFFICIENT_FEE, REJECT_INVALID, REJECT_MALFORMED,
+    REJECT_NONSTANDARD, REJECT_OBSOLETE,
+};
+pub use self::peer::{AddressFamilyPolicy, NetGroup, PeerAddress};
+pub use self::peer_scoring::{
+    peer_software_stats, quality_score, select_peers, ConnectionAttempt, ConnectionOutcome,
+    PeerHistory, PeerStatus, SessionStats, INACCESSIBLE_FAILURES, MAX_RECENT_ATTEMPTS,
+};
+pub use self::peer_store::{MemoryPeerStore, PeerStore, PeerUpdate, PeerWriteBehind};
+pub use self::recent_tx::{RecentTxCache, DEFAULT_RECENT_TX_ENTRIES, DEFAULT_RECENT_TX_TTL};
+pub use self::serve_cache::{
+    DataProvider, GetDataResponder, ServeCache, ServeCacheStats, DEFAULT_SERVE_CACHE_BYTES,
+};
+pub use self::session::{
+    CompactBlockState, HandshakeLimits, NegotiatedSession, PeerSession, SessionSummary,
+};
+pub use self::slots::{ConnectionSlots, SlotCounts, SlotGuard};
+pub use self::throughput::{MinThroughput, StallGuard};
 
 // size of the channel used to control actors
 // todo: to be removed
@@ -1,4 +1,5 @@
 use crate::bitcoin::BlockchainId;
+use std::time::Duration;
 
 /// Network Parameters for Bitcoin SV.
 #[derive(Clone, Debug)]
@@ -7,27 +8,36 @@ pub struct NetworkParams {
     pub magic: [u8; 4],
     /// The default port.
     pub port: u16,
+    /// The DNS seeds, host names that resolve to the addresses of nodes that accept connections.
+    pub dns_seeds: &'static [&'static str],
 }
 
 impl From<BlockchainId> for NetworkParams {
     fn from(blockchain_id: BlockchainId) -> Self {
-        match blockchain_id {
-            BlockchainId::Main => NetworkParams {
-                magic: [0xe3, 0xe1, 0xf3, 0xe8],
-                port: 8333,
-            },
-            BlockchainId::Test => NetworkParams {
-                magic: [0xf4, 0xe5, 0xf3, 0xf4],
-                port: 18333,
-            },
-            BlockchainId::Regtest => NetworkParams {
-                magic: [0xda, 0xb5, 0xbf, 0xfa],
-                port: 18444,
-            },
-            BlockchainId::Stn => NetworkParams {
-                magic: [0xfb, 0xce, 0xc4, 0xf9],
-                port: 9333,
-            },
+        let port = match blockchain_id {
+            BlockchainId::Main => 8333,
+            BlockchainId::Test => 18333,
+            BlockchainId::Regtest => 18444,
+            BlockchainId::Stn => 9333,
+        };
+        let dns_seeds: &'static [&'static str] = match blockchain_id {
+            BlockchainId::Main => &[
+                "seed.bitcoinsv.io",
+                "seed.satoshisvision.network",
+                "seed.bitcoinseed.directory",
+            ],
+            BlockchainId::Test => &[
+                "testnet-seed.bitcoinsv.io",
+                "testnet-seed.bitcoincloud.net",
+                "testnet-seed.bitcoinseed.directory",
+            ],
+            BlockchainId::Stn => &["stn-seed.bitcoinsv.io"],
+            BlockchainId::Regtest => &[],
+        };
+        NetworkParams {
+            magic: blockchain_id.magic(),
+            port,
+            dns_seeds,
         }
     }
 }
@@ -42,6 +52,9 @@ pub const DEFAULT_MAX_PAYLOAD_SIZE: u64 = 0x02000000;
 // then we assume that the peer is using the default 32MB and we wont receive a larger message.
 pub const DEFAULT_MAX_RECV_PAYLOAD_SIZE: u64 = 209_715_200;
 
+/// The smallest max receive payload size that may be sent in a protoconf message (1MB).
+pub const MIN_MAX_RECV_PAYLOAD_SIZE: u64 = 1_048_576;
+
 /// Default excessive block size (10GB).
 pub const DEFAULT_EXCESSIVE_BLOCK_SIZE: u64 = 10_000_000_000;
 
@@ -57,3 +70,7 @@ pub const PROTOCOL_VERSION: u32 = 70016;
 
 /// Minimum protocol version supported by this library
 pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 70015;
+
+/// The default maximum difference between the clock of a peer and our clock (70 minutes), the same
+/// limit as the node software.
+pub const DEFAULT_MAX_TIME_OFFSET: Duration = Duration::from_secs(70 * 60);
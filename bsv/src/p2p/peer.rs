@@ -1,8 +1,11 @@
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
 use std::net::{IpAddr, SocketAddr};
 use uuid::Uuid;
 
 /// A PeerAddress is a potential agent on the network to which a connection could be established.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PeerAddress {
     /// The unique identifier of the peer.
     ///
@@ -23,4 +26,165 @@ impl PeerAddress {
     pub fn ip(&self) -> IpAddr {
         self.address.ip()
     }
+
+    /// The network group of the peer, see [NetGroup].
+    pub fn netgroup(&self) -> NetGroup {
+        NetGroup::of(&self.ip())
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    /// The socket address of the peer, the id is left out to keep logs readable.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.address)
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerAddress {
+    /// A peer is deserialized from its socket address, such as `"203.0.113.7:8333"`, when it is given
+    /// a random id, or from a map with the `address` and an optional `peer_id`.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PeerVisitor;
+
+        impl<'de> Visitor<'de> for PeerVisitor {
+            type Value = PeerAddress;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a socket address or a map with an address and a peer_id")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<PeerAddress, E> {
+                v.parse().map(PeerAddress::new).map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PeerAddress, A::Error> {
+                let mut address: Option<SocketAddr> = None;
+                let mut peer_id = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "address" => address = Some(map.next_value()?),
+                        "peer_id" => {
+                            let id: String = map.next_value()?;
+                            peer_id = Some(Uuid::parse_str(&id).map_err(de::Error::custom)?);
+                        }
+                        _ => return Err(de::Error::unknown_field(&key, &["address", "peer_id"])),
+                    }
+                }
+                let address = address.ok_or_else(|| de::Error::missing_field("address"))?;
+                Ok(PeerAddress {
+                    peer_id: peer_id.unwrap_or_else(Uuid::new_v4),
+                    address,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(PeerVisitor)
+    }
+}
+
+/// A group of addresses that are likely to be operated by the same network provider.
+///
+/// IPv4 addresses are grouped by their /16 prefix and IPv6 addresses by their /32 prefix. An IPv6
+/// address that embeds an IPv4 address is grouped with that IPv4 address. Spreading connections
+/// across groups makes it harder for a single operator to control all of the connections of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetGroup {
+    V4([u8; 2]),
+    V6([u8; 4]),
+}
+
+impl NetGroup {
+    /// The group of an address.
+    pub fn of(ip: &IpAddr) -> NetGroup {
+        match ip {
+            IpAddr::V4(v4) => {
+                let o = v4.octets();
+                NetGroup::V4([o[0], o[1]])
+            }
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => NetGroup::of(&IpAddr::V4(v4)),
+                None => {
+                    let o = v6.octets();
+                    NetGroup::V6([o[0], o[1], o[2], o[3]])
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for NetGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetGroup::V4(p) => write!(f, "{}.{}.0.0/16", p[0], p[1]),
+            NetGroup::V6(p) => write!(
+                f,
+                "{:x}:{:x}::/32",
+                u16::from_be_bytes([p[0], p[1]]),
+                u16::from_be_bytes([p[2], p[3]])
+            ),
+        }
+    }
+}
+
+/// The address families to which outbound connections are made.
+///
+/// An IPv6 address that maps an IPv4 address is reached over IPv4, so it belongs to the IPv4 family.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamilyPolicy {
+    /// Only connect to IPv4 peers, for hosts without IPv6 connectivity.
+    V4Only,
+    /// Only connect to IPv6 peers, for IPv6-only hosts.
+    V6Only,
+    /// Connect to peers of either family.
+    #[default]
+    Both,
+}
+
+impl AddressFamilyPolicy {
+    /// Whether the policy allows connections to the address.
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        let v4 = match ip {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().is_some(),
+        };
+        match self {
+            AddressFamilyPolicy::V4Only => v4,
+            AddressFamilyPolicy::V6Only => !v4,
+            AddressFamilyPolicy::Both => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netgroups() {
+        let group = |s: &str| NetGroup::of(&s.parse().unwrap());
+        assert_eq!(group("10.1.2.3"), group("10.1.200.1"));
+        assert_ne!(group("10.1.2.3"), group("10.2.2.3"));
+        assert_eq!(group("2001:db8:1::1"), group("2001:db8:ffff::2"));
+        assert_ne!(group("2001:db8::1"), group("2001:db9::1"));
+        assert_eq!(group("::ffff:10.1.2.3"), group("10.1.9.9"));
+        assert_eq!(group("10.1.2.3").to_string(), "10.1.0.0/16");
+        assert_eq!(group("2001:db8::1").to_string(), "2001:db8::/32");
+    }
+
+    #[test]
+    fn address_families() {
+        use AddressFamilyPolicy::*;
+        let cases = [
+            ("10.1.2.3", [true, false, true]),
+            ("::ffff:10.1.2.3", [true, false, true]),
+            ("2001:db8::1", [false, true, true]),
+            ("fe80::1", [false, true, true]),
+        ];
+        for (ip, allowed) in cases {
+            let ip: IpAddr = ip.parse().unwrap();
+            let got = [V4Only, V6Only, Both].map(|p| p.allows(&ip));
+            assert_eq!(got, allowed, "{}", ip);
+        }
+    }
 }
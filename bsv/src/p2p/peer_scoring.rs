@@ -0,0 +1,769 @@
+use crate::p2p::{NetGroup, PeerAddress, SessionSummary};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+/// The weight given to the most recent round-trip time when updating the average.
+pub(crate) const RTT_ALPHA: f64 = 0.2;
+/// A round-trip time of this many milliseconds halves the latency component of the score.
+const RTT_REFERENCE_MS: f64 = 250.0;
+/// A connection lasting this long halves the uptime component of the score.
+const UPTIME_REFERENCE: Duration = Duration::from_secs(3600);
+/// Serving this many bytes halves the data component of the score.
+const SERVED_REFERENCE: f64 = 1_000_000.0;
+/// The minimum weight used when selecting peers, so that a peer with a score of zero can still be selected.
+const MIN_WEIGHT: f64 = 0.01;
+/// The weight used when selecting a peer that has no score.
+const UNKNOWN_WEIGHT: f64 = 0.5;
+/// The number of connection attempts remembered in the history of a peer.
+pub const MAX_RECENT_ATTEMPTS: usize = 16;
+/// A peer is inaccessible after this many consecutive failed connection attempts.
+pub const INACCESSIBLE_FAILURES: usize = 3;
+
+/// The result of an attempt to connect to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionOutcome {
+    /// The handshake completed.
+    Connected,
+    /// The peer refused the connection.
+    Refused,
+    /// The connection or the handshake timed out.
+    Timeout,
+    /// The connection was established but the handshake did not complete.
+    HandshakeFailed,
+    /// The connection was closed because the peer broke the protocol.
+    ProtocolViolation,
+}
+
+impl ConnectionOutcome {
+    /// Whether the attempt failed.
+    pub fn is_failure(&self) -> bool {
+        *self != ConnectionOutcome::Connected
+    }
+}
+
+impl fmt::Display for ConnectionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionOutcome::Connected => "connected",
+            ConnectionOutcome::Refused => "refused",
+            ConnectionOutcome::Timeout => "timeout",
+            ConnectionOutcome::HandshakeFailed => "handshake failed",
+            ConnectionOutcome::ProtocolViolation => "protocol violation",
+        })
+    }
+}
+
+/// A single attempt to connect to a peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionAttempt {
+    /// When the attempt was made.
+    pub timestamp: SystemTime,
+    /// The result of the attempt.
+    pub outcome: ConnectionOutcome,
+    /// How long the connection lasted, zero if it was not established.
+    pub duration: Duration,
+    /// The number of bytes of data received during the connection.
+    pub bytes: u64,
+}
+
+/// Whether a peer can be reached, derived from its recent connection attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    /// No connection has been attempted.
+    Untried,
+    /// The peer can be reached, or has not failed often enough to be considered unreachable.
+    Accessible,
+    /// The most recent [INACCESSIBLE_FAILURES] attempts failed.
+    Inaccessible,
+}
+
+impl fmt::Display for PeerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PeerStatus::Untried => "Untried",
+            PeerStatus::Accessible => "Accessible",
+            PeerStatus::Inaccessible => "Inaccessible",
+        })
+    }
+}
+
+/// The statistics collected during a single connection to a peer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStats {
+    /// Whether the handshake completed.
+    pub handshake_succeeded: bool,
+    /// The average round-trip time measured during the connection, if any.
+    pub rtt: Option<Duration>,
+    /// The number of protocol violations committed by the peer.
+    pub protocol_violations: u32,
+    /// The number of bytes of block and transaction data received from the peer.
+    pub bytes_served: u64,
+    /// How long the connection lasted.
+    pub duration: Duration,
+    /// The difference between the clock of the peer and ours in seconds, from its version message.
+    pub time_offset: Option<i64>,
+    /// The settings negotiated in the handshake, if it completed.
+    pub summary: Option<SessionSummary>,
+}
+
+/// The accumulated history of connections to a peer, from which its quality score is derived.
+///
+/// This is intended to be stored alongside the peer so that the score survives restarts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerHistory {
+    /// The number of connections attempted.
+    pub handshakes_attempted: u32,
+    /// The number of connections that completed the handshake.
+    pub handshakes_succeeded: u32,
+    /// The exponentially weighted moving average of the round-trip time.
+    pub rtt_ewma: Option<Duration>,
+    /// The total number of protocol violations.
+    pub protocol_violations: u32,
+    /// The total number of bytes of data served.
+    pub bytes_served: u64,
+    /// The total time connected.
+    pub uptime: Duration,
+    /// The quality score, see [quality_score()]. This is None for a peer that has not been tried.
+    pub quality_score: Option<f64>,
+    /// The most recent connection attempts, oldest first, at most [MAX_RECENT_ATTEMPTS].
+    pub attempts: VecDeque<ConnectionAttempt>,
+    /// The last known address of the peer.
+    pub address: Option<SocketAddr>,
+    /// The peer must not be connected to before this time.
+    pub banned_until: Option<SystemTime>,
+    /// The clock offset reported in the most recent handshake, see [SessionStats::time_offset].
+    pub time_offset: Option<i64>,
+    /// The settings negotiated in the most recent completed handshake, a hint for the next connection.
+    pub last_session: Option<SessionSummary>,
+    /// The peer is one of the [bootstrap peers](crate::p2p::P2PManagerConfig::bootstrap_peers) of
+    /// the operator. Stores that prune peers which have not been seen for a long time should keep
+    /// these.
+    pub bootstrap: bool,
+}
+
+impl PeerHistory {
+    /// Add the statistics of a connection that has ended and update the quality score.
+    pub fn record_session(&mut self, session: &SessionStats) {
+        self.handshakes_attempted = self.handshakes_attempted.saturating_add(1);
+        if session.handshake_succeeded {
+            self.handshakes_succeeded = self.handshakes_succeeded.saturating_add(1);
+        }
+        if let Some(rtt) = session.rtt {
+            self.rtt_ewma = Some(match self.rtt_ewma {
+                None => rtt,
+                Some(avg) => avg.mul_f64(1.0 - RTT_ALPHA) + rtt.mul_f64(RTT_ALPHA),
+            });
+        }
+        self.protocol_violations = self
+            .protocol_violations
+            .saturating_add(session.protocol_violations);
+        self.bytes_served = self.bytes_served.saturating_add(session.bytes_served);
+        self.uptime = self.uptime.saturating_add(session.duration);
+        if session.time_offset.is_some() {
+            self.time_offset = session.time_offset;
+        }
+        if session.summary.is_some() {
+            self.last_session = session.summary.clone();
+        }
+        let outcome = if session.protocol_violations > 0 {
+            ConnectionOutcome::ProtocolViolation
+        } else if session.handshake_succeeded {
+            ConnectionOutcome::Connected
+        } else {
+            ConnectionOutcome::HandshakeFailed
+        };
+        self.push_attempt(ConnectionAttempt {
+            timestamp: SystemTime::now(),
+            outcome,
+            duration: session.duration,
+            bytes: session.bytes_served,
+        });
+        self.quality_score = quality_score(self);
+    }
+
+    /// Record an attempt that failed before a connection was established, such as a refused
+    /// connection or a timeout.
+    pub fn record_failure(&mut self, outcome: ConnectionOutcome) {
+        self.handshakes_attempted = self.handshakes_attempted.saturating_add(1);
+        self.push_attempt(ConnectionAttempt {
+            timestamp: SystemTime::now(),
+            outcome,
+            duration: Duration::ZERO,
+            bytes: 0,
+        });
+        self.quality_score = quality_score(self);
+    }
+
+    /// Record a protocol violation that ended a connection early, such as a stalled transfer, and
+    /// update the quality score.
+    ///
+    /// This is recorded when it happens rather than with the rest of the session, so each repeat
+    /// offence lowers the score even if the session is never recorded.
+    pub fn record_violation(&mut self) {
+        self.protocol_violations = self.protocol_violations.saturating_add(1);
+        self.quality_score = quality_score(self);
+    }
+
+    /// The most recent connection attempts, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &ConnectionAttempt> {
+        self.attempts.iter()
+    }
+
+    /// The number of consecutive failed attempts since the last successful connection.
+    pub fn recent_failures(&self) -> usize {
+        self.attempts
+            .iter()
+            .rev()
+            .take_while(|a| a.outcome.is_failure())
+            .count()
+    }
+
+    /// Whether the peer can be reached, based on its recent connection attempts.
+    pub fn status(&self) -> PeerStatus {
+        if self.attempts.is_empty() {
+            PeerStatus::Untried
+        } else if self.recent_failures() >= INACCESSIBLE_FAILURES {
+            PeerStatus::Inaccessible
+        } else {
+            PeerStatus::Accessible
+        }
+    }
+
+    /// A concise description of the peer for logs, such as
+    /// `203.0.113.5:8333 [Accessible, rtt=43ms, seen 2m ago]`.
+    ///
+    /// The round-trip time and the time since the last connection attempt are left out if they are
+    /// not known, and the remaining time is added if the peer is banned.
+    pub fn summary(&self) -> String {
+        self.summary_at(SystemTime::now())
+    }
+
+    /// The [summary()](Self::summary) of the peer at the time `now`.
+    pub fn summary_at(&self, now: SystemTime) -> String {
+        let mut s = match self.address {
+            Some(address) => format!("{} [{}", address, self.status()),
+            None => format!("unknown address [{}", self.status()),
+        };
+        if let Some(rtt) = self.rtt_ewma {
+            s.push_str(&format!(", rtt={}ms", rtt.as_millis()));
+        }
+        if let Some(last) = self.attempts.back() {
+            let age = now.duration_since(last.timestamp).unwrap_or_default();
+            s.push_str(&format!(", seen {} ago", format_age(age)));
+        }
+        if let Some(until) = self.banned_until.filter(|_| self.is_banned(now)) {
+            let left = until.duration_since(now).unwrap_or_default();
+            s.push_str(&format!(", banned for {}", format_age(left)));
+        }
+        s.push(']');
+        s
+    }
+
+    /// Ban the peer until the given time, remembering the address at which it was connected.
+    pub fn ban(&mut self, address: SocketAddr, until: SystemTime) {
+        self.address = Some(address);
+        self.banned_until = Some(until);
+    }
+
+    /// Whether the peer is banned at `now`.
+    pub fn is_banned(&self, now: SystemTime) -> bool {
+        self.banned_until.is_some_and(|until| now < until)
+    }
+
+    /// Fold in the history of a duplicate record of the same peer.
+    ///
+    /// The counters are added, the recent attempts of both are interleaved by time, and the later
+    /// of the two bans is kept. Where both records hold a value that can not be combined, such as
+    /// the address, the value of this record is kept.
+    pub fn merge(&mut self, other: &PeerHistory) {
+        self.handshakes_attempted = self
+            .handshakes_attempted
+            .saturating_add(other.handshakes_attempted);
+        self.handshakes_succeeded = self
+            .handshakes_succeeded
+            .saturating_add(other.handshakes_succeeded);
+        self.rtt_ewma = match (self.rtt_ewma, other.rtt_ewma) {
+            (Some(a), Some(b)) => Some((a + b) / 2),
+            (a, b) => a.or(b),
+        };
+        self.protocol_violations = self
+            .protocol_violations
+            .saturating_add(other.protocol_violations);
+        self.bytes_served = self.bytes_served.saturating_add(other.bytes_served);
+        self.uptime = self.uptime.saturating_add(other.uptime);
+        let mut attempts: Vec<ConnectionAttempt> = self
+            .attempts
+            .drain(..)
+            .chain(other.attempts.iter().cloned())
+            .collect();
+        attempts.sort_by_key(|a| a.timestamp);
+        let skip = attempts.len().saturating_sub(MAX_RECENT_ATTEMPTS);
+        self.attempts = attempts.into_iter().skip(skip).collect();
+        self.address = self.address.or(other.address);
+        self.banned_until = self.banned_until.max(other.banned_until);
+        self.time_offset = self.time_offset.or(other.time_offset);
+        if self.last_session.is_none() {
+            self.last_session = other.last_session.clone();
+        }
+        self.bootstrap |= other.bootstrap;
+        self.quality_score = quality_score(self);
+    }
+
+    fn push_attempt(&mut self, attempt: ConnectionAttempt) {
+        if self.attempts.len() == MAX_RECENT_ATTEMPTS {
+            self.attempts.pop_front();
+        }
+        self.attempts.push_back(attempt);
+    }
+}
+
+/// Calculate the quality score of a peer from its history.
+///
+/// The score is between 0 and 1. It is the handshake success rate, reduced for each protocol violation,
+/// multiplied by a factor that increases with a lower round-trip time, a longer uptime, and more data
+/// served. Returns None if no connection to the peer has been attempted.
+pub fn quality_score(history: &PeerHistory) -> Option<f64> {
+    if history.handshakes_attempted == 0 {
+        return None;
+    }
+    let success = history.handshakes_succeeded as f64 / history.handshakes_attempted as f64;
+    let violations = 1.0 / (1.0 + history.protocol_violations as f64);
+    let latency = match history.rtt_ewma {
+        Some(rtt) => RTT_REFERENCE_MS / (RTT_REFERENCE_MS + rtt.as_secs_f64() * 1000.0),
+        None => 0.5,
+    };
+    let uptime = history.uptime.as_secs_f64()
+        / (history.uptime.as_secs_f64() + UPTIME_REFERENCE.as_secs_f64());
+    let served = history.bytes_served as f64 / (history.bytes_served as f64 + SERVED_REFERENCE);
+    Some(success * violations * (0.5 + 0.2 * latency + 0.15 * uptime + 0.15 * served))
+}
+
+/// Count the peers running each version of node software, by the user agent of their most recent
+/// handshake, most common first. Peers that have never completed a handshake are not counted.
+pub fn peer_software_stats<'a, I>(histories: I) -> Vec<(String, usize)>
+where
+    I: IntoIterator<Item = &'a PeerHistory>,
+{
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for summary in histories
+        .into_iter()
+        .filter_map(|h| h.last_session.as_ref())
+    {
+        *counts.entry(summary.user_agent.as_str()).or_default() += 1;
+    }
+    let mut stats: Vec<(String, usize)> = counts
+        .into_iter()
+        .map(|(agent, n)| (agent.to_string(), n))
+        .collect();
+    stats.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats
+}
+
+/// Select up to `n` peers to connect to.
+///
+/// Each candidate is paired with its quality score, or None if it has not been tried. A fraction of the
+/// selections, given by `exploration`, is reserved for untried peers, so that new peers continue to be
+/// tried. The remaining peers are sampled with probability proportional to their score, so peers with
+/// a low score are selected less often but are not excluded completely.
+///
+/// At most `max_per_group` peers are selected from each [NetGroup], counting the peers that are
+/// already `connected`, so fewer than `n` peers are returned if the candidates are not diverse enough.
+pub fn select_peers<R: Rng + ?Sized>(
+    candidates: &[(PeerAddress, Option<f64>)],
+    connected: &[PeerAddress],
+    n: usize,
+    exploration: f64,
+    max_per_group: usize,
+    rng: &mut R,
+) -> Vec<PeerAddress> {
+    let mut groups: HashMap<NetGroup, usize> = HashMap::new();
+    for p in connected {
+        *groups.entry(p.netgroup()).or_default() += 1;
+    }
+    let mut admit = |i: &usize| {
+        let count = groups.entry(candidates[*i].0.netgroup()).or_default();
+        *count < max_per_group && {
+            *count += 1;
+            true
+        }
+    };
+    let mut unknown: Vec<usize> = (0..candidates.len())
+        .filter(|i| candidates[*i].1.is_none())
+        .collect();
+    unknown.shuffle(rng);
+    let explore = (n as f64 * exploration.clamp(0.0, 1.0)).round() as usize;
+    let mut selected: Vec<usize> = unknown
+        .into_iter()
+        .filter(&mut admit)
+        .take(explore)
+        .collect();
+    // weighted sampling without replacement, each candidate gets the key u^(1/w) and the largest are taken
+    let mut keyed: Vec<(f64, usize)> = (0..candidates.len())
+        .filter(|i| !selected.contains(i))
+        .map(|i| {
+            let weight = candidates[i]
+                .1
+                .map_or(UNKNOWN_WEIGHT, |s| s.max(MIN_WEIGHT));
+            (rng.gen::<f64>().powf(1.0 / weight), i)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let remaining = n.saturating_sub(selected.len());
+    selected.extend(
+        keyed
+            .iter()
+            .map(|(_, i)| *i)
+            .filter(&mut admit)
+            .take(remaining),
+    );
+    selected
+        .into_iter()
+        .map(|i| candidates[i].0.clone())
+        .collect()
+}
+
+/// Format a duration in its largest whole unit, such as `45s`, `2m`, `3h` or `5d`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3_599 => format!("{}m", secs / 60),
+        3_600..=86_399 => format!("{}h", secs / 3_600),
+        _ => format!("{}d", secs / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::net::SocketAddr;
+
+    fn history(sessions: &[SessionStats]) -> PeerHistory {
+        let mut h = PeerHistory::default();
+        for s in sessions {
+            h.record_session(s);
+        }
+        h
+    }
+
+    fn good_session() -> SessionStats {
+        SessionStats {
+            handshake_succeeded: true,
+            rtt: Some(Duration::from_millis(50)),
+            protocol_violations: 0,
+            bytes_served: 5_000_000,
+            duration: Duration::from_secs(7200),
+            time_offset: Some(-3),
+            summary: None,
+        }
+    }
+
+    fn peer(i: u16) -> PeerAddress {
+        PeerAddress::new(SocketAddr::from(([10, 0, 0, 1], 8000 + i)))
+    }
+
+    #[test]
+    fn unknown_peer() {
+        assert_eq!(quality_score(&PeerHistory::default()), None);
+    }
+
+    #[test]
+    fn score_ordering() {
+        let good = history(&[good_session(), good_session()]);
+        let slow = history(&[SessionStats {
+            rtt: Some(Duration::from_millis(2000)),
+            ..good_session()
+        }]);
+        let failing = history(&[
+            good_session(),
+            SessionStats::default(),
+            SessionStats::default(),
+        ]);
+        let violating = history(&[SessionStats {
+            protocol_violations: 5,
+            ..good_session()
+        }]);
+        let never = history(&[SessionStats::default(), SessionStats::default()]);
+        let g = good.quality_score.unwrap();
+        assert!(g > 0.8 && g <= 1.0);
+        assert!(g > slow.quality_score.unwrap());
+        assert!(slow.quality_score.unwrap() > failing.quality_score.unwrap());
+        assert!(g > violating.quality_score.unwrap());
+        assert_eq!(never.quality_score, Some(0.0));
+    }
+
+    #[test]
+    fn rtt_average() {
+        let h = history(&[
+            SessionStats {
+                rtt: Some(Duration::from_millis(100)),
+                ..good_session()
+            },
+            SessionStats {
+                rtt: Some(Duration::from_millis(600)),
+                ..good_session()
+            },
+            SessionStats {
+                rtt: None,
+                ..good_session()
+            },
+        ]);
+        let rtt = h.rtt_ewma.unwrap().as_secs_f64();
+        assert!((rtt - 0.2).abs() < 1e-6);
+        assert_eq!(h.handshakes_attempted, 3);
+        assert_eq!(h.uptime, Duration::from_secs(3 * 7200));
+        assert_eq!(h.time_offset, Some(-3));
+    }
+
+    #[test]
+    fn connection_history() {
+        let mut h = PeerHistory::default();
+        assert_eq!(h.status(), PeerStatus::Untried);
+        h.record_failure(ConnectionOutcome::Timeout);
+        h.record_failure(ConnectionOutcome::Refused);
+        assert_eq!(h.status(), PeerStatus::Accessible);
+        assert_eq!(h.recent_failures(), 2);
+        h.record_session(&SessionStats::default());
+        assert_eq!(h.status(), PeerStatus::Inaccessible);
+        assert_eq!(h.quality_score, Some(0.0));
+        // a successful connection resets the failures
+        h.record_session(&good_session());
+        assert_eq!(h.status(), PeerStatus::Accessible);
+        assert_eq!(h.recent_failures(), 0);
+        h.record_session(&SessionStats {
+            protocol_violations: 1,
+            ..good_session()
+        });
+        assert_eq!(h.recent_failures(), 1);
+        let outcomes: Vec<_> = h.history().map(|a| a.outcome).collect();
+        use ConnectionOutcome::*;
+        assert_eq!(
+            outcomes,
+            vec![
+                Timeout,
+                Refused,
+                HandshakeFailed,
+                Connected,
+                ProtocolViolation
+            ]
+        );
+        assert_eq!(h.handshakes_attempted, 5);
+        assert_eq!(h.handshakes_succeeded, 2);
+        let last = h.history().last().unwrap();
+        assert_eq!(last.bytes, 5_000_000);
+        assert_eq!(last.duration, Duration::from_secs(7200));
+        // the history is bounded
+        for _ in 0..MAX_RECENT_ATTEMPTS {
+            h.record_failure(Timeout);
+        }
+        assert_eq!(h.history().count(), MAX_RECENT_ATTEMPTS);
+        assert!(h.history().all(|a| a.outcome == Timeout));
+        assert_eq!(h.status(), PeerStatus::Inaccessible);
+        assert_eq!(h.handshakes_attempted, 5 + MAX_RECENT_ATTEMPTS as u32);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = history(&[good_session()]);
+        let address: SocketAddr = "192.0.2.1:8333".parse().unwrap();
+        a.address = Some(address);
+        let mut b = history(&[SessionStats::default(), good_session()]);
+        let until = SystemTime::now() + Duration::from_secs(600);
+        b.ban("192.0.2.2:8333".parse().unwrap(), until);
+        b.attempts[0].timestamp -= Duration::from_secs(60);
+        let score_a = a.quality_score.unwrap();
+        a.merge(&b);
+        assert_eq!(a.handshakes_attempted, 3);
+        assert_eq!(a.handshakes_succeeded, 2);
+        assert_eq!(a.uptime, Duration::from_secs(2 * 7200));
+        assert_eq!(a.address, Some(address));
+        assert_eq!(a.banned_until, Some(until));
+        // the attempts are in time order, and the score reflects the failed attempt
+        let outcomes: Vec<_> = a.history().map(|h| h.outcome).collect();
+        assert_eq!(outcomes[0], ConnectionOutcome::HandshakeFailed);
+        assert!(a
+            .history()
+            .zip(a.history().skip(1))
+            .all(|(x, y)| x.timestamp <= y.timestamp));
+        assert!(a.quality_score.unwrap() < score_a);
+        // the number of attempts remains bounded
+        let many = history(&vec![good_session(); MAX_RECENT_ATTEMPTS]);
+        a.merge(&many);
+        assert_eq!(a.history().count(), MAX_RECENT_ATTEMPTS);
+    }
+
+    #[test]
+    fn select_distinct() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let candidates: Vec<_> = (0..10).map(|i| (peer(i), Some(0.5))).collect();
+        let selected = select_peers(&candidates, &[], 4, 0.25, usize::MAX, &mut rng);
+        assert_eq!(selected.len(), 4);
+        for (i, p) in selected.iter().enumerate() {
+            assert!(!selected[i + 1..].iter().any(|q| q.peer_id == p.peer_id));
+        }
+        // asking for more than are available returns them all
+        assert_eq!(
+            select_peers(&candidates, &[], 20, 0.25, usize::MAX, &mut rng).len(),
+            10
+        );
+    }
+
+    #[test]
+    fn exploration() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut candidates: Vec<_> = (0..20).map(|i| (peer(i), Some(1.0))).collect();
+        candidates.extend((20..30).map(|i| (peer(i), None)));
+        let unknown = |p: &PeerAddress| p.address.port() >= 8020;
+        for _ in 0..50 {
+            let selected = select_peers(&candidates, &[], 8, 0.25, usize::MAX, &mut rng);
+            assert!(selected.iter().filter(|p| unknown(p)).count() >= 2);
+        }
+        // without exploration, untried peers are still eligible but less likely
+        let selected = select_peers(&candidates[20..], &[], 3, 0.0, usize::MAX, &mut rng);
+        assert_eq!(selected.len(), 3);
+    }
+
+    #[test]
+    fn bad_peers_selected_less() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let good = history(&[good_session(), good_session()]);
+        let bad = history(&[
+            SessionStats::default(),
+            SessionStats {
+                protocol_violations: 3,
+                ..good_session()
+            },
+        ]);
+        let candidates: Vec<_> = (0..20)
+            .map(|i| {
+                let h = if i % 2 == 0 { &good } else { &bad };
+                (peer(i), h.quality_score)
+            })
+            .collect();
+        let (mut good_count, mut bad_count) = (0, 0);
+        for _ in 0..200 {
+            for p in select_peers(&candidates, &[], 5, 0.2, usize::MAX, &mut rng) {
+                if p.address.port() % 2 == 0 {
+                    good_count += 1;
+                } else {
+                    bad_count += 1;
+                }
+            }
+        }
+        assert!(bad_count > 0);
+        assert!(good_count > bad_count * 3, "{} {}", good_count, bad_count);
+    }
+
+    #[test]
+    fn select_diverse() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let candidates: Vec<_> = (0..10)
+            .map(|i| {
+                let address = format!("10.{}.0.{}:8333", i % 2 + 1, i).parse().unwrap();
+                let score = if i % 3 == 0 { None } else { Some(0.5) };
+                (PeerAddress::new(address), score)
+            })
+            .collect();
+        for cap in 1..=3 {
+            for _ in 0..50 {
+                let selected = select_peers(&candidates, &[], 6, 0.25, cap, &mut rng);
+                let mut groups: HashMap<NetGroup, usize> = HashMap::new();
+                for p in selected.iter() {
+                    *groups.entry(p.netgroup()).or_default() += 1;
+                }
+                assert_eq!(selected.len(), 2 * cap);
+                assert!(groups.values().all(|c| *c == cap));
+            }
+        }
+        // the connected peers count towards the limit
+        let connected = [PeerAddress::new("10.1.9.9:8333".parse().unwrap())];
+        for _ in 0..50 {
+            let selected = select_peers(&candidates, &connected, 6, 0.25, 2, &mut rng);
+            let first = selected
+                .iter()
+                .filter(|p| p.netgroup() == connected[0].netgroup());
+            assert_eq!(first.count(), 1);
+            assert_eq!(selected.len(), 3);
+        }
+    }
+
+    #[test]
+    fn software_stats() {
+        let summary = |agent: &str| SessionSummary {
+            user_agent: agent.to_string(),
+            version: 70016,
+            services: crate::p2p::Services::NETWORK,
+            max_recv_payload_length: None,
+            send_headers: true,
+        };
+        let session = |agent: &str| SessionStats {
+            summary: Some(summary(agent)),
+            ..good_session()
+        };
+        let mut upgraded = history(&[session("/Bitcoin SV:1.0.16/")]);
+        // the most recent handshake wins, a failed one does not clear it
+        upgraded.record_session(&session("/Bitcoin SV:1.1.0/"));
+        upgraded.record_session(&SessionStats::default());
+        assert_eq!(
+            upgraded.last_session.as_ref().unwrap().user_agent,
+            "/Bitcoin SV:1.1.0/"
+        );
+        let histories = [
+            upgraded,
+            history(&[session("/Bitcoin SV:1.1.0/")]),
+            history(&[session("/Bitcoin SV:1.0.16/")]),
+            history(&[SessionStats::default()]),
+        ];
+        assert_eq!(
+            peer_software_stats(&histories),
+            vec![
+                ("/Bitcoin SV:1.1.0/".to_string(), 2),
+                ("/Bitcoin SV:1.0.16/".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let now = SystemTime::now();
+        let mut h = PeerHistory::default();
+        assert_eq!(h.summary_at(now), "unknown address [Untried]");
+
+        let address: SocketAddr = "203.0.113.5:8333".parse().unwrap();
+        h.address = Some(address);
+        h.rtt_ewma = Some(Duration::from_millis(43));
+        h.attempts.push_back(ConnectionAttempt {
+            timestamp: now - Duration::from_secs(150),
+            outcome: ConnectionOutcome::Connected,
+            duration: Duration::from_secs(100),
+            bytes: 0,
+        });
+        assert_eq!(
+            h.summary_at(now),
+            "203.0.113.5:8333 [Accessible, rtt=43ms, seen 2m ago]"
+        );
+        h.ban(address, now + Duration::from_secs(3 * 3_600 + 5));
+        assert_eq!(
+            h.summary_at(now),
+            "203.0.113.5:8333 [Accessible, rtt=43ms, seen 2m ago, banned for 3h]"
+        );
+        // the ban has expired
+        assert_eq!(
+            h.summary_at(now + Duration::from_secs(86_400)),
+            "203.0.113.5:8333 [Accessible, rtt=43ms, seen 1d ago]"
+        );
+
+        assert_eq!(PeerStatus::Inaccessible.to_string(), "Inaccessible");
+        assert_eq!(
+            ConnectionOutcome::HandshakeFailed.to_string(),
+            "handshake failed"
+        );
+        assert_eq!(format_age(Duration::from_secs(59)), "59s");
+    }
+}
@@ -0,0 +1,612 @@
+use crate::p2p::{PeerHistory, ACTOR_CHANNEL_SIZE};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Persistent storage for the history of peers, keyed by peer id.
+///
+/// The address of a peer identifies it, so a store holds at most one peer per address. This is
+/// enforced by [create()](PeerStore::create), which is the only way to add a new peer. Peers that
+/// are discovered from several sources, such as DNS seeds and inbound connections, should be added
+/// with [find_or_create()](PeerStore::find_or_create) so that they share one history. Stores that
+/// were written without this guarantee can be cleaned up with [dedup()](PeerStore::dedup).
+#[async_trait]
+pub trait PeerStore: Debug + Send + Sync + 'static {
+    /// Get the history of a peer, None if the peer is not known.
+    async fn get(&self, peer_id: &Uuid) -> Result<Option<PeerHistory>>;
+    /// Store the history of several peers, replacing any existing history.
+    async fn put_batch(&self, histories: Vec<(Uuid, PeerHistory)>) -> Result<()>;
+    /// Add a peer at `address`, returning its new id.
+    ///
+    /// If a peer with the address is already stored then nothing is added and
+    /// [Error::PeerExists] is returned with the id of the existing peer. The check and the insert
+    /// must be atomic, so that concurrent calls for the same address create a single peer.
+    async fn create(&self, address: SocketAddr) -> Result<Uuid>;
+    /// Fold the history of `duplicate` into `survivor` and remove `duplicate`, see
+    /// [PeerHistory::merge()].
+    async fn merge(&self, survivor: &Uuid, duplicate: &Uuid) -> Result<()>;
+    /// Get every stored peer.
+    async fn list(&self) -> Result<Vec<(Uuid, PeerHistory)>>;
+
+    /// Apply `f` to the history of a peer and store the result. A peer without a stored history
+    /// starts with the default history.
+    ///
+    /// Concurrent updates of a peer must not be lost, so a store should apply `f` atomically. The
+    /// default implementation reads and then writes the history, which is only atomic if the store
+    /// has a single writer.
+    async fn update(&self, peer_id: &Uuid, f: PeerUpdate) -> Result<()> {
+        let mut history = self.get(peer_id).await?.unwrap_or_default();
+        f(&mut history);
+        self.put_batch(vec![(*peer_id, history)]).await
+    }
+
+    /// Get the id of the peer at `address`, adding the peer if it is not known.
+    async fn find_or_create(&self, address: SocketAddr) -> Result<Uuid> {
+        match self.create(address).await {
+            Err(Error::PeerExists(id)) => Ok(id),
+            r => r,
+        }
+    }
+
+    /// Merge all of the peers that share an address, returning the number of peers removed.
+    ///
+    /// The peer with the most connection attempts survives, the lowest id breaks a tie.
+    async fn dedup(&self) -> Result<usize> {
+        let mut by_address: HashMap<SocketAddr, Vec<(Uuid, u32)>> = HashMap::new();
+        for (id, history) in self.list().await? {
+            if let Some(address) = history.address {
+                by_address
+                    .entry(address)
+                    .or_default()
+                    .push((id, history.handshakes_attempted));
+            }
+        }
+        let mut removed = 0;
+        for (address, mut peers) in by_address.into_iter().filter(|(_, p)| p.len() > 1) {
+            peers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            let survivor = peers[0].0;
+            for (duplicate, _) in peers.iter().skip(1) {
+                self.merge(&survivor, duplicate).await?;
+                removed += 1;
+            }
+            info!(
+                "merged {} duplicate peers at {} into {}",
+                peers.len() - 1,
+                address,
+                survivor
+            );
+        }
+        Ok(removed)
+    }
+}
+
+/// A [PeerStore] that is held in memory.
+#[derive(Debug, Default)]
+pub struct MemoryPeerStore {
+    peers: Mutex<HashMap<Uuid, PeerHistory>>,
+}
+
+#[async_trait]
+impl PeerStore for MemoryPeerStore {
+    async fn get(&self, peer_id: &Uuid) -> Result<Option<PeerHistory>> {
+        Ok(self.peers.lock().unwrap().get(peer_id).cloned())
+    }
+
+    async fn put_batch(&self, histories: Vec<(Uuid, PeerHistory)>) -> Result<()> {
+        self.peers.lock().unwrap().extend(histories);
+        Ok(())
+    }
+
+    async fn create(&self, address: SocketAddr) -> Result<Uuid> {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some((id, _)) = peers.iter().find(|(_, h)| h.address == Some(address)) {
+            return Err(Error::PeerExists(*id));
+        }
+        let id = Uuid::new_v4();
+        let history = PeerHistory {
+            address: Some(address),
+            ..PeerHistory::default()
+        };
+        peers.insert(id, history);
+        Ok(id)
+    }
+
+    async fn merge(&self, survivor: &Uuid, duplicate: &Uuid) -> Result<()> {
+        if survivor == duplicate {
+            return Err(Error::BadArgument(
+                "a peer can not be merged into itself".to_string(),
+            ));
+        }
+        let mut peers = self.peers.lock().unwrap();
+        let Some(other) = peers.remove(duplicate) else {
+            return Err(Error::BadArgument(format!("unknown peer {}", duplicate)));
+        };
+        peers.entry(*survivor).or_default().merge(&other);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<(Uuid, PeerHistory)>> {
+        Ok(self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect())
+    }
+
+    async fn update(&self, peer_id: &Uuid, f: PeerUpdate) -> Result<()> {
+        f(self.peers.lock().unwrap().entry(*peer_id).or_default());
+        Ok(())
+    }
+}
+
+/// A change to the history of a peer, see [PeerStore::update()].
+pub type PeerUpdate = Box<dyn FnOnce(&mut PeerHistory) + Send>;
+
+enum WriterCommand {
+    Update(Uuid, PeerUpdate),
+    Flush(oneshot::Sender<Result<()>>),
+    Shutdown(oneshot::Sender<Result<()>>),
+}
+
+/// Buffers updates to a [PeerStore] so that connections are not stalled by writes to the store.
+///
+/// Updates are queued to a single writer task, which applies them to a pending copy of the history of
+/// the peer. Several updates to the same peer are coalesced into a single write. The pending histories
+/// are written to the store every `flush_interval`, or sooner when `max_pending` peers are waiting to
+/// be written. Reads return the pending history if there is one.
+///
+/// The write-behind layer is itself a [PeerStore], so it can be used in place of the store it wraps.
+/// The [P2PManager](crate::p2p::P2PManager) wraps its store in one, which the connections share.
+///
+/// [PeerWriteBehind::shutdown()] must be called to write the remaining updates.
+pub struct PeerWriteBehind<S: PeerStore + ?Sized> {
+    store: Arc<S>,
+    pending: Arc<Mutex<HashMap<Uuid, PeerHistory>>>,
+    sender: mpsc::Sender<WriterCommand>,
+}
+
+impl<S: PeerStore + ?Sized> Clone for PeerWriteBehind<S> {
+    fn clone(&self) -> Self {
+        PeerWriteBehind {
+            store: self.store.clone(),
+            pending: self.pending.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S: PeerStore + ?Sized> Debug for PeerWriteBehind<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PeerWriteBehind")
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: PeerStore + ?Sized> PeerWriteBehind<S> {
+    /// Create the write-behind layer and start its writer task.
+    ///
+    /// Returns the handle and the join handle of the writer task.
+    pub fn new(
+        store: Arc<S>,
+        flush_interval: Duration,
+        max_pending: usize,
+    ) -> (PeerWriteBehind<S>, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(ACTOR_CHANNEL_SIZE);
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let writer = Writer {
+            store: store.clone(),
+            pending: pending.clone(),
+            max_pending: max_pending.max(1),
+        };
+        let j = tokio::spawn(writer.run(receiver, flush_interval));
+        (
+            PeerWriteBehind {
+                store,
+                pending,
+                sender,
+            },
+            j,
+        )
+    }
+
+    /// Queue an update to the history of a peer.
+    ///
+    /// A peer without a stored history starts with the default history. This waits only if the queue
+    /// is full.
+    pub async fn update<F>(&self, peer_id: Uuid, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut PeerHistory) + Send + 'static,
+    {
+        self.send(WriterCommand::Update(peer_id, Box::new(f))).await
+    }
+
+    /// Write all of the queued updates to the store.
+    pub async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WriterCommand::Flush(tx)).await?;
+        rx.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Write all of the queued updates to the store and stop the writer task.
+    pub async fn shutdown(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(WriterCommand::Shutdown(tx)).await?;
+        rx.await.map_err(|_| writer_stopped())?
+    }
+
+    async fn send(&self, command: WriterCommand) -> Result<()> {
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| writer_stopped())
+    }
+}
+
+/// Reads include the updates that have been applied but not yet written.
+#[async_trait]
+impl<S: PeerStore + ?Sized> PeerStore for PeerWriteBehind<S> {
+    async fn get(&self, peer_id: &Uuid) -> Result<Option<PeerHistory>> {
+        if let Some(h) = self.pending.lock().unwrap().get(peer_id) {
+            return Ok(Some(h.clone()));
+        }
+        self.store.get(peer_id).await
+    }
+
+    /// The histories are queued as updates that replace the history of each peer.
+    async fn put_batch(&self, histories: Vec<(Uuid, PeerHistory)>) -> Result<()> {
+        for (peer_id, history) in histories {
+            self.update(peer_id, move |h| *h = history).await?;
+        }
+        Ok(())
+    }
+
+    async fn create(&self, address: SocketAddr) -> Result<Uuid> {
+        self.store.create(address).await
+    }
+
+    /// The queued updates are written first, so that they are merged.
+    async fn merge(&self, survivor: &Uuid, duplicate: &Uuid) -> Result<()> {
+        self.flush().await?;
+        self.store.merge(survivor, duplicate).await
+    }
+
+    async fn list(&self) -> Result<Vec<(Uuid, PeerHistory)>> {
+        let pending = self.pending.lock().unwrap().clone();
+        let mut peers: HashMap<Uuid, PeerHistory> = self.store.list().await?.into_iter().collect();
+        peers.extend(pending);
+        Ok(peers.into_iter().collect())
+    }
+
+    /// The update is queued, see [PeerWriteBehind::update()].
+    async fn update(&self, peer_id: &Uuid, f: PeerUpdate) -> Result<()> {
+        self.send(WriterCommand::Update(*peer_id, f)).await
+    }
+}
+
+fn writer_stopped() -> Error {
+    Error::Internal("peer store writer has stopped".to_string())
+}
+
+struct Writer<S: PeerStore + ?Sized> {
+    store: Arc<S>,
+    pending: Arc<Mutex<HashMap<Uuid, PeerHistory>>>,
+    max_pending: usize,
+}
+
+impl<S: PeerStore + ?Sized> Writer<S> {
+    async fn run(self, mut receiver: mpsc::Receiver<WriterCommand>, flush_interval: Duration) {
+        let mut interval = tokio::time::interval(flush_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                command = receiver.recv() => match command {
+                    Some(WriterCommand::Update(peer_id, f)) => {
+                        self.apply(peer_id, f).await;
+                        if self.pending.lock().unwrap().len() >= self.max_pending {
+                            self.flush_logged().await;
+                        }
+                    }
+                    Some(WriterCommand::Flush(reply)) => {
+                        let _ = reply.send(self.flush().await);
+                    }
+                    Some(WriterCommand::Shutdown(reply)) => {
+                        receiver.close();
+                        // apply the updates that were queued before the shutdown
+                        while let Some(command) = receiver.recv().await {
+                            if let WriterCommand::Update(peer_id, f) = command {
+                                self.apply(peer_id, f).await;
+                            }
+                        }
+                        let _ = reply.send(self.flush().await);
+                        return;
+                    }
+                    None => {
+                        self.flush_logged().await;
+                        return;
+                    }
+                },
+                _ = interval.tick() => self.flush_logged().await,
+            }
+        }
+    }
+
+    async fn apply(&self, peer_id: Uuid, f: PeerUpdate) {
+        if let Some(h) = self.pending.lock().unwrap().get_mut(&peer_id) {
+            f(h);
+            return;
+        }
+        let mut history = match self.store.get(&peer_id).await {
+            Ok(h) => h.unwrap_or_default(),
+            Err(e) => {
+                warn!("could not read peer {} from store: {}", peer_id, e);
+                PeerHistory::default()
+            }
+        };
+        f(&mut history);
+        self.pending.lock().unwrap().insert(peer_id, history);
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let batch: Vec<(Uuid, PeerHistory)> = self
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        if batch.is_empty() {
+            return Ok(());
+        }
+        // only the writer modifies the pending histories, so they can be removed once written
+        self.store.put_batch(batch).await?;
+        self.pending.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn flush_logged(&self) {
+        if let Err(e) = self.flush().await {
+            warn!("could not write peers to store: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::SessionStats;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct CountingStore {
+        inner: MemoryPeerStore,
+        batches: AtomicUsize,
+        writes: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PeerStore for CountingStore {
+        async fn get(&self, peer_id: &Uuid) -> Result<Option<PeerHistory>> {
+            self.inner.get(peer_id).await
+        }
+
+        async fn put_batch(&self, histories: Vec<(Uuid, PeerHistory)>) -> Result<()> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            self.writes.fetch_add(histories.len(), Ordering::SeqCst);
+            self.inner.put_batch(histories).await
+        }
+
+        async fn create(&self, address: SocketAddr) -> Result<Uuid> {
+            self.inner.create(address).await
+        }
+
+        async fn merge(&self, survivor: &Uuid, duplicate: &Uuid) -> Result<()> {
+            self.inner.merge(survivor, duplicate).await
+        }
+
+        async fn list(&self) -> Result<Vec<(Uuid, PeerHistory)>> {
+            self.inner.list().await
+        }
+    }
+
+    fn rtt_session(ms: u64) -> SessionStats {
+        SessionStats {
+            handshake_succeeded: true,
+            rtt: Some(Duration::from_millis(ms)),
+            ..SessionStats::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_updates() {
+        let store = Arc::new(CountingStore::default());
+        let (wb, j) = PeerWriteBehind::new(store.clone(), Duration::from_secs(3600), 100);
+        let peer = Uuid::new_v4();
+        for i in 0..10 {
+            wb.update(peer, move |h| h.record_session(&rtt_session(100 + i)))
+                .await
+                .unwrap();
+        }
+        wb.flush().await.unwrap();
+        assert_eq!(store.batches.load(Ordering::SeqCst), 1);
+        assert_eq!(store.writes.load(Ordering::SeqCst), 1);
+        let stored = store.get(&peer).await.unwrap().unwrap();
+        assert_eq!(stored.handshakes_attempted, 10);
+        // nothing pending, so another flush does not write
+        wb.flush().await.unwrap();
+        assert_eq!(store.batches.load(Ordering::SeqCst), 1);
+        wb.shutdown().await.unwrap();
+        j.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_pending_and_shutdown() {
+        let store = Arc::new(CountingStore::default());
+        let (wb, j) = PeerWriteBehind::new(store.clone(), Duration::from_secs(3600), 4);
+        let peers: Vec<Uuid> = (0..6).map(|_| Uuid::new_v4()).collect();
+        for p in peers.iter() {
+            wb.update(*p, |h| h.record_session(&rtt_session(50)))
+                .await
+                .unwrap();
+        }
+        // the fourth peer filled the pending set and caused a write
+        wb.update(peers[5], |h| h.record_session(&rtt_session(50)))
+            .await
+            .unwrap();
+        wb.shutdown().await.unwrap();
+        j.await.unwrap();
+        assert_eq!(store.batches.load(Ordering::SeqCst), 2);
+        assert_eq!(store.writes.load(Ordering::SeqCst), 6);
+        for (i, p) in peers.iter().enumerate() {
+            let h = store.get(p).await.unwrap().unwrap();
+            assert_eq!(h.handshakes_attempted, if i == 5 { 2 } else { 1 });
+        }
+        assert!(wb.update(peers[0], |_| {}).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn overlay() {
+        let store = Arc::new(MemoryPeerStore::default());
+        let peer = Uuid::new_v4();
+        let mut h = PeerHistory::default();
+        h.record_session(&rtt_session(10));
+        store.put_batch(vec![(peer, h)]).await.unwrap();
+        let (wb, j) = PeerWriteBehind::new(store.clone(), Duration::from_secs(3600), 100);
+        assert_eq!(
+            wb.get(&peer).await.unwrap().unwrap().handshakes_attempted,
+            1
+        );
+        wb.update(peer, |h| h.record_session(&rtt_session(10)))
+            .await
+            .unwrap();
+        // wait for the writer to apply the update, without flushing it
+        while wb.get(&peer).await.unwrap().unwrap().handshakes_attempted != 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            store
+                .get(&peer)
+                .await
+                .unwrap()
+                .unwrap()
+                .handshakes_attempted,
+            1
+        );
+        assert_eq!(wb.get(&Uuid::new_v4()).await.unwrap(), None);
+        let listed = wb.list().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].1.handshakes_attempted, 2);
+        wb.shutdown().await.unwrap();
+        j.await.unwrap();
+        assert_eq!(
+            store
+                .get(&peer)
+                .await
+                .unwrap()
+                .unwrap()
+                .handshakes_attempted,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates() {
+        let store = Arc::new(MemoryPeerStore::default());
+        let peer = Uuid::new_v4();
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .update(&peer, Box::new(|h| h.record_session(&rtt_session(10))))
+                        .await
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+        let h = store.get(&peer).await.unwrap().unwrap();
+        assert_eq!(h.handshakes_attempted, 16);
+    }
+
+    #[tokio::test]
+    async fn create_is_unique() {
+        let store = Arc::new(MemoryPeerStore::default());
+        let address: SocketAddr = "192.0.2.1:8333".parse().unwrap();
+        // a DNS seed and an inbound connection race to add the same peer
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move { store.find_or_create(address).await })
+            })
+            .collect();
+        let mut ids = Vec::new();
+        for h in handles {
+            ids.push(h.await.unwrap().unwrap());
+        }
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        assert!(matches!(
+            store.create(address).await,
+            Err(Error::PeerExists(id)) if id == ids[0]
+        ));
+        let other = store
+            .create("192.0.2.1:8334".parse().unwrap())
+            .await
+            .unwrap();
+        assert_ne!(other, ids[0]);
+    }
+
+    #[tokio::test]
+    async fn dedup() {
+        let store = MemoryPeerStore::default();
+        let address: SocketAddr = "192.0.2.1:8333".parse().unwrap();
+        // records written before addresses were unique
+        let mut seeded = PeerHistory {
+            address: Some(address),
+            ..PeerHistory::default()
+        };
+        seeded.record_session(&rtt_session(100));
+        seeded.record_session(&rtt_session(100));
+        let mut inbound = PeerHistory {
+            address: Some(address),
+            ..PeerHistory::default()
+        };
+        let until = std::time::SystemTime::now() + Duration::from_secs(600);
+        inbound.ban(address, until);
+        inbound.record_session(&rtt_session(300));
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let unrelated = store
+            .create("192.0.2.2:8333".parse().unwrap())
+            .await
+            .unwrap();
+        store
+            .put_batch(vec![(a, seeded), (b, inbound)])
+            .await
+            .unwrap();
+        assert_eq!(store.dedup().await.unwrap(), 1);
+        assert_eq!(store.dedup().await.unwrap(), 0);
+        // the peer with the most attempts survives with the merged state
+        assert!(store.get(&b).await.unwrap().is_none());
+        let merged = store.get(&a).await.unwrap().unwrap();
+        assert_eq!(merged.handshakes_attempted, 3);
+        assert!(merged.is_banned(std::time::SystemTime::now()));
+        assert_eq!(merged.rtt_ewma, Some(Duration::from_millis(200)));
+        assert_eq!(merged.quality_score, crate::p2p::quality_score(&merged));
+        assert!(store.get(&unrelated).await.unwrap().is_some());
+        assert_eq!(store.find_or_create(address).await.unwrap(), a);
+        assert!(store.merge(&a, &b).await.is_err());
+        assert!(store.merge(&a, &a).await.is_err());
+    }
+}
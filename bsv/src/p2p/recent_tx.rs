@@ -0,0 +1,182 @@
+use crate::bitcoin::TxHash;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The default number of transactions remembered by a [RecentTxCache].
+pub const DEFAULT_RECENT_TX_ENTRIES: usize = 100_000;
+/// The default time for which a transaction is remembered by a [RecentTxCache].
+pub const DEFAULT_RECENT_TX_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+struct Entry {
+    first_seen: Instant,
+    /// The peer that announced the transaction first, if it has been announced.
+    announcer: Option<Uuid>,
+    /// Whether the transaction itself has been received.
+    received: bool,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<TxHash, Entry>,
+    /// The transactions in the order they were first seen, for expiry.
+    order: VecDeque<TxHash>,
+    /// The number of transactions that each peer announced first.
+    first_announcements: HashMap<Uuid, u64>,
+}
+
+/// Remembers the transactions recently announced and received from all peers.
+///
+/// When several peers announce the same transaction, only the first announcement and the first copy
+/// of the transaction are passed on to the subscribers of the data channel. A single instance is shared
+/// by all of the connections of a [P2PManager](crate::p2p::P2PManager). The cache is bounded both in
+/// the number of transactions and in the time for which they are remembered.
+#[derive(Debug)]
+pub struct RecentTxCache {
+    max_entries: usize,
+    ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl RecentTxCache {
+    /// Create a cache that remembers at most `max_entries` transactions, each for at most `ttl`.
+    pub fn new(max_entries: usize, ttl: Duration) -> RecentTxCache {
+        RecentTxCache {
+            max_entries: max_entries.max(1),
+            ttl,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Record that a peer announced a transaction, returning true if it is the first announcement.
+    pub fn announced(&self, hash: &TxHash, peer_id: &Uuid, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = self.entry(&mut inner, hash, now);
+        if entry.announcer.is_some() || entry.received {
+            return false;
+        }
+        entry.announcer = Some(*peer_id);
+        *inner.first_announcements.entry(*peer_id).or_default() += 1;
+        true
+    }
+
+    /// Record that a transaction was received, returning true if it is the first copy.
+    pub fn received(&self, hash: &TxHash, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = self.entry(&mut inner, hash, now);
+        !std::mem::replace(&mut entry.received, true)
+    }
+
+    /// The peer that announced the transaction first, if it is remembered.
+    pub fn first_announcer(&self, hash: &TxHash) -> Option<Uuid> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .get(hash)
+            .and_then(|e| e.announcer)
+    }
+
+    /// The number of transactions that the peer announced before any other peer.
+    pub fn first_announcements(&self, peer_id: &Uuid) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .first_announcements
+            .get(peer_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The number of transactions remembered.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Whether no transactions are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the entry for the transaction, adding it if necessary, after removing the expired entries.
+    fn entry<'a>(&self, inner: &'a mut Inner, hash: &TxHash, now: Instant) -> &'a mut Entry {
+        while let Some(oldest) = inner.order.front() {
+            if now.saturating_duration_since(inner.entries[oldest].first_seen) < self.ttl {
+                break;
+            }
+            let oldest = inner.order.pop_front().unwrap();
+            inner.entries.remove(&oldest);
+        }
+        if !inner.entries.contains_key(hash) {
+            if inner.order.len() >= self.max_entries {
+                let oldest = inner.order.pop_front().unwrap();
+                inner.entries.remove(&oldest);
+            }
+            inner.order.push_back(*hash);
+            inner.entries.insert(
+                *hash,
+                Entry {
+                    first_seen: now,
+                    announcer: None,
+                    received: false,
+                },
+            );
+        }
+        inner.entries.get_mut(hash).unwrap()
+    }
+}
+
+impl Default for RecentTxCache {
+    fn default() -> Self {
+        RecentTxCache::new(DEFAULT_RECENT_TX_ENTRIES, DEFAULT_RECENT_TX_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::Hash;
+
+    #[test]
+    fn first_only() {
+        let cache = RecentTxCache::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = Instant::now();
+        let tx1 = Hash::sha256d(b"1");
+        let tx2 = Hash::sha256d(b"2");
+        assert!(cache.announced(&tx1, &a, now));
+        assert!(!cache.announced(&tx1, &b, now));
+        assert!(cache.announced(&tx2, &b, now));
+        assert_eq!(cache.first_announcer(&tx1), Some(a));
+        assert_eq!(cache.first_announcements(&a), 1);
+        assert_eq!(cache.first_announcements(&b), 1);
+        // the announced transaction is received once
+        assert!(cache.received(&tx1, now));
+        assert!(!cache.received(&tx1, now));
+        // a transaction received without an announcement is not announced afterwards
+        let tx3 = Hash::sha256d(b"3");
+        assert!(cache.received(&tx3, now));
+        assert!(!cache.announced(&tx3, &a, now));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn bounded() {
+        let cache = RecentTxCache::new(10, Duration::from_secs(60));
+        let peer = Uuid::new_v4();
+        let start = Instant::now();
+        let hashes: Vec<TxHash> = (0..20u8).map(|i| Hash::sha256d(&[i])).collect();
+        for h in hashes.iter() {
+            assert!(cache.received(h, start));
+        }
+        assert_eq!(cache.len(), 10);
+        // the oldest were forgotten
+        assert!(cache.received(&hashes[0], start));
+        assert!(!cache.received(&hashes[19], start));
+        // everything expires
+        let later = start + Duration::from_secs(60);
+        assert!(cache.announced(&hashes[19], &peer, later));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+//! Reading capture files recorded by a [MessageTap](crate::p2p::MessageTap).
+//!
+//! A capture file starts with [CAPTURE_MAGIC] and is followed by [CaptureRecord]s. The
+//! [CaptureReader] iterates over the records and can reconstruct the messages that were sent and
+//! received, offline.
+use crate::p2p::capture::{CaptureRecord, Direction, CAPTURE_MAGIC};
+use crate::p2p::channel::ChannelConfig;
+use crate::p2p::messages::{MessageFramer, P2PMessage};
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+use uuid::Uuid;
+
+/// A message reconstructed from a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedMessage {
+    pub direction: Direction,
+    pub peer_id: Uuid,
+    /// The time of the record in which the message was completed, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub message: P2PMessage,
+}
+
+/// Reads the records of a capture file.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Open the capture file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Create a reader, checking that the data starts with the capture file magic.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(Error::BadData("not a capture file".to_string()));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Reconstruct the messages from the remaining records.
+    ///
+    /// The bytes of each peer and direction are passed through a separate [MessageFramer], which uses
+    /// `config` for the magic bytes and size limits. Bytes that can not be decoded are skipped.
+    pub fn messages(self, config: &ChannelConfig) -> Result<Vec<CapturedMessage>> {
+        let mut framers: HashMap<(Uuid, Direction), MessageFramer> = HashMap::new();
+        let mut messages = Vec::new();
+        for record in self {
+            let record = record?;
+            let framer = framers
+                .entry((record.peer_id, record.direction))
+                .or_insert_with(|| MessageFramer::new(config.clone()));
+            framer.push_bytes(&record.data);
+            while let Some(r) = framer.next_message() {
+                if let Ok(message) = r {
+                    messages.push(CapturedMessage {
+                        direction: record.direction,
+                        peer_id: record.peer_id,
+                        timestamp: record.timestamp,
+                        message,
+                    });
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>> {
+        let mut direction = [0u8; 1];
+        match self.reader.read_exact(&mut direction) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let direction = match direction[0] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            d => return Err(Error::BadData(format!("unknown direction: {}", d))),
+        };
+        let mut fixed = [0u8; 28];
+        self.reader.read_exact(&mut fixed)?;
+        let peer_id = Uuid::from_slice(&fixed[..16]).unwrap();
+        let timestamp = u64::from_le_bytes(fixed[16..24].try_into().unwrap());
+        let len = u32::from_le_bytes(fixed[24..].try_into().unwrap()) as u64;
+        let mut data = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(Error::DataTooSmall);
+        }
+        Ok(Some(CaptureRecord {
+            direction,
+            peer_id,
+            timestamp,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
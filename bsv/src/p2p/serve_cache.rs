@@ -0,0 +1,304 @@
+use crate::bitcoin::Hash;
+use crate::p2p::messages::{InvItem, InvType};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// The default number of bytes held by a [ServeCache], 256MB.
+pub const DEFAULT_SERVE_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// A source of the blocks and transactions that are sent to peers in response to `getdata` requests.
+pub trait DataProvider: Send + Sync {
+    /// Get the encoded payload of the `tx` or `block` message for the item, or None if it is not known.
+    ///
+    /// This may read from disk or a database, the [ServeCache] avoids repeating the work.
+    fn get_data(&self, item: &InvItem) -> Option<Bytes>;
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Bytes,
+    /// The tick at which the entry was last used, its key in the order.
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Hash, Entry>,
+    /// The hashes of the entries by the tick at which they were last used, least recent first.
+    order: BTreeMap<u64, Hash>,
+    tick: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, hash: &Hash) -> Option<Bytes> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(hash)?;
+        self.order.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.order.insert(self.tick, *hash);
+        Some(entry.data.clone())
+    }
+}
+
+/// Statistics of a [ServeCache].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServeCacheStats {
+    /// The number of requests answered from the cache.
+    pub hits: u64,
+    /// The number of requests that were passed to the provider.
+    pub misses: u64,
+    /// The number of entries in the cache.
+    pub entries: usize,
+    /// The total size of the entries, in bytes.
+    pub bytes: usize,
+}
+
+impl ServeCacheStats {
+    /// The fraction of requests that were answered from the cache, zero if there have been none.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Holds the encoded blocks and transactions most recently served to peers.
+///
+/// Peers tend to request the same recently relayed objects, so a single instance is shared by all
+/// connections. Entries are [Bytes], which are sent to each peer without being copied. The least
+/// recently used entries are evicted to keep the total size within the byte budget, and an object
+/// that is larger than the whole budget is not cached at all.
+#[derive(Debug)]
+pub struct ServeCache {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+}
+
+impl ServeCache {
+    /// Create a cache that holds at most `max_bytes` of data.
+    pub fn new(max_bytes: usize) -> ServeCache {
+        ServeCache {
+            max_bytes,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Get the data for the item from the cache, or else from the provider, caching the result.
+    pub fn get_or_fetch(&self, item: &InvItem, provider: &dyn DataProvider) -> Option<Bytes> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(data) = inner.touch(&item.hash) {
+                inner.hits += 1;
+                return Some(data);
+            }
+            inner.misses += 1;
+        }
+        // the lock is not held while fetching, a concurrent request may fetch the same item
+        let data = provider.get_data(item)?;
+        self.insert(item.hash, data.clone());
+        Some(data)
+    }
+
+    /// Get the data for the hash if it is cached, without counting a hit or a miss.
+    pub fn get(&self, hash: &Hash) -> Option<Bytes> {
+        self.inner.lock().unwrap().touch(hash)
+    }
+
+    /// Add the data to the cache, evicting the least recently used entries to make room.
+    pub fn insert(&self, hash: Hash, data: Bytes) {
+        if data.len() > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.touch(&hash).is_some() {
+            return;
+        }
+        while inner.bytes + data.len() > self.max_bytes {
+            let (_, oldest) = inner.order.pop_first().unwrap();
+            let entry = inner.entries.remove(&oldest).unwrap();
+            inner.bytes -= entry.data.len();
+        }
+        let tick = inner.tick;
+        inner.bytes += data.len();
+        inner.order.insert(tick, hash);
+        inner.entries.insert(
+            hash,
+            Entry {
+                data,
+                last_used: tick,
+            },
+        );
+    }
+
+    /// Whether the data for the hash is cached.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.inner.lock().unwrap().entries.contains_key(hash)
+    }
+
+    /// The number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The total size of the entries, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
+    /// The current statistics of the cache.
+    pub fn stats(&self) -> ServeCacheStats {
+        let inner = self.inner.lock().unwrap();
+        ServeCacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
+
+impl Default for ServeCache {
+    fn default() -> Self {
+        ServeCache::new(DEFAULT_SERVE_CACHE_BYTES)
+    }
+}
+
+/// Determines how a peer connection responds to `getdata` requests.
+///
+/// When there is a [DataProvider], the transactions and blocks that are requested are sent to the
+/// peer, and the items that the provider does not have are listed in a `notfound` message. Without
+/// a provider the requests are only passed on to the data channel.
+#[derive(Clone, Default)]
+pub struct GetDataResponder {
+    /// The source of the data.
+    pub provider: Option<Arc<dyn DataProvider>>,
+    /// The cache in front of the provider, usually shared by all connections, if any.
+    pub cache: Option<Arc<ServeCache>>,
+}
+
+impl GetDataResponder {
+    /// Create a responder that answers requests from the provider, through a cache of `max_bytes`.
+    pub fn new(provider: Arc<dyn DataProvider>, max_bytes: usize) -> GetDataResponder {
+        GetDataResponder {
+            provider: Some(provider),
+            cache: Some(Arc::new(ServeCache::new(max_bytes))),
+        }
+    }
+
+    /// Whether the responder answers requests.
+    pub fn is_enabled(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    /// Get the data for a transaction or block item, None if the item is of another type or the
+    /// provider does not have it.
+    pub(crate) fn fetch(&self, item: &InvItem) -> Option<Bytes> {
+        if !matches!(item.obj_type, InvType::Tx | InvType::Block) {
+            return None;
+        }
+        let provider = self.provider.as_deref()?;
+        match &self.cache {
+            Some(cache) => cache.get_or_fetch(item, provider),
+            None => provider.get_data(item),
+        }
+    }
+}
+
+impl fmt::Debug for GetDataResponder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GetDataResponder")
+            .field("provider", &self.provider.is_some())
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves blocks of the given size, counting the calls.
+    struct CountingProvider {
+        size: usize,
+        calls: AtomicUsize,
+    }
+
+    impl CountingProvider {
+        fn new(size: usize) -> CountingProvider {
+            CountingProvider {
+                size,
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl DataProvider for CountingProvider {
+        fn get_data(&self, item: &InvItem) -> Option<Bytes> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Some(Bytes::from(vec![item.hash.hash[0]; self.size]))
+        }
+    }
+
+    fn block(i: u8) -> InvItem {
+        InvItem::block(Hash::sha256d(&[i]))
+    }
+
+    #[test]
+    fn one_fetch_for_two_peers() {
+        let provider = Arc::new(CountingProvider::new(1_000));
+        let responder = GetDataResponder::new(provider.clone(), 10_000);
+        // each connection has a clone of the responder, sharing the cache
+        let peers = [responder.clone(), responder.clone()];
+        let sent: Vec<Bytes> = peers.iter().map(|r| r.fetch(&block(1)).unwrap()).collect();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+        // the peers are sent the same buffer
+        assert_eq!(sent[0].as_ptr(), sent[1].as_ptr());
+        let stats = responder.cache.as_ref().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
+        assert_eq!(stats.bytes, 1_000);
+        // only transactions and blocks are served
+        let item = InvItem {
+            obj_type: InvType::CompactBlock,
+            hash: block(1).hash,
+        };
+        assert!(responder.fetch(&item).is_none());
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn evict_oldest() {
+        let provider = CountingProvider::new(400);
+        let cache = ServeCache::new(1_000);
+        cache.get_or_fetch(&block(1), &provider);
+        cache.get_or_fetch(&block(2), &provider);
+        // using the first makes the second the oldest
+        cache.get_or_fetch(&block(1), &provider);
+        cache.get_or_fetch(&block(3), &provider);
+        assert!(cache.contains(&block(1).hash));
+        assert!(!cache.contains(&block(2).hash));
+        assert!(cache.contains(&block(3).hash));
+        assert_eq!(cache.bytes(), 800);
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+
+        // too large to cache, but still served
+        let large = CountingProvider::new(2_000);
+        assert_eq!(cache.get_or_fetch(&block(4), &large).unwrap().len(), 2_000);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(ServeCacheStats::default().hit_rate(), 0.0);
+    }
+}
@@ -0,0 +1,684 @@
+use crate::p2p::channel::ChannelConfig;
+use crate::p2p::messages::{
+    P2PMessage, P2PMessageType, Ping, Protoconf, SendCmpct, Services, Version,
+};
+use crate::p2p::params::DEFAULT_MAX_PAYLOAD_SIZE;
+use crate::{Error, Result};
+use log::{trace, warn};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The result of a successful handshake with a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedSession {
+    /// The version message sent by the peer.
+    pub peer_version: Version,
+    /// How long the handshake took.
+    pub duration: Duration,
+    /// The protoconf sent by the peer before the handshake completed.
+    pub protoconf: Option<Protoconf>,
+    /// Whether the peer asked for headers announcements before the handshake completed.
+    pub send_headers: bool,
+    /// The difference between the timestamp in the version message of the peer and our clock, in
+    /// seconds. This is positive if the clock of the peer is ahead of ours.
+    pub time_offset: i64,
+}
+
+impl NegotiatedSession {
+    /// The settings of the session that are kept with the history of the peer.
+    pub fn summary(&self) -> SessionSummary {
+        SessionSummary::new(
+            &self.peer_version,
+            self.protoconf.as_ref(),
+            self.send_headers,
+        )
+    }
+}
+
+/// The settings that a peer negotiated in its most recent handshake.
+///
+/// These rarely change between connections to the same peer, so they are stored in its
+/// [PeerHistory](crate::p2p::PeerHistory) and offered as a hint when it is next connected to, for
+/// example to size buffers before its protoconf arrives. A hint is only a guess: the peer may have
+/// been upgraded or reconfigured since, so limits are only enforced once the peer has sent them again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// The user agent of the peer.
+    pub user_agent: String,
+    /// The protocol version of the peer.
+    pub version: u32,
+    /// The services offered by the peer.
+    pub services: Services,
+    /// The maximum payload the peer accepts, from its protoconf, if it sent one.
+    pub max_recv_payload_length: Option<u32>,
+    /// Whether the peer asked for headers announcements.
+    pub send_headers: bool,
+}
+
+impl SessionSummary {
+    /// Summarize a handshake from the version of the peer and the settings it sent with it.
+    pub fn new(version: &Version, protoconf: Option<&Protoconf>, send_headers: bool) -> Self {
+        SessionSummary {
+            user_agent: version.user_agent.clone(),
+            version: version.version,
+            services: version.services,
+            max_recv_payload_length: protoconf.map(|p| p.max_recv_payload_length),
+            send_headers,
+        }
+    }
+}
+
+/// The compact block settings exchanged with a peer, see BIP 152.
+///
+/// A peer sends its sendcmpct once the handshake is complete. Compact blocks are not supported yet,
+/// so the sendcmpct is recorded and answered with one that declines high-bandwidth mode, which tells
+/// the peer that the message was understood.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactBlockState {
+    /// The most recent sendcmpct from the peer, None if it has not sent one.
+    pub peer: Option<SendCmpct>,
+    /// The sendcmpct that was sent to the peer, None if none has been sent.
+    pub ours: Option<SendCmpct>,
+}
+
+impl CompactBlockState {
+    /// Whether the peer asked for new blocks to be announced as version 1 compact blocks.
+    pub fn peer_high_bandwidth(&self) -> bool {
+        self.peer.as_ref().is_some_and(|s| s.use_cmpctblock())
+    }
+
+    /// Record a sendcmpct from the peer, returning the reply if none has been sent yet.
+    pub(crate) fn received(&mut self, send_cmpct: &SendCmpct) -> Option<P2PMessage> {
+        self.peer = Some(send_cmpct.clone());
+        if self.ours.is_some() {
+            return None;
+        }
+        let reply = SendCmpct::new(false);
+        self.ours = Some(reply.clone());
+        Some(P2PMessage::SendCmpct(reply))
+    }
+}
+
+/// The most that a peer may send before it completes the handshake.
+///
+/// Pings and settings are accepted before the verack, so without a limit a peer could hold a
+/// connection slot by sending them and never completing the handshake. The handshake fails, as a
+/// protocol violation, once the peer sends more than `max_messages` messages or more than
+/// `max_bytes` bytes of payload, or if it is not complete within `timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HandshakeLimits {
+    /// The number of messages that may be received before the handshake is complete.
+    pub max_messages: u32,
+    /// The number of payload bytes that may be received before the handshake is complete.
+    pub max_bytes: u64,
+    /// The time from sending our version in which the handshake must be complete.
+    #[serde(deserialize_with = "crate::util::duration::deserialize")]
+    pub timeout: Duration,
+}
+
+impl Default for HandshakeLimits {
+    /// 32 messages, 64KB and 60 seconds, many times what a node needs for a handshake.
+    fn default() -> Self {
+        HandshakeLimits {
+            max_messages: 32,
+            max_bytes: 64 * 1024,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Counts the messages received during a handshake against the [HandshakeLimits].
+#[derive(Debug)]
+pub(crate) struct HandshakeBudget {
+    limits: HandshakeLimits,
+    messages: u32,
+    bytes: u64,
+}
+
+impl HandshakeBudget {
+    pub(crate) fn new(limits: HandshakeLimits) -> HandshakeBudget {
+        HandshakeBudget {
+            limits,
+            messages: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Count a message, failing if either of the limits is exceeded.
+    pub(crate) fn spend(&mut self, msg: &P2PMessage) -> Result<()> {
+        self.messages = self.messages.saturating_add(1);
+        self.bytes = self.bytes.saturating_add(msg.size() as u64);
+        if self.messages > self.limits.max_messages || self.bytes > self.limits.max_bytes {
+            return Err(Error::HandshakeLimitExceeded {
+                messages: self.messages,
+                bytes: self.bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The protocol logic of a connection to a peer, without the actor framework.
+///
+/// A PeerSession performs the handshake, reads and writes messages, and keeps track of the settings
+/// that the peer sends (protoconf, sendheaders, feefilter) and of ping round-trip times. It works over
+/// any stream that implements the tokio IO traits, so the caller can establish the connection (over
+/// TCP, TLS or a proxy) and schedule the reads and writes however it likes.
+pub struct PeerSession<S: AsyncRead + AsyncWrite + Unpin + Send> {
+    stream: S,
+    config: ChannelConfig,
+    /// Whether the peer has asked for headers instead of block inventory announcements.
+    send_headers: bool,
+    /// Whether the peer wants transactions to be relayed.
+    relay_tx: bool,
+    /// The nonce of the outstanding ping and when it was sent.
+    ping: Option<(u64, Instant)>,
+    /// The round-trip time of the most recent ping.
+    rtt: Option<Duration>,
+    /// The sendcmpct messages exchanged with the peer.
+    compact_blocks: CompactBlockState,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> PeerSession<S> {
+    /// Create a new session over a connected stream.
+    pub fn new(stream: S, config: ChannelConfig) -> PeerSession<S> {
+        PeerSession {
+            stream,
+            config,
+            send_headers: false,
+            relay_tx: true,
+            ping: None,
+            rtt: None,
+            compact_blocks: CompactBlockState::default(),
+        }
+    }
+
+    /// Perform the handshake, sending `version` and waiting for the version and verack of the peer.
+    ///
+    /// Some nodes send pings and their settings (protoconf, sendheaders) before the verack. Pings are
+    /// answered immediately and the settings are applied once the handshake is complete. A data
+    /// message before the handshake is complete, or a sendcmpct before the verack of the peer, is a
+    /// protocol violation and fails the handshake, as does a version message whose timestamp is
+    /// further from our clock than the `max_time_offset` of the configuration, or that does not
+    /// offer all of the `required_services`, or more messages than the `handshake_limits` allow. The
+    /// handshake fails with [Error::HandshakeTimeout] if it is not complete within the timeout of
+    /// the `handshake_limits`.
+    ///
+    /// Once the handshake is complete, the configuration messages (protoconf and sendheaders) are sent.
+    pub async fn handshake(&mut self, version: Version) -> Result<NegotiatedSession> {
+        let started = Instant::now();
+        let mut peer_version = None;
+        let mut verack_received = false;
+        let mut protoconf = None;
+        let mut send_headers = false;
+        let mut send_cmpct = None;
+        let mut time_offset = 0;
+        let mut budget = HandshakeBudget::new(self.config.handshake_limits);
+        let limit = self.config.handshake_limits.timeout;
+        let exchange = async {
+            self.send_message(&P2PMessage::Version(version)).await?;
+            while peer_version.is_none() || !verack_received {
+                let msg = P2PMessage::read(&mut self.stream, &self.config).await?;
+                budget.spend(&msg)?;
+                match msg {
+                    P2PMessage::Version(v) => {
+                        if peer_version.is_some() {
+                            return Err(Error::BadData("duplicate version message".to_string()));
+                        }
+                        v.validate()?;
+                        v.services.check_required(self.config.required_services)?;
+                        time_offset = v.check_time_offset(self.config.max_time_offset)?;
+                        if let Some(slot) = &self.config.slot {
+                            slot.record_time_offset(time_offset);
+                        }
+                        self.config.protocol_version = v.version;
+                        self.config
+                            .external_address
+                            .report(SocketAddr::new(v.recv_addr.ip, v.recv_addr.port));
+                        self.relay_tx = v.relay;
+                        self.send_message(&P2PMessage::Verack).await?;
+                        peer_version = Some(v);
+                    }
+                    P2PMessage::Verack => verack_received = true,
+                    P2PMessage::Ping(p) => {
+                        self.send_message(&P2PMessage::Pong(Ping::new(p.nonce)))
+                            .await?;
+                    }
+                    P2PMessage::Protoconf(p) => protoconf = Some(p),
+                    P2PMessage::SendHeaders => send_headers = true,
+                    P2PMessage::SendCmpct(_) if !verack_received => {
+                        return Err(Error::BadData(
+                            "received sendcmpct before verack".to_string(),
+                        ));
+                    }
+                    P2PMessage::SendCmpct(s) => send_cmpct = Some(s),
+                    msg if matches!(P2PMessageType::from(&msg), P2PMessageType::Data) => {
+                        return Err(Error::BadData(format!(
+                            "received {} message during handshake",
+                            msg.command()
+                        )));
+                    }
+                    msg => warn!(
+                        "received unexpected message during handshake, message: {:?}",
+                        msg
+                    ),
+                }
+            }
+            Ok::<(), Error>(())
+        };
+        tokio::time::timeout(limit, exchange)
+            .await
+            .map_err(|_| Error::HandshakeTimeout(limit))??;
+        if let Some(p) = &protoconf {
+            apply_peer_settings(&mut self.config, &P2PMessage::Protoconf(p.clone()));
+        }
+        self.send_headers |= send_headers;
+        for msg in config_messages(&self.config) {
+            self.send_message(&msg).await?;
+        }
+        if let Some(reply) = send_cmpct.and_then(|s| self.compact_blocks.received(&s)) {
+            self.send_message(&reply).await?;
+        }
+        Ok(NegotiatedSession {
+            peer_version: peer_version.unwrap(),
+            duration: started.elapsed(),
+            protoconf,
+            send_headers,
+            time_offset,
+        })
+    }
+
+    /// Read the next message from the peer.
+    ///
+    /// Pings are answered, pongs are matched with the outstanding ping, and the settings sent by the
+    /// peer are applied to the configuration. The first sendcmpct of the peer is answered, see
+    /// [CompactBlockState]. All messages are returned to the caller.
+    pub async fn read_message(&mut self) -> Result<P2PMessage> {
+        let msg = P2PMessage::read(&mut self.stream, &self.config).await?;
+        match &msg {
+            P2PMessage::Ping(p) => {
+                self.send_message(&P2PMessage::Pong(Ping::new(p.nonce)))
+                    .await?;
+            }
+            P2PMessage::Pong(p) => match self.ping {
+                Some((nonce, sent)) if nonce == p.nonce => {
+                    self.rtt = Some(sent.elapsed());
+                    self.ping = None;
+                }
+                _ => trace!("received unexpected pong, nonce: {}", p.nonce),
+            },
+            P2PMessage::SendHeaders => self.send_headers = true,
+            P2PMessage::SendCmpct(s) => {
+                if let Some(reply) = self.compact_blocks.received(s) {
+                    self.send_message(&reply).await?;
+                }
+            }
+            _ => {
+                apply_peer_settings(&mut self.config, &msg);
+            }
+        }
+        Ok(msg)
+    }
+
+    /// Send a message to the peer.
+    pub async fn send_message(&mut self, msg: &P2PMessage) -> Result<()> {
+        msg.write(&mut self.stream, &self.config).await
+    }
+
+    /// Send a ping to the peer, returning its nonce.
+    ///
+    /// The round-trip time is measured when the matching pong is read. A new ping replaces an
+    /// outstanding one.
+    pub async fn send_ping(&mut self) -> Result<u64> {
+        let nonce = rand::random();
+        self.send_message(&P2PMessage::Ping(Ping::new(nonce)))
+            .await?;
+        self.ping = Some((nonce, Instant::now()));
+        Ok(nonce)
+    }
+
+    /// How long the outstanding ping has been waiting for a pong, None if there is no outstanding ping.
+    pub fn ping_outstanding(&self) -> Option<Duration> {
+        self.ping.map(|(_, sent)| sent.elapsed())
+    }
+
+    /// The round-trip time of the most recently answered ping.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Whether the peer has asked to receive headers instead of block inventory announcements.
+    pub fn send_headers(&self) -> bool {
+        self.send_headers
+    }
+
+    /// The sendcmpct messages exchanged with the peer.
+    pub fn compact_blocks(&self) -> &CompactBlockState {
+        &self.compact_blocks
+    }
+
+    /// Whether the peer wants transactions to be announced.
+    pub fn relay_tx(&self) -> bool {
+        self.relay_tx
+    }
+
+    /// The configuration of the session, including the settings received from the peer.
+    pub fn config(&self) -> &ChannelConfig {
+        &self.config
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+/// The messages that configure the connection, sent once the handshake is complete.
+pub(crate) fn config_messages(config: &ChannelConfig) -> Vec<P2PMessage> {
+    let mut msgs = Vec::new();
+    // ask for larger messages if necessary
+    if config.max_recv_payload_size > DEFAULT_MAX_PAYLOAD_SIZE
+        && config.max_recv_payload_size <= u32::MAX as u64
+    {
+        msgs.push(P2PMessage::Protoconf(Protoconf::new(
+            config.max_recv_payload_size as u32,
+        )));
+    }
+    msgs.push(P2PMessage::SendHeaders);
+    msgs
+}
+
+/// Apply a setting sent by the peer to the configuration, returning false if the message is not a
+/// setting.
+pub(crate) fn apply_peer_settings(config: &mut ChannelConfig, msg: &P2PMessage) -> bool {
+    match msg {
+        // we can send larger messages to the peer
+        P2PMessage::Protoconf(p) => config.max_send_payload_size = p.max_recv_payload_length as u64,
+        P2PMessage::FeeFilter(f) => config.min_fee_rate = f.min_fee_rate,
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::{FeeFilter, Services};
+    use crate::util::FeeRate;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn pair() -> (PeerSession<DuplexStream>, PeerSession<DuplexStream>) {
+        let (a, b) = duplex(1 << 16);
+        (
+            PeerSession::new(a, ChannelConfig::default()),
+            PeerSession::new(b, ChannelConfig::default()),
+        )
+    }
+
+    #[tokio::test]
+    async fn handshake_and_ping() {
+        let (mut a, mut b) = pair();
+        let b_version = Version {
+            user_agent: "other".to_string(),
+            relay: false,
+            ..Version::default()
+        };
+        let (ra, rb) = tokio::join!(a.handshake(Version::default()), b.handshake(b_version));
+        let (ra, rb) = (ra.unwrap(), rb.unwrap());
+        assert_eq!(ra.peer_version.user_agent, "other");
+        assert_eq!(rb.peer_version.user_agent, "rust-bitcoinsv");
+        assert!(!a.relay_tx());
+        assert!(b.relay_tx());
+
+        // the configuration messages sent at the end of the handshake
+        assert!(matches!(
+            b.read_message().await.unwrap(),
+            P2PMessage::Protoconf(_)
+        ));
+        assert_eq!(b.read_message().await.unwrap(), P2PMessage::SendHeaders);
+        assert!(b.send_headers());
+        assert_eq!(
+            b.config().max_send_payload_size,
+            a.config().max_recv_payload_size
+        );
+
+        let nonce = a.send_ping().await.unwrap();
+        assert!(a.ping_outstanding().is_some());
+        // b answers the ping while reading it
+        assert_eq!(
+            b.read_message().await.unwrap(),
+            P2PMessage::Ping(Ping::new(nonce))
+        );
+        // a reads b's configuration messages and then the pong
+        a.read_message().await.unwrap();
+        a.read_message().await.unwrap();
+        assert_eq!(
+            a.read_message().await.unwrap(),
+            P2PMessage::Pong(Ping::new(nonce))
+        );
+        assert!(a.ping_outstanding().is_none());
+        assert!(a.rtt().is_some());
+    }
+
+    #[tokio::test]
+    async fn skewed_clock() {
+        let (mut a, mut b) = pair();
+        let ahead = Version {
+            timestamp: crate::util::epoch_secs() + 80 * 60,
+            ..Version::default()
+        };
+        let other = tokio::spawn(async move { b.handshake(ahead).await });
+        assert!(matches!(
+            a.handshake(Version::default()).await,
+            Err(Error::BadData(_))
+        ));
+        // the peer is not sent a verack and sees the stream close
+        drop(a);
+        assert!(other.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn required_services() {
+        let required = ChannelConfig {
+            required_services: Services::NETWORK,
+            ..ChannelConfig::default()
+        };
+        // a peer offering more than the required services is accepted
+        let (a, b) = duplex(1 << 16);
+        let mut a = PeerSession::new(a, required.clone());
+        let mut b = PeerSession::new(b, ChannelConfig::default());
+        let full = Version {
+            services: Services::NETWORK | Services::BLOOM | Services(1 << 40),
+            ..Version::default()
+        };
+        let other = tokio::spawn(async move { b.handshake(full).await });
+        let negotiated = a.handshake(Version::default()).await.unwrap();
+        assert!(negotiated.peer_version.services.has(Services::NETWORK));
+        assert!(other.await.unwrap().is_ok());
+
+        // a peer that lacks one of them is refused
+        let (a, b) = duplex(1 << 16);
+        let mut a = PeerSession::new(a, required);
+        let mut b = PeerSession::new(b, ChannelConfig::default());
+        let limited = Version {
+            services: Services::NETWORK_LIMITED | Services::BLOOM,
+            ..Version::default()
+        };
+        let other = tokio::spawn(async move { b.handshake(limited).await });
+        assert!(matches!(
+            a.handshake(Version::default()).await,
+            Err(Error::BadData(_))
+        ));
+        drop(a);
+        assert!(other.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn handshake_limits() {
+        let limited = ChannelConfig {
+            handshake_limits: HandshakeLimits {
+                max_messages: 8,
+                ..Default::default()
+            },
+            ..ChannelConfig::default()
+        };
+        let (a, b) = duplex(1 << 16);
+        let mut a = PeerSession::new(a, limited);
+        let mut b = PeerSession::new(b, ChannelConfig::default());
+        // the peer sends its version and then pings, but never a verack
+        b.send_message(&P2PMessage::Version(Version::default()))
+            .await
+            .unwrap();
+        for nonce in 0..8 {
+            b.send_message(&P2PMessage::Ping(Ping::new(nonce)))
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            a.handshake(Version::default()).await,
+            Err(Error::HandshakeLimitExceeded { messages: 9, .. })
+        ));
+
+        // the payload bytes are counted too
+        let mut budget = HandshakeBudget::new(HandshakeLimits {
+            max_messages: 100,
+            max_bytes: 20,
+            ..Default::default()
+        });
+        assert!(budget.spend(&P2PMessage::Ping(Ping::new(1))).is_ok());
+        assert!(budget.spend(&P2PMessage::Ping(Ping::new(2))).is_ok());
+        assert!(budget.spend(&P2PMessage::Ping(Ping::new(3))).is_err());
+    }
+
+    /// A peer that says nothing fails the handshake once the timeout has elapsed.
+    #[tokio::test(start_paused = true)]
+    async fn handshake_timeout() {
+        let (a, _b) = duplex(1 << 16);
+        let mut a = PeerSession::new(a, ChannelConfig::default());
+        let start = tokio::time::Instant::now();
+        assert!(matches!(
+            a.handshake(Version::default()).await,
+            Err(Error::HandshakeTimeout(limit)) if limit == Duration::from_secs(60)
+        ));
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn peer_settings() {
+        let (mut a, mut b) = pair();
+        let rate = FeeRate::from_sats_per_kb(500);
+        a.send_message(&P2PMessage::FeeFilter(FeeFilter::new(rate)))
+            .await
+            .unwrap();
+        b.read_message().await.unwrap();
+        assert_eq!(b.config().min_fee_rate, rate);
+        // an unexpected pong is returned but does not produce a round-trip time
+        a.send_message(&P2PMessage::Pong(Ping::new(7)))
+            .await
+            .unwrap();
+        b.read_message().await.unwrap();
+        assert_eq!(b.rtt(), None);
+    }
+
+    #[tokio::test]
+    async fn early_handshake_messages() {
+        let (mut a, mut b) = pair();
+        let peer = async {
+            b.send_message(&P2PMessage::Version(Version::default()))
+                .await?;
+            b.send_message(&P2PMessage::Ping(Ping::new(9))).await?;
+            b.send_message(&P2PMessage::Protoconf(Protoconf::new(4_000_000)))
+                .await?;
+            b.send_message(&P2PMessage::SendHeaders).await?;
+            // the version from a, the verack and the pong arrive before our verack is sent
+            assert!(matches!(b.read_message().await?, P2PMessage::Version(_)));
+            assert_eq!(b.read_message().await?, P2PMessage::Verack);
+            assert_eq!(b.read_message().await?, P2PMessage::Pong(Ping::new(9)));
+            b.send_message(&P2PMessage::Verack).await
+        };
+        let (ra, rb) = tokio::join!(a.handshake(Version::default()), peer);
+        rb.unwrap();
+        let session = ra.unwrap();
+        assert_eq!(session.protoconf, Some(Protoconf::new(4_000_000)));
+        assert!(session.send_headers);
+        assert!(a.send_headers());
+        assert_eq!(a.config().max_send_payload_size, 4_000_000);
+    }
+
+    #[tokio::test]
+    async fn send_cmpct_negotiation() {
+        let (mut a, mut b) = pair();
+        let (ra, rb) = tokio::join!(
+            a.handshake(Version::default()),
+            b.handshake(Version::default())
+        );
+        ra.unwrap();
+        rb.unwrap();
+        for _ in 0..2 {
+            b.send_message(&P2PMessage::SendCmpct(SendCmpct::new(true)))
+                .await
+                .unwrap();
+        }
+        // a reads the configuration messages of b and both sendcmpct, answering only the first
+        while !matches!(a.read_message().await.unwrap(), P2PMessage::SendCmpct(_)) {}
+        a.read_message().await.unwrap();
+        assert!(a.compact_blocks().peer_high_bandwidth());
+        assert_eq!(a.compact_blocks().ours, Some(SendCmpct::new(false)));
+        let nonce = a.send_ping().await.unwrap();
+        let mut replies = Vec::new();
+        loop {
+            match b.read_message().await.unwrap() {
+                P2PMessage::SendCmpct(s) => replies.push(s),
+                P2PMessage::Ping(p) if p.nonce == nonce => break,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            replies,
+            vec![SendCmpct {
+                enable: 0,
+                version: 1
+            }]
+        );
+        assert!(!b.compact_blocks().peer_high_bandwidth());
+    }
+
+    #[tokio::test]
+    async fn send_cmpct_before_verack() {
+        let (mut a, mut b) = pair();
+        let (ra, _) = tokio::join!(a.handshake(Version::default()), async {
+            b.send_message(&P2PMessage::Version(Version::default()))
+                .await?;
+            b.send_message(&P2PMessage::SendCmpct(SendCmpct::new(false)))
+                .await
+        });
+        assert!(matches!(ra, Err(Error::BadData(_))));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_early_data() {
+        let (mut a, mut b) = pair();
+        let (ra, _) = tokio::join!(a.handshake(Version::default()), async {
+            b.send_message(&P2PMessage::Version(Version::default()))
+                .await?;
+            b.send_message(&P2PMessage::Mempool).await
+        });
+        assert!(matches!(ra, Err(Error::BadData(_))));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_old_version() {
+        let (mut a, mut b) = pair();
+        let old = Version {
+            version: 60000,
+            ..Version::default()
+        };
+        let (ra, _) = tokio::join!(a.handshake(Version::default()), async {
+            b.send_message(&P2PMessage::Version(old)).await
+        });
+        assert!(ra.is_err());
+    }
+}
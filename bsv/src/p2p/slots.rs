@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The number of connection slots in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlotCounts {
+    /// Slots of connections that have not completed the handshake.
+    pub pending: usize,
+    /// Slots of connections that have completed the handshake.
+    pub established: usize,
+}
+
+impl SlotCounts {
+    /// The number of slots in use.
+    pub fn total(&self) -> usize {
+        self.pending + self.established
+    }
+}
+
+#[derive(Debug, Default)]
+struct SlotState {
+    counts: SlotCounts,
+    // the clock offsets reported by the peers of the slots, in no particular order
+    time_offsets: Vec<i64>,
+}
+
+/// Limits the number of connections, counting pending and established connections separately.
+///
+/// A slot is reserved before a connection is started, and is released when the [SlotGuard] is
+/// dropped, so a slot can not be leaked by a connection that ends unexpectedly. The slots can be
+/// shared between threads, cloning produces a handle to the same slots.
+///
+/// The slots also hold the difference between the clock of each connected peer and ours, from which
+/// the network time offset is estimated.
+#[derive(Debug, Clone)]
+pub struct ConnectionSlots {
+    max: usize,
+    state: Arc<Mutex<SlotState>>,
+}
+
+impl ConnectionSlots {
+    /// Create slots for at most `max` connections.
+    pub fn new(max: usize) -> ConnectionSlots {
+        ConnectionSlots {
+            max,
+            state: Arc::new(Mutex::new(SlotState::default())),
+        }
+    }
+
+    /// Reserve a slot for a pending connection, None if all of the slots are in use.
+    pub fn reserve(&self) -> Option<SlotGuard> {
+        let mut state = self.state.lock().unwrap();
+        if state.counts.total() >= self.max {
+            return None;
+        }
+        state.counts.pending += 1;
+        Some(SlotGuard {
+            state: self.state.clone(),
+            established: AtomicBool::new(false),
+            released: AtomicBool::new(false),
+            time_offset: Mutex::new(None),
+        })
+    }
+
+    /// The number of slots in use.
+    pub fn counts(&self) -> SlotCounts {
+        self.state.lock().unwrap().counts
+    }
+
+    /// The median of the clock offsets recorded for the slots in use, in seconds, None if no offset
+    /// has been recorded. With an even number of offsets, this is the mean of the middle two.
+    pub fn median_time_offset(&self) -> Option<i64> {
+        let mut offsets = self.state.lock().unwrap().time_offsets.clone();
+        offsets.sort_unstable();
+        let n = offsets.len();
+        match n {
+            0 => None,
+            _ if n % 2 == 1 => Some(offsets[n / 2]),
+            _ => Some(((offsets[n / 2 - 1] as i128 + offsets[n / 2] as i128) / 2) as i64),
+        }
+    }
+
+    /// The maximum number of connections.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// A reserved connection slot, released when dropped or by [release()](SlotGuard::release).
+#[derive(Debug)]
+pub struct SlotGuard {
+    state: Arc<Mutex<SlotState>>,
+    established: AtomicBool,
+    released: AtomicBool,
+    time_offset: Mutex<Option<i64>>,
+}
+
+impl SlotGuard {
+    /// Move the slot from pending to established, when the connection has completed the handshake.
+    /// Marking a slot more than once has no effect.
+    pub fn mark_established(&self) {
+        let mut state = self.state.lock().unwrap();
+        if self.is_released() {
+            return;
+        }
+        if !self.established.swap(true, Ordering::SeqCst) {
+            state.counts.pending -= 1;
+            state.counts.established += 1;
+        }
+    }
+
+    /// Record the difference between the clock of the peer and ours, replacing any earlier offset.
+    pub fn record_time_offset(&self, offset: i64) {
+        let mut state = self.state.lock().unwrap();
+        if self.is_released() {
+            return;
+        }
+        if let Some(old) = self.time_offset.lock().unwrap().replace(offset) {
+            remove_offset(&mut state.time_offsets, old);
+        }
+        state.time_offsets.push(offset);
+    }
+
+    /// The difference between the clock of the peer and ours, if it has been recorded.
+    pub fn time_offset(&self) -> Option<i64> {
+        *self.time_offset.lock().unwrap()
+    }
+
+    /// Whether the connection has completed the handshake.
+    pub fn is_established(&self) -> bool {
+        self.established.load(Ordering::SeqCst)
+    }
+
+    /// Release the slot before the guard is dropped, for example when the connection that holds
+    /// the guard has crashed and its tasks may still be holding it. Releasing more than once has
+    /// no effect, and the guard can not be marked or record an offset afterwards.
+    pub fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        if self.released.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if self.is_established() {
+            state.counts.established -= 1;
+        } else {
+            state.counts.pending -= 1;
+        }
+        if let Some(offset) = self.time_offset.lock().unwrap().take() {
+            remove_offset(&mut state.time_offsets, offset);
+        }
+    }
+
+    /// Whether the slot has been released.
+    pub fn is_released(&self) -> bool {
+        self.released.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+fn remove_offset(offsets: &mut Vec<i64>, offset: i64) {
+    if let Some(i) = offsets.iter().position(|o| *o == offset) {
+        offsets.swap_remove(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_and_establish() {
+        let slots = ConnectionSlots::new(2);
+        let a = slots.reserve().unwrap();
+        let b = slots.reserve().unwrap();
+        assert!(slots.reserve().is_none());
+        assert_eq!(
+            slots.counts(),
+            SlotCounts {
+                pending: 2,
+                established: 0
+            }
+        );
+        a.mark_established();
+        a.mark_established();
+        assert!(a.is_established());
+        assert_eq!(
+            slots.counts(),
+            SlotCounts {
+                pending: 1,
+                established: 1
+            }
+        );
+        // still full, establishing does not free a slot
+        assert!(slots.reserve().is_none());
+        drop(b);
+        assert_eq!(slots.counts().pending, 0);
+        assert!(slots.reserve().is_some());
+    }
+
+    #[test]
+    fn release() {
+        let slots = ConnectionSlots::new(2);
+        let guard = Arc::new(slots.reserve().unwrap());
+        guard.record_time_offset(30);
+        let held = guard.clone();
+        guard.release();
+        guard.release();
+        assert!(held.is_released());
+        assert_eq!(slots.counts(), SlotCounts::default());
+        assert_eq!(slots.median_time_offset(), None);
+        // a task that still holds the guard can not use the slot
+        held.mark_established();
+        held.record_time_offset(40);
+        assert_eq!(slots.counts(), SlotCounts::default());
+        assert_eq!(slots.median_time_offset(), None);
+        drop(guard);
+        drop(held);
+        assert_eq!(slots.counts(), SlotCounts::default());
+    }
+
+    #[test]
+    fn drop_releases_slot() {
+        let slots = ConnectionSlots::new(1);
+        {
+            let guard = slots.reserve().unwrap();
+            guard.mark_established();
+            assert!(slots.reserve().is_none());
+        }
+        assert_eq!(slots.counts(), SlotCounts::default());
+        // a guard dropped on another thread releases the slot too
+        let guard = slots.reserve().unwrap();
+        std::thread::spawn(move || drop(guard)).join().unwrap();
+        assert_eq!(slots.counts().total(), 0);
+    }
+
+    #[test]
+    fn median_time_offset() {
+        let slots = ConnectionSlots::new(8);
+        assert_eq!(slots.median_time_offset(), None);
+        let guards: Vec<SlotGuard> = [30, -10, 600]
+            .iter()
+            .map(|o| {
+                let g = slots.reserve().unwrap();
+                g.record_time_offset(*o);
+                g
+            })
+            .collect();
+        assert_eq!(slots.median_time_offset(), Some(30));
+        // with an even number of offsets, the mean of the middle two
+        let fourth = slots.reserve().unwrap();
+        fourth.record_time_offset(-20);
+        assert_eq!(slots.median_time_offset(), Some(10));
+        fourth.record_time_offset(50);
+        assert_eq!(fourth.time_offset(), Some(50));
+        assert_eq!(slots.median_time_offset(), Some(40));
+        // the offset goes with the slot
+        drop(fourth);
+        assert_eq!(slots.median_time_offset(), Some(30));
+        drop(guards);
+        assert_eq!(slots.median_time_offset(), None);
+    }
+
+    #[test]
+    fn concurrent_reservations() {
+        let slots = ConnectionSlots::new(10);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let slots = slots.clone();
+                std::thread::spawn(move || {
+                    (0..100).filter_map(|_| slots.reserve()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let guards: Vec<SlotGuard> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        assert_eq!(guards.len(), 10);
+        assert_eq!(slots.counts().pending, 10);
+        drop(guards);
+        assert_eq!(slots.counts().total(), 0);
+    }
+}
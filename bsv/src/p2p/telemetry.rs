@@ -0,0 +1,240 @@
+//! Structured log records for P2P connections.
+//!
+//! The library logs using the [log] crate, which has no spans, so the context of a connection is included
+//! in every record that relates to it as `name=value` fields. The records for a connection can then be
+//! correlated even though they are emitted by different tasks. The targets and field names are defined
+//! here as constants and are stable, so they can be relied upon by filters and log processors.
+//!
+//! * [TARGET_CONNECTION] - connection events, such as connected and disconnected.
+//! * [TARGET_HANDSHAKE] - completion of the version handshake, including its duration.
+//! * [TARGET_MESSAGE] - each message received and handled, including its command, payload size and
+//!   the time taken to handle it. These are logged at trace level.
+//! * [TARGET_BAN] - the decisions to ban and unban peers, see [LogAuditSink](crate::p2p::LogAuditSink).
+//!
+//! Error events are also counted, see [error_counts()].
+use crate::p2p::PeerAddress;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+/// Target for connection events.
+pub const TARGET_CONNECTION: &str = "bitcoinsv::p2p::connection";
+/// Target for handshake events.
+pub const TARGET_HANDSHAKE: &str = "bitcoinsv::p2p::handshake";
+/// Target for message events.
+pub const TARGET_MESSAGE: &str = "bitcoinsv::p2p::message";
+/// Target for ban and unban decisions.
+pub const TARGET_BAN: &str = "bitcoinsv::p2p::ban";
+
+/// The id of the peer.
+pub const FIELD_PEER_ID: &str = "peer_id";
+/// The socket address of the peer.
+pub const FIELD_ADDR: &str = "addr";
+/// The direction of the connection, either [DIRECTION_OUTBOUND] or [DIRECTION_INBOUND].
+pub const FIELD_DIRECTION: &str = "direction";
+/// The event that occurred.
+pub const FIELD_EVENT: &str = "event";
+/// The command of the message.
+pub const FIELD_COMMAND: &str = "command";
+/// The size of the payload of the message, in bytes.
+pub const FIELD_PAYLOAD_SIZE: &str = "payload_size";
+/// The duration of the operation, in microseconds.
+pub const FIELD_DURATION_US: &str = "duration_us";
+/// Why the connection ended, see [DisconnectCause](crate::p2p::DisconnectCause).
+pub const FIELD_CAUSE: &str = "cause";
+
+/// A connection that we initiated.
+pub const DIRECTION_OUTBOUND: &str = "outbound";
+/// A connection initiated by the peer.
+pub const DIRECTION_INBOUND: &str = "inbound";
+
+/// The event recorded when the TCP connection is established.
+pub const EVENT_CONNECTED: &str = "connected";
+/// The event recorded when the connection is closed.
+pub const EVENT_DISCONNECTED: &str = "disconnected";
+/// The event recorded when the handshake is complete.
+pub const EVENT_HANDSHAKE_COMPLETE: &str = "handshake_complete";
+
+/// The context of a connection, which is included in every record relating to the connection.
+///
+/// The Display implementation produces the `peer_id`, `addr` and `direction` fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionContext {
+    pub peer_id: Uuid,
+    pub addr: SocketAddr,
+    pub direction: &'static str,
+}
+
+impl ConnectionContext {
+    /// The context for a connection that we initiated.
+    pub fn outbound(peer: &PeerAddress) -> ConnectionContext {
+        ConnectionContext {
+            peer_id: peer.peer_id,
+            addr: peer.address,
+            direction: DIRECTION_OUTBOUND,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}={} {}={} {}={}",
+            FIELD_PEER_ID, self.peer_id, FIELD_ADDR, self.addr, FIELD_DIRECTION, self.direction
+        )
+    }
+}
+
+static READ_ERRORS: AtomicU64 = AtomicU64::new(0);
+static WRITE_ERRORS: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKE_ERRORS: AtomicU64 = AtomicU64::new(0);
+static PROTOCOL_ANOMALIES: AtomicU64 = AtomicU64::new(0);
+static RESOURCE_EXHAUSTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The kinds of error that are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A message could not be read from the peer.
+    Read,
+    /// A message could not be written to the peer.
+    Write,
+    /// The peer sent an unexpected message during the handshake.
+    Handshake,
+    /// The peer sent data that was tolerated but should not have been sent, such as extra bytes
+    /// before the magic.
+    ProtocolAnomaly,
+    /// A connection ended because this process ran out of a resource, such as file descriptors.
+    ResourceExhausted,
+}
+
+/// The number of errors of each kind since the process started, across all connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorCounts {
+    pub read: u64,
+    pub write: u64,
+    pub handshake: u64,
+    pub protocol_anomaly: u64,
+    pub resource_exhausted: u64,
+}
+
+/// Get the number of errors of each kind since the process started.
+pub fn error_counts() -> ErrorCounts {
+    ErrorCounts {
+        read: READ_ERRORS.load(Ordering::Relaxed),
+        write: WRITE_ERRORS.load(Ordering::Relaxed),
+        handshake: HANDSHAKE_ERRORS.load(Ordering::Relaxed),
+        protocol_anomaly: PROTOCOL_ANOMALIES.load(Ordering::Relaxed),
+        resource_exhausted: RESOURCE_EXHAUSTIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// Count an error.
+pub(crate) fn record_error(kind: ErrorKind) {
+    let counter = match kind {
+        ErrorKind::Read => &READ_ERRORS,
+        ErrorKind::Write => &WRITE_ERRORS,
+        ErrorKind::Handshake => &HANDSHAKE_ERRORS,
+        ErrorKind::ProtocolAnomaly => &PROTOCOL_ANOMALIES,
+        ErrorKind::ResourceExhausted => &RESOURCE_EXHAUSTIONS,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::BlockchainId;
+    use crate::p2p::channel::{ChannelConfig, PeerChannel};
+    use crate::p2p::fake_peer::{FakePeer, FakePeerStep};
+    use crate::p2p::messages::{P2PMessage, Ping};
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::RwLock;
+
+    /// Captures all log records, for all tests in the process.
+    struct CaptureLogger {
+        records: Mutex<Vec<(String, Level, String)>>,
+    }
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.records.lock().unwrap().push((
+                record.target().to_string(),
+                record.level(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    #[tokio::test]
+    async fn handshake_records() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+        let steps = vec![
+            FakePeerStep::expect(|m| matches!(m, P2PMessage::SendHeaders)),
+            FakePeerStep::Send(P2PMessage::Ping(Ping::new(9))),
+            FakePeerStep::expect(|m| matches!(m, P2PMessage::Pong(_))),
+        ];
+        let peer = FakePeer::start(BlockchainId::Main, steps).await;
+        let address = peer.peer_address();
+        let (data_tx, _data_rx) = tokio::sync::broadcast::channel(10);
+        let config = Arc::new(RwLock::new(ChannelConfig::default()));
+        let (channel, j) = PeerChannel::new(address.clone(), config, data_tx)
+            .await
+            .unwrap();
+        peer.finish().await.unwrap();
+        channel.close().await;
+        j.await.unwrap();
+
+        let context = ConnectionContext::outbound(&address).to_string();
+        assert!(context.contains("direction=outbound"));
+        // the records for this connection, without the context
+        let records: Vec<(String, Level, String)> = LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, msg)| msg.starts_with(&context))
+            .map(|(t, l, msg)| (t.clone(), *l, msg[context.len()..].trim().to_string()))
+            .collect();
+        let position = |target: &str, prefix: &str| {
+            records
+                .iter()
+                .position(|(t, _, msg)| t == target && msg.starts_with(prefix))
+                .unwrap_or_else(|| panic!("no record {} {}", target, prefix))
+        };
+        let connected = position(TARGET_CONNECTION, "event=connected");
+        let version = position(TARGET_MESSAGE, "command=version payload_size=");
+        let verack = position(TARGET_MESSAGE, "command=verack payload_size=0 duration_us=");
+        let handshake = position(TARGET_HANDSHAKE, "event=handshake_complete duration_us=");
+        let ping = position(TARGET_MESSAGE, "command=ping payload_size=8 duration_us=");
+        let disconnected = position(TARGET_CONNECTION, "event=disconnected");
+        assert_eq!(connected, 0);
+        // message records are written after the message has been handled, the handshake completes
+        // while the verack is being handled
+        assert!(version < handshake && handshake < verack);
+        assert!(verack < ping && ping < disconnected);
+        assert_eq!(records[connected].1, Level::Info);
+        assert_eq!(records[ping].1, Level::Trace);
+    }
+
+    #[test]
+    fn count_errors() {
+        let before = error_counts();
+        record_error(ErrorKind::Write);
+        let after = error_counts();
+        assert!(after.write > before.write);
+    }
+}
@@ -0,0 +1,277 @@
+use serde::Deserialize;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{sleep, Instant, Sleep};
+
+/// The minimum rate at which a peer must send the payload of a message.
+///
+/// Reading a payload is given `intervals` consecutive intervals in which fewer than `min_bytes` are
+/// received before it is abandoned. This stops a peer from holding a connection by trickling a large
+/// message a few bytes at a time, which a timeout that is reset on every read would not catch. The
+/// time between messages is not limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinThroughput {
+    /// The number of bytes that must be received in each interval.
+    pub min_bytes: u64,
+    /// The length of the intervals.
+    #[serde(deserialize_with = "crate::util::duration::deserialize")]
+    pub interval: Duration,
+    /// The number of consecutive slow intervals after which the read is abandoned.
+    pub intervals: u32,
+}
+
+impl Default for MinThroughput {
+    /// 1,000 bytes every 10 seconds, abandoned after a minute.
+    fn default() -> Self {
+        MinThroughput {
+            min_bytes: 1_000,
+            interval: Duration::from_secs(10),
+            intervals: 6,
+        }
+    }
+}
+
+/// Passes reads through to a reader, failing them with [io::ErrorKind::TimedOut] if the data
+/// arrives more slowly than the [MinThroughput].
+///
+/// The intervals are measured from the first read, so wrap the reader just before the payload is
+/// read. This is used when reading messages and can also wrap the reader of a
+/// [FullBlockStream](crate::bitcoin::FullBlockStream).
+pub struct StallGuard<R> {
+    reader: R,
+    limit: Option<MinThroughput>,
+    received: u64,
+    in_interval: u64,
+    slow_intervals: u32,
+    deadline: Option<Pin<Box<Sleep>>>,
+    stalled: bool,
+}
+
+impl<R> StallGuard<R> {
+    /// Guard the reader, no limit is applied if `limit` is None.
+    pub fn new(reader: R, limit: Option<MinThroughput>) -> StallGuard<R> {
+        StallGuard {
+            reader,
+            limit,
+            received: 0,
+            in_interval: 0,
+            slow_intervals: 0,
+            deadline: None,
+            stalled: false,
+        }
+    }
+
+    /// The number of bytes read through the guard.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Whether reading has been abandoned because the data arrived too slowly.
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    /// Check the intervals that have ended, returning true if the limit has been exceeded.
+    fn check(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(limit) = self.limit else {
+            return false;
+        };
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Box::pin(sleep(limit.interval)));
+        while deadline.as_mut().poll(cx).is_ready() {
+            if self.in_interval < limit.min_bytes {
+                self.slow_intervals += 1;
+            } else {
+                self.slow_intervals = 0;
+            }
+            self.in_interval = 0;
+            if self.slow_intervals >= limit.intervals {
+                self.stalled = true;
+                return true;
+            }
+            let next = deadline.deadline() + limit.interval;
+            deadline.as_mut().reset(next.max(Instant::now()));
+        }
+        false
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for StallGuard<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.stalled || this.check(cx) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("transfer stalled after {} bytes", this.received),
+            )));
+        }
+        let before = buf.filled().len();
+        let r = Pin::new(&mut this.reader).poll_read(cx, buf);
+        let n = (buf.filled().len() - before) as u64;
+        this.received += n;
+        this.in_interval += n;
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Send `total` bytes, `chunk` bytes at a time with `pause` between them.
+    fn trickle(
+        mut writer: tokio::io::DuplexStream,
+        total: usize,
+        chunk: usize,
+        pause: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut sent = 0;
+            while sent < total {
+                if writer.write_all(&vec![0; chunk]).await.is_err() {
+                    return;
+                }
+                sent += chunk;
+                sleep(pause).await;
+            }
+        })
+    }
+
+    fn limit() -> Option<MinThroughput> {
+        Some(MinThroughput {
+            min_bytes: 100,
+            interval: Duration::from_secs(10),
+            intervals: 3,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_reader_stalls() {
+        let (reader, writer) = tokio::io::duplex(1 << 16);
+        let _peer = trickle(writer, 10_000, 1, Duration::from_secs(1));
+        let mut guard = StallGuard::new(reader, limit());
+        let start = Instant::now();
+        let mut buf = vec![0; 10_000];
+        let e = guard.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(guard.is_stalled());
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        // one byte a second for 30 seconds
+        assert!(
+            (29..=31).contains(&guard.received()),
+            "{}",
+            guard.received()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_reader_stalls() {
+        let (reader, _writer) = tokio::io::duplex(1 << 16);
+        let mut guard = StallGuard::new(reader, limit());
+        let start = Instant::now();
+        let mut buf = [0; 10];
+        assert!(guard.read_exact(&mut buf).await.is_err());
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        assert_eq!(guard.received(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fast_enough() {
+        // 50 bytes a second is 500 per interval
+        let (reader, writer) = tokio::io::duplex(1 << 16);
+        let _peer = trickle(writer, 5_000, 50, Duration::from_secs(1));
+        let mut guard = StallGuard::new(reader, limit());
+        let mut buf = vec![0; 5_000];
+        guard.read_exact(&mut buf).await.unwrap();
+        assert!(!guard.is_stalled());
+
+        let (reader, writer) = tokio::io::duplex(1 << 16);
+        let _peer = trickle(writer, 10_000, 1, Duration::from_secs(1));
+        let mut guard = StallGuard::new(reader, None);
+        let mut buf = vec![0; 100];
+        guard.read_exact(&mut buf).await.unwrap();
+    }
+
+    /// Reading a message reports how much of the payload was received before it stalled.
+    #[tokio::test(start_paused = true)]
+    async fn read_message_stalls() {
+        use crate::bitcoin::{Block, BlockHeader};
+        use crate::p2p::{ChannelConfig, P2PMessage};
+        use crate::Error;
+
+        let block = Block {
+            header: BlockHeader::default(),
+            transactions: vec![],
+        };
+        let mut bytes = Vec::new();
+        let config = ChannelConfig::default();
+        P2PMessage::Block(block)
+            .write(&mut bytes, &config)
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = tokio::io::duplex(1 << 16);
+        // the header and half of the payload
+        writer.write_all(&bytes[..24 + 40]).await.unwrap();
+        let config = ChannelConfig {
+            min_throughput: limit(),
+            ..config
+        };
+        match P2PMessage::read(&mut reader, &config).await {
+            Err(Error::StalledTransfer {
+                command,
+                received,
+                expected,
+            }) => {
+                assert_eq!(command, "block");
+                assert_eq!(received, 40);
+                assert_eq!(expected, 81);
+            }
+            r => panic!("expected a stalled transfer, got {:?}", r),
+        }
+    }
+
+    /// The transactions of a streamed block stop when the block stalls.
+    #[tokio::test(start_paused = true)]
+    async fn block_stream_stalls() {
+        use crate::bitcoin::{
+            AsyncEncodable, Block, BlockHeader, FullBlockStream, Script, Tx, TxOutput,
+        };
+        use tokio_stream::StreamExt;
+
+        let tx = |i: u8| Tx {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![TxOutput::new(1, Script::from(vec![i; 100]))],
+            lock_time: 0,
+        };
+        let block = Block {
+            header: BlockHeader::default(),
+            transactions: (0..10).map(tx).collect(),
+        };
+        let bytes = block.to_binary_buf().unwrap();
+        let (reader, mut writer) = tokio::io::duplex(1 << 16);
+        // the header and the first two transactions, then nothing
+        writer.write_all(&bytes[..81 + 2 * 119]).await.unwrap();
+        let guard = StallGuard::new(reader, limit());
+        let mut stream = FullBlockStream::new(Box::new(guard)).await.unwrap();
+        let mut received = 0;
+        while let Some(r) = stream.next().await {
+            match r {
+                Ok(_) => received += 1,
+                Err(_) => break,
+            }
+        }
+        assert_eq!(received, 2);
+    }
+}
@@ -0,0 +1,62 @@
+//! The types that most users of the library need, so that they can be imported together.
+//!
+//! ```
+//! use bitcoinsv::prelude::*;
+//!
+//! let genesis = BlockHeader::get_genesis(BlockchainId::Main);
+//! assert_eq!(genesis.prev_hash, Hash::ZERO);
+//! ```
+pub use crate::bitcoin::{
+    Address, AsyncEncodable, Block, BlockHash, BlockHeader, BlockchainId, Encodable, Hash, Script,
+    Tx, TxHash,
+};
+pub use crate::util::Amount;
+
+#[cfg(test)]
+mod tests {
+    /// The names re-exported by the prelude, parsed from its source.
+    fn exported_names() -> Vec<String> {
+        let source = include_str!("prelude.rs")
+            .split("#[cfg(test)]")
+            .next()
+            .unwrap();
+        let mut names = Vec::new();
+        for export in source.split("pub use ").skip(1) {
+            let path = export.split(';').next().unwrap();
+            let items = match path.find('{') {
+                Some(open) => &path[open + 1..path.rfind('}').unwrap()],
+                None => path.rsplit("::").next().unwrap(),
+            };
+            names.extend(
+                items
+                    .split(',')
+                    .map(|n| n.trim().to_string())
+                    .filter(|n| !n.is_empty()),
+            );
+        }
+        names.sort();
+        names
+    }
+
+    /// A snapshot of the prelude, so that adding or removing an item is a deliberate change.
+    #[test]
+    fn prelude_items() {
+        assert_eq!(
+            exported_names(),
+            [
+                "Address",
+                "Amount",
+                "AsyncEncodable",
+                "Block",
+                "BlockHash",
+                "BlockHeader",
+                "BlockchainId",
+                "Encodable",
+                "Hash",
+                "Script",
+                "Tx",
+                "TxHash",
+            ]
+        );
+    }
+}
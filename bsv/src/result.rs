@@ -1,3 +1,6 @@
+use crate::bitcoin::BlockValidationError;
+#[cfg(feature = "p2p")]
+use crate::p2p::{ConfigError, InventoryError};
 use base58::FromBase58Error;
 use hex::FromHexError;
 use std::fmt::Formatter;
@@ -26,6 +29,59 @@ pub enum Error {
     DataTooSmall,
     /// The data provided is too large to perform the operation.
     DataTooLarge,
+    /// A Script number is not minimally encoded.
+    NonMinimalNumber,
+    /// The data does not have the required length in bytes.
+    WrongLength { expected: usize, actual: usize },
+    /// A script element is larger than the maximum allowed size.
+    ElementTooLarge { size: usize, max: usize },
+    /// The structure of a block is invalid.
+    BlockValidation(BlockValidationError),
+    /// The configuration is invalid.
+    #[cfg(feature = "p2p")]
+    ConfigError(ConfigError),
+    /// All of the connection slots are in use.
+    NoConnectionSlot,
+    /// A peer with the same address is already stored, with the given id.
+    #[cfg(feature = "p2p")]
+    PeerExists(uuid::Uuid),
+    /// The payload of a message arrived too slowly and reading it was abandoned, see
+    /// [MinThroughput](crate::p2p::MinThroughput).
+    StalledTransfer {
+        command: String,
+        received: u64,
+        expected: u64,
+    },
+    /// The payload of a message is larger than is allowed for its command, see
+    /// [max_size_for_command()](crate::p2p::max_size_for_command).
+    OversizedMessage {
+        command: String,
+        size: u64,
+        max: u64,
+    },
+    /// The peer sent more messages, or more bytes, than are allowed before the handshake is complete,
+    /// see [HandshakeLimits](crate::p2p::HandshakeLimits).
+    HandshakeLimitExceeded { messages: u32, bytes: u64 },
+    /// The handshake was not complete within the given time, see
+    /// [HandshakeLimits](crate::p2p::HandshakeLimits).
+    HandshakeTimeout(std::time::Duration),
+    /// The inventory of an `inv`, `getdata` or `notfound` message is invalid.
+    #[cfg(feature = "p2p")]
+    InvalidInventory {
+        command: String,
+        error: InventoryError,
+    },
+    /// A message can not be sent because its payload is larger than the peer accepts, and it can not
+    /// be split into smaller messages.
+    MessageTooLargeForPeer {
+        command: String,
+        size: u64,
+        max: u64,
+    },
+    /// The transaction spends an outpoint that is already spent by the given transaction in the mempool.
+    MempoolConflict(crate::bitcoin::TxHash),
+    /// There are insufficient funds to pay for the outputs and fee, the shortfall in satoshis is given.
+    InsufficientFunds(u64),
     /// Internal error
     Internal(String),
     /// Internal errors
@@ -41,6 +97,7 @@ pub enum Error {
     /// String conversion error
     Utf8Error(FromUtf8Error),
     /// Error from minactor
+    #[cfg(feature = "p2p")]
     MinActorError(minactor::Error),
 }
 
@@ -55,6 +112,56 @@ impl std::fmt::Display for Error {
             Error::UnrecognizedOpCode => f.write_str("unrecognized opcode"),
             Error::DataTooSmall => f.write_str("data too small"),
             Error::DataTooLarge => f.write_str("data too large"),
+            Error::NonMinimalNumber => f.write_str("script number is not minimally encoded"),
+            Error::WrongLength { expected, actual } => f.write_str(&format!(
+                "wrong length: expected {} bytes, got {}",
+                expected, actual
+            )),
+            Error::ElementTooLarge { size, max } => f.write_str(&format!(
+                "script element size {} exceeds maximum {}",
+                size, max
+            )),
+            Error::BlockValidation(e) => f.write_str(&format!("Invalid block: {}", e)),
+            #[cfg(feature = "p2p")]
+            Error::ConfigError(e) => f.write_str(&format!("Invalid configuration: {}", e)),
+            Error::NoConnectionSlot => f.write_str("No connection slot available"),
+            #[cfg(feature = "p2p")]
+            Error::PeerExists(id) => f.write_str(&format!("Peer already exists: {}", id)),
+            Error::StalledTransfer {
+                command,
+                received,
+                expected,
+            } => f.write_str(&format!(
+                "Stalled transfer of {} message: received {} of {} bytes",
+                command, received, expected
+            )),
+            Error::OversizedMessage { command, size, max } => f.write_str(&format!(
+                "Oversized {} message: payload of {} bytes exceeds {}",
+                command, size, max
+            )),
+            Error::HandshakeLimitExceeded { messages, bytes } => f.write_str(&format!(
+                "Handshake limit exceeded: {} messages of {} bytes received before the handshake completed",
+                messages, bytes
+            )),
+            Error::HandshakeTimeout(d) => f.write_str(&format!(
+                "Handshake timeout: the handshake was not complete within {:?}",
+                d
+            )),
+            #[cfg(feature = "p2p")]
+            Error::InvalidInventory { command, error } => {
+                f.write_str(&format!("Invalid {} message: {}", command, error))
+            }
+            Error::MessageTooLargeForPeer { command, size, max } => f.write_str(&format!(
+                "{} message too large for peer: payload of {} bytes exceeds {}",
+                command, size, max
+            )),
+            Error::MempoolConflict(hash) => {
+                f.write_str(&format!("Conflicts with mempool transaction {}", hash))
+            }
+            Error::InsufficientFunds(shortfall) => f.write_str(&format!(
+                "Insufficient funds: short by {} satoshis",
+                shortfall
+            )),
             Error::Internal(s) => f.write_str(&format!("Internal error: {}", s)), // Added this line
             Error::InternalError(e) => e.fmt(f),
             Error::FromHexError(e) => f.write_str(&format!("Hex decoding error: {}", e)),
@@ -62,6 +169,7 @@ impl std::fmt::Display for Error {
             Error::Secp256k1Error(e) => f.write_str(&format!("secpk256k1 error: {:?}", e)),
             Error::IOError(e) => f.write_str(&format!("IO error: {}", e)),
             Error::Utf8Error(e) => f.write_str(&format!("UTF8 error: {}", e)),
+            #[cfg(feature = "p2p")]
             Error::MinActorError(e) => f.write_str(&format!("Minactor error: {:?}", e)), // todo: revert to display when implemented
         }
     }
@@ -73,6 +181,19 @@ impl From<InternalError> for Error {
     }
 }
 
+impl From<BlockValidationError> for Error {
+    fn from(e: BlockValidationError) -> Self {
+        Error::BlockValidation(e)
+    }
+}
+
+#[cfg(feature = "p2p")]
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::ConfigError(e)
+    }
+}
+
 impl From<FromHexError> for Error {
     fn from(e: FromHexError) -> Self {
         Error::FromHexError(e)
@@ -103,6 +224,7 @@ impl From<secp256k1::Error> for Error {
     }
 }
 
+#[cfg(feature = "p2p")]
 impl From<minactor::Error> for Error {
     fn from(e: minactor::Error) -> Self {
         Error::MinActorError(e)
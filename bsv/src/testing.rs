@@ -0,0 +1,292 @@
+//! [proptest] strategies that generate transactions, scripts, block headers and blocks.
+//!
+//! These are for property tests, both in this crate and in applications that use it. They are
+//! available with the `test-utils` feature. The values are structurally valid: they encode and
+//! decode, every transaction has at least one input and one output, and a generated
+//! [Block](crate::bitcoin::Block) passes [Block::validate()](crate::bitcoin::Block::validate).
+//! Signatures are not valid and proof of work is not checked.
+//!
+//! ```ignore
+//! use bitcoinsv::bitcoin::{AsyncEncodable, Tx};
+//! use bitcoinsv::testing::arb_tx;
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn round_trip(tx in arb_tx()) {
+//!         prop_assert_eq!(Tx::from_binary_buf(&tx.to_binary_buf().unwrap()).unwrap(), tx);
+//!     }
+//! }
+//! ```
+use crate::bitcoin::{
+    merkle_root, Block, BlockHeader, Hash, Outpoint, Script, Tx, TxHash, TxInput, TxOutput,
+};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::collections::HashSet;
+
+/// The largest number of satoshis that will ever exist.
+const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+/// The kinds of script that can be generated by [arb_script()].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// Pay to public key hash.
+    P2pkh,
+    /// Pay to a public key, which is not necessarily a point on the curve.
+    P2pk,
+    /// An unspendable output carrying data, `OP_FALSE OP_RETURN <data>`.
+    OpReturn,
+    /// A script of data pushes, see [arb_push_only_script()].
+    PushOnly,
+}
+
+impl ScriptKind {
+    /// Every kind of script.
+    pub const ALL: [ScriptKind; 4] = [
+        ScriptKind::P2pkh,
+        ScriptKind::P2pk,
+        ScriptKind::OpReturn,
+        ScriptKind::PushOnly,
+    ];
+}
+
+/// The shape of the transactions generated by [arb_tx_with()].
+#[derive(Debug, Clone)]
+pub struct TxParams {
+    /// The maximum number of inputs, there is always at least one.
+    pub max_inputs: usize,
+    /// The maximum number of outputs, there is always at least one.
+    pub max_outputs: usize,
+    /// The kinds of script used for the outputs. The input scripts are always push-only.
+    pub script_kinds: Vec<ScriptKind>,
+}
+
+impl Default for TxParams {
+    fn default() -> Self {
+        TxParams {
+            max_inputs: 4,
+            max_outputs: 4,
+            script_kinds: ScriptKind::ALL.to_vec(),
+        }
+    }
+}
+
+/// Any hash.
+pub fn arb_hash() -> impl Strategy<Value = Hash> {
+    any::<[u8; 32]>().prop_map(|hash| Hash { hash })
+}
+
+/// A script of at most `max_pushes` data pushes, using the small integer opcodes, direct pushes
+/// and OP_PUSHDATA1.
+pub fn arb_push_only_script(max_pushes: usize) -> impl Strategy<Value = Script> {
+    let push = prop_oneof![
+        (0u8..=16).prop_map(|n| if n == 0 { vec![0x00] } else { vec![0x50 + n] }),
+        vec(any::<u8>(), 1..=75).prop_map(|data| {
+            let mut op = vec![data.len() as u8];
+            op.extend(data);
+            op
+        }),
+        vec(any::<u8>(), 76..=255).prop_map(|data| {
+            let mut op = vec![0x4c, data.len() as u8];
+            op.extend(data);
+            op
+        }),
+    ];
+    vec(push, 0..=max_pushes).prop_map(|ops| Script::from(ops.concat()))
+}
+
+/// A script of one of the given kinds, which must not be empty.
+pub fn arb_script(kinds: &[ScriptKind]) -> BoxedStrategy<Script> {
+    assert!(!kinds.is_empty(), "at least one script kind is needed");
+    let strategies: Vec<BoxedStrategy<Script>> = kinds
+        .iter()
+        .map(|kind| match kind {
+            ScriptKind::P2pkh => any::<[u8; 20]>()
+                .prop_map(|hash| {
+                    let mut raw = vec![0x76, 0xa9, 0x14];
+                    raw.extend_from_slice(&hash);
+                    raw.extend_from_slice(&[0x88, 0xac]);
+                    Script::from(raw)
+                })
+                .boxed(),
+            ScriptKind::P2pk => (prop_oneof![Just(0x02u8), Just(0x03u8)], any::<[u8; 32]>())
+                .prop_map(|(prefix, x)| {
+                    let mut raw = vec![0x21, prefix];
+                    raw.extend_from_slice(&x);
+                    raw.push(0xac);
+                    Script::from(raw)
+                })
+                .boxed(),
+            ScriptKind::OpReturn => vec(any::<u8>(), 0..=75)
+                .prop_map(|data| {
+                    let mut raw = vec![0x00, 0x6a, data.len() as u8];
+                    raw.extend(data);
+                    Script::from(raw)
+                })
+                .boxed(),
+            ScriptKind::PushOnly => arb_push_only_script(4).boxed(),
+        })
+        .collect();
+    proptest::strategy::Union::new(strategies).boxed()
+}
+
+/// Any outpoint. The null outpoint of a coinbase input is possible but very unlikely.
+pub fn arb_outpoint() -> impl Strategy<Value = Outpoint> {
+    (arb_hash(), any::<u32>()).prop_map(|(tx_hash, index)| Outpoint { tx_hash, index })
+}
+
+/// An input spending any outpoint, with a push-only script.
+pub fn arb_tx_input() -> impl Strategy<Value = TxInput> {
+    (arb_outpoint(), arb_push_only_script(3), any::<u32>()).prop_map(
+        |(outpoint, script, sequence)| TxInput {
+            outpoint,
+            script,
+            sequence,
+        },
+    )
+}
+
+/// An output of at most the total supply, with a script of one of the given kinds.
+pub fn arb_tx_output(kinds: &[ScriptKind]) -> impl Strategy<Value = TxOutput> {
+    (0..=MAX_MONEY, arb_script(kinds)).prop_map(|(value, script)| TxOutput { value, script })
+}
+
+/// A transaction with the default [TxParams].
+pub fn arb_tx() -> BoxedStrategy<Tx> {
+    arb_tx_with(TxParams::default())
+}
+
+/// A transaction with the shape given by `params`.
+pub fn arb_tx_with(params: TxParams) -> BoxedStrategy<Tx> {
+    (
+        any::<u32>(),
+        vec(arb_tx_input(), 1..=params.max_inputs.max(1)),
+        vec(
+            arb_tx_output(&params.script_kinds),
+            1..=params.max_outputs.max(1),
+        ),
+        any::<u32>(),
+    )
+        .prop_map(|(version, inputs, outputs, lock_time)| Tx {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+        .boxed()
+}
+
+/// A coinbase transaction, with a script of 2 to 100 bytes and one or two outputs.
+pub fn arb_coinbase_tx() -> impl Strategy<Value = Tx> {
+    (
+        vec(any::<u8>(), 2..=100),
+        vec(arb_tx_output(&[ScriptKind::P2pkh]), 1..=2),
+    )
+        .prop_map(|(coinbase_script, outputs)| Tx {
+            version: 1,
+            inputs: vec![TxInput {
+                outpoint: Outpoint {
+                    tx_hash: Hash::ZERO,
+                    index: u32::MAX,
+                },
+                script: Script::from(coinbase_script),
+                sequence: u32::MAX,
+            }],
+            outputs,
+            lock_time: 0,
+        })
+}
+
+/// Any block header. The proof of work is not valid.
+pub fn arb_block_header() -> impl Strategy<Value = BlockHeader> {
+    (
+        any::<u32>(),
+        arb_hash(),
+        arb_hash(),
+        any::<u32>(),
+        any::<u32>(),
+        any::<u32>(),
+    )
+        .prop_map(
+            |(version, prev_hash, merkle_root, timestamp, bits, nonce)| BlockHeader {
+                version,
+                prev_hash,
+                merkle_root,
+                timestamp,
+                bits,
+                nonce,
+            },
+        )
+}
+
+/// A block of a coinbase and at most `max_txs - 1` other transactions, with the merkle root of the
+/// transactions in the header.
+pub fn arb_block(max_txs: usize) -> BoxedStrategy<Block> {
+    let others = vec(arb_tx(), 0..max_txs.max(1));
+    (arb_block_header(), arb_coinbase_tx(), others)
+        .prop_map(|(mut header, coinbase, others)| {
+            // shrinking can make transactions equal, a block can not hold duplicates
+            let mut seen = HashSet::new();
+            let mut transactions = vec![coinbase];
+            transactions.extend(
+                others
+                    .into_iter()
+                    .filter(|tx| !tx.is_coinbase() && seen.insert(tx.hash())),
+            );
+            let hashes: Vec<TxHash> = transactions.iter().map(|t| t.hash()).collect();
+            header.merkle_root = merkle_root(&hashes);
+            Block {
+                header,
+                transactions,
+            }
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::AsyncEncodable;
+    use proptest::test_runner::{Config, TestError, TestRunner};
+
+    proptest! {
+        #[test]
+        fn scripts_have_their_kind(s in arb_script(&[ScriptKind::PushOnly]),
+                                   r in arb_script(&[ScriptKind::OpReturn])) {
+            prop_assert!(s.is_push_only());
+            prop_assert!(r.is_op_return());
+        }
+    }
+
+    /// Written the way an application would use the strategies: a codec that stores output values
+    /// in 32 bits fails the round trip, and proptest shrinks the failure to the smallest value that
+    /// does not fit in a transaction with a single input and output.
+    #[test]
+    fn shrinks_failing_round_trip() {
+        fn lossy_round_trip(tx: &Tx) -> Tx {
+            let mut copy = tx.clone();
+            for o in copy.outputs.iter_mut() {
+                o.value &= u32::MAX as u64;
+            }
+            Tx::from_binary_buf(&copy.to_binary_buf().unwrap()).unwrap()
+        }
+
+        let mut runner = TestRunner::new(Config {
+            failure_persistence: None,
+            ..Config::default()
+        });
+        let result = runner.run(&arb_tx(), |tx| {
+            prop_assert_eq!(lossy_round_trip(&tx), tx);
+            Ok(())
+        });
+        match result {
+            Err(TestError::Fail(_, minimal)) => {
+                assert_eq!(minimal.inputs.len(), 1);
+                assert_eq!(minimal.outputs.len(), 1);
+                assert_eq!(minimal.outputs[0].value, 1 << 32);
+            }
+            r => panic!("expected the property to fail, got {:?}", r),
+        }
+    }
+}
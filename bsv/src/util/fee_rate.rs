@@ -0,0 +1,70 @@
+use crate::util::Amount;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// A FeeRate is the fee paid per kilobyte of serialized transaction, expressed in satoshis.
+///
+/// Fee rates are conventionally quoted per 1000 bytes by node software, which is the unit used here.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct FeeRate {
+    pub sats_per_kb: u64,
+}
+
+impl FeeRate {
+    /// The zero fee rate.
+    pub const ZERO: FeeRate = FeeRate::from_sats_per_kb(0);
+
+    pub const fn from_sats_per_kb(sats_per_kb: u64) -> Self {
+        FeeRate { sats_per_kb }
+    }
+
+    /// Create a FeeRate from a number of satoshis per byte.
+    pub const fn from_sats_per_byte(sats_per_byte: u64) -> Self {
+        FeeRate {
+            sats_per_kb: sats_per_byte * 1000,
+        }
+    }
+
+    /// The fee rate in satoshis per byte. Don't use this in calculations.
+    pub fn as_sats_per_byte_f64(&self) -> f64 {
+        self.sats_per_kb as f64 / 1000.0
+    }
+
+    /// Calculate the fee for a transaction of `size` bytes.
+    ///
+    /// As with the node software, the result is rounded down except that a non-zero fee rate never produces
+    /// a zero fee for a non-empty transaction.
+    pub fn fee_for_size(&self, size: usize) -> Amount {
+        let mut fee = self.sats_per_kb.saturating_mul(size as u64) / 1000;
+        if fee == 0 && size != 0 && self.sats_per_kb > 0 {
+            fee = 1;
+        }
+        Amount::from_satoshis(fee as i64)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kB", self.sats_per_kb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_for_size() {
+        let r = FeeRate::from_sats_per_kb(1000);
+        assert_eq!(r.fee_for_size(250), Amount::from_satoshis(250));
+        assert_eq!(r, FeeRate::from_sats_per_byte(1));
+        let r = FeeRate::from_sats_per_kb(50);
+        assert_eq!(r.fee_for_size(226), Amount::from_satoshis(11));
+        assert_eq!(r.fee_for_size(10), Amount::from_satoshis(1));
+        assert_eq!(r.fee_for_size(0), Amount::ZERO);
+        assert_eq!(FeeRate::ZERO.fee_for_size(1000), Amount::ZERO);
+        assert_eq!(r.to_string(), "50 sat/kB");
+    }
+}
@@ -1,6 +1,10 @@
 mod amount;
+pub(crate) mod duration;
+mod fee_rate;
 
 pub use amount::Amount;
+pub use duration::parse_duration;
+pub use fee_rate::FeeRate;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Gets the time in seconds since UNIX_EPOCH, as an i64.
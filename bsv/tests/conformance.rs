@@ -0,0 +1,252 @@
+//! Replays the P2P sessions in `testdata/conformance` to check the wire format.
+//!
+//! Each capture file holds the bytes of one session, recorded by a [MessageTap], and is named after
+//! its blockchain. Every message received must decode, and every message sent must have a valid
+//! header and re-encode to exactly the bytes that were sent. See the README in that directory for
+//! how the captures are recorded.
+//!
+//! The captures named `selftest-*.cap` were recorded against the in-process stand-in node of
+//! [record_session()], so both ends of the session are this library. They check that the wire format
+//! is stable, but they are not evidence of interoperability with SV Node, only a capture recorded
+//! against a real node is. No such capture has been committed yet.
+#![cfg(feature = "p2p")]
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockHeader, BlockchainId, Hash};
+use bitcoinsv::p2p::replay::CaptureReader;
+use bitcoinsv::p2p::{
+    max_size_for_command, ChannelConfig, Connection, ConnectionConfig, Direction, Headers,
+    MessageFramer, MessageTap, P2PMessage, PeerAddress, PeerSession, Protoconf, Services, Version,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CAPTURE_DIR: &str = "../testdata/conformance";
+/// The prefix of the captures recorded against the stand-in node.
+const SELFTEST_PREFIX: &str = "selftest-";
+const HEADER_SIZE: usize = 24;
+const BLOCK_FILE: &str =
+    "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin";
+
+/// The blockchain of a capture, from the name of the file.
+fn chain_of(path: &Path) -> BlockchainId {
+    let name = path.file_stem().unwrap().to_str().unwrap();
+    match name.strip_prefix(SELFTEST_PREFIX).unwrap_or(name) {
+        "mainnet" => BlockchainId::Main,
+        "testnet" => BlockchainId::Test,
+        name => panic!("capture {} is not named after a blockchain", name),
+    }
+}
+
+fn config_for(chain: BlockchainId) -> ChannelConfig {
+    ChannelConfig {
+        magic: chain.magic(),
+        ..ChannelConfig::default()
+    }
+}
+
+/// Split a stream of bytes into frames, checking the header of each frame.
+fn frames(data: &[u8], config: &ChannelConfig, context: &str) -> Vec<(String, Vec<u8>)> {
+    let mut frames = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        assert!(rest.len() >= HEADER_SIZE, "{}: truncated header", context);
+        assert_eq!(rest[..4], config.magic, "{}: wrong magic", context);
+        let command: [u8; 12] = rest[4..16].try_into().unwrap();
+        let name_len = command.iter().position(|b| *b == 0).unwrap_or(12);
+        let name = std::str::from_utf8(&command[..name_len])
+            .unwrap()
+            .to_string();
+        assert!(
+            name.bytes().all(|b| b.is_ascii_lowercase()),
+            "{}: bad command {:?}",
+            context,
+            command
+        );
+        assert!(
+            command[name_len..].iter().all(|b| *b == 0),
+            "{}: command {} is not padded with zeros",
+            context,
+            name
+        );
+        let size = u32::from_le_bytes(rest[16..20].try_into().unwrap()) as usize;
+        assert!(
+            size as u64 <= max_size_for_command(&command, config),
+            "{}: {} payload of {} bytes is too large",
+            context,
+            name,
+            size
+        );
+        let frame_size = HEADER_SIZE + size;
+        assert!(rest.len() >= frame_size, "{}: truncated {}", context, name);
+        let payload = &rest[HEADER_SIZE..frame_size];
+        assert_eq!(
+            rest[20..24],
+            Hash::sha256d(payload).hash[..4],
+            "{}: bad checksum of {}",
+            context,
+            name
+        );
+        frames.push((name, rest[..frame_size].to_vec()));
+        rest = &rest[frame_size..];
+    }
+    frames
+}
+
+/// Replay a capture, returning the commands received and sent.
+async fn replay(path: &Path) -> (BTreeSet<String>, BTreeSet<String>) {
+    let chain = chain_of(path);
+    let config = config_for(chain);
+    let mut streams: BTreeMap<(bool, String), Vec<u8>> = BTreeMap::new();
+    for record in CaptureReader::open(path).unwrap() {
+        let record = record.unwrap();
+        let outbound = record.direction == Direction::Outbound;
+        streams
+            .entry((outbound, record.peer_id.to_string()))
+            .or_default()
+            .extend(record.data);
+    }
+    let mut received = BTreeSet::new();
+    let mut sent = BTreeSet::new();
+    for ((outbound, peer), data) in streams {
+        let context = format!("{} {}", path.display(), peer);
+        for (name, frame) in frames(&data, &config, &context) {
+            let mut framer = MessageFramer::new(config.clone());
+            framer.push_bytes(&frame);
+            let message = match framer.next_message() {
+                Some(Ok(message)) => message,
+                r => panic!("{}: {} did not decode: {:?}", context, name, r),
+            };
+            assert_eq!(message.command(), name);
+            if outbound {
+                let mut encoded = Vec::new();
+                message.write(&mut encoded, &config).await.unwrap();
+                assert_eq!(encoded, frame, "{}: {} encodes differently", context, name);
+                sent.insert(name);
+            } else {
+                received.insert(name);
+            }
+        }
+    }
+    (received, sent)
+}
+
+#[tokio::test]
+async fn replay_captures() {
+    let mut chains = Vec::new();
+    for entry in std::fs::read_dir(CAPTURE_DIR).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("cap") {
+            continue;
+        }
+        let (received, sent) = replay(&path).await;
+        for command in ["version", "verack", "headers"] {
+            assert!(received.contains(command), "{} not received", command);
+        }
+        for command in ["version", "verack", "getheaders"] {
+            assert!(sent.contains(command), "{} not sent", command);
+        }
+        chains.push(chain_of(&path));
+    }
+    assert!(chains.contains(&BlockchainId::Main));
+    assert!(chains.contains(&BlockchainId::Test));
+}
+
+/// Answer a connection as a node would, serving the headers to a getheaders request.
+async fn stand_in_node(listener: tokio::net::TcpListener, chain: BlockchainId) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut session = PeerSession::new(stream, config_for(chain));
+    let version = Version {
+        services: Services(0x25),
+        user_agent: "/Bitcoin SV:1.1.0/".to_string(),
+        start_height: 1,
+        ..Version::default()
+    };
+    session.handshake(version).await.unwrap();
+    session
+        .send_message(&P2PMessage::Protoconf(Protoconf::default()))
+        .await
+        .unwrap();
+    let mut headers = vec![BlockHeader::get_genesis(chain)];
+    if chain == BlockchainId::Main {
+        let block = std::fs::read(BLOCK_FILE).unwrap();
+        headers.push(BlockHeader::from_binary_buf(&block[..BlockHeader::SIZE]).unwrap());
+    }
+    while let Ok(message) = session.read_message().await {
+        if let P2PMessage::GetHeaders(_) = message {
+            let answer = P2PMessage::Headers(Headers {
+                headers: headers.clone(),
+            });
+            session.send_message(&answer).await.unwrap();
+        }
+    }
+}
+
+/// Record a session that syncs headers into `testdata/conformance`.
+///
+/// Set `CONFORMANCE_PEER` to the address of a node and `CONFORMANCE_CHAIN` to `main` or `test` to
+/// record a session with that node, into `mainnet.cap` or `testnet.cap`. Without a peer, an
+/// in-process stand-in node is used and the session is recorded into `selftest-mainnet.cap` or
+/// `selftest-testnet.cap`.
+/// Run with `cargo test -p bitcoinsv --test conformance -- --ignored record_session`.
+#[tokio::test]
+#[ignore]
+async fn record_session() {
+    let chain = BlockchainId::from(
+        std::env::var("CONFORMANCE_CHAIN")
+            .unwrap_or("main".to_string())
+            .as_str(),
+    );
+    let (address, prefix): (SocketAddr, &str) = match std::env::var("CONFORMANCE_PEER") {
+        Ok(peer) => (peer.parse().unwrap(), ""),
+        Err(_) => {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(stand_in_node(listener, chain));
+            (address, SELFTEST_PREFIX)
+        }
+    };
+    let name = match chain {
+        BlockchainId::Main => "mainnet",
+        BlockchainId::Test => "testnet",
+        _ => panic!("only mainnet and testnet are recorded"),
+    };
+    let path = PathBuf::from(CAPTURE_DIR).join(format!("{}{}.cap", prefix, name));
+    let _ = std::fs::remove_file(&path);
+    let tap = MessageTap::open(&path).await.unwrap();
+    let config = ConnectionConfig {
+        tap: Some(tap.clone()),
+        ..ConnectionConfig::default_for(chain)
+    };
+    let (connection, handle) = Connection::new(PeerAddress::new(address), Arc::new(config), None);
+    let mut rx = connection.subscribe();
+    let locator = bitcoinsv::p2p::BlockLocator {
+        version: Version::default().version,
+        block_locator_hashes: vec![BlockHeader::get_genesis(chain).hash()],
+        hash_stop: Hash::ZERO,
+    };
+    // the request is dropped until the handshake is complete, so it is repeated until answered
+    let answered = tokio::time::timeout(Duration::from_secs(60), async {
+        loop {
+            connection.request_headers(locator.clone()).await.unwrap();
+            let wait = tokio::time::sleep(Duration::from_millis(500));
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    r = rx.recv() => if let Ok(envelope) = r {
+                        if let P2PMessage::Headers(_) = envelope.message {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await;
+    connection.close().await;
+    handle.await.unwrap();
+    tap.flush().await.unwrap();
+    assert!(answered.is_ok(), "the headers were not received");
+}
@@ -0,0 +1,49 @@
+//! Uses the library as an application that only works with transactions, scripts and blocks would.
+//!
+//! This test only uses the `bitcoin` and `util` modules, which must build without the `p2p` feature.
+//! CI runs it with `cargo test -p bitcoinsv --no-default-features --test minimal` so that a
+//! dependency of those modules on the P2P stack is caught.
+use bitcoinsv::bitcoin::{
+    AsyncEncodable, Block, BlockHeader, BlockchainId, CoinbaseInfo, FromHex, Hash, KeyPair,
+    PrivateKey, ScriptTemplate, Tx, TxBuilder, TxOutput,
+};
+use bitcoinsv::util::FeeRate;
+
+const BLOCK_FILE: &str =
+    "../testdata/0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7.bin";
+
+#[test]
+fn parse_block() {
+    let bin = std::fs::read(BLOCK_FILE).unwrap();
+    let block = Block::from_binary_buf(&bin).unwrap();
+    assert_eq!(
+        block.hash(),
+        Hash::from_hex("0000000000000000000988036522057056727ae85ad7cea92b2198418c9bb8f7").unwrap()
+    );
+    block.validate().unwrap();
+    assert_eq!(
+        CoinbaseInfo::parse(&block.transactions[0]).unwrap().height,
+        825_188
+    );
+    let genesis = BlockHeader::get_genesis(BlockchainId::Main);
+    assert_eq!(genesis.prev_hash, Hash::ZERO);
+}
+
+#[test]
+fn build_transaction() {
+    let key = PrivateKey::from_slice(&[1; 32]).unwrap();
+    let keys = KeyPair::from_private_key(key, BlockchainId::Main);
+    let script = keys.address.locking_script();
+    assert_eq!(
+        ScriptTemplate::classify(&script),
+        Some(ScriptTemplate::P2pkh)
+    );
+    let tx = TxBuilder::new()
+        .add_output(&TxOutput::new(1_000, script))
+        .build()
+        .unwrap();
+    let bin = tx.to_binary_buf().unwrap();
+    assert_eq!(Tx::from_binary_buf(&bin).unwrap(), tx);
+    let fee = FeeRate::from_sats_per_byte(1).fee_for_size(bin.len());
+    assert_eq!(fee.satoshis, bin.len() as i64);
+}
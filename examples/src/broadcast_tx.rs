@@ -0,0 +1,140 @@
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockchainId, Tx, TxHash};
+use bitcoinsv::p2p::{
+    DataProvider, GetDataResponder, InvItem, OperatingMode, P2PManager, P2PManagerConfig,
+    PeerAddress,
+};
+use bitcoinsv::util::FeeRate;
+use bytes::Bytes;
+use clap::Parser;
+use env_logger::Env;
+use hex::FromHex;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Broadcast a transaction to the network and report how the peers respond.
+///
+/// The transaction is sent to a single peer if one is given, otherwise to peers found from the DNS
+/// seeds. The exit code is 1 if every peer rejected the transaction, 2 if no peer completed the
+/// handshake, and 3 if the transaction could not be decoded.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The raw transaction, in hex.
+    #[clap(index = 1)]
+    tx: String,
+    /// The blockchain: main, test, stn or regtest.
+    #[clap(long, default_value = "main")]
+    chain: String,
+    /// The address of a peer to send the transaction to, such as 127.0.0.1:18444.
+    #[clap(long)]
+    peer: Option<SocketAddr>,
+    /// The number of peers to connect to when the peers are discovered.
+    #[clap(long, default_value = "4")]
+    connections: u16,
+    /// The number of seconds to wait for the handshakes to complete.
+    #[clap(long, default_value = "30")]
+    connect_timeout: u64,
+    /// The number of seconds to wait for the peers to respond to the broadcast.
+    #[clap(long, default_value = "30")]
+    wait: u64,
+}
+
+/// Serves the transaction to the peers that request it.
+struct RawTx {
+    hash: TxHash,
+    data: Bytes,
+}
+
+impl DataProvider for RawTx {
+    fn get_data(&self, item: &InvItem) -> Option<Bytes> {
+        (item.hash == self.hash).then(|| self.data.clone())
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+    let args: Args = Args::parse();
+    let data = match Vec::<u8>::from_hex(args.tx.trim()) {
+        Ok(d) => Bytes::from(d),
+        Err(e) => {
+            eprintln!("the transaction is not valid hex: {}", e);
+            return ExitCode::from(3);
+        }
+    };
+    let tx = match Tx::from_binary_buf(&data) {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("the transaction could not be decoded: {}", e);
+            return ExitCode::from(3);
+        }
+    };
+    let hash = tx.hash();
+    let responder = GetDataResponder::new(Arc::new(RawTx { hash, data }), 1_000_000_000);
+
+    let mut builder = P2PManagerConfig::builder();
+    builder
+        .blockchain(BlockchainId::from(args.chain.as_str()))
+        .listen(false)
+        .getdata_responder(responder);
+    match args.peer {
+        Some(address) => builder
+            .connections_target(1)
+            .initial_peers(vec![PeerAddress::new(address)])
+            .operating_mode(OperatingMode::FixedPeerList),
+        None => builder
+            .connections_target(args.connections)
+            .query_dns_seeds(true),
+    };
+    let (manager, handle) = P2PManager::new(builder.build().unwrap()).await.unwrap();
+
+    // wait for the handshakes, until all of the connections are established or the time is up
+    let target = if args.peer.is_some() {
+        1
+    } else {
+        args.connections
+    };
+    let start = Instant::now();
+    while manager.connection_counts().established < usize::from(target)
+        && start.elapsed() < Duration::from_secs(args.connect_timeout)
+    {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    if manager.connection_counts().established == 0 {
+        println!("txid: {}", hash);
+        println!("result: no peers");
+        manager.stop().await.unwrap();
+        handle.await.unwrap();
+        return ExitCode::from(2);
+    }
+
+    // fee filters are only applied if respect_fee_filter is set, so the rate is not used
+    let report = manager
+        .broadcast_tx_and_report(hash, FeeRate::ZERO, Duration::from_secs(args.wait))
+        .await
+        .unwrap();
+    println!("txid: {}", hash);
+    println!("announced to: {} peers", report.announced_to.len());
+    println!("requested by: {} peers", report.requested_by.len());
+    println!("rejected by: {} peers", report.rejected_by.len());
+    for (peer_id, reject) in report.rejected_by.iter() {
+        println!(
+            "  {}: code {:#04x}, {}",
+            peer_id, reject.code, reject.reason
+        );
+    }
+    let rejected = report.is_rejected();
+    println!(
+        "result: {}",
+        if rejected { "rejected" } else { "broadcast" }
+    );
+    manager.stop().await.unwrap();
+    handle.await.unwrap();
+    if rejected {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,129 @@
+use bitcoinsv::bitcoin::BlockchainId;
+use bitcoinsv::p2p::{
+    FileHeaderStore, HeaderIngest, MemoryPeerStore, P2PManager, P2PManagerConfig, P2PMessage,
+    DEFAULT_MAX_ORPHANS,
+};
+use clap::Parser;
+use env_logger::Env;
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+// how often progress is printed and stalled requests are retried
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+// a request that has not been answered in this time is sent to another peer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// the most headers sent in answer to a getheaders, fewer means that the peer has no more
+const MAX_HEADERS: usize = 2000;
+
+/// A minimal node that syncs the block headers of mainnet, headers first.
+///
+/// Peers are found from the DNS seeds and the headers are kept in a file, so a sync that is
+/// interrupted with ctrl-c continues from where it stopped.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The file in which the headers are kept, it is created if it does not exist.
+    #[clap(long, default_value = "headers.dat")]
+    headers: PathBuf,
+    /// The number of peers to connect to.
+    #[clap(long, default_value = "4")]
+    connections: u16,
+}
+
+/// The getheaders request that is waiting for an answer.
+struct Request {
+    peer_id: Uuid,
+    sent: Instant,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let args: Args = Args::parse();
+    let mut store = FileHeaderStore::open(&args.headers, BlockchainId::Main).unwrap();
+    info!("header store opened at height {}", store.height());
+
+    let config = P2PManagerConfig::builder()
+        .blockchain(BlockchainId::Main)
+        .listen(false)
+        .connections_target(args.connections)
+        .query_dns_seeds(true)
+        .peer_store(Some(Arc::new(MemoryPeerStore::default())))
+        .build()
+        .unwrap();
+    let (manager, handle) = P2PManager::new(config).await.unwrap();
+    let mut rx = manager.subscribe();
+    let mut ingest = HeaderIngest::new(DEFAULT_MAX_ORPHANS);
+    let mut request: Option<Request> = None;
+    let mut synced = false;
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut last_height = store.height();
+    let mut last_progress = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                info!("stopping");
+                break;
+            }
+            r = rx.recv() => {
+                let envelope = match r {
+                    Ok(envelope) => envelope,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("missed {} messages", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let P2PMessage::Headers(headers) = &envelope.message else {
+                    continue;
+                };
+                let applied = ingest.ingest(envelope.peer_id, &headers.headers, &mut store);
+                let answered = request.as_ref().is_some_and(|r| r.peer_id == envelope.peer_id);
+                if answered {
+                    request = None;
+                    if headers.headers.len() < MAX_HEADERS {
+                        if !synced {
+                            info!("synced to height {}", store.height());
+                        }
+                        synced = true;
+                    } else if applied > 0 {
+                        // ask the same peer for the next batch straight away
+                        manager.request_headers(envelope.peer_id, store.locator()).await.unwrap();
+                        request = Some(Request { peer_id: envelope.peer_id, sent: Instant::now() });
+                    }
+                } else if applied > 0 && synced {
+                    info!("new tip at height {}", store.height());
+                }
+            }
+            _ = progress.tick() => {
+                let height = store.height();
+                let rate = f64::from(height - last_height) / last_progress.elapsed().as_secs_f64();
+                let peers = manager.connection_counts().established;
+                info!("height {}, {:.0} headers/sec, {} peers", height, rate, peers);
+                last_height = height;
+                last_progress = Instant::now();
+                if synced || request.as_ref().is_some_and(|r| r.sent.elapsed() < REQUEST_TIMEOUT) {
+                    continue;
+                }
+                // start the sync, or move a request that was not answered to another peer
+                let stalled = request.take().map(|r| r.peer_id);
+                let connected = manager.connected_peers().await.unwrap();
+                if let Some(peer) = connected.iter().find(|p| Some(p.peer_id) != stalled) {
+                    manager.request_headers(peer.peer_id, store.locator()).await.unwrap();
+                    request = Some(Request { peer_id: peer.peer_id, sent: Instant::now() });
+                }
+            }
+        }
+    }
+
+    if let Err(e) = store.sync() {
+        warn!("could not sync header store: {}", e);
+    }
+    manager.stop().await.unwrap();
+    handle.await.unwrap();
+}
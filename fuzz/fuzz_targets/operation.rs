@@ -0,0 +1,18 @@
+#![no_main]
+
+use bitcoinsv::bitcoin::{Encodable, Operation};
+use bytes::{Bytes, BytesMut};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = Bytes::copy_from_slice(data);
+    if let Ok(op) = Operation::from_binary(&mut buf) {
+        let mut encoded = BytesMut::new();
+        op.to_binary(&mut encoded)
+            .expect("encoding a decoded operation failed");
+        assert_eq!(encoded.len(), op.size());
+        let decoded = Operation::from_binary(&mut encoded.freeze())
+            .expect("decoding a re-encoded operation failed");
+        assert_eq!(decoded, op);
+    }
+});
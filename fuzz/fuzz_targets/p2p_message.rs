@@ -0,0 +1,25 @@
+#![no_main]
+
+use bitcoinsv::p2p::{ChannelConfig, P2PMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let config = ChannelConfig::default();
+    let mut reader = data;
+    if let Ok(msg) = rt.block_on(P2PMessage::read(&mut reader, &config)) {
+        if let P2PMessage::Unknown(..) = msg {
+            // unknown messages are not retained and can not be written
+            return;
+        }
+        let mut encoded = Vec::new();
+        rt.block_on(msg.write(&mut encoded, &config))
+            .expect("encoding a decoded message failed");
+        let decoded = rt
+            .block_on(P2PMessage::read(&mut encoded.as_slice(), &config))
+            .expect("decoding a re-encoded message failed");
+        assert_eq!(decoded, msg);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use bitcoinsv::bitcoin::{Encodable, Script};
+use bytes::{BufMut, BytesMut};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let script = Script::from(data.to_vec());
+    let _ = script.is_push_only();
+    let _ = script.is_op_return();
+    if let Ok((ops, trailing)) = script.decode() {
+        // the no-op codes all decode to the same operation, so the bytes may differ but decoding
+        // the re-encoded script must produce the same result
+        let mut buf = BytesMut::new();
+        for op in ops.iter() {
+            op.to_binary(&mut buf)
+                .expect("encoding a decoded operation failed");
+        }
+        if let Some(t) = trailing.as_ref() {
+            buf.put(t.get_bytes());
+        }
+        let encoded = Script::from(buf.to_vec());
+        let decoded = encoded
+            .decode()
+            .expect("decoding a re-encoded script failed");
+        assert_eq!(decoded, (ops, trailing));
+    }
+});
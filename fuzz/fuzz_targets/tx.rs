@@ -0,0 +1,13 @@
+#![no_main]
+
+use bitcoinsv::bitcoin::{AsyncEncodable, Tx};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tx) = Tx::from_binary_buf(data) {
+        let encoded = tx.to_binary_buf().expect("encoding a decoded tx failed");
+        assert_eq!(encoded.len(), tx.async_size());
+        let decoded = Tx::from_binary_buf(&encoded).expect("decoding a re-encoded tx failed");
+        assert_eq!(decoded, tx);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use bitcoinsv::bitcoin::{varint_decode, varint_encode, varint_size};
+use futures::executor::block_on;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    if let Ok(n) = block_on(varint_decode(&mut reader)) {
+        let mut encoded = Vec::new();
+        block_on(varint_encode(&mut encoded, n)).expect("encoding a varint failed");
+        assert_eq!(encoded.len(), varint_size(n));
+        let decoded = block_on(varint_decode(&mut encoded.as_slice()))
+            .expect("decoding a re-encoded varint failed");
+        assert_eq!(decoded, n);
+    }
+});